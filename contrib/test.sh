@@ -2,7 +2,7 @@
 
 set -e
 
-FEATURES="compiler use-serde rand"
+FEATURES="compiler use-serde rand json test-utils smallvec"
 
 # Use toolchain if explicitly specified
 if [ -n "$TOOLCHAIN" ]
@@ -45,6 +45,9 @@ then
         cargo test --features="$feature"
     done
 
+    # Shared secp context, which is std-only
+    cargo test --features="global-context"
+
     # Run all the examples
     cargo build --examples
     cargo run --example htlc --features=compiler
@@ -63,6 +66,9 @@ then
   # Test no_std
   cargo test --verbose --no-default-features --features="no-std"
 
+  # Test no_std with all features, including the compiler
+  cargo test --verbose --no-default-features --features="no-std $FEATURES"
+
   # Build all features
   cargo build --verbose --no-default-features --features="no-std $FEATURES"
 
@@ -23,15 +23,16 @@ use core::fmt;
 use bitcoin::blockdata::script;
 use bitcoin::{Address, Network, Script};
 
-use super::checksum::{desc_checksum, verify_checksum};
+use super::checksum::{desc_checksum, verify_checksum_located};
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::ScriptContext;
+use crate::miniscript::satisfy::SigSizeAssumptions;
 use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
 use crate::util::{varint_len, witness_to_scriptsig};
 use crate::{
-    BareCtx, Error, ForEach, ForEachKey, Miniscript, MiniscriptKey, Satisfier, ToPublicKey,
-    TranslatePk, Translator,
+    BareCtx, Error, ForEach, ForEachKey, FromStrParser, KeyParser, Miniscript, MiniscriptKey,
+    Satisfier, ToPublicKey, TranslatePk, Translator,
 };
 
 /// Create a Bare Descriptor. That is descriptor that is
@@ -76,7 +77,17 @@ impl<Pk: MiniscriptKey> Bare<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
-        let scriptsig_len = self.ms.max_satisfaction_size()?;
+        self.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Bare::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(
+        &self,
+        sig_sizes: SigSizeAssumptions,
+    ) -> Result<usize, Error> {
+        let scriptsig_len = self.ms.max_satisfaction_size_with_sig_sizes(sig_sizes)?;
         Ok(4 * (varint_len(scriptsig_len) + scriptsig_len))
     }
 }
@@ -147,18 +158,33 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Bare<Pk> {
 impl_from_tree!(
     Bare<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
-        let sub = Miniscript::<Pk, BareCtx>::from_tree(top)?;
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Bare<Pk> {
+    /// Parses an expression tree into a bare descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        let sub = Miniscript::<Pk, BareCtx>::from_tree_with(top, parser)?;
         BareCtx::top_level_checks(&sub)?;
         Bare::new(sub)
     }
-);
+}
 
 impl_from_str!(
     Bare<Pk>,
     type Err = Error;,
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let desc_str = verify_checksum(s)?;
-        let top = expression::Tree::from_str(desc_str)?;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let desc_str = verify_checksum_located(s)?;
+        let top = expression::Tree::parse(desc_str)?;
         Self::from_tree(&top)
     }
 );
@@ -219,7 +245,14 @@ impl<Pk: MiniscriptKey> Pkh<Pk> {
     /// sighash suffix. Includes the weight of the VarInts encoding the
     /// scriptSig and witness stack length.
     pub fn max_satisfaction_weight(&self) -> usize {
-        4 * (1 + 73 + BareCtx::pk_len(&self.pk))
+        self.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Pkh::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(&self, sig_sizes: SigSizeAssumptions) -> usize {
+        4 * (1 + (sig_sizes.ecdsa() + 1) + BareCtx::pk_len(&self.pk))
     }
 }
 
@@ -301,9 +334,24 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Pkh<Pk> {
 impl_from_tree!(
     Pkh<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Pkh<Pk> {
+    /// Parses an expression tree into a `pkh` descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         if top.name == "pkh" && top.args.len() == 1 {
             Ok(Pkh::new(expression::terminal(&top.args[0], |pk| {
-                Pk::from_str(pk)
+                parser.pk(pk)
             })?))
         } else {
             Err(Error::Unexpected(format!(
@@ -313,14 +361,14 @@ impl_from_tree!(
             )))
         }
     }
-);
+}
 
 impl_from_str!(
     Pkh<Pk>,
     type Err = Error;,
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let desc_str = verify_checksum(s)?;
-        let top = expression::Tree::from_str(desc_str)?;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let desc_str = verify_checksum_located(s)?;
+        let top = expression::Tree::parse(desc_str)?;
         Self::from_tree(&top)
     }
 );
@@ -1,12 +1,15 @@
 //! Descriptor checksum
 //!
 //! This module contains a re-implementation of the function used by Bitcoin Core to calculate the
-//! checksum of a descriptor
+//! checksum of a descriptor, along with helpers to verify and strip the `#` suffix
 
+use core::fmt;
 use core::iter::FromIterator;
+#[cfg(feature = "std")]
+use std::error;
 
 use crate::prelude::*;
-use crate::Error;
+use crate::{expression, Error};
 
 const INPUT_CHARSET: &str =  "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
 const CHECKSUM_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
@@ -34,19 +37,71 @@ fn poly_mod(mut c: u64, val: u64) -> u64 {
     c
 }
 
+/// An error validating the checksum of a descriptor string
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChecksumError {
+    /// The descriptor contains a character outside of the checksum charset
+    InvalidCharacter(char),
+    /// The descriptor string has no `#` checksum suffix
+    MissingChecksum {
+        /// The checksum the descriptor should carry
+        expected: String,
+    },
+    /// The checksum does not match the descriptor
+    Mismatch {
+        /// The checksum the descriptor should carry
+        expected: String,
+        /// The checksum found after the `#`
+        found: String,
+    },
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChecksumError::InvalidCharacter(ch) => {
+                write!(f, "Invalid character in checksum: '{}'", ch)
+            }
+            ChecksumError::MissingChecksum { ref expected } => {
+                write!(f, "Missing checksum, expected '{}'", expected)
+            }
+            ChecksumError::Mismatch {
+                ref expected,
+                ref found,
+            } => write!(f, "Invalid checksum '{}', expected '{}'", found, expected),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ChecksumError {}
+
+impl From<ChecksumError> for Error {
+    fn from(e: ChecksumError) -> Error {
+        Error::BadChecksum(e, None)
+    }
+}
+
 /// Compute the checksum of a descriptor
 /// Note that this function does not check if the
 /// descriptor string is syntactically correct or not.
 /// This only computes the checksum
 pub fn desc_checksum(desc: &str) -> Result<String, Error> {
+    compute_checksum(desc).map_err(Error::from)
+}
+
+/// Compute the checksum of a descriptor, reporting failures as a [`ChecksumError`]
+///
+/// The input must not carry a `#` suffix; use [`strip_checksum`] first if it may.
+pub fn compute_checksum(desc: &str) -> Result<String, ChecksumError> {
     let mut c = 1;
     let mut cls = 0;
     let mut clscount = 0;
 
     for ch in desc.chars() {
-        let pos = INPUT_CHARSET.find(ch).ok_or_else(|| {
-            Error::BadDescriptor(format!("Invalid character in checksum: '{}'", ch))
-        })? as u64;
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .ok_or(ChecksumError::InvalidCharacter(ch))? as u64;
         c = poly_mod(c, pos & 31);
         cls = cls * 3 + (pos >> 5);
         clscount += 1;
@@ -75,29 +130,45 @@ pub fn desc_checksum(desc: &str) -> Result<String, Error> {
     Ok(String::from_iter(chars))
 }
 
+/// Remove the `#` checksum suffix from a descriptor string, if any,
+/// without verifying it
+pub fn strip_checksum(s: &str) -> &str {
+    s.split('#').next().unwrap()
+}
+
+/// Check that a descriptor string carries a `#` checksum and that it is
+/// correct, returning the descriptor without the checksum
+pub fn validate_checksum(s: &str) -> Result<&str, ChecksumError> {
+    let mut parts = s.splitn(2, '#');
+    let desc_str = parts.next().unwrap();
+    let expected = compute_checksum(desc_str)?;
+    match parts.next() {
+        None => Err(ChecksumError::MissingChecksum { expected }),
+        Some(found) if found != expected => Err(ChecksumError::Mismatch {
+            expected,
+            found: found.to_owned(),
+        }),
+        Some(_) => Ok(desc_str),
+    }
+}
+
 /// Helper function for FromStr for various
 /// descriptor types. Checks and verifies the checksum
 /// if it is present and returns the descriptor string
 /// without the checksum
-pub(super) fn verify_checksum(s: &str) -> Result<&str, Error> {
-    for ch in s.as_bytes() {
-        if *ch < 20 || *ch > 127 {
-            return Err(Error::Unprintable(*ch));
-        }
-    }
+pub fn verify_checksum(s: &str) -> Result<&str, Error> {
+    verify_checksum_located(s).map_err(Error::unlocated)
+}
 
-    let mut parts = s.splitn(2, '#');
-    let desc_str = parts.next().unwrap();
-    if let Some(checksum_str) = parts.next() {
-        let expected_sum = desc_checksum(desc_str)?;
-        if checksum_str != expected_sum {
-            return Err(Error::BadDescriptor(format!(
-                "Invalid checksum '{}', expected '{}'",
-                checksum_str, expected_sum
-            )));
-        }
+// Same as `verify_checksum`, with the position of the error kept in it
+pub(crate) fn verify_checksum_located(s: &str) -> Result<&str, Error> {
+    expression::printable(s)?;
+
+    if s.contains('#') {
+        Ok(validate_checksum(s)?)
+    } else {
+        Ok(s)
     }
-    Ok(desc_str)
 }
 #[cfg(test)]
 mod test {
@@ -151,4 +222,32 @@ mod test {
             )
         );
     }
+
+    #[test]
+    fn validate_and_strip() {
+        let desc = "wpkh(tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/1/2/*)";
+        let with_sum = format!("{}#tqz0nc62", desc);
+
+        assert_eq!(strip_checksum(&with_sum), desc);
+        assert_eq!(strip_checksum(desc), desc);
+        assert_eq!(validate_checksum(&with_sum), Ok(desc));
+        assert_eq!(verify_checksum(desc).unwrap(), desc);
+        assert_eq!(
+            validate_checksum(desc),
+            Err(ChecksumError::MissingChecksum {
+                expected: "tqz0nc62".to_owned()
+            })
+        );
+        assert_eq!(
+            validate_checksum(&format!("{}#tqz0nc63", desc)),
+            Err(ChecksumError::Mismatch {
+                expected: "tqz0nc62".to_owned(),
+                found: "tqz0nc63".to_owned(),
+            })
+        );
+        assert_eq!(
+            compute_checksum("wpkh(\u{e9})"),
+            Err(ChecksumError::InvalidCharacter('\u{e9}'))
+        );
+    }
 }
@@ -0,0 +1,213 @@
+//! Bitcoin Core `importdescriptors` Export
+//!
+//! Serializes descriptors into the JSON request array accepted by Bitcoin Core's
+//! `importdescriptors` RPC, so wallets can be migrated into Core without
+//! hand-formatting the request.
+
+use core::fmt::{self, Write};
+
+use super::{ConversionError, DerivationRange, Descriptor, DescriptorPublicKey, KeyMap};
+use crate::prelude::*;
+
+/// The `timestamp` field of an `importdescriptors` request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImportTimestamp {
+    /// `"now"`: no rescan is done, only use for freshly created descriptors
+    Now,
+    /// UNIX time of the earliest transaction that may involve the descriptor
+    Time(u64),
+}
+
+/// A single entry of an `importdescriptors` request
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImportRequest {
+    /// The descriptor string, including its checksum
+    pub descriptor: String,
+    /// Inclusive `[begin, end]` range of indices to import, for ranged descriptors
+    pub range: Option<(u32, u32)>,
+    /// Where to start rescanning the chain from
+    pub timestamp: ImportTimestamp,
+    /// Whether the descriptor is used for change outputs
+    pub internal: bool,
+    /// Whether the descriptor becomes the active one for new addresses of its type
+    pub active: bool,
+    /// Label for the imported addresses; Core rejects labels on internal descriptors
+    pub label: Option<String>,
+}
+
+impl ImportRequest {
+    /// Serialize this request as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json)
+            .expect("Writing to a String cannot fail");
+        json
+    }
+
+    fn write_json<W: Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_str("{\"desc\":")?;
+        write_json_str(w, &self.descriptor)?;
+        if let Some((begin, end)) = self.range {
+            write!(w, ",\"range\":[{},{}]", begin, end)?;
+        }
+        match self.timestamp {
+            ImportTimestamp::Now => w.write_str(",\"timestamp\":\"now\"")?,
+            ImportTimestamp::Time(t) => write!(w, ",\"timestamp\":{}", t)?,
+        }
+        write!(w, ",\"internal\":{}", self.internal)?;
+        write!(w, ",\"active\":{}", self.active)?;
+        if let Some(ref label) = self.label {
+            w.write_str(",\"label\":")?;
+            write_json_str(w, label)?;
+        }
+        w.write_str("}")
+    }
+}
+
+/// Serialize a list of requests as the JSON array taken by `importdescriptors`
+pub fn import_descriptors_json(requests: &[ImportRequest]) -> String {
+    let mut json = String::from("[");
+    for (i, request) in requests.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        request
+            .write_json(&mut json)
+            .expect("Writing to a String cannot fail");
+    }
+    json.push(']');
+    json
+}
+
+pub(super) fn write_json_str<W: Write>(w: &mut W, s: &str) -> fmt::Result {
+    w.write_char('"')?;
+    for ch in s.chars() {
+        match ch {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Build an `importdescriptors` request for this descriptor
+    ///
+    /// Ranged descriptors import the indices in `range` and are marked active; for other
+    /// descriptors `range` is ignored. Fails with [`ConversionError::EmptyRange`] if `range`
+    /// is empty.
+    pub fn import_request(
+        &self,
+        range: DerivationRange,
+        timestamp: ImportTimestamp,
+        internal: bool,
+    ) -> Result<ImportRequest, ConversionError> {
+        if range.is_empty() {
+            return Err(ConversionError::EmptyRange);
+        }
+        let deriveable = self.is_deriveable();
+        let range = range.to_range();
+        Ok(ImportRequest {
+            descriptor: self.to_string(),
+            range: if deriveable {
+                Some((range.start, range.end - 1))
+            } else {
+                None
+            },
+            timestamp,
+            internal,
+            active: deriveable,
+            label: None,
+        })
+    }
+
+    /// Like [`Descriptor::import_request`], but exports the secret keys in `key_map`
+    /// so that Core can sign for the descriptor
+    pub fn import_request_with_secret(
+        &self,
+        key_map: &KeyMap,
+        range: DerivationRange,
+        timestamp: ImportTimestamp,
+        internal: bool,
+    ) -> Result<ImportRequest, ConversionError> {
+        Ok(ImportRequest {
+            descriptor: self.to_string_with_secret(key_map),
+            ..self.import_request(range, timestamp, internal)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn import_descriptors_json_format() {
+        let external = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*)").unwrap();
+        let single = Descriptor::<DescriptorPublicKey>::from_str(
+            "pkh(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)",
+        )
+        .unwrap();
+
+        let range = DerivationRange::new(0..1000).unwrap();
+        let ranged = external
+            .import_request(range, ImportTimestamp::Time(1_600_000_000), false)
+            .unwrap();
+        assert_eq!(ranged.range, Some((0, 999)));
+        assert!(ranged.active);
+        let mut labelled = single
+            .import_request(range, ImportTimestamp::Now, false)
+            .unwrap();
+        labelled.label = Some("cold \"storage\"".to_string());
+        assert_eq!(labelled.range, None);
+        assert!(!labelled.active);
+
+        assert_eq!(
+            import_descriptors_json(&[ranged, labelled]),
+            format!(
+                "[{{\"desc\":\"{}\",\"range\":[0,999],\"timestamp\":1600000000,\"internal\":false,\"active\":true}},\
+                 {{\"desc\":\"{}\",\"timestamp\":\"now\",\"internal\":false,\"active\":false,\"label\":\"cold \\\"storage\\\"\"}}]",
+                external, single
+            )
+        );
+        assert_eq!(import_descriptors_json(&[]), "[]");
+    }
+
+    #[test]
+    fn import_request_with_secret() {
+        let secp = bitcoin::secp256k1::Secp256k1::signing_only();
+        let (descriptor, key_map) = Descriptor::parse_descriptor(&secp, "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/*)").unwrap();
+        let range = DerivationRange::new(0..10).unwrap();
+        let request = descriptor
+            .import_request_with_secret(&key_map, range, ImportTimestamp::Now, true)
+            .unwrap();
+        assert!(request.descriptor.starts_with("wpkh(xprv"));
+        assert!(request.internal);
+        assert_eq!(request.range, Some((0, 9)));
+    }
+
+    #[test]
+    fn import_request_range() {
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*)").unwrap();
+        let last = DerivationRange::new((1 << 31) - 1..1 << 31).unwrap();
+        let request = descriptor
+            .import_request(last, ImportTimestamp::Now, false)
+            .unwrap();
+        assert_eq!(request.range, Some(((1 << 31) - 1, (1 << 31) - 1)));
+
+        let empty = DerivationRange::new(5..5).unwrap();
+        assert_eq!(
+            descriptor.import_request(empty, ImportTimestamp::Now, false),
+            Err(ConversionError::EmptyRange)
+        );
+        // Hardened indices cannot be requested
+        assert_eq!(
+            DerivationRange::new(0..(1 << 31) + 1),
+            Err(ConversionError::HardenedIndex(1 << 31))
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Descriptor Inference
+//!
+//! Recovers the most specific descriptor possible from a scriptPubKey and,
+//! optionally, the scriptSig and witness that spent it. This is the reverse
+//! of address generation and is useful for chain analysis and watch-only
+//! imports.
+
+use core::fmt;
+
+use bitcoin::blockdata::opcodes::{Class, ClassifyContext};
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::blockdata::witness::Witness;
+use bitcoin::hashes::{hash160, sha256, Hash};
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX};
+use bitcoin::{self, Script};
+use sync::Arc;
+
+use super::{Descriptor, TapTree};
+use crate::prelude::*;
+use crate::util::with_secp;
+use crate::{
+    BareCtx, Legacy, Miniscript, MiniscriptKey, PkTranslator, Segwitv0, Tap, ToPublicKey,
+    TranslatePk,
+};
+
+/// The result of inferring a descriptor from chain data
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)] // The full descriptor is the common case.
+pub enum InferredDescriptor {
+    /// The full descriptor could be recovered
+    Descriptor(Descriptor<bitcoin::PublicKey>),
+    /// A P2PKH output whose public key was not revealed
+    RawPkh(hash160::Hash),
+    /// A P2WPKH output whose public key was not revealed
+    RawWpkh(hash160::Hash),
+    /// A P2SH output whose redeem script was not revealed or is not miniscript
+    RawSh(hash160::Hash),
+    /// A P2WSH output whose witness script was not revealed or is not miniscript
+    RawWsh(sha256::Hash),
+    /// A script that is neither a known output type nor a bare miniscript
+    Raw(Script),
+    /// A taproot script path spend of a leaf with unknown semantics, which
+    /// anyone can spend
+    AnyoneCanSpendLeaf {
+        /// The internal key of the output
+        internal_key: bitcoin::XOnlyPublicKey,
+        /// The spent leaf script
+        script: Script,
+        /// Why the semantics of the leaf are unknown
+        reason: UnknownTapLeaf,
+    },
+}
+
+/// Why the semantics of a taproot leaf are unknown
+///
+/// Consensus rules let anyone spend such a leaf, reserving it for future
+/// soft forks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UnknownTapLeaf {
+    /// The leaf version is not tapscript (`0xc0`)
+    LeafVersion(LeafVersion),
+    /// The tapscript contains the `OP_SUCCESSx` opcode with this code
+    OpSuccess(u8),
+}
+
+impl fmt::Display for UnknownTapLeaf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnknownTapLeaf::LeafVersion(version) => {
+                write!(f, "unknown leaf version {:#x}", version)
+            }
+            UnknownTapLeaf::OpSuccess(code) => write!(f, "OP_SUCCESS{} opcode", code),
+        }
+    }
+}
+
+/// Checks whether a taproot leaf has unknown semantics
+///
+/// Scripts which cannot be decoded are not checked for `OP_SUCCESSx`, as
+/// consensus rules require them to decode up to such an opcode.
+pub(crate) fn unknown_tap_leaf(
+    script: &Script,
+    leaf_version: LeafVersion,
+) -> Option<UnknownTapLeaf> {
+    if leaf_version != LeafVersion::TapScript {
+        return Some(UnknownTapLeaf::LeafVersion(leaf_version));
+    }
+    for ins in script.instructions() {
+        match ins {
+            Ok(Instruction::Op(op)) => {
+                if op.classify(ClassifyContext::TapScript) == Class::SuccessOp {
+                    return Some(UnknownTapLeaf::OpSuccess(op.into_u8()));
+                }
+            }
+            Ok(Instruction::PushBytes(_)) => {}
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+/// Infer a descriptor from a scriptPubKey and the scriptSig and witness spending it
+///
+/// Pass an empty `script_sig` and `witness` for unspent outputs. Whenever the revealed
+/// data does not match the scriptPubKey or cannot be parsed as miniscript, the
+/// corresponding raw variant of [`InferredDescriptor`] is returned instead. Scripts are
+/// parsed without sanity checks, since chain data need not be safe. Taproot x-only keys
+/// are returned with an even (`0x02`) y-coordinate, and taproot script path spends give
+/// a tree whose only known leaf is the one spent, the others being opaque. Spent leaves
+/// with unknown semantics give [`InferredDescriptor::AnyoneCanSpendLeaf`].
+pub fn infer_descriptor(
+    spk: &Script,
+    script_sig: &Script,
+    witness: &Witness,
+) -> InferredDescriptor {
+    // ** pay to pubkeyhash **
+    if spk.is_p2pkh() {
+        let hash = hash160::Hash::from_slice(&spk[3..23]).expect("20 bytes");
+        let desc = last_push(script_sig)
+            .and_then(|elem| bitcoin::PublicKey::from_slice(&elem).ok())
+            .filter(|pk| pk.to_pubkeyhash()[..] == hash[..])
+            .map(Descriptor::new_pkh);
+        wrap(desc, InferredDescriptor::RawPkh(hash))
+    // ** pay to witness pubkeyhash **
+    } else if spk.is_v0_p2wpkh() {
+        let hash = hash160::Hash::from_slice(&spk[2..]).expect("20 bytes");
+        let desc = witness
+            .last()
+            .and_then(|elem| bitcoin::PublicKey::from_slice(elem).ok())
+            .filter(|pk| pk.to_pubkeyhash()[..] == hash[..])
+            .and_then(|pk| Descriptor::new_wpkh(pk).ok());
+        wrap(desc, InferredDescriptor::RawWpkh(hash))
+    // ** pay to witness scripthash **
+    } else if spk.is_v0_p2wsh() {
+        let hash = sha256::Hash::from_slice(&spk[2..]).expect("32 bytes");
+        let desc = witness_script(witness, &hash).and_then(|ms| Descriptor::new_wsh(ms).ok());
+        wrap(desc, InferredDescriptor::RawWsh(hash))
+    // ** pay to taproot **
+    } else if spk.is_v1_p2tr() {
+        let output_key = match bitcoin::XOnlyPublicKey::from_slice(&spk[2..]) {
+            Ok(key) => key,
+            Err(_) => return InferredDescriptor::Raw(spk.clone()),
+        };
+        // The internal key and the spent leaf can only be recovered from a script
+        // path spend, otherwise fall back to `rawtr`
+        spent_leaf_tr(output_key, witness).unwrap_or_else(|| {
+            InferredDescriptor::Descriptor(Descriptor::new_rawtr(output_key.to_public_key()))
+        })
+    // ** pay to scripthash **
+    } else if spk.is_p2sh() {
+        let hash = hash160::Hash::from_slice(&spk[2..22]).expect("20 bytes");
+        let redeem_script = last_push(script_sig)
+            .filter(|elem| hash160::Hash::hash(elem) == hash)
+            .map(Script::from);
+        let desc = redeem_script.and_then(|redeem| {
+            if redeem.is_v0_p2wpkh() {
+                witness
+                    .last()
+                    .and_then(|elem| bitcoin::PublicKey::from_slice(elem).ok())
+                    .filter(|pk| Script::new_v0_p2wpkh(&pk.to_pubkeyhash().into()) == redeem)
+                    .and_then(|pk| Descriptor::new_sh_wpkh(pk).ok())
+            } else if redeem.is_v0_p2wsh() {
+                let wsh_hash = sha256::Hash::from_slice(&redeem[2..]).expect("32 bytes");
+                witness_script(witness, &wsh_hash).and_then(|ms| Descriptor::new_sh_wsh(ms).ok())
+            } else {
+                Miniscript::<bitcoin::PublicKey, Legacy>::parse_insane(&redeem)
+                    .ok()
+                    .and_then(|ms| Descriptor::new_sh(ms).ok())
+            }
+        });
+        wrap(desc, InferredDescriptor::RawSh(hash))
+    // ** bare script **
+    } else {
+        let desc = Miniscript::<bitcoin::PublicKey, BareCtx>::parse_insane(spk)
+            .ok()
+            .and_then(|ms| Descriptor::new_bare(ms).ok());
+        wrap(desc, InferredDescriptor::Raw(spk.clone()))
+    }
+}
+
+fn wrap(
+    desc: Option<Descriptor<bitcoin::PublicKey>>,
+    raw: InferredDescriptor,
+) -> InferredDescriptor {
+    match desc {
+        Some(desc) => InferredDescriptor::Descriptor(desc),
+        None => raw,
+    }
+}
+
+/// The data pushed by the last push opcode of a scriptSig
+fn last_push(script_sig: &Script) -> Option<Vec<u8>> {
+    match script_sig.instructions_minimal().last() {
+        Some(Ok(Instruction::PushBytes(data))) => Some(data.to_vec()),
+        _ => None,
+    }
+}
+
+/// The witness script of a P2WSH spend, if it matches `hash` and is miniscript
+fn witness_script(
+    witness: &Witness,
+    hash: &sha256::Hash,
+) -> Option<Miniscript<bitcoin::PublicKey, Segwitv0>> {
+    witness
+        .last()
+        .filter(|elem| sha256::Hash::hash(elem) == *hash)
+        .and_then(|elem| Miniscript::parse_insane(&Script::from(elem.to_vec())).ok())
+}
+
+/// Recover `tr(internal_key,tree)` from a script path spend
+///
+/// The spent leaf is the only known leaf of the tree, the rest of which is
+/// made of opaque leaves with the hashes of its merkle branch.
+fn spent_leaf_tr(
+    output_key: bitcoin::XOnlyPublicKey,
+    witness: &Witness,
+) -> Option<InferredDescriptor> {
+    struct XOnlyToFull;
+
+    impl PkTranslator<bitcoin::XOnlyPublicKey, bitcoin::PublicKey, ()> for XOnlyToFull {
+        fn pk(&mut self, pk: &bitcoin::XOnlyPublicKey) -> Result<bitcoin::PublicKey, ()> {
+            Ok(pk.to_public_key())
+        }
+
+        fn pkh(&mut self, pkh: &hash160::Hash) -> Result<hash160::Hash, ()> {
+            Ok(*pkh)
+        }
+    }
+
+    let mut elems: Vec<&[u8]> = witness.iter().collect();
+    // The annex is not part of the spend of the script
+    if elems.len() >= 2 && elems.last()?.first() == Some(&TAPROOT_ANNEX_PREFIX) {
+        elems.pop();
+    }
+    // Key spends only have a signature
+    if elems.len() < 2 {
+        return None;
+    }
+    let control_block = ControlBlock::from_slice(elems.pop()?).ok()?;
+    let script = Script::from(elems.pop()?.to_vec());
+    if !with_secp(|secp| control_block.verify_taproot_commitment(secp, output_key, &script)) {
+        return None;
+    }
+    if let Some(reason) = unknown_tap_leaf(&script, control_block.leaf_version) {
+        return Some(InferredDescriptor::AnyoneCanSpendLeaf {
+            internal_key: control_block.internal_key,
+            script,
+            reason,
+        });
+    }
+    let ms = Miniscript::<bitcoin::XOnlyPublicKey, Tap>::parse_insane(&script).ok()?;
+    let ms = ms.translate_pk(&mut XOnlyToFull).ok()?;
+    // Sibling hashes are sorted when computing the merkle root, so their side
+    // in the tree does not matter
+    let tree = control_block.merkle_branch.as_inner().iter().fold(
+        TapTree::Leaf(Arc::new(ms)),
+        |tree, sibling| {
+            let sibling = TapLeafHash::from_inner(sibling.into_inner());
+            TapTree::Tree(Arc::new(tree), Arc::new(TapTree::Opaque(sibling)))
+        },
+    );
+    Descriptor::new_tr(control_block.internal_key.to_public_key(), Some(tree))
+        .ok()
+        .map(InferredDescriptor::Descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::util::taproot::TaprootBuilder;
+
+    use super::*;
+    use crate::interpreter::{self, Interpreter};
+
+    #[test]
+    fn infer_from_chain_data() {
+        let pk = bitcoin::PublicKey::from_str(
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+        )
+        .unwrap();
+        let other = bitcoin::PublicKey::from_str(
+            "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+        )
+        .unwrap();
+        let empty = Witness::new();
+        let infer = |desc: &Descriptor<bitcoin::PublicKey>, ssig: &Script, wit: &Witness| {
+            infer_descriptor(&desc.script_pubkey(), ssig, wit)
+        };
+
+        // Unspent outputs only reveal hashes
+        let pkh = Descriptor::new_pkh(pk);
+        assert_eq!(
+            infer(&pkh, &Script::new(), &empty),
+            InferredDescriptor::RawPkh(pk.to_pubkeyhash())
+        );
+        // ...until the key is revealed by the spend
+        let ssig = bitcoin::blockdata::script::Builder::new()
+            .push_slice(&[1; 72])
+            .push_key(&pk)
+            .into_script();
+        assert_eq!(
+            infer(&pkh, &ssig, &empty),
+            InferredDescriptor::Descriptor(pkh.clone())
+        );
+
+        let wpkh = Descriptor::new_wpkh(pk).unwrap();
+        let wit = Witness::from_vec(vec![vec![1; 72], pk.to_bytes()]);
+        assert_eq!(
+            infer(&wpkh, &Script::new(), &wit),
+            InferredDescriptor::Descriptor(wpkh)
+        );
+        // A mismatching key is not trusted
+        let wit_other = Witness::from_vec(vec![vec![1; 72], other.to_bytes()]);
+        assert_eq!(
+            infer(
+                &Descriptor::new_wpkh(pk).unwrap(),
+                &Script::new(),
+                &wit_other
+            ),
+            InferredDescriptor::RawWpkh(pk.to_pubkeyhash())
+        );
+
+        // wsh and sh-wsh with a miniscript witness script, including a pk_h by hash
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str(&format!(
+            "or_d(pk({}),c:pk_h({}))",
+            pk,
+            other.to_pubkeyhash()
+        ))
+        .unwrap();
+        let wit = Witness::from_vec(vec![vec![], ms.encode().into_bytes()]);
+        let wsh = Descriptor::new_wsh(ms.clone()).unwrap();
+        assert_eq!(
+            infer(&wsh, &Script::new(), &wit),
+            InferredDescriptor::Descriptor(wsh.clone())
+        );
+        let sh_wsh = Descriptor::new_sh_wsh(ms).unwrap();
+        let ssig = bitcoin::blockdata::script::Builder::new()
+            .push_slice(&wsh.script_pubkey()[..])
+            .into_script();
+        assert_eq!(
+            infer(&sh_wsh, &ssig, &wit),
+            InferredDescriptor::Descriptor(sh_wsh)
+        );
+
+        // Bare miniscript needs no spending data
+        let bare = Descriptor::new_pk(pk);
+        assert_eq!(
+            infer(&bare, &Script::new(), &empty),
+            InferredDescriptor::Descriptor(bare)
+        );
+        assert_eq!(
+            infer_descriptor(&Script::from(vec![0x6a]), &Script::new(), &empty),
+            InferredDescriptor::Raw(Script::from(vec![0x6a]))
+        );
+
+        // Single-leaf taproot trees are recovered from a script path spend
+        // x-only keys come back with an even y-coordinate, so use such keys here
+        let leaf_key = "0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8";
+        let tr =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("tr({},pk({}))", pk, leaf_key))
+                .unwrap();
+        let output_key = match tr {
+            Descriptor::Tr(ref tr) => tr.spend_info().output_key().to_inner(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            infer(&tr, &Script::new(), &empty),
+            InferredDescriptor::Descriptor(Descriptor::new_rawtr(output_key.to_public_key()))
+        );
+        let leaf = match tr {
+            Descriptor::Tr(ref tr) => tr.iter_leaves().next().unwrap(),
+            _ => unreachable!(),
+        };
+        let wit = Witness::from_vec(vec![
+            vec![1; 64],
+            leaf.script.to_bytes(),
+            leaf.control_block.serialize(),
+        ]);
+        assert_eq!(
+            infer(&tr, &Script::new(), &wit),
+            InferredDescriptor::Descriptor(tr.clone())
+        );
+
+        // In larger trees, the leaves which were not spent are opaque
+        let tr = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),{{pk({}),pk({})}}}})",
+            pk, leaf_key, pk, other
+        ))
+        .unwrap();
+        let leaves: Vec<_> = match tr {
+            Descriptor::Tr(ref tr) => tr.iter_leaves().collect(),
+            _ => unreachable!(),
+        };
+        let leaf = &leaves[1];
+        // with an annex, which is skipped
+        let wit = Witness::from_vec(vec![
+            vec![1; 64],
+            leaf.script.to_bytes(),
+            leaf.control_block.serialize(),
+            vec![TAPROOT_ANNEX_PREFIX],
+        ]);
+        let inferred = match infer(&tr, &Script::new(), &wit) {
+            InferredDescriptor::Descriptor(Descriptor::Tr(inferred)) => inferred,
+            res => panic!("unexpected inference {:?}", res),
+        };
+        assert_eq!(inferred.internal_key(), &pk);
+        let tree = inferred.taptree().as_ref().unwrap();
+        assert!(tree.has_opaque_leaves());
+        assert_eq!(
+            tree.iter()
+                .map(|(depth, ms)| (depth, ms.to_string()))
+                .collect::<Vec<_>>(),
+            vec![(2, format!("pk({})", pk))]
+        );
+        assert_eq!(Descriptor::Tr(inferred).script_pubkey(), tr.script_pubkey());
+    }
+
+    #[test]
+    fn infer_unknown_tap_leaf() {
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let internal_key = bitcoin::XOnlyPublicKey::from_str(
+            "e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+        )
+        .unwrap();
+        // OP_SUCCESS80 in a tapscript, and OP_TRUE with a future leaf version
+        let success = Script::from(vec![0x50]);
+        let future = LeafVersion::from_consensus(0xc2).unwrap();
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, success.clone())
+            .unwrap()
+            .add_leaf_with_ver(1, Script::from(vec![0x51]), future)
+            .unwrap()
+            .finalize(&secp, internal_key)
+            .unwrap();
+        let spk = Script::new_v1_p2tr_tweaked(spend_info.output_key());
+        let script_sig = Script::new();
+
+        let leaves = [
+            (
+                success,
+                LeafVersion::TapScript,
+                UnknownTapLeaf::OpSuccess(0x50),
+            ),
+            (
+                Script::from(vec![0x51]),
+                future,
+                UnknownTapLeaf::LeafVersion(future),
+            ),
+        ];
+        for &(ref script, version, reason) in leaves.iter() {
+            let control_block = spend_info
+                .control_block(&(script.clone(), version))
+                .unwrap();
+            let wit = Witness::from_vec(vec![script.to_bytes(), control_block.serialize()]);
+            assert_eq!(
+                infer_descriptor(&spk, &script_sig, &wit),
+                InferredDescriptor::AnyoneCanSpendLeaf {
+                    internal_key,
+                    script: script.clone(),
+                    reason,
+                }
+            );
+            match Interpreter::from_spend(&spk, &script_sig, &wit) {
+                Err(interpreter::Error::AnyoneCanSpendLeaf(r)) => assert_eq!(r, reason),
+                res => panic!("unexpected result {:?}", res.err()),
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Structured JSON Export
+//!
+//! Serializes descriptors as a JSON object describing their structure, so
+//! that tools without a descriptor parser can inspect their keys, scripts and
+//! taproot trees.
+//!
+//! Every object has a `type` field naming its descriptor fragment. Keys are
+//! objects with the key expression, its origin and its wildcard. Scripts are
+//! given as miniscript strings along with the list of their keys, and taproot
+//! trees as nested two-element arrays of leaves. Opaque leaves are objects
+//! with only their leaf hash.
+
+use core::fmt::{self, Write};
+
+use super::import::write_json_str;
+use super::{
+    Descriptor, DescriptorPublicKey, ShInner, SortedMultiVec, TapTree, Wildcard, WshInner,
+};
+use crate::miniscript::{Miniscript, ScriptContext};
+use crate::prelude::*;
+use crate::ForEachKey;
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Serialize the structure of the descriptor as a JSON object
+    ///
+    /// The `descriptor` field of the top-level object holds the descriptor
+    /// string, including its checksum.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        write_descriptor(&mut json, self).expect("Writing to a String cannot fail");
+        json
+    }
+}
+
+fn write_descriptor<W: Write>(w: &mut W, desc: &Descriptor<DescriptorPublicKey>) -> fmt::Result {
+    w.write_str("{\"descriptor\":")?;
+    write_json_str(w, &desc.to_string())?;
+    w.write_char(',')?;
+    match *desc {
+        Descriptor::Bare(ref bare) => {
+            w.write_str("\"type\":\"bare\",")?;
+            write_script(w, bare.as_inner())?;
+        }
+        Descriptor::Pkh(ref pkh) => {
+            w.write_str("\"type\":\"pkh\",\"key\":")?;
+            write_key(w, pkh.as_inner())?;
+        }
+        Descriptor::Wpkh(ref wpkh) => write_wpkh(w, wpkh.as_inner())?,
+        Descriptor::Sh(ref sh) => {
+            w.write_str("\"type\":\"sh\",")?;
+            match *sh.as_inner() {
+                ShInner::Wsh(ref wsh) => {
+                    w.write_str("\"inner\":{")?;
+                    write_wsh(w, wsh.as_inner())?;
+                    w.write_char('}')?;
+                }
+                ShInner::Wpkh(ref wpkh) => {
+                    w.write_str("\"inner\":{")?;
+                    write_wpkh(w, wpkh.as_inner())?;
+                    w.write_char('}')?;
+                }
+                ShInner::SortedMulti(ref smv) => write_sortedmulti(w, smv)?,
+                ShInner::Ms(ref ms) => write_script(w, ms)?,
+            }
+        }
+        Descriptor::Wsh(ref wsh) => write_wsh(w, wsh.as_inner())?,
+        Descriptor::Tr(ref tr) => {
+            w.write_str("\"type\":\"tr\",\"internal_key\":")?;
+            write_key(w, tr.internal_key())?;
+            w.write_str(",\"tree\":")?;
+            match *tr.taptree() {
+                Some(ref tree) => write_taptree(w, tree)?,
+                None => w.write_str("null")?,
+            }
+        }
+        Descriptor::RawTr(ref rawtr) => {
+            w.write_str("\"type\":\"rawtr\",\"key\":")?;
+            write_key(w, rawtr.output_key())?;
+        }
+        Descriptor::Raw(ref spk) => write!(w, "\"type\":\"raw\",\"script\":\"{:x}\"", spk)?,
+        Descriptor::Addr(ref addr) => write!(w, "\"type\":\"addr\",\"address\":\"{}\"", addr)?,
+    }
+    w.write_char('}')
+}
+
+fn write_wpkh<W: Write>(w: &mut W, pk: &DescriptorPublicKey) -> fmt::Result {
+    w.write_str("\"type\":\"wpkh\",\"key\":")?;
+    write_key(w, pk)
+}
+
+fn write_wsh<W: Write>(w: &mut W, inner: &WshInner<DescriptorPublicKey>) -> fmt::Result {
+    w.write_str("\"type\":\"wsh\",")?;
+    match *inner {
+        WshInner::SortedMulti(ref smv) => write_sortedmulti(w, smv),
+        WshInner::Ms(ref ms) => write_script(w, ms),
+    }
+}
+
+fn write_sortedmulti<W: Write, Ctx: ScriptContext>(
+    w: &mut W,
+    smv: &SortedMultiVec<DescriptorPublicKey, Ctx>,
+) -> fmt::Result {
+    write!(w, "\"sortedmulti\":{{\"k\":{},\"keys\":", smv.k)?;
+    write_keys(w, smv.pks.iter())?;
+    w.write_char('}')
+}
+
+fn write_script<W: Write, Ctx: ScriptContext>(
+    w: &mut W,
+    ms: &Miniscript<DescriptorPublicKey, Ctx>,
+) -> fmt::Result {
+    w.write_str("\"script\":")?;
+    write_json_str(w, &ms.to_string())?;
+    w.write_str(",\"keys\":")?;
+    let mut keys = vec![];
+    ms.for_each_key(|key| {
+        keys.push(key.as_key());
+        true
+    });
+    write_keys(w, keys.into_iter())
+}
+
+fn write_taptree<W: Write>(w: &mut W, tree: &TapTree<DescriptorPublicKey>) -> fmt::Result {
+    match *tree {
+        TapTree::Tree(ref left, ref right) => {
+            w.write_char('[')?;
+            write_taptree(w, left)?;
+            w.write_char(',')?;
+            write_taptree(w, right)?;
+            w.write_char(']')
+        }
+        TapTree::Leaf(ref ms) => {
+            w.write_char('{')?;
+            write_script(w, ms)?;
+            w.write_char('}')
+        }
+        TapTree::Opaque(ref hash) => write!(w, "{{\"opaque\":\"{}\"}}", hash),
+    }
+}
+
+fn write_keys<'a, W, I>(w: &mut W, keys: I) -> fmt::Result
+where
+    W: Write,
+    I: Iterator<Item = &'a DescriptorPublicKey>,
+{
+    w.write_char('[')?;
+    for (i, key) in keys.enumerate() {
+        if i > 0 {
+            w.write_char(',')?;
+        }
+        write_key(w, key)?;
+    }
+    w.write_char(']')
+}
+
+fn write_key<W: Write>(w: &mut W, key: &DescriptorPublicKey) -> fmt::Result {
+    let wildcard = match *key {
+        DescriptorPublicKey::Single(..) => Wildcard::None,
+        DescriptorPublicKey::XPub(ref xpub) => xpub.wildcard,
+    };
+    w.write_str("{\"key\":")?;
+    write_json_str(w, &key.to_string())?;
+    write!(
+        w,
+        ",\"fingerprint\":\"{}\",\"path\":\"{}\",\"wildcard\":\"{}\"}}",
+        key.master_fingerprint(),
+        key.full_derivation_path(),
+        match wildcard {
+            Wildcard::None => "none",
+            Wildcard::Unhardened => "unhardened",
+            Wildcard::Hardened => "hardened",
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn to_json() {
+        let key = "[d34db33f/86'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
+        let key_json = format!(
+            "{{\"key\":\"{}\",\"fingerprint\":\"d34db33f\",\"path\":\"m/86'/0'/0'/1\",\"wildcard\":\"unhardened\"}}",
+            key
+        );
+        let single = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+        let single_json = format!(
+            "{{\"key\":\"{}\",\"fingerprint\":\"{}\",\"path\":\"m\",\"wildcard\":\"none\"}}",
+            single,
+            DescriptorPublicKey::from_str(single)
+                .unwrap()
+                .master_fingerprint()
+        );
+
+        let desc = Descriptor::from_str(&format!("sh(wpkh({}))", key)).unwrap();
+        assert_eq!(
+            desc.to_json(),
+            format!(
+                "{{\"descriptor\":\"{}\",\"type\":\"sh\",\"inner\":{{\"type\":\"wpkh\",\"key\":{}}}}}",
+                desc, key_json
+            )
+        );
+
+        let desc = Descriptor::from_str(&format!(
+            "tr({},{{pk({}),and_v(v:pk({}),older(10))}})",
+            &single[2..],
+            key,
+            single
+        ))
+        .unwrap();
+        assert_eq!(
+            desc.to_json(),
+            format!(
+                "{{\"descriptor\":\"{}\",\"type\":\"tr\",\"internal_key\":{{\"key\":\"{}\",\"fingerprint\":\"{}\",\"path\":\"m\",\"wildcard\":\"none\"}},\"tree\":[{{\"script\":\"pk({})\",\"keys\":[{}]}},{{\"script\":\"and_v(v:pk({}),older(10))\",\"keys\":[{}]}}]}}",
+                desc,
+                &single[2..],
+                DescriptorPublicKey::from_str(&single[2..])
+                    .unwrap()
+                    .master_fingerprint(),
+                key,
+                key_json,
+                single,
+                single_json
+            )
+        );
+
+        let desc =
+            Descriptor::from_str(&format!("wsh(sortedmulti(1,{},{}))", key, single)).unwrap();
+        assert_eq!(
+            desc.to_json(),
+            format!(
+                "{{\"descriptor\":\"{}\",\"type\":\"wsh\",\"sortedmulti\":{{\"k\":1,\"keys\":[{},{}]}}}}",
+                desc, key_json, single_json
+            )
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use core::fmt;
+use core::ops::Range;
 use core::str::FromStr;
 #[cfg(feature = "std")]
 use std::error;
@@ -10,6 +11,7 @@ use bitcoin::util::bip32;
 use bitcoin::{self, XOnlyPublicKey, XpubIdentifier};
 
 use crate::prelude::*;
+use crate::util::with_secp;
 use crate::{MiniscriptKey, ToPublicKey};
 
 /// The descriptor pubkey, either a single pubkey or an xpub.
@@ -363,6 +365,10 @@ pub enum ConversionError {
     HardenedChild,
     /// Attempted to convert a key with a hardened wildcard to a bitcoin public key
     HardenedWildcard,
+    /// Attempted to use an index ≥ 2^31 as a non-hardened derivation index
+    HardenedIndex(u32),
+    /// Attempted to use an empty range of derivation indices
+    EmptyRange,
 }
 
 impl fmt::Display for ConversionError {
@@ -373,6 +379,10 @@ impl fmt::Display for ConversionError {
             ConversionError::HardenedWildcard => {
                 "hardened and uninstantiated wildcard in bip32 path"
             }
+            ConversionError::HardenedIndex(index) => {
+                return write!(f, "derivation index {} is not below 2^31", index)
+            }
+            ConversionError::EmptyRange => "empty range of derivation indices",
         })
     }
 }
@@ -383,11 +393,96 @@ impl error::Error for ConversionError {
         use self::ConversionError::*;
 
         match self {
-            Wildcard | HardenedChild | HardenedWildcard => None,
+            Wildcard | HardenedChild | HardenedWildcard | HardenedIndex(_) | EmptyRange => None,
+        }
+    }
+}
+
+/// A non-hardened BIP32 child index, guaranteed to be below 2^31
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DerivationIndex(u32);
+
+impl DerivationIndex {
+    /// Create a derivation index, failing if `index` ≥ 2^31
+    pub fn new(index: u32) -> Result<Self, ConversionError> {
+        if index < HARDENED_INDEX {
+            Ok(DerivationIndex(index))
+        } else {
+            Err(ConversionError::HardenedIndex(index))
         }
     }
+
+    /// The index as a `u32`
+    pub fn get(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<DerivationIndex> for u32 {
+    fn from(index: DerivationIndex) -> u32 {
+        index.0
+    }
+}
+
+impl fmt::Display for DerivationIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// A half-open range of non-hardened BIP32 child indices, ending at 2^31 at most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DerivationRange {
+    start: u32,
+    end: u32,
 }
 
+impl DerivationRange {
+    /// Create a derivation range, failing if `range` goes beyond 2^31
+    ///
+    /// Ranges with `start >= end` are empty.
+    pub fn new(range: Range<u32>) -> Result<Self, ConversionError> {
+        if range.end > HARDENED_INDEX {
+            return Err(ConversionError::HardenedIndex(range.end - 1));
+        }
+        Ok(DerivationRange {
+            start: range.start,
+            end: range.end.max(range.start),
+        })
+    }
+
+    /// The range containing only `index`
+    pub fn single(index: DerivationIndex) -> Self {
+        DerivationRange {
+            start: index.0,
+            end: index.0 + 1,
+        }
+    }
+
+    /// The range as a [`Range<u32>`]
+    pub fn to_range(&self) -> Range<u32> {
+        self.start..self.end
+    }
+
+    /// Number of indices in the range
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    /// Whether the range has no index
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Iterates over the indices of the range
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = DerivationIndex> {
+        self.to_range().map(DerivationIndex)
+    }
+}
+
+// BIP32 indices at or above this one are hardened
+const HARDENED_INDEX: u32 = 1 << 31;
+
 impl DescriptorPublicKey {
     /// The fingerprint of the master key associated with this key, `0x00000000` if none.
     pub fn master_fingerprint(&self) -> bip32::Fingerprint {
@@ -449,6 +544,45 @@ impl DescriptorPublicKey {
         }
     }
 
+    /// Whether the key can be used on `network`
+    ///
+    /// Extended keys only tell mainnet (`xpub`) from the test networks (`tpub`),
+    /// single keys are valid on every network.
+    pub fn is_valid_for_network(&self, network: bitcoin::Network) -> bool {
+        match *self {
+            DescriptorPublicKey::Single(..) => true,
+            DescriptorPublicKey::XPub(ref xpub) => {
+                (xpub.xkey.network == bitcoin::Network::Bitcoin)
+                    == (network == bitcoin::Network::Bitcoin)
+            }
+        }
+    }
+
+    /// The x-only form of the key, as required for the internal key of a
+    /// `tr()` descriptor
+    ///
+    /// Compressed single keys drop their parity, keeping their origin.
+    /// Extended and x-only keys are returned unchanged, while uncompressed
+    /// keys have no x-only form.
+    pub fn to_x_only(&self) -> Option<DescriptorPublicKey> {
+        match *self {
+            DescriptorPublicKey::Single(SinglePub {
+                ref origin,
+                key: SinglePubKey::FullKey(pk),
+            }) => {
+                if pk.compressed {
+                    Some(DescriptorPublicKey::Single(SinglePub {
+                        origin: origin.clone(),
+                        key: SinglePubKey::XOnly(pk.inner.into()),
+                    }))
+                } else {
+                    None
+                }
+            }
+            _ => Some(self.clone()),
+        }
+    }
+
     /// Derives the [`DescriptorPublicKey`] at `index` if this key is an xpub and has a wildcard.
     ///
     /// # Returns
@@ -778,11 +912,26 @@ impl DerivedDescriptorKey {
         self.key.derive_public_key(secp)
     }
 
+    /// The descriptor key this key was derived to, without any wildcard
+    pub fn as_key(&self) -> &DescriptorPublicKey {
+        &self.key
+    }
+
     /// Return the derivation index of this key
     pub fn index(&self) -> u32 {
         self.index
     }
 
+    /// The fingerprint of the master key associated with this key, `0x00000000` if none.
+    pub fn master_fingerprint(&self) -> bip32::Fingerprint {
+        self.key.master_fingerprint()
+    }
+
+    /// Full path of this key from the master key, including the derivation index.
+    pub fn full_derivation_path(&self) -> bip32::DerivationPath {
+        self.key.full_derivation_path()
+    }
+
     /// Construct an instance from a descriptor key and a derivation index
     ///
     /// Returns `None` if the key contains a wildcard
@@ -820,8 +969,7 @@ impl MiniscriptKey for DerivedDescriptorKey {
 
 impl ToPublicKey for DerivedDescriptorKey {
     fn to_public_key(&self) -> bitcoin::PublicKey {
-        let secp = Secp256k1::verification_only();
-        self.key.derive_public_key(&secp).unwrap()
+        with_secp(|secp| self.key.derive_public_key(secp)).unwrap()
     }
 
     fn hash_to_hash160(hash: &Self) -> hash160::Hash {
@@ -839,7 +987,10 @@ mod test {
 
     use bitcoin::secp256k1;
 
-    use super::{DescriptorKeyParseError, DescriptorPublicKey, DescriptorSecretKey};
+    use super::{
+        ConversionError, DerivationIndex, DerivationRange, DescriptorKeyParseError,
+        DescriptorPublicKey, DescriptorSecretKey,
+    };
     use crate::prelude::*;
 
     #[test]
@@ -1003,4 +1154,46 @@ mod test {
             b"\xb0\x59\x11\x6a"
         );
     }
+
+    #[test]
+    fn test_derivation_index() {
+        assert_eq!(
+            DerivationIndex::new((1 << 31) - 1).unwrap().get(),
+            (1 << 31) - 1
+        );
+        assert_eq!(
+            DerivationIndex::new(1 << 31),
+            Err(ConversionError::HardenedIndex(1 << 31))
+        );
+
+        let range = DerivationRange::new(3..7).unwrap();
+        assert_eq!(range.len(), 4);
+        assert_eq!(
+            range.iter().map(u32::from).collect::<Vec<_>>(),
+            vec![3, 4, 5, 6]
+        );
+        assert!(DerivationRange::new(5..5).unwrap().is_empty());
+        assert!(DerivationRange::new(0..(1 << 31)).is_ok());
+        assert_eq!(
+            DerivationRange::new(0..(1 << 31) + 1),
+            Err(ConversionError::HardenedIndex(1 << 31))
+        );
+    }
+
+    #[test]
+    fn test_to_x_only() {
+        let x_only =
+            "[78412e3a/86'/0'/0']e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+        let compressed = DescriptorPublicKey::from_str(
+            "[78412e3a/86'/0'/0']02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+        )
+        .unwrap();
+        assert_eq!(compressed.to_x_only().unwrap().to_string(), x_only);
+
+        let xpub = DescriptorPublicKey::from_str("xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*").unwrap();
+        assert_eq!(xpub.to_x_only(), Some(xpub.clone()));
+
+        let uncompressed = DescriptorPublicKey::from_str("04a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd5b8dec5235a0fa8722476c7709c02559e3aa73aa03918ba2d492eea75abea235").unwrap();
+        assert_eq!(uncompressed.to_x_only(), None);
+    }
 }
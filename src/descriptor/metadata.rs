@@ -0,0 +1,287 @@
+//! Descriptor Metadata
+//!
+//! Facts about the output of a descriptor which callers would otherwise
+//! have to derive by matching on [`DescriptorType`].
+
+use bitcoin::util::address::WitnessVersion;
+use bitcoin::{Amount, Script};
+
+use super::tr::leaf_spend_weight;
+use super::{Descriptor, DescriptorMiniscript, DescriptorType, ShInner, WshInner};
+use crate::miniscript::context::{ScriptContext, ScriptContextError};
+use crate::miniscript::limits::{
+    MAX_SCRIPTSIG_SIZE, MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS,
+    MAX_STANDARD_TX_WEIGHT,
+};
+use crate::miniscript::satisfy::SigSizeAssumptions;
+use crate::plan::TXIN_BASE_WEIGHT;
+use crate::util::varint_len;
+use crate::{BareCtx, Error, Legacy, MiniscriptKey, Segwitv0, Tap};
+
+/// Metadata of a descriptor's output, see [`Descriptor::metadata`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorMetadata {
+    /// Type of the descriptor
+    pub desc_type: DescriptorType,
+    /// Segwit version of the output, `None` for legacy outputs
+    pub segwit_version: Option<WitnessVersion>,
+    /// Whether spending the output may reveal a script
+    pub has_script_path: bool,
+    /// Whether the output can be spent with a single signature, without
+    /// revealing a script. Taproot outputs always report `true`, even when
+    /// their internal key is unspendable.
+    pub key_path_spendable: bool,
+    /// Largest weight of an input spending the output that is still standard
+    pub max_standard_weight: usize,
+    /// Smallest value of the output for it to be relayed
+    pub dust_threshold: Amount,
+}
+
+/// Shape of a scriptPubKey, as far as standardness is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputKind {
+    Legacy,
+    P2sh,
+    ShWpkh,
+    ShWsh,
+    Wpkh,
+    Wsh,
+    // Taproot and future witness versions
+    WitnessOther,
+}
+
+impl OutputKind {
+    fn from_script(spk: &Script) -> Self {
+        if spk.is_p2sh() {
+            OutputKind::P2sh
+        } else if spk.is_v0_p2wpkh() {
+            OutputKind::Wpkh
+        } else if spk.is_v0_p2wsh() {
+            OutputKind::Wsh
+        } else if spk.is_witness_program() {
+            OutputKind::WitnessOther
+        } else {
+            OutputKind::Legacy
+        }
+    }
+
+    fn max_standard_weight(self) -> usize {
+        // Signature and compressed key
+        let wpkh_witness = 1 + (1 + 73) + (1 + 33);
+        // The stack items are at most 80 bytes each
+        let wsh_witness = varint_len(MAX_STANDARD_P2WSH_STACK_ITEMS + 1)
+            + MAX_STANDARD_P2WSH_STACK_ITEMS * (1 + 80)
+            + varint_len(MAX_STANDARD_P2WSH_SCRIPT_SIZE)
+            + MAX_STANDARD_P2WSH_SCRIPT_SIZE;
+        // scriptSig pushing a 22 or 34-byte witness program
+        let sh_wpkh_base = TXIN_BASE_WEIGHT + 4 * (1 + 23);
+        let sh_wsh_base = TXIN_BASE_WEIGHT + 4 * (1 + 35);
+        match self {
+            OutputKind::Legacy => {
+                TXIN_BASE_WEIGHT + 4 * (varint_len(MAX_SCRIPTSIG_SIZE) + MAX_SCRIPTSIG_SIZE)
+            }
+            OutputKind::ShWpkh => sh_wpkh_base + wpkh_witness,
+            OutputKind::ShWsh | OutputKind::P2sh => sh_wsh_base + wsh_witness,
+            OutputKind::Wpkh => TXIN_BASE_WEIGHT + 4 + wpkh_witness,
+            OutputKind::Wsh => TXIN_BASE_WEIGHT + 4 + wsh_witness,
+            // Only bounded by the size of the transaction
+            OutputKind::WitnessOther => MAX_STANDARD_TX_WEIGHT,
+        }
+    }
+}
+
+// Mirrors Bitcoin Core's GetDustThreshold at the default 3 sat/vB dust relay fee
+fn dust_threshold(spk_len: usize, is_witness: bool) -> Amount {
+    let spend_size = if is_witness {
+        32 + 4 + 1 + 107 / 4 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    let output_size = 8 + varint_len(spk_len) + spk_len;
+    Amount::from_sat(3 * (spend_size + output_size) as u64)
+}
+
+impl<Pk: MiniscriptKey> Descriptor<Pk> {
+    /// Metadata of the descriptor's output
+    ///
+    /// For `raw()` and `addr()` descriptors, this is inferred from the
+    /// scriptPubKey. Outputs of unknown content, such as `rawtr()`, are
+    /// assumed to be spendable the widest way their type allows.
+    pub fn metadata(&self) -> DescriptorMetadata {
+        let desc_type = self.desc_type();
+        let (kind, spk_len, has_script_path, key_path_spendable) = match *self {
+            Descriptor::Bare(ref bare) => (
+                OutputKind::Legacy,
+                bare.as_inner().script_size(),
+                true,
+                false,
+            ),
+            Descriptor::Pkh(..) => (OutputKind::Legacy, 25, false, true),
+            Descriptor::Wpkh(..) => (OutputKind::Wpkh, 22, false, true),
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Wpkh(..) => (OutputKind::ShWpkh, 23, false, true),
+                ShInner::Wsh(..) => (OutputKind::ShWsh, 23, true, false),
+                ShInner::SortedMulti(..) | ShInner::Ms(..) => (OutputKind::Legacy, 23, true, false),
+            },
+            Descriptor::Wsh(ref wsh) => match *wsh.as_inner() {
+                WshInner::SortedMulti(..) | WshInner::Ms(..) => (OutputKind::Wsh, 34, true, false),
+            },
+            Descriptor::Tr(ref tr) => (OutputKind::WitnessOther, 34, tr.taptree().is_some(), true),
+            Descriptor::RawTr(..) => (OutputKind::WitnessOther, 34, true, true),
+            Descriptor::Raw(ref spk) => script_facts(spk),
+            Descriptor::Addr(ref addr) => script_facts(&addr.script_pubkey()),
+        };
+        let segwit_version = match *self {
+            Descriptor::Raw(ref spk) => spk.witness_version(),
+            Descriptor::Addr(ref addr) => addr.script_pubkey().witness_version(),
+            _ => desc_type.segwit_version(),
+        };
+        DescriptorMetadata {
+            desc_type,
+            segwit_version,
+            has_script_path,
+            key_path_spendable,
+            max_standard_weight: kind.max_standard_weight(),
+            dust_threshold: dust_threshold(spk_len, segwit_version.is_some()),
+        }
+    }
+
+    /// Checks that spending the descriptor's output is standard, returning
+    /// its dust threshold
+    ///
+    /// Unlike [`Descriptor::sanity_check`], which is about consensus and
+    /// malleability, this enforces the relay policy limits of each script:
+    /// 520 bytes for a redeem script, 1650 bytes for a scriptSig, 3600 bytes
+    /// and 100 stack items for a witness script, and, for a tapleaf, a
+    /// worst-case spend that fits in a standard transaction.
+    pub fn sanity_check_standardness(&self) -> Result<Amount, Error> {
+        for ms in self.iter_miniscripts() {
+            match ms {
+                DescriptorMiniscript::Bare(ms) => BareCtx::check_local_validity(ms)?,
+                DescriptorMiniscript::Legacy(ms) => Legacy::check_local_validity(ms)?,
+                DescriptorMiniscript::Segwitv0(ms) => Segwitv0::check_local_validity(ms)?,
+                DescriptorMiniscript::Tap(ms) => Tap::check_local_validity(ms)?,
+            }
+        }
+        if let Descriptor::Tr(ref tr) = *self {
+            for (depth, ms) in tr.iter_scripts() {
+                match leaf_spend_weight(depth, ms, SigSizeAssumptions::WORST_CASE) {
+                    Some(weight) if weight > MAX_STANDARD_TX_WEIGHT => {
+                        return Err(ScriptContextError::MaxWitnessScriptSizeExceeded.into())
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(self.metadata().dust_threshold)
+    }
+}
+
+fn script_facts(spk: &Script) -> (OutputKind, usize, bool, bool) {
+    let kind = OutputKind::from_script(spk);
+    let (has_script_path, key_path_spendable) = match kind {
+        OutputKind::Wpkh => (false, true),
+        OutputKind::Legacy if spk.is_p2pkh() => (false, true),
+        OutputKind::Legacy | OutputKind::Wsh => (true, false),
+        OutputKind::P2sh | OutputKind::WitnessOther => (true, true),
+        OutputKind::ShWpkh | OutputKind::ShWsh => unreachable!("not inferred from a script"),
+    };
+    (kind, spk.len(), has_script_path, key_path_spendable)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn metadata() {
+        for (desc, segwit_version, script_path, key_path) in &[
+            ("pkh(A)", None, false, true),
+            ("sh(wpkh(A))", Some(WitnessVersion::V0), false, true),
+            ("wpkh(A)", Some(WitnessVersion::V0), false, true),
+            ("wsh(multi(1,A,B))", Some(WitnessVersion::V0), true, false),
+            ("sh(sortedmulti(1,A,B))", None, true, false),
+            ("tr(A)", Some(WitnessVersion::V1), false, true),
+            ("tr(A,pk(B))", Some(WitnessVersion::V1), true, true),
+        ] {
+            let metadata = Descriptor::<String>::from_str(desc).unwrap().metadata();
+            assert_eq!(metadata.segwit_version, *segwit_version, "{}", desc);
+            assert_eq!(metadata.has_script_path, *script_path, "{}", desc);
+            assert_eq!(metadata.key_path_spendable, *key_path, "{}", desc);
+        }
+
+        // Dust thresholds and weights match the well-known values
+        let pkh = Descriptor::<String>::from_str("pkh(A)").unwrap().metadata();
+        assert_eq!(pkh.dust_threshold, Amount::from_sat(546));
+        assert_eq!(pkh.max_standard_weight, 4 * (40 + 3 + 1650));
+        let wpkh = Descriptor::<String>::from_str("wpkh(A)")
+            .unwrap()
+            .metadata();
+        assert_eq!(wpkh.dust_threshold, Amount::from_sat(294));
+        assert_eq!(wpkh.max_standard_weight, 4 * 41 + 109);
+        let tr = Descriptor::<String>::from_str("tr(A)").unwrap().metadata();
+        assert_eq!(tr.dust_threshold, Amount::from_sat(330));
+        assert_eq!(tr.max_standard_weight, MAX_STANDARD_TX_WEIGHT);
+
+        // Raw descriptors are inferred from the scriptPubKey and agree with
+        // the descriptor they come from
+        let wsh = Descriptor::<bitcoin::PublicKey>::from_str(
+            "wsh(pk(020000000000000000000000000000000000000000000000000000000000000002))",
+        )
+        .unwrap();
+        let raw = Descriptor::<bitcoin::PublicKey>::Raw(wsh.script_pubkey()).metadata();
+        assert_eq!(raw.desc_type, DescriptorType::Raw);
+        assert_eq!(
+            (
+                raw.segwit_version,
+                raw.max_standard_weight,
+                raw.dust_threshold
+            ),
+            (
+                wsh.metadata().segwit_version,
+                wsh.metadata().max_standard_weight,
+                wsh.metadata().dust_threshold
+            )
+        );
+        assert_eq!(
+            raw.dust_threshold,
+            wsh.script_pubkey().dust_value(),
+            "matches rust-bitcoin"
+        );
+    }
+
+    #[test]
+    fn sanity_check_standardness() {
+        let wpkh = Descriptor::<String>::from_str("wpkh(A)").unwrap();
+        assert_eq!(
+            wpkh.sanity_check_standardness().unwrap(),
+            Amount::from_sat(294)
+        );
+        let tr = Descriptor::<String>::from_str("tr(A,{pk(B),and_v(v:pk(C),older(10))})").unwrap();
+        assert_eq!(
+            tr.sanity_check_standardness().unwrap(),
+            Amount::from_sat(330)
+        );
+
+        // Five 20-of-20 multisigs take 105 witness items, over the standard
+        // limit of 100 while being valid by consensus
+        let multis: Vec<String> = (0..5)
+            .map(|i| {
+                let keys: Vec<String> = (0..20).map(|j| format!("K{}_{}", i, j)).collect();
+                format!("multi(20,{})", keys.join(","))
+            })
+            .collect();
+        let wsh = Descriptor::<String>::from_str(&format!(
+            "wsh(and_v(v:{},and_v(v:{},and_v(v:{},and_v(v:{},{})))))",
+            multis[0], multis[1], multis[2], multis[3], multis[4]
+        ))
+        .unwrap();
+        match wsh.sanity_check_standardness() {
+            Err(Error::ContextError(ScriptContextError::MaxWitnessItemssExceeded { .. })) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Taproot Migration
+//!
+//! Recompiles the spending conditions of a segwit v0 descriptor into a
+//! taproot descriptor, checking that the spending conditions are unchanged.
+
+use super::{Descriptor, ShInner};
+use crate::policy::{Concrete, Liftable, Semantic};
+use crate::prelude::*;
+use crate::{Error, MiniscriptKey};
+
+/// Outcome of a [`Descriptor::migrate_to_tr`] migration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrMigrationReport<Pk: MiniscriptKey> {
+    /// Normalized semantic policy of the original descriptor
+    pub original_policy: Semantic<Pk>,
+    /// Normalized semantic policy of the taproot descriptor, with the
+    /// unspendable internal key (if used) replaced by an unsatisfiable policy
+    pub migrated_policy: Semantic<Pk>,
+    /// Whether both policies were shown to accept exactly the same satisfactions
+    pub equivalent: bool,
+}
+
+impl<Pk: MiniscriptKey<Hash = Pk>> Descriptor<Pk> {
+    /// Recompiles a `wsh` or `sh(wsh)` miniscript descriptor into a `tr` descriptor
+    ///
+    /// The miniscript is lifted to its semantic policy, which is compiled with
+    /// [`Concrete::compile_tr`] giving every branch the same probability.
+    /// `unspendable` is used as the internal key when no key can be spent on
+    /// its own. The returned report holds both semantic policies; they are
+    /// `equivalent` if they are identical once normalized, or if each one
+    /// entails the other.
+    ///
+    /// # Errors
+    /// If the descriptor is not a `wsh` miniscript or the compilation fails.
+    pub fn migrate_to_tr(
+        &self,
+        unspendable: Option<Pk>,
+    ) -> Result<(Descriptor<Pk>, TrMigrationReport<Pk>), Error> {
+        let wsh = match *self {
+            Descriptor::Wsh(ref wsh) => wsh,
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Wsh(ref wsh) => wsh,
+                _ => return Err(errstr_not_wsh()),
+            },
+            _ => return Err(errstr_not_wsh()),
+        };
+        let original_policy = wsh.lift()?.normalized();
+        let tr = to_concrete(&original_policy).compile_tr(unspendable.clone())?;
+
+        let mut migrated_policy = tr.lift()?;
+        if let Some(ref unspendable) = unspendable {
+            migrated_policy = without_key(migrated_policy, unspendable);
+        }
+        let migrated_policy = migrated_policy.normalized();
+
+        let equivalent = original_policy.clone().sorted() == migrated_policy.clone().sorted()
+            || (original_policy
+                .clone()
+                .entails(migrated_policy.clone())
+                .unwrap_or(false)
+                && migrated_policy
+                    .clone()
+                    .entails(original_policy.clone())
+                    .unwrap_or(false));
+
+        Ok((
+            tr,
+            TrMigrationReport {
+                original_policy,
+                migrated_policy,
+                equivalent,
+            },
+        ))
+    }
+}
+
+fn errstr_not_wsh() -> Error {
+    Error::BadDescriptor("only wsh and sh(wsh) descriptors can be migrated to tr".to_string())
+}
+
+/// Turns a semantic policy into a concrete one with binary `and`/`or`
+fn to_concrete<Pk: MiniscriptKey<Hash = Pk>>(policy: &Semantic<Pk>) -> Concrete<Pk> {
+    match *policy {
+        Semantic::Unsatisfiable => Concrete::Unsatisfiable,
+        Semantic::Trivial => Concrete::Trivial,
+        Semantic::KeyHash(ref pk) => Concrete::Key(pk.clone()),
+        Semantic::After(n) => Concrete::After(n),
+        Semantic::Older(n) => Concrete::Older(n),
+        Semantic::Sha256(ref h) => Concrete::Sha256(h.clone()),
+        Semantic::Hash256(h) => Concrete::Hash256(h),
+        Semantic::Ripemd160(h) => Concrete::Ripemd160(h),
+        Semantic::Hash160(h) => Concrete::Hash160(h),
+        Semantic::Threshold(k, ref subs) => {
+            let subs: Vec<_> = subs.iter().map(to_concrete).collect();
+            if subs.len() == 1 {
+                subs.into_iter().next().expect("one sub-policy")
+            } else if k == subs.len() {
+                nest_and(subs)
+            } else if k == 1 {
+                nest_or(subs)
+            } else {
+                Concrete::Threshold(k, subs)
+            }
+        }
+    }
+}
+
+fn nest_and<Pk: MiniscriptKey>(mut subs: Vec<Concrete<Pk>>) -> Concrete<Pk> {
+    if subs.len() == 1 {
+        return subs.pop().expect("one sub-policy");
+    }
+    let first = subs.remove(0);
+    Concrete::And(vec![first, nest_and(subs)])
+}
+
+// Keeps the branches equally likely: the first one against all the others
+fn nest_or<Pk: MiniscriptKey>(mut subs: Vec<Concrete<Pk>>) -> Concrete<Pk> {
+    if subs.len() == 1 {
+        return subs.pop().expect("one sub-policy");
+    }
+    let first = subs.remove(0);
+    let n_rest = subs.len();
+    Concrete::Or(vec![(1, first), (n_rest, nest_or(subs))])
+}
+
+/// Replaces the key `pk` by an unsatisfiable policy
+fn without_key<Pk: MiniscriptKey<Hash = Pk>>(policy: Semantic<Pk>, pk: &Pk) -> Semantic<Pk> {
+    match policy {
+        Semantic::KeyHash(ref key) if key == pk => Semantic::Unsatisfiable,
+        Semantic::Threshold(k, subs) => Semantic::Threshold(
+            k,
+            subs.into_iter().map(|sub| without_key(sub, pk)).collect(),
+        ),
+        policy => policy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn migrate_wsh_to_tr() {
+        // A key that can spend alone becomes the internal key
+        let wsh =
+            Descriptor::<String>::from_str("wsh(or_d(pk(A),and_v(v:pk(B),older(144))))").unwrap();
+        let (tr, report) = wsh.migrate_to_tr(None).unwrap();
+        assert!(report.equivalent);
+        match tr {
+            Descriptor::Tr(ref tr) => assert_eq!(tr.internal_key(), "A"),
+            _ => unreachable!(),
+        }
+
+        // Otherwise the unspendable key is used and not part of the policy
+        let multi = Descriptor::<String>::from_str("sh(wsh(multi(2,A,B,C)))").unwrap();
+        let (tr, report) = multi
+            .migrate_to_tr(Some("UNSPENDABLE".to_string()))
+            .unwrap();
+        assert!(report.equivalent);
+        assert_eq!(report.original_policy, report.migrated_policy);
+        match tr {
+            Descriptor::Tr(ref tr) => assert_eq!(tr.internal_key(), "UNSPENDABLE"),
+            _ => unreachable!(),
+        }
+        assert!(multi.migrate_to_tr(None).is_err());
+
+        let wpkh = Descriptor::<String>::from_str("wpkh(A)").unwrap();
+        assert!(wpkh.migrate_to_tr(None).is_err());
+    }
+}
@@ -30,36 +30,62 @@ use core::str::{self, FromStr};
 use bitcoin::blockdata::witness::Witness;
 use bitcoin::hashes::sha256;
 use bitcoin::util::address::WitnessVersion;
+use bitcoin::util::bip32;
 use bitcoin::{self, secp256k1, Address, Network, Script, TxIn};
 use sync::Arc;
 
-use self::checksum::verify_checksum;
-use crate::miniscript::{Legacy, Miniscript, Segwitv0};
+use self::checksum::{desc_checksum, verify_checksum_located};
+use crate::miniscript::analyzable::AnalysisError;
+use crate::miniscript::satisfy::SigSizeAssumptions;
+use crate::miniscript::{Legacy, Miniscript, Segwitv0, Tap};
+use crate::plan::TXIN_BASE_WEIGHT;
 use crate::prelude::*;
+use crate::util::with_secp;
 use crate::{
-    expression, miniscript, BareCtx, Error, ForEach, ForEachKey, MiniscriptKey, PkTranslator,
-    Satisfier, ToPublicKey, TranslatePk, Translator,
+    expression, miniscript, BareCtx, Error, ForEach, ForEachKey, FromStrParser, KeyParser,
+    MiniscriptKey, PkTranslator, Satisfier, ToPublicKey, TranslatePk, Translator,
 };
 
 mod bare;
+mod import;
+mod inference;
+#[cfg(feature = "json")]
+mod json;
+mod metadata;
+#[cfg(feature = "compiler")]
+mod migrate;
+mod rawtr;
 mod segwitv0;
 mod sh;
 mod sortedmulti;
+mod summary;
+mod template;
 mod tr;
 
 // Descriptor Exports
 pub use self::bare::{Bare, Pkh};
+pub use self::import::{import_descriptors_json, ImportRequest, ImportTimestamp};
+pub(crate) use self::inference::unknown_tap_leaf;
+pub use self::inference::{infer_descriptor, InferredDescriptor, UnknownTapLeaf};
+pub use self::metadata::DescriptorMetadata;
+#[cfg(feature = "compiler")]
+pub use self::migrate::TrMigrationReport;
+pub use self::rawtr::RawTr;
 pub use self::segwitv0::{Wpkh, Wsh, WshInner};
 pub use self::sh::{Sh, ShInner};
 pub use self::sortedmulti::SortedMultiVec;
-pub use self::tr::{TapTree, Tr};
+pub use self::summary::{DescriptorSummary, KeyRole, SpendPath};
+pub use self::template::KeychainKind;
+use self::tr::TapTreeIter;
+pub use self::tr::{TapLeafInfo, TapLeafIter, TapTree, TapTreeBuilder, Tr};
 
-mod checksum;
+pub mod checksum;
 mod key;
 
 pub use self::key::{
-    ConversionError, DerivedDescriptorKey, DescriptorKeyParseError, DescriptorPublicKey,
-    DescriptorSecretKey, DescriptorXKey, InnerXKey, SinglePriv, SinglePub, SinglePubKey, Wildcard,
+    ConversionError, DerivationIndex, DerivationRange, DerivedDescriptorKey,
+    DescriptorKeyParseError, DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey, InnerXKey,
+    SinglePriv, SinglePub, SinglePubKey, Wildcard,
 };
 
 /// Alias type for a map of public key to secret key
@@ -85,6 +111,12 @@ pub enum Descriptor<Pk: MiniscriptKey> {
     Wsh(Wsh<Pk>),
     /// Pay-to-Taproot
     Tr(Tr<Pk>),
+    /// Pay-to-Taproot with only the output key known
+    RawTr(RawTr<Pk>),
+    /// A raw scriptPubKey given as hex, for watch-only use
+    Raw(Script),
+    /// An address, for watch-only use
+    Addr(Address),
 }
 
 impl<Pk: MiniscriptKey> From<Bare<Pk>> for Descriptor<Pk> {
@@ -129,6 +161,13 @@ impl<Pk: MiniscriptKey> From<Tr<Pk>> for Descriptor<Pk> {
     }
 }
 
+impl<Pk: MiniscriptKey> From<RawTr<Pk>> for Descriptor<Pk> {
+    #[inline]
+    fn from(inner: RawTr<Pk>) -> Self {
+        Descriptor::RawTr(inner)
+    }
+}
+
 /// Descriptor Type of the descriptor
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub enum DescriptorType {
@@ -154,6 +193,12 @@ pub enum DescriptorType {
     ShWshSortedMulti,
     /// Tr Descriptor
     Tr,
+    /// Raw Tr Descriptor, with only the output key known
+    RawTr,
+    /// Raw scriptPubKey Descriptor
+    Raw,
+    /// Address Descriptor
+    Addr,
 }
 
 impl DescriptorType {
@@ -163,11 +208,12 @@ impl DescriptorType {
     pub fn segwit_version(&self) -> Option<WitnessVersion> {
         use self::DescriptorType::*;
         match self {
-            Tr => Some(WitnessVersion::V1),
+            Tr | RawTr => Some(WitnessVersion::V1),
             Wpkh | ShWpkh | Wsh | ShWsh | ShWshSortedMulti | WshSortedMulti => {
                 Some(WitnessVersion::V0)
             }
-            Bare | Sh | Pkh | ShSortedMulti => None,
+            // The segwit version of raw and addr descriptors depends on their content
+            Bare | Sh | Pkh | ShSortedMulti | Raw | Addr => None,
         }
     }
 }
@@ -274,6 +320,25 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
         Ok(Descriptor::Tr(Tr::new(key, script)?))
     }
 
+    /// Create new rawtr descriptor from a taproot output key
+    pub fn new_rawtr(output_key: Pk) -> Self {
+        Descriptor::RawTr(RawTr::new(output_key))
+    }
+
+    /// Create a new raw descriptor from a scriptPubKey
+    ///
+    /// Raw descriptors are watch-only and cannot be satisfied.
+    pub fn new_raw(script: Script) -> Self {
+        Descriptor::Raw(script)
+    }
+
+    /// Create a new addr descriptor from an address
+    ///
+    /// Addr descriptors are watch-only and cannot be satisfied.
+    pub fn new_addr(address: Address) -> Self {
+        Descriptor::Addr(address)
+    }
+
     /// Get the [DescriptorType] of [Descriptor]
     pub fn desc_type(&self) -> DescriptorType {
         match *self {
@@ -294,6 +359,9 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
                 WshInner::Ms(ref _ms) => DescriptorType::Wsh,
             },
             Descriptor::Tr(ref _tr) => DescriptorType::Tr,
+            Descriptor::RawTr(ref _rawtr) => DescriptorType::RawTr,
+            Descriptor::Raw(_) => DescriptorType::Raw,
+            Descriptor::Addr(_) => DescriptorType::Addr,
         }
     }
 
@@ -314,7 +382,170 @@ impl<Pk: MiniscriptKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.sanity_check(),
             Descriptor::Sh(ref sh) => sh.sanity_check(),
             Descriptor::Tr(ref tr) => tr.sanity_check(),
+            Descriptor::RawTr(_) | Descriptor::Raw(_) | Descriptor::Addr(_) => Ok(()),
+        }
+    }
+
+    /// Iterate over the miniscripts of the descriptor, whatever its type
+    ///
+    /// This yields the miniscript of `bare`, `sh` and `wsh` descriptors
+    /// (nested or not) and every leaf of a `tr` descriptor, in the order of
+    /// [`Tr::iter_scripts`]. `sortedmulti` and key-only descriptors have no
+    /// miniscript and yield nothing.
+    pub fn iter_miniscripts(&self) -> MiniscriptIter<'_, Pk> {
+        let single = match *self {
+            Descriptor::Bare(ref bare) => Some(DescriptorMiniscript::Bare(bare.as_inner())),
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::Ms(ref ms) => Some(DescriptorMiniscript::Legacy(ms)),
+                ShInner::Wsh(ref wsh) => match *wsh.as_inner() {
+                    WshInner::Ms(ref ms) => Some(DescriptorMiniscript::Segwitv0(ms)),
+                    WshInner::SortedMulti(..) => None,
+                },
+                ShInner::Wpkh(..) | ShInner::SortedMulti(..) => None,
+            },
+            Descriptor::Wsh(ref wsh) => match *wsh.as_inner() {
+                WshInner::Ms(ref ms) => Some(DescriptorMiniscript::Segwitv0(ms)),
+                WshInner::SortedMulti(..) => None,
+            },
+            Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::Tr(..)
+            | Descriptor::RawTr(..)
+            | Descriptor::Raw(..)
+            | Descriptor::Addr(..) => None,
+        };
+        let leaves = match *self {
+            Descriptor::Tr(ref tr) => Some(tr.iter_scripts()),
+            _ => None,
+        };
+        MiniscriptIter { single, leaves }
+    }
+
+    /// Canonical string representation of the descriptor, checksum included
+    ///
+    /// Parsing already normalizes hex to lowercase, hardened derivation steps
+    /// to `'` and recomputes the checksum; on top of that, the keys of
+    /// `sortedmulti()` and of `sortedmulti_a()` leaves are sorted since their
+    /// order does not affect the script.
+    pub fn canonical_string(&self) -> String {
+        fn sorted<Pk: Clone + Ord>(pks: &[Pk]) -> Vec<Pk> {
+            let mut pks = pks.to_vec();
+            pks.sort();
+            pks
+        }
+
+        let canonical = match *self {
+            Descriptor::Sh(ref sh) => match *sh.as_inner() {
+                ShInner::SortedMulti(ref smv) => Some(
+                    Descriptor::new_sh_sortedmulti(smv.k, sorted(&smv.pks))
+                        .expect("Reordering keys keeps the descriptor valid"),
+                ),
+                ShInner::Wsh(ref wsh) => match *wsh.as_inner() {
+                    WshInner::SortedMulti(ref smv) => Some(
+                        Descriptor::new_sh_wsh_sortedmulti(smv.k, sorted(&smv.pks))
+                            .expect("Reordering keys keeps the descriptor valid"),
+                    ),
+                    WshInner::Ms(..) => None,
+                },
+                ShInner::Wpkh(..) | ShInner::Ms(..) => None,
+            },
+            Descriptor::Wsh(ref wsh) => match *wsh.as_inner() {
+                WshInner::SortedMulti(ref smv) => Some(
+                    Descriptor::new_wsh_sortedmulti(smv.k, sorted(&smv.pks))
+                        .expect("Reordering keys keeps the descriptor valid"),
+                ),
+                WshInner::Ms(..) => None,
+            },
+            Descriptor::Tr(ref tr) => {
+                let mut tree = tr.taptree().clone();
+                if let Some(ref mut tree) = tree {
+                    tree.sort_multi_a_keys();
+                }
+                Some(Descriptor::Tr(
+                    Tr::new(tr.internal_key().clone(), tree)
+                        .expect("Reordering keys keeps the descriptor valid"),
+                ))
+            }
+            _ => None,
+        };
+        match canonical {
+            Some(canonical) => canonical.to_string(),
+            None => self.to_string(),
+        }
+    }
+
+    /// Whether both descriptors have the same [`Descriptor::canonical_string`]
+    pub fn eq_canonical(&self, other: &Self) -> bool {
+        self.canonical_string() == other.canonical_string()
+    }
+}
+
+/// A miniscript found in a descriptor, along with its script context
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DescriptorMiniscript<'a, Pk: MiniscriptKey> {
+    /// The script of a `bare` descriptor
+    Bare(&'a Miniscript<Pk, BareCtx>),
+    /// The redeem script of an `sh` descriptor
+    Legacy(&'a Miniscript<Pk, Legacy>),
+    /// The witness script of a `wsh` or `sh(wsh)` descriptor
+    Segwitv0(&'a Miniscript<Pk, Segwitv0>),
+    /// A leaf of a `tr` descriptor
+    Tap(&'a Miniscript<Pk, Tap>),
+}
+
+impl<'a, Pk: MiniscriptKey> DescriptorMiniscript<'a, Pk> {
+    /// Size, in bytes of the script-pubkey. See [`Miniscript::script_size`]
+    pub fn script_size(&self) -> usize {
+        match *self {
+            DescriptorMiniscript::Bare(ms) => ms.script_size(),
+            DescriptorMiniscript::Legacy(ms) => ms.script_size(),
+            DescriptorMiniscript::Segwitv0(ms) => ms.script_size(),
+            DescriptorMiniscript::Tap(ms) => ms.script_size(),
+        }
+    }
+
+    /// Checks whether the miniscript is safe. See [`Miniscript::sanity_check`]
+    pub fn sanity_check(&self) -> Result<(), AnalysisError> {
+        match *self {
+            DescriptorMiniscript::Bare(ms) => ms.sanity_check(),
+            DescriptorMiniscript::Legacy(ms) => ms.sanity_check(),
+            DescriptorMiniscript::Segwitv0(ms) => ms.sanity_check(),
+            DescriptorMiniscript::Tap(ms) => ms.sanity_check(),
+        }
+    }
+}
+
+impl<'b, Pk: MiniscriptKey> ForEachKey<Pk> for DescriptorMiniscript<'b, Pk> {
+    fn for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, pred: F) -> bool
+    where
+        Pk: 'a,
+        Pk::Hash: 'a,
+    {
+        match *self {
+            DescriptorMiniscript::Bare(ms) => ms.for_each_key(pred),
+            DescriptorMiniscript::Legacy(ms) => ms.for_each_key(pred),
+            DescriptorMiniscript::Segwitv0(ms) => ms.for_each_key(pred),
+            DescriptorMiniscript::Tap(ms) => ms.for_each_key(pred),
+        }
+    }
+}
+
+/// Iterator over the miniscripts of a descriptor, see
+/// [`Descriptor::iter_miniscripts`]
+pub struct MiniscriptIter<'a, Pk: MiniscriptKey> {
+    single: Option<DescriptorMiniscript<'a, Pk>>,
+    leaves: Option<TapTreeIter<'a, Pk>>,
+}
+
+impl<'a, Pk: MiniscriptKey> Iterator for MiniscriptIter<'a, Pk> {
+    type Item = DescriptorMiniscript<'a, Pk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ms) = self.single.take() {
+            return Some(ms);
         }
+        let (_, leaf) = self.leaves.as_mut()?.next()?;
+        Some(DescriptorMiniscript::Tap(leaf))
     }
 }
 
@@ -324,7 +555,9 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
     /// Some descriptors like pk() don't have an address.
     ///
     /// # Errors
-    /// For raw/bare descriptors that don't have an address.
+    /// For raw/bare descriptors that don't have an address, and for addr()
+    /// descriptors whose address is for another network. The test networks
+    /// (testnet, signet and regtest) are interchangeable.
     pub fn address(&self, network: Network) -> Result<Address, Error> {
         match *self {
             Descriptor::Bare(_) => Err(Error::BareDescriptorAddr),
@@ -333,6 +566,19 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => Ok(wsh.address(network)),
             Descriptor::Sh(ref sh) => Ok(sh.address(network)),
             Descriptor::Tr(ref tr) => Ok(tr.address(network)),
+            Descriptor::RawTr(ref rawtr) => Ok(rawtr.address(network)),
+            Descriptor::Raw(ref script) => {
+                Address::from_script(script, network).ok_or(Error::BareDescriptorAddr)
+            }
+            Descriptor::Addr(ref addr) => {
+                if (addr.network == Network::Bitcoin) != (network == Network::Bitcoin) {
+                    return Err(Error::AddrNetworkMismatch(addr.network, network));
+                }
+                Ok(Address {
+                    payload: addr.payload.clone(),
+                    network,
+                })
+            }
         }
     }
 
@@ -345,6 +591,9 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.script_pubkey(),
             Descriptor::Sh(ref sh) => sh.script_pubkey(),
             Descriptor::Tr(ref tr) => tr.script_pubkey(),
+            Descriptor::RawTr(ref rawtr) => rawtr.script_pubkey(),
+            Descriptor::Raw(ref script) => script.clone(),
+            Descriptor::Addr(ref addr) => addr.script_pubkey(),
         }
     }
 
@@ -362,7 +611,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wpkh(_) => Script::new(),
             Descriptor::Wsh(_) => Script::new(),
             Descriptor::Sh(ref sh) => sh.unsigned_script_sig(),
-            Descriptor::Tr(_) => Script::new(),
+            Descriptor::Tr(_) | Descriptor::RawTr(_) => Script::new(),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => Script::new(),
         }
     }
 
@@ -371,7 +621,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
     /// this is the redeemScript; for the others it is the witness script.
     ///
     /// # Errors
-    /// If the descriptor is a taproot descriptor.
+    /// If the descriptor is a taproot or a watch-only descriptor.
     pub fn explicit_script(&self) -> Result<Script, Error> {
         match *self {
             Descriptor::Bare(ref bare) => Ok(bare.script_pubkey()),
@@ -379,7 +629,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => Ok(wpkh.script_pubkey()),
             Descriptor::Wsh(ref wsh) => Ok(wsh.inner_script()),
             Descriptor::Sh(ref sh) => Ok(sh.inner_script()),
-            Descriptor::Tr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::Tr(_) | Descriptor::RawTr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => Err(Error::WatchOnlyDescriptor),
         }
     }
 
@@ -389,7 +640,7 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
     /// serialized in the sighash when evaluating a `CHECKSIG` & co. OP code.
     ///
     /// # Errors
-    /// If the descriptor is a taproot descriptor.
+    /// If the descriptor is a taproot or a watch-only descriptor.
     pub fn script_code(&self) -> Result<Script, Error> {
         match *self {
             Descriptor::Bare(ref bare) => Ok(bare.ecdsa_sighash_script_code()),
@@ -397,7 +648,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wpkh(ref wpkh) => Ok(wpkh.ecdsa_sighash_script_code()),
             Descriptor::Wsh(ref wsh) => Ok(wsh.ecdsa_sighash_script_code()),
             Descriptor::Sh(ref sh) => Ok(sh.ecdsa_sighash_script_code()),
-            Descriptor::Tr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::Tr(_) | Descriptor::RawTr(_) => Err(Error::TrNoScriptCode),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => Err(Error::WatchOnlyDescriptor),
         }
     }
 
@@ -415,6 +667,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.get_satisfaction(satisfier),
             Descriptor::Sh(ref sh) => sh.get_satisfaction(satisfier),
             Descriptor::Tr(ref tr) => tr.get_satisfaction(satisfier),
+            Descriptor::RawTr(ref rawtr) => rawtr.get_satisfaction(satisfier),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => Err(Error::WatchOnlyDescriptor),
         }
     }
 
@@ -432,6 +686,8 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.get_satisfaction_mall(satisfier),
             Descriptor::Sh(ref sh) => sh.get_satisfaction_mall(satisfier),
             Descriptor::Tr(ref tr) => tr.get_satisfaction_mall(satisfier),
+            Descriptor::RawTr(ref rawtr) => rawtr.get_satisfaction_mall(satisfier),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => Err(Error::WatchOnlyDescriptor),
         }
     }
 
@@ -465,9 +721,74 @@ impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.max_satisfaction_weight()?,
             Descriptor::Sh(ref sh) => sh.max_satisfaction_weight()?,
             Descriptor::Tr(ref tr) => tr.max_satisfaction_weight()?,
+            Descriptor::RawTr(ref rawtr) => rawtr.max_satisfaction_weight(),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => return Err(Error::WatchOnlyDescriptor),
         };
         Ok(weight)
     }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Descriptor::max_satisfaction_weight`] but
+    /// assuming signatures of the given sizes, e.g.
+    /// [`SigSizeAssumptions::COMPACT`] for signers grinding low-R ECDSA
+    /// signatures and using the default taproot sighash type.
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
+    pub fn max_satisfaction_weight_with_sig_sizes(
+        &self,
+        sig_sizes: SigSizeAssumptions,
+    ) -> Result<usize, Error> {
+        let weight = match *self {
+            Descriptor::Bare(ref bare) => bare.max_satisfaction_weight_with_sig_sizes(sig_sizes)?,
+            Descriptor::Pkh(ref pkh) => pkh.max_satisfaction_weight_with_sig_sizes(sig_sizes),
+            Descriptor::Wpkh(ref wpkh) => wpkh.max_satisfaction_weight_with_sig_sizes(sig_sizes),
+            Descriptor::Wsh(ref wsh) => wsh.max_satisfaction_weight_with_sig_sizes(sig_sizes)?,
+            Descriptor::Sh(ref sh) => sh.max_satisfaction_weight_with_sig_sizes(sig_sizes)?,
+            Descriptor::Tr(ref tr) => tr.max_satisfaction_weight_with_sig_sizes(sig_sizes)?,
+            Descriptor::RawTr(ref rawtr) => rawtr.max_satisfaction_weight_with_sig_sizes(sig_sizes),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => return Err(Error::WatchOnlyDescriptor),
+        };
+        Ok(weight)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, when an annex of `annex_len` bytes (including the `0x50`
+    /// prefix) is attached to the witness.
+    ///
+    /// Makes the same assumptions as [`Descriptor::max_satisfaction_weight`].
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to safisfy, or is not a taproot
+    /// descriptor.
+    pub fn max_satisfaction_weight_with_annex(&self, annex_len: usize) -> Result<usize, Error> {
+        match *self {
+            Descriptor::Tr(ref tr) => tr.max_satisfaction_weight_with_annex(annex_len),
+            Descriptor::RawTr(ref rawtr) => Ok(rawtr.max_satisfaction_weight_with_annex(annex_len)),
+            _ => Err(Error::InvalidTapAnnex),
+        }
+    }
+
+    /// Computes an upper bound on the virtual size, in vbytes, of a transaction
+    /// input spending an output of this descriptor.
+    ///
+    /// Unlike [`Descriptor::max_satisfaction_weight`], this includes the outpoint
+    /// and `nSequence` of the input, so it can be used as is for fee estimation.
+    /// The scriptSig of sh-wrapped descriptors is counted in full, and non-segwit
+    /// inputs account for their empty witness in case the transaction has
+    /// other segwit inputs. Makes the same assumptions as
+    /// [`Descriptor::max_satisfaction_weight`].
+    ///
+    /// # Errors
+    /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
+    pub fn max_spend_vbytes(&self) -> Result<usize, Error> {
+        let mut weight = TXIN_BASE_WEIGHT + self.max_satisfaction_weight()?;
+        if self.desc_type().segwit_version().is_none() {
+            // Empty witness stack count
+            weight += 1;
+        }
+        Ok((weight + 3) / 4)
+    }
 }
 
 impl<P, Q> TranslatePk<P, Q> for Descriptor<P>
@@ -489,6 +810,9 @@ where
             Descriptor::Sh(ref sh) => Descriptor::Sh(sh.translate_pk(t)?),
             Descriptor::Wsh(ref wsh) => Descriptor::Wsh(wsh.translate_pk(t)?),
             Descriptor::Tr(ref tr) => Descriptor::Tr(tr.translate_pk(t)?),
+            Descriptor::RawTr(ref rawtr) => Descriptor::RawTr(rawtr.translate_pk(t)?),
+            Descriptor::Raw(ref script) => Descriptor::Raw(script.clone()),
+            Descriptor::Addr(ref addr) => Descriptor::Addr(addr.clone()),
         };
         Ok(desc)
     }
@@ -507,6 +831,8 @@ impl<Pk: MiniscriptKey> ForEachKey<Pk> for Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.for_each_key(pred),
             Descriptor::Sh(ref sh) => sh.for_each_key(pred),
             Descriptor::Tr(ref tr) => tr.for_each_key(pred),
+            Descriptor::RawTr(ref rawtr) => rawtr.for_each_key(pred),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => true,
         }
     }
 }
@@ -517,13 +843,60 @@ impl Descriptor<DescriptorPublicKey> {
         self.for_any_key(|key| key.as_key().is_deriveable())
     }
 
-    /// Derives all wildcard keys in the descriptor using the supplied index
+    /// Checks that every extended key of the descriptor is meant for `network`
     ///
-    /// Panics if given an index ≥ 2^31
+    /// Returns the offending keys otherwise. Extended private keys parsed with
+    /// [`Descriptor::parse_descriptor`] are checked through their public keys,
+    /// which keep the network of the private key.
+    pub fn check_network(&self, network: Network) -> Result<(), Vec<DescriptorPublicKey>> {
+        let mut wrong_network = vec![];
+        self.for_each_key(|key| {
+            let key = key.as_key();
+            if !key.is_valid_for_network(network) {
+                wrong_network.push(key.clone());
+            }
+            true
+        });
+        if wrong_network.is_empty() {
+            Ok(())
+        } else {
+            Err(wrong_network)
+        }
+    }
+
+    /// Iterates over the origin of every key in the descriptor, including keys
+    /// in taproot leaves, as `(master fingerprint, full derivation path, key)`
+    ///
+    /// The fingerprint and path are the ones of [`DescriptorPublicKey::master_fingerprint`]
+    /// and [`DescriptorPublicKey::full_derivation_path`]: for wildcard keys the path stops
+    /// before the wildcard. Keys appearing several times are yielded for every occurrence.
+    pub fn key_origins(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            bip32::Fingerprint,
+            bip32::DerivationPath,
+            DescriptorPublicKey,
+        ),
+    > {
+        let mut origins = vec![];
+        self.for_each_key(|key| {
+            let key = key.as_key();
+            origins.push((
+                key.master_fingerprint(),
+                key.full_derivation_path(),
+                key.clone(),
+            ));
+            true
+        });
+        origins.into_iter()
+    }
+
+    /// Derives all wildcard keys in the descriptor using the supplied index
     ///
     /// In most cases, you would want to use [`Self::derived_descriptor`] directly to obtain
     /// a [`Descriptor<bitcoin::PublicKey>`]
-    pub fn derive(&self, index: u32) -> Descriptor<DerivedDescriptorKey> {
+    pub fn derive(&self, index: DerivationIndex) -> Descriptor<DerivedDescriptorKey> {
         struct Derivator(u32);
 
         impl PkTranslator<DescriptorPublicKey, DerivedDescriptorKey, ()> for Derivator {
@@ -535,10 +908,63 @@ impl Descriptor<DescriptorPublicKey> {
                 Ok(pkh.clone().derive(self.0))
             }
         }
-        self.translate_pk(&mut Derivator(index))
+        self.translate_pk(&mut Derivator(index.get()))
             .expect("BIP 32 key index substitution cannot fail")
     }
 
+    /// Replaces every occurrence of the key `old` by `new`, in all the scripts
+    /// and tapleaves of the descriptor
+    ///
+    /// The descriptor is left unchanged if `old` does not appear in it, if `new`
+    /// already appears in it, or if `new` is not valid in every context `old`
+    /// was used in (e.g. an uncompressed key inside `wsh`).
+    pub fn replace_key(
+        &mut self,
+        old: &DescriptorPublicKey,
+        new: DescriptorPublicKey,
+    ) -> Result<(), Error> {
+        struct Replacer<'a> {
+            old: &'a DescriptorPublicKey,
+            new: &'a DescriptorPublicKey,
+        }
+
+        impl<'a> PkTranslator<DescriptorPublicKey, DescriptorPublicKey, ()> for Replacer<'a> {
+            fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                if pk == self.old {
+                    Ok(self.new.clone())
+                } else {
+                    Ok(pk.clone())
+                }
+            }
+
+            fn pkh(&mut self, pkh: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+                PkTranslator::pk(self, pkh)
+            }
+        }
+
+        if !self.for_any_key(|key| key.as_key() == old) {
+            return Err(Error::BadDescriptor(format!(
+                "key {} is not part of the descriptor",
+                old
+            )));
+        }
+        if *old == new {
+            return Ok(());
+        }
+        if self.for_any_key(|key| *key.as_key() == new) {
+            return Err(Error::AnalysisError(
+                miniscript::analyzable::AnalysisError::RepeatedPubkeys,
+            ));
+        }
+
+        let replaced = self
+            .translate_pk(&mut Replacer { old, new: &new })
+            .expect("Key substitution cannot fail");
+        // Parse the result again to check `new` against every script context
+        *self = Descriptor::from_str(&replaced.to_string())?;
+        Ok(())
+    }
+
     /// Derive a [`Descriptor`] with a concrete [`bitcoin::PublicKey`] at a given index
     /// Removes all extended pubkeys and wildcards from the descriptor and only leaves
     /// concrete [`bitcoin::PublicKey`]. All [`bitcoin::XOnlyPublicKey`]s are converted
@@ -548,7 +974,7 @@ impl Descriptor<DescriptorPublicKey> {
     /// # Examples
     ///
     /// ```
-    /// use miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+    /// use miniscript::descriptor::{DerivationIndex, Descriptor, DescriptorPublicKey};
     /// use miniscript::bitcoin::secp256k1;
     /// use std::str::FromStr;
     ///
@@ -556,7 +982,8 @@ impl Descriptor<DescriptorPublicKey> {
     /// let secp = secp256k1::Secp256k1::verification_only();
     /// let descriptor = Descriptor::<DescriptorPublicKey>::from_str("tr(xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)")
     ///     .expect("Valid ranged descriptor");
-    /// let result = descriptor.derived_descriptor(&secp, 0).expect("Non-hardened derivation");
+    /// let index = DerivationIndex::new(0).expect("Non-hardened index");
+    /// let result = descriptor.derived_descriptor(&secp, index).expect("Non-hardened derivation");
     /// assert_eq!(result.to_string(), "tr(03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115)#6qm9h8ym");
     /// ```
     ///
@@ -566,7 +993,7 @@ impl Descriptor<DescriptorPublicKey> {
     pub fn derived_descriptor<C: secp256k1::Verification>(
         &self,
         secp: &secp256k1::Secp256k1<C>,
-        index: u32,
+        index: DerivationIndex,
     ) -> Result<Descriptor<bitcoin::PublicKey>, ConversionError> {
         struct Derivator<'a, C: secp256k1::Verification>(&'a secp256k1::Secp256k1<C>);
 
@@ -656,6 +1083,17 @@ impl Descriptor<DescriptorPublicKey> {
         Ok((descriptor, keymap_pk.0))
     }
 
+    /// Parse a descriptor that may contain xprv or WIF secret keys, using a shared signing context
+    ///
+    /// This is a convenience wrapper around [`Self::parse_descriptor`]. The returned descriptor
+    /// only contains public keys, while the [`KeyMap`] holds the secret material; pass it to
+    /// [`Self::to_string_with_secret`] to serialize the original descriptor back.
+    pub fn parse_descriptor_secret(
+        s: &str,
+    ) -> Result<(Descriptor<DescriptorPublicKey>, KeyMap), Error> {
+        with_secp(|secp| Descriptor::parse_descriptor(secp, s))
+    }
+
     /// Serialize a descriptor to string with its secret keys
     pub fn to_string_with_secret(&self, key_map: &KeyMap) -> String {
         struct KeyMapLookUp<'a>(&'a KeyMap);
@@ -699,12 +1137,17 @@ impl Descriptor<DescriptorPublicKey> {
         &self,
         secp: &secp256k1::Secp256k1<C>,
         script_pubkey: &Script,
-        range: Range<u32>,
-    ) -> Result<Option<(u32, Descriptor<bitcoin::PublicKey>)>, ConversionError> {
-        let range = if self.is_deriveable() { range } else { 0..1 };
+        range: DerivationRange,
+    ) -> Result<Option<(DerivationIndex, Descriptor<bitcoin::PublicKey>)>, ConversionError> {
+        let range = if self.is_deriveable() {
+            range
+        } else {
+            DerivationRange::new(0..1).expect("1 is below 2^31")
+        };
 
-        for i in range {
-            let concrete = self.derived_descriptor(secp, i)?;
+        let mut derivator = BatchDerivator::new(secp, self)?;
+        for i in range.iter() {
+            let concrete = derivator.derive(self, i)?;
             if &concrete.script_pubkey() == script_pubkey {
                 return Ok(Some((i, concrete)));
             }
@@ -712,37 +1155,278 @@ impl Descriptor<DescriptorPublicKey> {
 
         Ok(None)
     }
+
+    /// Search `range` for the derivation index at which this descriptor produces
+    /// `script_pubkey`
+    ///
+    /// Like [`Self::derive_batch`], the non-ranged part of every key is only derived once.
+    /// Non-derivable descriptors are only checked at index `0`.
+    pub fn find_derivation_index<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        script_pubkey: &Script,
+        range: DerivationRange,
+    ) -> Result<Option<DerivationIndex>, ConversionError> {
+        Ok(self
+            .find_derivation_index_for_spk(secp, script_pubkey, range)?
+            .map(|(index, _)| index))
+    }
+
+    /// Derive a concrete [`Descriptor`] at every index in `range`
+    ///
+    /// This is equivalent to calling [`Self::derived_descriptor`] for each index, but the
+    /// non-wildcard part of every xpub derivation path and every non-ranged key is only
+    /// derived once, so each index costs a single BIP32 child derivation per ranged key.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if hardened derivation is attempted.
+    pub fn derive_batch<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        range: DerivationRange,
+    ) -> Result<Vec<Descriptor<bitcoin::PublicKey>>, ConversionError> {
+        let mut derivator = BatchDerivator::new(secp, self)?;
+        range
+            .iter()
+            .map(|index| derivator.derive(self, index))
+            .collect()
+    }
+
+    /// Iterates over the scriptPubKeys of the descriptor at every index in `range`,
+    /// e.g. for BIP158 filter matching or Electrum scripthash subscriptions
+    ///
+    /// The scriptPubKeys are derived lazily, sharing the per-key work between indices
+    /// like [`Self::derive_batch`]. Descriptors without wildcards yield the same
+    /// scriptPubKey at every index.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if hardened derivation is attempted.
+    pub fn spk_range_iter<'a, C: secp256k1::Verification>(
+        &'a self,
+        secp: &'a secp256k1::Secp256k1<C>,
+        range: DerivationRange,
+    ) -> Result<SpkIter<'a, C>, ConversionError> {
+        Ok(SpkIter {
+            derivator: BatchDerivator::new(secp, self)?,
+            descriptor: self,
+            range: range.to_range(),
+        })
+    }
+}
+
+/// Iterator over the `(index, scriptPubKey)` pairs of a descriptor, see
+/// [`Descriptor::spk_range_iter`]
+pub struct SpkIter<'a, C: secp256k1::Verification> {
+    derivator: BatchDerivator<'a, C>,
+    descriptor: &'a Descriptor<DescriptorPublicKey>,
+    range: Range<u32>,
+}
+
+impl<'a, C: secp256k1::Verification> Iterator for SpkIter<'a, C> {
+    type Item = (DerivationIndex, Script);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index =
+            DerivationIndex::new(self.range.next()?).expect("Range checked by DerivationRange");
+        let derived = self
+            .derivator
+            .derive(self.descriptor, index)
+            .expect("Hardened derivation is rejected when creating the iterator");
+        Some((index, derived.script_pubkey()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a, C: secp256k1::Verification> ExactSizeIterator for SpkIter<'a, C> {}
+
+/// A descriptor key with all of its non-ranged derivation already done
+enum BatchKey {
+    Fixed(bitcoin::PublicKey),
+    Parent(bip32::ExtendedPubKey),
+}
+
+/// Derives a descriptor at many indices, sharing the per-key work between them
+struct BatchDerivator<'a, C: secp256k1::Verification> {
+    secp: &'a secp256k1::Secp256k1<C>,
+    keys: HashMap<DescriptorPublicKey, BatchKey>,
+    index: u32,
+}
+
+impl<'a, C: secp256k1::Verification> BatchDerivator<'a, C> {
+    fn new(
+        secp: &'a secp256k1::Secp256k1<C>,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<Self, ConversionError> {
+        let mut keys = HashMap::new();
+        let mut error = None;
+        descriptor.for_each_key(|key| {
+            let key = key.as_key();
+            if keys.contains_key(key) {
+                return true;
+            }
+            let batch_key = match *key {
+                DescriptorPublicKey::XPub(ref xpk) if xpk.wildcard == Wildcard::Unhardened => {
+                    match xpk.xkey.derive_pub(secp, &xpk.derivation_path) {
+                        Ok(xpub) => Ok(BatchKey::Parent(xpub)),
+                        Err(bip32::Error::CannotDeriveFromHardenedKey) => {
+                            Err(ConversionError::HardenedChild)
+                        }
+                        Err(e) => unreachable!("cryptographically unreachable: {}", e),
+                    }
+                }
+                DescriptorPublicKey::XPub(ref xpk) if xpk.wildcard == Wildcard::Hardened => {
+                    Err(ConversionError::HardenedChild)
+                }
+                _ => key.derive_public_key(secp).map(BatchKey::Fixed),
+            };
+            match batch_key {
+                Ok(batch_key) => {
+                    keys.insert(key.clone(), batch_key);
+                    true
+                }
+                Err(e) => {
+                    error = Some(e);
+                    false
+                }
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(BatchDerivator {
+                secp,
+                keys,
+                index: 0,
+            }),
+        }
+    }
+
+    fn derive(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        index: DerivationIndex,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, ConversionError> {
+        self.index = index.get();
+        descriptor.translate_pk(self)
+    }
+}
+
+impl<'a, C: secp256k1::Verification>
+    PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, ConversionError>
+    for BatchDerivator<'a, C>
+{
+    fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<bitcoin::PublicKey, ConversionError> {
+        match self.keys[pk] {
+            BatchKey::Fixed(pk) => Ok(pk),
+            BatchKey::Parent(ref xpub) => {
+                let child = bip32::ChildNumber::from_normal_idx(self.index)
+                    .expect("DerivationIndex is below 2^31");
+                match xpub.ckd_pub(self.secp, child) {
+                    Ok(xpub) => Ok(bitcoin::PublicKey::new(xpub.public_key)),
+                    Err(e) => unreachable!("cryptographically unreachable: {}", e),
+                }
+            }
+        }
+    }
+
+    fn pkh(
+        &mut self,
+        pkh: &DescriptorPublicKey,
+    ) -> Result<bitcoin::hashes::hash160::Hash, ConversionError> {
+        Ok(PkTranslator::pk(self, pkh)?.to_pubkeyhash())
+    }
 }
 
 impl_from_tree!(
     Descriptor<Pk>,
     /// Parse an expression tree into a descriptor.
     fn from_tree(top: &expression::Tree) -> Result<Descriptor<Pk>, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Descriptor<Pk> {
+    /// Parse a descriptor from string, with the keys and hashes parsed by
+    /// `parser` rather than by their [`FromStr`] implementations. This
+    /// builds descriptors over key types which cannot be written as strings
+    /// on their own, or whose keys are written as aliases.
+    pub fn from_str_with<P, E>(s: &str, parser: &mut P) -> Result<Descriptor<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        Descriptor::parse_with(s, parser).map_err(Error::unlocated)
+    }
+
+    /// Same as [`Descriptor::from_str_with`], with the errors located in `s`
+    pub(crate) fn parse_with<P, E>(s: &str, parser: &mut P) -> Result<Descriptor<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        // tr tree parsing has special code
+        // Tr::from_str will check the checksum
+        // match "tr(" to handle more extensibly
+        let mut parse = |s: &str| {
+            if s.starts_with("tr(") {
+                Ok(Descriptor::Tr(Tr::parse_with(s, parser)?))
+            } else {
+                let desc_str = verify_checksum_located(s)?;
+                let top = expression::Tree::parse(desc_str)?;
+                Descriptor::from_tree_with(&top, parser)
+            }
+        };
+        match parse(s) {
+            // Suggest the right checksum if it is the only problem
+            Err(Error::BadChecksum(e, None)) => {
+                let suggestion = match e {
+                    checksum::ChecksumError::Mismatch { ref expected, .. } => {
+                        let desc_str = checksum::strip_checksum(s);
+                        parse(desc_str)
+                            .ok()
+                            .map(|_| format!("{}#{}", desc_str, expected))
+                    }
+                    _ => None,
+                };
+                Err(Error::BadChecksum(e, suggestion))
+            }
+            res => res,
+        }
+    }
+
+    /// Parses an expression tree into a descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Descriptor<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         Ok(match (top.name, top.args.len() as u32) {
-            ("pkh", 1) => Descriptor::Pkh(Pkh::from_tree(top)?),
-            ("wpkh", 1) => Descriptor::Wpkh(Wpkh::from_tree(top)?),
-            ("sh", 1) => Descriptor::Sh(Sh::from_tree(top)?),
-            ("wsh", 1) => Descriptor::Wsh(Wsh::from_tree(top)?),
-            ("tr", _) => Descriptor::Tr(Tr::from_tree(top)?),
-            _ => Descriptor::Bare(Bare::from_tree(top)?),
+            ("pkh", 1) => Descriptor::Pkh(Pkh::from_tree_with(top, parser)?),
+            ("wpkh", 1) => Descriptor::Wpkh(Wpkh::from_tree_with(top, parser)?),
+            ("sh", 1) => Descriptor::Sh(Sh::from_tree_with(top, parser)?),
+            ("wsh", 1) => Descriptor::Wsh(Wsh::from_tree_with(top, parser)?),
+            ("tr", _) => Descriptor::Tr(Tr::from_tree_with(top, parser)?),
+            ("rawtr", 1) => Descriptor::RawTr(RawTr::from_tree_with(top, parser)?),
+            ("raw", 1) => Descriptor::Raw(expression::terminal(&top.args[0], Script::from_str)?),
+            ("addr", 1) => Descriptor::Addr(expression::terminal(&top.args[0], Address::from_str)?),
+            _ => Descriptor::Bare(Bare::from_tree_with(top, parser)?),
         })
     }
-);
+}
 
 impl_from_str!(
     Descriptor<Pk>,
     type Err = Error;,
     fn from_str(s: &str) -> Result<Descriptor<Pk>, Error> {
-        // tr tree parsing has special code
-        // Tr::from_str will check the checksum
-        // match "tr(" to handle more extensibly
-        if s.starts_with("tr(") {
-            Ok(Descriptor::Tr(Tr::from_str(s)?))
-        } else {
-            let desc_str = verify_checksum(s)?;
-            let top = expression::Tree::from_str(desc_str)?;
-            expression::FromTree::from_tree(&top)
-        }
+        Descriptor::parse_with(s, &mut FromStrParser)
     }
 );
 
@@ -755,6 +1439,9 @@ impl<Pk: MiniscriptKey> fmt::Debug for Descriptor<Pk> {
             Descriptor::Sh(ref sub) => write!(f, "{:?}", sub),
             Descriptor::Wsh(ref sub) => write!(f, "{:?}", sub),
             Descriptor::Tr(ref tr) => write!(f, "{:?}", tr),
+            Descriptor::RawTr(ref rawtr) => write!(f, "{:?}", rawtr),
+            Descriptor::Raw(ref script) => write!(f, "raw({:x})", script),
+            Descriptor::Addr(ref addr) => write!(f, "addr({})", addr),
         }
     }
 }
@@ -768,6 +1455,17 @@ impl<Pk: MiniscriptKey> fmt::Display for Descriptor<Pk> {
             Descriptor::Sh(ref sub) => write!(f, "{}", sub),
             Descriptor::Wsh(ref sub) => write!(f, "{}", sub),
             Descriptor::Tr(ref tr) => write!(f, "{}", tr),
+            Descriptor::RawTr(ref rawtr) => write!(f, "{}", rawtr),
+            Descriptor::Raw(ref script) => {
+                let desc = format!("raw({:x})", script);
+                let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+                write!(f, "{}#{}", &desc, &checksum)
+            }
+            Descriptor::Addr(ref addr) => {
+                let desc = format!("addr({})", addr);
+                let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+                write!(f, "{}#{}", &desc, &checksum)
+            }
         }
     }
 }
@@ -787,11 +1485,12 @@ mod tests {
     use bitcoin::util::bip32;
     use bitcoin::{self, secp256k1, EcdsaSighashType, PublicKey};
 
-    use super::checksum::desc_checksum;
+    use super::checksum::{desc_checksum, ChecksumError};
     use super::tr::Tr;
     use super::*;
     use crate::descriptor::key::Wildcard;
     use crate::descriptor::{DescriptorPublicKey, DescriptorSecretKey, DescriptorXKey, SinglePub};
+    use crate::expression::FromStrLocated;
     #[cfg(feature = "compiler")]
     use crate::policy;
     use crate::{hex_script, Descriptor, DummyKey, Error, Miniscript, Satisfier};
@@ -1533,12 +2232,12 @@ mod tests {
 
             // Same address
             let addr_one = desc_one
-                .derived_descriptor(&secp_ctx, index)
+                .derived_descriptor(&secp_ctx, DerivationIndex::new(index).unwrap())
                 .unwrap()
                 .address(bitcoin::Network::Bitcoin)
                 .unwrap();
             let addr_two = desc_two
-                .derived_descriptor(&secp_ctx, index)
+                .derived_descriptor(&secp_ctx, DerivationIndex::new(index).unwrap())
                 .unwrap()
                 .address(bitcoin::Network::Bitcoin)
                 .unwrap();
@@ -1569,6 +2268,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn checksum_suggestion() {
+        let desc = "wpkh(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)";
+        let correct = Descriptor::<DescriptorPublicKey>::from_str(desc)
+            .unwrap()
+            .to_string();
+        match Descriptor::<DescriptorPublicKey>::from_str(&format!("{}#aaaaaaaa", desc)) {
+            Err(Error::BadChecksum(ChecksumError::Mismatch { expected, found }, suggestion)) => {
+                assert_eq!(found, "aaaaaaaa");
+                assert_eq!(format!("{}#{}", desc, expected), correct);
+                assert_eq!(suggestion, Some(correct));
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // No suggestion when the descriptor is invalid anyway
+        match Descriptor::<DescriptorPublicKey>::from_str("wpkh(02e6)#aaaaaaaa") {
+            Err(Error::BadChecksum(ChecksumError::Mismatch { .. }, None)) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
     #[test]
     fn test_parse_descriptor() {
         let secp = &secp256k1::Secp256k1::signing_only();
@@ -1581,7 +2302,7 @@ mod tests {
             ($secp: ident,$($desc: expr),*) => {
                 $(
                     match Descriptor::parse_descriptor($secp, $desc) {
-                        Err(Error::BadDescriptor(_)) => {},
+                        Err(Error::BadDescriptor(_)) | Err(Error::BadChecksum(..)) => {},
                         Err(e) => panic!("Expected bad checksum for {}, got '{}'", $desc, e),
                         _ => panic!("Invalid checksum treated as valid: {}", $desc),
                     };
@@ -1615,7 +2336,7 @@ pk(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHW
 pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         let policy: policy::concrete::Policy<DescriptorPublicKey> = descriptor_str.parse().unwrap();
         let descriptor = Descriptor::new_sh(policy.compile().unwrap()).unwrap();
-        let derived_descriptor = descriptor.derive(42);
+        let derived_descriptor = descriptor.derive(DerivationIndex::new(42).unwrap());
 
         let res_descriptor_str = "thresh(2,\
 pk([d34db33f/44'/0'/0']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/42),\
@@ -1643,6 +2364,31 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         assert_eq!(descriptor_str, descriptor.to_string_with_secret(&keymap));
     }
 
+    #[test]
+    fn parse_descriptor_secret() {
+        let descriptor_str = "wsh(multi(1,xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*,L4rK1yDtCWekvXuE6oXD9jCYfFNV2cWRpVuPLBcCU2z8TrisoyY1,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
+        let (descriptor, keymap) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor_secret(descriptor_str).unwrap();
+
+        assert_eq!(keymap.len(), 2);
+        assert!(!descriptor.to_string().contains("xprv"));
+        assert!(keymap.values().any(matches_single));
+
+        let with_secret = descriptor.to_string_with_secret(&keymap);
+        assert!(with_secret.starts_with(descriptor_str));
+        let (roundtrip, roundtrip_keymap) =
+            Descriptor::<DescriptorPublicKey>::parse_descriptor_secret(&with_secret).unwrap();
+        assert_eq!(roundtrip, descriptor);
+        assert_eq!(roundtrip_keymap, keymap);
+
+        fn matches_single(sk: &DescriptorSecretKey) -> bool {
+            match *sk {
+                DescriptorSecretKey::Single(_) => true,
+                DescriptorSecretKey::XPrv(_) => false,
+            }
+        }
+    }
+
     #[test]
     fn checksum_for_nested_sh() {
         let descriptor_str = "sh(wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL))";
@@ -1659,9 +2405,51 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         let comp_key = "0308c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
         let x_only_key = "08c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d4103";
 
-        // Both x-only keys and comp keys allowed in tr
-        Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", comp_key)).unwrap();
+        // Comp keys are accepted as the internal key of tr, as in Bitcoin
+        // Core, and written x-only
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", comp_key));
+        assert_eq!(
+            desc.unwrap(),
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", x_only_key)).unwrap()
+        );
+        // The origin of the key is kept
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr([d34db33f/86'/0'/0']{})",
+            comp_key
+        ))
+        .unwrap();
+        assert_eq!(
+            desc.to_string(),
+            Descriptor::<DescriptorPublicKey>::from_str(&format!(
+                "tr([d34db33f/86'/0'/0']{})",
+                x_only_key
+            ))
+            .unwrap()
+            .to_string()
+        );
+        // Uncompressed keys are rejected
+        let uncomp_key = "0408c0fcf8895f4361b4fc77afe2ad53b0bd27dcebfd863421b2b246dc283d41038bdd5d56e14e9ff22ba99fd1f6dc0fc4dda8dd5b9bf2d4fe5ae8a9e4c1f0f2a0";
+        let err =
+            Descriptor::<DescriptorPublicKey>::from_str_located(&format!("tr({})", uncomp_key))
+                .unwrap_err();
+        assert_eq!(err.position(), Some(3));
+        assert_eq!(
+            *err.error(),
+            Error::NonXOnlyInternalKey(uncomp_key.to_string())
+        );
         Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", x_only_key)).unwrap();
+        // Comp keys remain allowed in tapleaves
+        Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr({},pk({}))",
+            x_only_key, comp_key
+        ))
+        .unwrap();
+        // Hashes are rejected before key parsing
+        assert_eq!(
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({})", &x_only_key[..40]))
+                .unwrap_err(),
+            Error::NonXOnlyInternalKey(x_only_key[..40].to_string())
+        );
 
         // Only compressed keys allowed in wsh
         Descriptor::<DescriptorPublicKey>::from_str(&format!("wsh(pk({}))", comp_key)).unwrap();
@@ -1683,16 +2471,611 @@ pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))";
         .unwrap();
 
         assert_eq!(
-            descriptor.find_derivation_index_for_spk(&secp, &script_at_0_1, 0..1),
+            descriptor.find_derivation_index_for_spk(
+                &secp,
+                &script_at_0_1,
+                DerivationRange::new(0..1).unwrap()
+            ),
+            Ok(None)
+        );
+        assert_eq!(
+            descriptor.find_derivation_index_for_spk(
+                &secp,
+                &script_at_0_1,
+                DerivationRange::new(0..2).unwrap()
+            ),
+            Ok(Some((
+                DerivationIndex::new(1).unwrap(),
+                expected_concrete.clone()
+            )))
+        );
+        assert_eq!(
+            descriptor.find_derivation_index_for_spk(
+                &secp,
+                &script_at_0_1,
+                DerivationRange::new(0..10).unwrap()
+            ),
+            Ok(Some((DerivationIndex::new(1).unwrap(), expected_concrete)))
+        );
+    }
+
+    #[test]
+    fn find_derivation_index() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str("wsh(multi(1,xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))").unwrap();
+        let spk = descriptor
+            .derived_descriptor(&secp, DerivationIndex::new(17).unwrap())
+            .unwrap()
+            .script_pubkey();
+
+        assert_eq!(
+            descriptor.find_derivation_index(&secp, &spk, DerivationRange::new(0..100).unwrap()),
+            Ok(Some(DerivationIndex::new(17).unwrap()))
+        );
+        assert_eq!(
+            descriptor.find_derivation_index(&secp, &spk, DerivationRange::new(0..17).unwrap()),
+            Ok(None)
+        );
+        assert_eq!(
+            descriptor.find_derivation_index(
+                &secp,
+                &Script::new(),
+                DerivationRange::new(0..10).unwrap()
+            ),
             Ok(None)
         );
+    }
+
+    #[test]
+    fn rawtr() {
+        let tr = Descriptor::<bitcoin::PublicKey>::from_str(
+            "tr(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        let output_key = match tr {
+            Descriptor::Tr(ref tr) => tr.spend_info().output_key().to_inner(),
+            _ => unreachable!(),
+        };
+        let rawtr_str = format!("rawtr({})", output_key.to_public_key());
+        let rawtr = Descriptor::<bitcoin::PublicKey>::from_str(&rawtr_str).unwrap();
+        assert!(rawtr.to_string().starts_with(&format!("{}#", rawtr_str)));
+        assert_eq!(rawtr.desc_type(), DescriptorType::RawTr);
+        assert_eq!(rawtr.script_pubkey(), tr.script_pubkey());
+        assert_eq!(
+            rawtr.address(Network::Bitcoin).unwrap(),
+            tr.address(Network::Bitcoin).unwrap()
+        );
+        assert_eq!(rawtr.explicit_script(), Err(Error::TrNoScriptCode));
+        assert_eq!(rawtr.max_satisfaction_weight().unwrap(), 4 + 1 + 1 + 65);
+
+        let sig = bitcoin::SchnorrSig {
+            sig: secp256k1::schnorr::Signature::from_slice(&[1; 64]).unwrap(),
+            hash_ty: bitcoin::SchnorrSighashType::Default,
+        };
+        struct KeySpend(bitcoin::SchnorrSig);
+        impl Satisfier<bitcoin::PublicKey> for KeySpend {
+            fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+                Some(self.0)
+            }
+        }
+        let (witness, script_sig) = rawtr.get_satisfaction(KeySpend(sig)).unwrap();
+        assert_eq!(witness, vec![sig.to_vec()]);
+        assert!(script_sig.is_empty());
+
+        // Ranged output keys derive like any other key
+        let ranged = Descriptor::<DescriptorPublicKey>::from_str("rawtr(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*)").unwrap();
+        let secp = secp256k1::Secp256k1::verification_only();
+        assert!(ranged.is_deriveable());
+        assert!(ranged
+            .derived_descriptor(&secp, DerivationIndex::new(3).unwrap())
+            .unwrap()
+            .to_string()
+            .starts_with("rawtr(0"));
+    }
+
+    #[test]
+    fn watch_only_descriptors() {
+        let wpkh = Descriptor::<bitcoin::PublicKey>::from_str(
+            "wpkh(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        let spk = wpkh.script_pubkey();
+        let address = wpkh.address(Network::Bitcoin).unwrap();
+
+        let raw = Descriptor::<DescriptorPublicKey>::from_str(&format!("raw({:x})", spk)).unwrap();
+        assert_eq!(raw, Descriptor::new_raw(spk.clone()));
+        assert_eq!(raw.desc_type(), DescriptorType::Raw);
+        assert!(!raw.is_deriveable());
+        let secp = secp256k1::Secp256k1::verification_only();
+        let derived = raw
+            .derived_descriptor(&secp, DerivationIndex::new(0).unwrap())
+            .unwrap();
+        assert_eq!(derived.script_pubkey(), spk);
+        assert_eq!(derived.address(Network::Bitcoin).unwrap(), address);
+
+        let addr =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("addr({})", address)).unwrap();
+        assert_eq!(addr, Descriptor::new_addr(address.clone()));
+        assert_eq!(addr.desc_type(), DescriptorType::Addr);
+        assert_eq!(addr.script_pubkey(), spk);
+        assert_eq!(addr.address(Network::Bitcoin).unwrap(), address);
+        assert_eq!(
+            addr.address(Network::Testnet),
+            Err(Error::AddrNetworkMismatch(
+                Network::Bitcoin,
+                Network::Testnet
+            ))
+        );
+        // The test networks share their addresses
+        let testnet = wpkh.address(Network::Testnet).unwrap();
+        let addr =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("addr({})", testnet)).unwrap();
+        assert_eq!(addr.address(Network::Testnet).unwrap(), testnet);
+        assert_eq!(
+            addr.address(Network::Regtest).unwrap(),
+            wpkh.address(Network::Regtest).unwrap()
+        );
+        assert_eq!(
+            addr.address(Network::Bitcoin),
+            Err(Error::AddrNetworkMismatch(
+                Network::Testnet,
+                Network::Bitcoin
+            ))
+        );
+        let addr =
+            Descriptor::<bitcoin::PublicKey>::from_str(&format!("addr({})", address)).unwrap();
+        assert_eq!(
+            addr.to_string(),
+            Descriptor::<bitcoin::PublicKey>::from_str(&addr.to_string())
+                .unwrap()
+                .to_string()
+        );
+        assert!(addr.to_string().starts_with(&format!("addr({})#", address)));
+
+        // Watch-only descriptors cannot be spent from
+        assert_eq!(addr.explicit_script(), Err(Error::WatchOnlyDescriptor));
+        assert_eq!(
+            derived.max_satisfaction_weight(),
+            Err(Error::WatchOnlyDescriptor)
+        );
         assert_eq!(
-            descriptor.find_derivation_index_for_spk(&secp, &script_at_0_1, 0..2),
-            Ok(Some((1, expected_concrete.clone())))
+            derived.get_satisfaction(HashMap::<bitcoin::PublicKey, bitcoin::EcdsaSig>::new()),
+            Err(Error::WatchOnlyDescriptor)
         );
+
+        // Non-standard scripts have no address
+        let op_true = Descriptor::<bitcoin::PublicKey>::from_str("raw(51)").unwrap();
         assert_eq!(
-            descriptor.find_derivation_index_for_spk(&secp, &script_at_0_1, 0..10),
-            Ok(Some((1, expected_concrete)))
+            op_true.address(Network::Bitcoin),
+            Err(Error::BareDescriptorAddr)
+        );
+        assert!(Descriptor::<bitcoin::PublicKey>::from_str("raw(zz)").is_err());
+        assert!(Descriptor::<bitcoin::PublicKey>::from_str("addr(notanaddress)").is_err());
+    }
+
+    #[test]
+    fn sortedmulti_a() {
+        let xpub_a = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*";
+        let xpub_b = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*";
+        let internal = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+        let sorted = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr({},sortedmulti_a(1,{},{}))",
+            &internal[2..],
+            xpub_a,
+            xpub_b
+        ))
+        .unwrap();
+        assert!(sorted
+            .to_string()
+            .contains(&format!("sortedmulti_a(1,{},{})", xpub_a, xpub_b)));
+
+        // The keys are sorted separately for every derivation index
+        let secp = secp256k1::Secp256k1::verification_only();
+        let mut reordered = false;
+        for index in 0..10 {
+            let derived = sorted
+                .derived_descriptor(&secp, DerivationIndex::new(index).unwrap())
+                .unwrap();
+            let leaf = match derived {
+                Descriptor::Tr(ref tr) => tr.iter_scripts().next().unwrap().1.clone(),
+                _ => unreachable!(),
+            };
+            let keys = leaf.iter_pk().collect::<Vec<_>>();
+            let mut sorted_keys = keys.clone();
+            sorted_keys.sort_by_key(|pk| pk.to_x_only_pubkey().serialize());
+            reordered |= keys != sorted_keys;
+
+            let multi_a = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+                "tr({},multi_a(1,{},{}))",
+                internal, sorted_keys[0], sorted_keys[1]
+            ))
+            .unwrap();
+            assert_eq!(derived.script_pubkey(), multi_a.script_pubkey());
+
+            // Signatures are placed according to the sorted keys as well
+            struct TapSigs;
+            impl Satisfier<bitcoin::PublicKey> for TapSigs {
+                fn lookup_tap_leaf_script_sig(
+                    &self,
+                    pk: &bitcoin::PublicKey,
+                    _: &bitcoin::util::taproot::TapLeafHash,
+                ) -> Option<bitcoin::SchnorrSig> {
+                    let sig = [pk.to_x_only_pubkey().serialize()[0]; 64];
+                    Some(bitcoin::SchnorrSig {
+                        sig: secp256k1::schnorr::Signature::from_slice(&sig).unwrap(),
+                        hash_ty: bitcoin::SchnorrSighashType::Default,
+                    })
+                }
+            }
+            let (witness, _) = derived.get_satisfaction_mall(TapSigs).unwrap();
+            let (multi_a_witness, _) = multi_a.get_satisfaction_mall(TapSigs).unwrap();
+            assert_eq!(witness, multi_a_witness);
+        }
+        assert!(reordered, "derivation never changed the key order");
+
+        // Like multi_a, only allowed in tapscript
+        assert!(Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(sortedmulti_a(1,{},{}))",
+            xpub_a, xpub_b
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn key_origins() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("tr([aabbccdd/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*,{pk([11223344/1']02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443),pk(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)})").unwrap();
+        let origins = desc
+            .key_origins()
+            .map(|(fp, path, _)| (fp.to_string(), path.to_string()))
+            .collect::<Vec<_>>();
+        let no_origin = DescriptorPublicKey::from_str(
+            "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8",
+        )
+        .unwrap()
+        .master_fingerprint()
+        .to_string();
+        assert_eq!(
+            origins,
+            vec![
+                ("11223344".to_string(), "m/1'".to_string()),
+                (no_origin, "m".to_string()),
+                ("aabbccdd".to_string(), "m/86'/0'/0'/0".to_string()),
+            ]
+        );
+
+        let (_, _, key) = desc.key_origins().last().unwrap();
+        assert!(key.is_deriveable());
+    }
+
+    #[test]
+    fn max_spend_vbytes() {
+        let key = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+        for (desc, witness_count) in &[
+            ("pkh(KEY)", 1),
+            ("wpkh(KEY)", 0),
+            ("sh(wpkh(KEY))", 0),
+            ("sh(wsh(pk(KEY)))", 0),
+            ("tr(KEY)", 0),
+        ] {
+            let desc =
+                Descriptor::<bitcoin::PublicKey>::from_str(&desc.replace("KEY", key)).unwrap();
+            // outpoint and nSequence, satisfaction, empty witness of non-segwit inputs
+            let weight = 4 * 40 + desc.max_satisfaction_weight().unwrap() + witness_count;
+            assert_eq!(desc.max_spend_vbytes().unwrap(), (weight + 3) / 4);
+        }
+        let vbytes = |desc: &str| {
+            Descriptor::<bitcoin::PublicKey>::from_str(&desc.replace("KEY", key))
+                .unwrap()
+                .max_spend_vbytes()
+                .unwrap()
+        };
+        assert_eq!(vbytes("wpkh(KEY)"), 68);
+        assert_eq!(vbytes("tr(KEY)"), 58);
+        assert!(Descriptor::<bitcoin::PublicKey>::from_str("sh(0)")
+            .unwrap()
+            .max_spend_vbytes()
+            .is_err());
+    }
+
+    #[test]
+    fn check_network() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let (mixed, _) = Descriptor::parse_descriptor(&secp, "wsh(multi(1,xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*,tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc/0/*,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))").unwrap();
+
+        let wrong = mixed.check_network(Network::Bitcoin).unwrap_err();
+        assert_eq!(wrong.len(), 1);
+        assert!(wrong[0].to_string().starts_with("tpub"));
+        let wrong = mixed.check_network(Network::Testnet).unwrap_err();
+        assert_eq!(wrong.len(), 1);
+        assert!(wrong[0].to_string().starts_with("xpub"));
+
+        let single = Descriptor::<DescriptorPublicKey>::from_str(
+            "wpkh(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)",
+        )
+        .unwrap();
+        assert_eq!(single.check_network(Network::Bitcoin), Ok(()));
+        assert_eq!(single.check_network(Network::Regtest), Ok(()));
+    }
+
+    #[test]
+    fn spk_range_iter() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = Descriptor::<DescriptorPublicKey>::from_str("sh(wsh(multi(1,xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)))").unwrap();
+
+        let iter = desc
+            .spk_range_iter(&secp, DerivationRange::new(5..10).unwrap())
+            .unwrap();
+        assert_eq!(iter.len(), 5);
+        let spks: Vec<_> = iter.collect();
+        let expected: Vec<_> = desc
+            .derive_batch(&secp, DerivationRange::new(5..10).unwrap())
+            .unwrap()
+            .iter()
+            .map(|d| d.script_pubkey())
+            .collect();
+        assert_eq!(
+            spks.iter().map(|(i, _)| i.get()).collect::<Vec<_>>(),
+            vec![5, 6, 7, 8, 9]
+        );
+        assert_eq!(
+            spks.into_iter().map(|(_, spk)| spk).collect::<Vec<_>>(),
+            expected
+        );
+
+        let hardened = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*')").unwrap();
+        assert!(hardened
+            .spk_range_iter(&secp, DerivationRange::new(0..10).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn replace_key() {
+        let xpub_a = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
+        let xpub_b = "xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0/*";
+        let single = "03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8";
+        let old = DescriptorPublicKey::from_str(xpub_a).unwrap();
+        let new = DescriptorPublicKey::from_str(xpub_b).unwrap();
+
+        let mut tr = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "tr({},{{pk({}),and_v(v:pk({}),older(10))}})",
+            xpub_a, single, xpub_a
+        ))
+        .unwrap();
+        tr.replace_key(&old, new.clone()).unwrap();
+        assert_eq!(
+            tr.to_string(),
+            Descriptor::<DescriptorPublicKey>::from_str(&format!(
+                "tr({},{{pk({}),and_v(v:pk({}),older(10))}})",
+                xpub_b, single, xpub_b
+            ))
+            .unwrap()
+            .to_string()
+        );
+
+        // The key must be present, and its replacement must not be
+        assert!(tr.replace_key(&old, new.clone()).is_err());
+        let single = DescriptorPublicKey::from_str(single).unwrap();
+        let before = tr.clone();
+        assert_eq!(
+            tr.replace_key(&single, new),
+            Err(Error::AnalysisError(
+                miniscript::analyzable::AnalysisError::RepeatedPubkeys
+            ))
+        );
+        assert_eq!(tr, before);
+
+        // Uncompressed keys are rejected in segwit contexts
+        let uncompressed = DescriptorPublicKey::from_str("0414fc03b8df87cd7b872996810db8458d61da8448e531569c8517b469a119d267be5645686309c6e6736dbd93940707cc9143d3cf29f1b877ff340e2cb2d259cf").unwrap();
+        let mut wsh =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("wsh(pk({}))", single)).unwrap();
+        assert!(wsh.replace_key(&single, uncompressed.clone()).is_err());
+        let mut pkh =
+            Descriptor::<DescriptorPublicKey>::from_str(&format!("pkh({})", single)).unwrap();
+        assert!(pkh.replace_key(&single, uncompressed).is_ok());
+    }
+
+    #[test]
+    fn canonical_string() {
+        let parse = |desc: &str| Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+
+        // Hex case, hardened markers and missing checksums are normalized
+        let lower = parse("wpkh([d34db33f/84'/0'/0']03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)");
+        let upper = parse("wpkh([D34DB33F/84h/0h/0h]03F28773C2D975288BC7D1D205C3748651B075FBC6610E58CDDEEDDF8F19405AA8)");
+        assert!(lower.eq_canonical(&upper));
+        assert_eq!(lower.canonical_string(), lower.to_string());
+        assert!(lower.canonical_string().contains('#'));
+
+        // The order of the keys of sortedmulti does not matter, unlike multi
+        let xpub_a = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*";
+        let xpub_b = "xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0/*";
+        for template in &["wsh(sortedmulti(1,{},{}))", "sh(wsh(sortedmulti(1,{},{})))"] {
+            let ab = parse(&template.replacen("{}", xpub_a, 1).replacen("{}", xpub_b, 1));
+            let ba = parse(&template.replacen("{}", xpub_b, 1).replacen("{}", xpub_a, 1));
+            assert_ne!(ab.to_string(), ba.to_string());
+            assert!(ab.eq_canonical(&ba));
+        }
+        let ab = parse(&format!("wsh(multi(1,{},{}))", xpub_a, xpub_b));
+        let ba = parse(&format!("wsh(multi(1,{},{}))", xpub_b, xpub_a));
+        assert!(!ab.eq_canonical(&ba));
+
+        // Same for sortedmulti_a, wherever it appears in the taproot tree
+        let tr = |a: &str, b: &str| {
+            parse(&format!(
+                "tr({},{{pk({}),and_v(v:sortedmulti_a(1,{},{}),older(10))}})",
+                xpub_a, xpub_b, a, b
+            ))
+        };
+        let (ab, ba) = (tr(xpub_a, xpub_b), tr(xpub_b, xpub_a));
+        assert_ne!(ab.to_string(), ba.to_string());
+        assert!(ab.eq_canonical(&ba));
+        assert_eq!(
+            parse(&ab.canonical_string()).canonical_string(),
+            ab.canonical_string()
+        );
+        let multi_a = |a: &str, b: &str| parse(&format!("tr({},multi_a(1,{},{}))", xpub_a, a, b));
+        assert!(!multi_a(xpub_a, xpub_b).eq_canonical(&multi_a(xpub_b, xpub_a)));
+    }
+
+    #[test]
+    fn iter_miniscripts() {
+        let count = |desc: &str| {
+            Descriptor::<String>::from_str(desc)
+                .unwrap()
+                .iter_miniscripts()
+                .count()
+        };
+        assert_eq!(count("pkh(A)"), 0);
+        assert_eq!(count("wsh(sortedmulti(1,A,B))"), 0);
+        assert_eq!(count("tr(A)"), 0);
+        assert_eq!(count("sh(pk(A))"), 1);
+        assert_eq!(count("sh(wsh(pk(A)))"), 1);
+        assert_eq!(count("tr(A,{pk(B),{pk(C),pk(D)}})"), 3);
+
+        let tr = Descriptor::<String>::from_str("tr(A,{pk(B),multi_a(1,C,D)})").unwrap();
+        let mut keys = vec![];
+        for ms in tr.iter_miniscripts() {
+            match ms {
+                DescriptorMiniscript::Tap(_) => {}
+                _ => unreachable!(),
+            }
+            assert!(ms.sanity_check().is_ok());
+            ms.for_each_key(|key| {
+                keys.push(key.as_key().clone());
+                true
+            });
+        }
+        assert_eq!(keys, vec!["B", "C", "D"]);
+
+        let wsh = Descriptor::<String>::from_str("wsh(and_v(v:pk(A),older(10)))").unwrap();
+        match wsh.iter_miniscripts().next() {
+            Some(DescriptorMiniscript::Segwitv0(ms)) => assert_eq!(ms.script_size(), 37),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn derive_batch() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        for desc in &[
+            "wsh(multi(2,xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*,xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0,03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8))",
+            "tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*,pk(xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/2/*))",
+            "pkh(03f28773c2d975288bc7d1d205c3748651b075fbc6610e58cddeeddf8f19405aa8)",
+        ] {
+            let descriptor = Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+            let batch = descriptor.derive_batch(&secp, DerivationRange::new(3..8).unwrap()).unwrap();
+            assert_eq!(batch.len(), 5);
+            for (index, derived) in (3..8).zip(batch) {
+                assert_eq!(
+                    derived,
+                    descriptor.derived_descriptor(&secp, DerivationIndex::new(index).unwrap()).unwrap()
+                );
+            }
+        }
+
+        let hardened = Descriptor::<DescriptorPublicKey>::from_str("wpkh(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/1/*')").unwrap();
+        assert_eq!(
+            hardened.derive_batch(&secp, DerivationRange::new(0..2).unwrap()),
+            Err(ConversionError::HardenedChild)
+        );
+    }
+
+    #[test]
+    fn max_satisfaction_weight_with_sig_sizes() {
+        use crate::miniscript::satisfy::SigSizeAssumptions;
+
+        let compact = SigSizeAssumptions::COMPACT;
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(
+            "wpkh(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        let max = desc.max_satisfaction_weight().unwrap();
+        assert_eq!(
+            desc.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+                .unwrap(),
+            max
+        );
+        assert_eq!(
+            desc.max_satisfaction_weight_with_sig_sizes(compact)
+                .unwrap(),
+            max - 1
+        );
+
+        // Scriptsig bytes weigh four times as much
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(
+            "sh(multi(2,02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443,\
+             0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07))",
+        )
+        .unwrap();
+        assert_eq!(
+            desc.max_satisfaction_weight_with_sig_sizes(compact)
+                .unwrap(),
+            desc.max_satisfaction_weight().unwrap() - 4 * 2
+        );
+
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(
+            "tr(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        assert_eq!(
+            desc.max_satisfaction_weight_with_sig_sizes(compact)
+                .unwrap(),
+            desc.max_satisfaction_weight().unwrap() - 1
+        );
+    }
+
+    #[test]
+    fn parse_with_key_parser() {
+        // Resolves the keys written as names
+        struct Aliases;
+        impl KeyParser<PublicKey, String> for Aliases {
+            fn pk(&mut self, s: &str) -> Result<PublicKey, String> {
+                let key = match s {
+                    "alice" => "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+                    "bob" => "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+                    _ => return Err(format!("unknown key alias {}", s)),
+                };
+                Ok(PublicKey::from_str(key).unwrap())
+            }
+
+            fn pkh(&mut self, s: &str) -> Result<hash160::Hash, String> {
+                self.pk(s).map(|pk| pk.to_pubkeyhash())
+            }
+
+            fn sha256(&mut self, s: &str) -> Result<sha256::Hash, String> {
+                sha256::Hash::from_str(s).map_err(|e| e.to_string())
+            }
+        }
+
+        let alice = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+        let bob = "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07";
+        for s in &[
+            "wsh(or_d(pk(alice),and_v(v:pk(bob),older(144))))",
+            "sh(sortedmulti(1,alice,bob))",
+            "tr(alice,{pk(bob),multi_a(1,alice,bob)})",
+        ] {
+            let desc = Descriptor::<PublicKey>::from_str_with(s, &mut Aliases).unwrap();
+            let resolved = s.replace("alice", alice).replace("bob", bob);
+            assert_eq!(desc, Descriptor::<PublicKey>::from_str(&resolved).unwrap());
+        }
+
+        let pol = crate::policy::Concrete::<PublicKey>::from_str_with(
+            "or(pk(alice),pk(bob))",
+            &mut Aliases,
+        );
+        let resolved = format!("or(pk({}),pk({}))", alice, bob);
+        assert_eq!(
+            pol.unwrap(),
+            crate::policy::Concrete::from_str(&resolved).unwrap()
+        );
+
+        assert!(Descriptor::<PublicKey>::from_str_with("wsh(pk(carol))", &mut Aliases).is_err());
+        assert!(
+            Descriptor::<PublicKey>::from_str_with("tr(alice,pk(carol))", &mut Aliases).is_err()
+        );
+        // Keys are only written as aliases
+        assert!(
+            Descriptor::<PublicKey>::from_str_with(&format!("wpkh({})", alice), &mut Aliases)
+                .is_err()
         );
     }
 }
@@ -0,0 +1,203 @@
+//! Raw Taproot Descriptor
+//!
+//! Implementation of the `rawtr(KEY)` descriptor, describing a taproot output
+//! by its (already tweaked) output key when the internal key and script tree
+//! are unknown.
+
+use core::fmt;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::schnorr::TweakedPublicKey;
+use bitcoin::util::taproot::TAPROOT_ANNEX_PREFIX;
+use bitcoin::{self, Address, Network, Script};
+
+use super::checksum::{desc_checksum, verify_checksum_located};
+use crate::expression::{self, FromTree};
+use crate::miniscript::satisfy::{SatisfactionError, SigSizeAssumptions};
+use crate::policy::{semantic, Liftable};
+use crate::prelude::*;
+use crate::util::varint_len;
+use crate::{
+    Error, ForEach, ForEachKey, FromStrParser, KeyParser, MiniscriptKey, Satisfier, ToPublicKey,
+    TranslatePk, Translator,
+};
+
+/// A taproot output given by its output key, with unknown internal key and script tree
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RawTr<Pk: MiniscriptKey> {
+    /// The tweaked output key
+    output_key: Pk,
+}
+
+impl<Pk: MiniscriptKey> RawTr<Pk> {
+    /// Create a new [`RawTr`] descriptor from the output key
+    pub fn new(output_key: Pk) -> Self {
+        Self { output_key }
+    }
+
+    /// Obtain the output key of the [`RawTr`] descriptor
+    pub fn output_key(&self) -> &Pk {
+        &self.output_key
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, which can only be a key path spend.
+    ///
+    /// Assumes the signature is 65 bytes, including the sighash suffix. Includes
+    /// the weight of the VarInts encoding the scriptSig and witness stack length.
+    pub fn max_satisfaction_weight(&self) -> usize {
+        self.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`RawTr::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(&self, sig_sizes: SigSizeAssumptions) -> usize {
+        // scriptSig len byte, witness count and the signature with its length prefix
+        4 + 1 + 1 + sig_sizes.schnorr()
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, when an annex of `annex_len` bytes (including the `0x50`
+    /// prefix) is attached to the witness.
+    pub fn max_satisfaction_weight_with_annex(&self, annex_len: usize) -> usize {
+        self.max_satisfaction_weight() + varint_len(annex_len) + annex_len
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> RawTr<Pk> {
+    /// Obtains the corresponding script pubkey for this descriptor.
+    pub fn script_pubkey(&self) -> Script {
+        bitcoin::blockdata::script::Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_slice(&self.output_key.to_x_only_pubkey().serialize())
+            .into_script()
+    }
+
+    /// Obtains the corresponding address for this descriptor.
+    pub fn address(&self, network: Network) -> Address {
+        let output_key =
+            TweakedPublicKey::dangerous_assume_tweaked(self.output_key.to_x_only_pubkey());
+        Address::p2tr_tweaked(output_key, network)
+    }
+
+    /// Returns a key path spend witness if the `satisfier` has a key spend signature.
+    pub fn get_satisfaction<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        let annex = satisfier.lookup_tap_annex();
+        if let Some(ref annex) = annex {
+            if annex.first() != Some(&TAPROOT_ANNEX_PREFIX) {
+                return Err(Error::InvalidTapAnnex);
+            }
+        }
+        let sig = satisfier.lookup_tap_key_spend_sig().ok_or_else(|| {
+            Error::Unsatisfiable(vec![SatisfactionError::MissingSignature {
+                path: vec![],
+                key: self.output_key.to_string(),
+            }])
+        })?;
+        let mut wit = vec![sig.to_vec()];
+        if let Some(annex) = annex {
+            wit.push(annex);
+        }
+        Ok((wit, Script::new()))
+    }
+
+    /// Same as [`RawTr::get_satisfaction`], as key path spends cannot be malleated.
+    pub fn get_satisfaction_mall<S>(&self, satisfier: S) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        S: Satisfier<Pk>,
+    {
+        self.get_satisfaction(satisfier)
+    }
+}
+
+impl<Pk: MiniscriptKey> fmt::Debug for RawTr<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rawtr({:?})", self.output_key)
+    }
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for RawTr<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let desc = format!("rawtr({})", self.output_key);
+        let checksum = desc_checksum(&desc).map_err(|_| fmt::Error)?;
+        write!(f, "{}#{}", &desc, &checksum)
+    }
+}
+
+impl<Pk: MiniscriptKey> Liftable<Pk> for RawTr<Pk> {
+    fn lift(&self) -> Result<semantic::Policy<Pk>, Error> {
+        // Whoever knows the tweaked secret key can spend
+        Ok(semantic::Policy::KeyHash(self.output_key.to_pubkeyhash()))
+    }
+}
+
+impl_from_tree!(
+    RawTr<Pk>,
+    fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> RawTr<Pk> {
+    /// Parses an expression tree into a `rawtr` descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        if top.name == "rawtr" && top.args.len() == 1 {
+            Ok(RawTr::new(expression::terminal(&top.args[0], |pk| {
+                parser.pk(pk)
+            })?))
+        } else {
+            Err(Error::Unexpected(format!(
+                "{}({} args) while parsing rawtr descriptor",
+                top.name,
+                top.args.len(),
+            )))
+        }
+    }
+}
+
+impl_from_str!(
+    RawTr<Pk>,
+    type Err = Error;,
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let desc_str = verify_checksum_located(s)?;
+        let top = expression::Tree::parse(desc_str)?;
+        Self::from_tree(&top)
+    }
+);
+
+impl<Pk: MiniscriptKey> ForEachKey<Pk> for RawTr<Pk> {
+    fn for_each_key<'a, F: FnMut(ForEach<'a, Pk>) -> bool>(&'a self, mut pred: F) -> bool
+    where
+        Pk: 'a,
+        Pk::Hash: 'a,
+    {
+        pred(ForEach::Key(&self.output_key))
+    }
+}
+
+impl<P, Q> TranslatePk<P, Q> for RawTr<P>
+where
+    P: MiniscriptKey,
+    Q: MiniscriptKey,
+{
+    type Output = RawTr<Q>;
+
+    fn translate_pk<T, E>(&self, t: &mut T) -> Result<Self::Output, E>
+    where
+        T: Translator<P, Q, E>,
+    {
+        Ok(RawTr::new(t.pk(&self.output_key)?))
+    }
+}
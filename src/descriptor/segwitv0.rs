@@ -20,16 +20,17 @@ use core::fmt;
 
 use bitcoin::{self, Address, Network, Script};
 
-use super::checksum::{desc_checksum, verify_checksum};
+use super::checksum::{desc_checksum, verify_checksum_located};
 use super::SortedMultiVec;
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::{ScriptContext, ScriptContextError};
+use crate::miniscript::satisfy::SigSizeAssumptions;
 use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
 use crate::util::varint_len;
 use crate::{
-    Error, ForEach, ForEachKey, Miniscript, MiniscriptKey, Satisfier, Segwitv0, ToPublicKey,
-    TranslatePk, Translator,
+    Error, ForEach, ForEachKey, FromStrParser, KeyParser, Miniscript, MiniscriptKey, Satisfier,
+    Segwitv0, ToPublicKey, TranslatePk, Translator,
 };
 /// A Segwitv0 wsh descriptor
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
@@ -94,16 +95,26 @@ impl<Pk: MiniscriptKey> Wsh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        self.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Wsh::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(
+        &self,
+        sig_sizes: SigSizeAssumptions,
+    ) -> Result<usize, Error> {
         let (script_size, max_sat_elems, max_sat_size) = match self.inner {
             WshInner::SortedMulti(ref smv) => (
                 smv.script_size(),
                 smv.max_satisfaction_witness_elements(),
-                smv.max_satisfaction_size(),
+                smv.max_satisfaction_size_with_sig_sizes(sig_sizes),
             ),
             WshInner::Ms(ref ms) => (
                 ms.script_size(),
                 ms.max_satisfaction_witness_elements()?,
-                ms.max_satisfaction_size()?,
+                ms.max_satisfaction_size_with_sig_sizes(sig_sizes)?,
             ),
         };
         Ok(4 +  // scriptSig length byte
@@ -196,14 +207,31 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Wsh<Pk> {
 impl_from_tree!(
     Wsh<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Wsh<Pk> {
+    /// Parses an expression tree into a `wsh` descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         if top.name == "wsh" && top.args.len() == 1 {
             let top = &top.args[0];
             if top.name == "sortedmulti" {
                 return Ok(Wsh {
-                    inner: WshInner::SortedMulti(SortedMultiVec::from_tree(top)?),
+                    inner: WshInner::SortedMulti(SortedMultiVec::from_tree_with(top, |pk| {
+                        parser.pk(pk)
+                    })?),
                 });
             }
-            let sub = Miniscript::from_tree(top)?;
+            let sub = Miniscript::from_tree_with(top, parser)?;
             Segwitv0::top_level_checks(&sub)?;
             Ok(Wsh {
                 inner: WshInner::Ms(sub),
@@ -216,7 +244,7 @@ impl_from_tree!(
             )))
         }
     }
-);
+}
 
 impl<Pk: MiniscriptKey> fmt::Debug for Wsh<Pk> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -238,9 +266,9 @@ impl<Pk: MiniscriptKey> fmt::Display for Wsh<Pk> {
 impl_from_str!(
     Wsh<Pk>,
     type Err = Error;,
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let desc_str = verify_checksum(s)?;
-        let top = expression::Tree::from_str(desc_str)?;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let desc_str = verify_checksum_located(s)?;
+        let top = expression::Tree::parse(desc_str)?;
         Wsh::<Pk>::from_tree(&top)
     }
 );
@@ -330,7 +358,14 @@ impl<Pk: MiniscriptKey> Wpkh<Pk> {
     /// sighash suffix. Includes the weight of the VarInts encoding the
     /// scriptSig and witness stack length.
     pub fn max_satisfaction_weight(&self) -> usize {
-        4 + 1 + 73 + Segwitv0::pk_len(&self.pk)
+        self.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Wpkh::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(&self, sig_sizes: SigSizeAssumptions) -> usize {
+        4 + 1 + (sig_sizes.ecdsa() + 1) + Segwitv0::pk_len(&self.pk)
     }
 }
 
@@ -414,9 +449,24 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Wpkh<Pk> {
 impl_from_tree!(
     Wpkh<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Wpkh<Pk> {
+    /// Parses an expression tree into a `wpkh` descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         if top.name == "wpkh" && top.args.len() == 1 {
             Ok(Wpkh::new(expression::terminal(&top.args[0], |pk| {
-                Pk::from_str(pk)
+                parser.pk(pk)
             })?)?)
         } else {
             Err(Error::Unexpected(format!(
@@ -426,14 +476,14 @@ impl_from_tree!(
             )))
         }
     }
-);
+}
 
 impl_from_str!(
     Wpkh<Pk>,
     type Err = Error;,
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let desc_str = verify_checksum(s)?;
-        let top = expression::Tree::from_str(desc_str)?;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let desc_str = verify_checksum_located(s)?;
+        let top = expression::Tree::parse(desc_str)?;
         Self::from_tree(&top)
     }
 );
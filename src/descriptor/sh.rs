@@ -23,16 +23,17 @@ use core::fmt;
 use bitcoin::blockdata::script;
 use bitcoin::{Address, Network, Script};
 
-use super::checksum::{desc_checksum, verify_checksum};
+use super::checksum::{desc_checksum, verify_checksum_located};
 use super::{SortedMultiVec, Wpkh, Wsh};
 use crate::expression::{self, FromTree};
 use crate::miniscript::context::ScriptContext;
+use crate::miniscript::satisfy::SigSizeAssumptions;
 use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
 use crate::util::{varint_len, witness_to_scriptsig};
 use crate::{
-    push_opcode_size, Error, ForEach, ForEachKey, Legacy, Miniscript, MiniscriptKey, Satisfier,
-    Segwitv0, ToPublicKey, TranslatePk, Translator,
+    push_opcode_size, Error, ForEach, ForEachKey, FromStrParser, KeyParser, Legacy, Miniscript,
+    MiniscriptKey, Satisfier, Segwitv0, ToPublicKey, TranslatePk, Translator,
 };
 
 /// A Legacy p2sh Descriptor
@@ -93,14 +94,31 @@ impl<Pk: MiniscriptKey> fmt::Display for Sh<Pk> {
 impl_from_tree!(
     Sh<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Sh<Pk> {
+    /// Parses an expression tree into an `sh` descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         if top.name == "sh" && top.args.len() == 1 {
             let top = &top.args[0];
             let inner = match top.name {
-                "wsh" => ShInner::Wsh(Wsh::from_tree(top)?),
-                "wpkh" => ShInner::Wpkh(Wpkh::from_tree(top)?),
-                "sortedmulti" => ShInner::SortedMulti(SortedMultiVec::from_tree(top)?),
+                "wsh" => ShInner::Wsh(Wsh::from_tree_with(top, parser)?),
+                "wpkh" => ShInner::Wpkh(Wpkh::from_tree_with(top, parser)?),
+                "sortedmulti" => {
+                    ShInner::SortedMulti(SortedMultiVec::from_tree_with(top, |pk| parser.pk(pk))?)
+                }
                 _ => {
-                    let sub = Miniscript::from_tree(top)?;
+                    let sub = Miniscript::from_tree_with(top, parser)?;
                     Legacy::top_level_checks(&sub)?;
                     ShInner::Ms(sub)
                 }
@@ -114,14 +132,14 @@ impl_from_tree!(
             )))
         }
     }
-);
+}
 
 impl_from_str!(
     Sh<Pk>,
     type Err = Error;,
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let desc_str = verify_checksum(s)?;
-        let top = expression::Tree::from_str(desc_str)?;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let desc_str = verify_checksum_located(s)?;
+        let top = expression::Tree::parse(desc_str)?;
         Self::from_tree(&top)
     }
 );
@@ -215,21 +233,35 @@ impl<Pk: MiniscriptKey> Sh<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
+        self.max_satisfaction_weight_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Sh::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(
+        &self,
+        sig_sizes: SigSizeAssumptions,
+    ) -> Result<usize, Error> {
         Ok(match self.inner {
             // add weighted script sig, len byte stays the same
-            ShInner::Wsh(ref wsh) => 4 * 35 + wsh.max_satisfaction_weight()?,
+            ShInner::Wsh(ref wsh) => {
+                4 * 35 + wsh.max_satisfaction_weight_with_sig_sizes(sig_sizes)?
+            }
             ShInner::SortedMulti(ref smv) => {
                 let ss = smv.script_size();
                 let ps = push_opcode_size(ss);
-                let scriptsig_len = ps + ss + smv.max_satisfaction_size();
+                let scriptsig_len = ps + ss + smv.max_satisfaction_size_with_sig_sizes(sig_sizes);
                 4 * (varint_len(scriptsig_len) + scriptsig_len)
             }
             // add weighted script sig, len byte stays the same
-            ShInner::Wpkh(ref wpkh) => 4 * 23 + wpkh.max_satisfaction_weight(),
+            ShInner::Wpkh(ref wpkh) => {
+                4 * 23 + wpkh.max_satisfaction_weight_with_sig_sizes(sig_sizes)
+            }
             ShInner::Ms(ref ms) => {
                 let ss = ms.script_size();
                 let ps = push_opcode_size(ss);
-                let scriptsig_len = ps + ss + ms.max_satisfaction_size()?;
+                let scriptsig_len = ps + ss + ms.max_satisfaction_size_with_sig_sizes(sig_sizes)?;
                 4 * (varint_len(scriptsig_len) + scriptsig_len)
             }
         })
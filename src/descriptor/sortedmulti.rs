@@ -25,6 +25,7 @@ use bitcoin::blockdata::script;
 use crate::miniscript::context::ScriptContext;
 use crate::miniscript::decode::Terminal;
 use crate::miniscript::limits::MAX_PUBKEYS_PER_MULTISIG;
+use crate::miniscript::satisfy::SigSizeAssumptions;
 use crate::prelude::*;
 use crate::{
     errstr, expression, miniscript, policy, script_num_size, Error, ForEach, ForEachKey,
@@ -73,6 +74,19 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> SortedMultiVec<Pk, Ctx> {
     where
         Pk: FromStr,
         <Pk as FromStr>::Err: ToString,
+    {
+        Self::from_tree_with(tree, Pk::from_str)
+    }
+
+    /// Parse an expression tree into a SortedMultiVec, with the keys parsed
+    /// by `parse_pk`
+    pub(crate) fn from_tree_with<F, E>(
+        tree: &expression::Tree,
+        mut parse_pk: F,
+    ) -> Result<Self, Error>
+    where
+        F: FnMut(&str) -> Result<Pk, E>,
+        E: ToString,
     {
         if tree.args.is_empty() {
             return Err(errstr("no arguments given for sortedmulti"));
@@ -85,7 +99,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> SortedMultiVec<Pk, Ctx> {
         }
         let pks: Result<Vec<Pk>, _> = tree.args[1..]
             .iter()
-            .map(|sub| expression::terminal(sub, Pk::from_str))
+            .map(|sub| expression::terminal(sub, &mut parse_pk))
             .collect();
 
         pks.map(|pks| SortedMultiVec::new(k as usize, pks))?
@@ -207,7 +221,14 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> SortedMultiVec<Pk, Ctx> {
     /// length prefix (segwit) or push opcode (pre-segwit) and sighash
     /// postfix.
     pub fn max_satisfaction_size(&self) -> usize {
-        1 + 73 * self.k
+        self.max_satisfaction_size_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+    }
+
+    /// Maximum size, in bytes, of a satisfying witness, like
+    /// [`SortedMultiVec::max_satisfaction_size`] but assuming signatures of
+    /// the given sizes.
+    pub fn max_satisfaction_size_with_sig_sizes(&self, sig_sizes: SigSizeAssumptions) -> usize {
+        1 + (sig_sizes.ecdsa() + 1) * self.k
     }
 }
 
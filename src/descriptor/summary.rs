@@ -0,0 +1,254 @@
+//! Descriptor Summary
+//!
+//! A structured overview of a descriptor, for display in wallets and
+//! backup documents.
+
+use core::fmt;
+
+use bitcoin::{Address, AddressType, Network};
+
+use super::tr::{key_spend_weight, leaf_spend_weight};
+use super::{Descriptor, DescriptorType};
+use crate::miniscript::satisfy::SigSizeAssumptions;
+use crate::policy::Liftable;
+use crate::prelude::*;
+use crate::{ForEachKey, MiniscriptKey};
+
+/// Role of a key in a descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyRole {
+    /// The key of a `pkh`, `wpkh` or `sh(wpkh)` descriptor
+    Single,
+    /// A key in the script of a `bare`, `sh` or `wsh` descriptor
+    Script,
+    /// The internal key of a `tr` descriptor
+    Internal,
+    /// The output key of a `rawtr` descriptor
+    Output,
+    /// A key in the tapleaf with the given index, in the order of
+    /// [`super::Tr::iter_scripts`]
+    TapLeaf(usize),
+}
+
+/// A way to spend a descriptor's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpendPath {
+    /// A signature for a single key, without revealing a script
+    Key,
+    /// A satisfaction of the script of a `bare`, `sh` or `wsh` descriptor
+    Script,
+    /// A satisfaction of the tapleaf with the given index
+    TapLeaf(usize),
+}
+
+/// Overview of a descriptor, see [`Descriptor::summary`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorSummary<Pk: MiniscriptKey> {
+    /// Type of the descriptor
+    pub desc_type: DescriptorType,
+    /// Type of the descriptor's addresses, `None` if it has none
+    pub address_type: Option<AddressType>,
+    /// Every key with its role, once per occurrence
+    pub keys: Vec<(Pk, KeyRole)>,
+    /// Absolute timelocks of all spend paths, sorted and deduplicated
+    pub absolute_timelocks: Vec<u32>,
+    /// Relative timelocks of all spend paths, sorted and deduplicated
+    pub relative_timelocks: Vec<u32>,
+    /// Every spend path, with an upper bound on the weight of its
+    /// satisfaction (`None` if it cannot be satisfied)
+    pub spend_paths: Vec<(SpendPath, Option<usize>)>,
+}
+
+impl<Pk: MiniscriptKey<Hash = Pk>> Descriptor<Pk> {
+    /// Structured overview of the descriptor: its keys and their role, its
+    /// timelocks, and the weight of each of its spend paths
+    ///
+    /// The weights assume worst-case signature sizes, as in
+    /// [`Descriptor::max_satisfaction_weight`]. `raw()` and `addr()`
+    /// descriptors have neither keys nor spend paths.
+    pub fn summary(&self) -> DescriptorSummary<Pk> {
+        let mut keys = vec![];
+        let mut spend_paths = vec![];
+        match *self {
+            Descriptor::Bare(..)
+            | Descriptor::Pkh(..)
+            | Descriptor::Wpkh(..)
+            | Descriptor::Sh(..)
+            | Descriptor::Wsh(..) => {
+                let (role, path) = match self.desc_type() {
+                    DescriptorType::Pkh | DescriptorType::Wpkh | DescriptorType::ShWpkh => {
+                        (KeyRole::Single, SpendPath::Key)
+                    }
+                    _ => (KeyRole::Script, SpendPath::Script),
+                };
+                push_keys(&mut keys, self, role);
+                spend_paths.push((path, self.script_spend_weight()));
+            }
+            Descriptor::Tr(ref tr) => {
+                let sig_sizes = SigSizeAssumptions::WORST_CASE;
+                keys.push((tr.internal_key().clone(), KeyRole::Internal));
+                spend_paths.push((SpendPath::Key, Some(key_spend_weight(sig_sizes))));
+                for (index, (depth, ms)) in tr.iter_scripts().enumerate() {
+                    push_keys(&mut keys, ms, KeyRole::TapLeaf(index));
+                    spend_paths.push((
+                        SpendPath::TapLeaf(index),
+                        leaf_spend_weight(depth, ms, sig_sizes),
+                    ));
+                }
+            }
+            Descriptor::RawTr(ref rawtr) => {
+                keys.push((rawtr.output_key().clone(), KeyRole::Output));
+                spend_paths.push((
+                    SpendPath::Key,
+                    Some(key_spend_weight(SigSizeAssumptions::WORST_CASE)),
+                ));
+            }
+            Descriptor::Raw(..) | Descriptor::Addr(..) => {}
+        }
+
+        let (absolute_timelocks, relative_timelocks) = match self.lift() {
+            Ok(policy) => (policy.absolute_timelocks(), policy.relative_timelocks()),
+            Err(..) => (vec![], vec![]),
+        };
+
+        DescriptorSummary {
+            desc_type: self.desc_type(),
+            address_type: self.address_type(),
+            keys,
+            absolute_timelocks,
+            relative_timelocks,
+            spend_paths,
+        }
+    }
+
+    // Like `max_satisfaction_weight`, without requiring `ToPublicKey`
+    fn script_spend_weight(&self) -> Option<usize> {
+        match *self {
+            Descriptor::Bare(ref bare) => bare.max_satisfaction_weight().ok(),
+            Descriptor::Pkh(ref pkh) => Some(pkh.max_satisfaction_weight()),
+            Descriptor::Wpkh(ref wpkh) => Some(wpkh.max_satisfaction_weight()),
+            Descriptor::Sh(ref sh) => sh.max_satisfaction_weight().ok(),
+            Descriptor::Wsh(ref wsh) => wsh.max_satisfaction_weight().ok(),
+            Descriptor::Tr(..)
+            | Descriptor::RawTr(..)
+            | Descriptor::Raw(..)
+            | Descriptor::Addr(..) => None,
+        }
+    }
+
+    fn address_type(&self) -> Option<AddressType> {
+        match *self {
+            Descriptor::Bare(..) => None,
+            Descriptor::Pkh(..) => Some(AddressType::P2pkh),
+            Descriptor::Wpkh(..) => Some(AddressType::P2wpkh),
+            Descriptor::Sh(..) => Some(AddressType::P2sh),
+            Descriptor::Wsh(..) => Some(AddressType::P2wsh),
+            Descriptor::Tr(..) | Descriptor::RawTr(..) => Some(AddressType::P2tr),
+            // The network does not matter for the type
+            Descriptor::Raw(ref spk) => {
+                Address::from_script(spk, Network::Bitcoin).and_then(|addr| addr.address_type())
+            }
+            Descriptor::Addr(ref addr) => addr.address_type(),
+        }
+    }
+}
+
+fn push_keys<Pk, T>(keys: &mut Vec<(Pk, KeyRole)>, item: &T, role: KeyRole)
+where
+    Pk: MiniscriptKey<Hash = Pk>,
+    T: ForEachKey<Pk>,
+{
+    item.for_each_key(|key| {
+        keys.push((key.as_key().clone(), role));
+        true
+    });
+}
+
+impl<Pk: MiniscriptKey> fmt::Display for DescriptorSummary<Pk> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "type: {:?}", self.desc_type)?;
+        if let Some(address_type) = self.address_type {
+            write!(f, " ({})", address_type)?;
+        }
+        writeln!(f)?;
+        for (key, role) in &self.keys {
+            writeln!(f, "key: {} ({:?})", key, role)?;
+        }
+        for n in &self.absolute_timelocks {
+            writeln!(f, "absolute timelock: {}", n)?;
+        }
+        for n in &self.relative_timelocks {
+            writeln!(f, "relative timelock: {}", n)?;
+        }
+        for (path, weight) in &self.spend_paths {
+            match *weight {
+                Some(weight) => writeln!(f, "spend path: {:?}, at most {} WU", path, weight)?,
+                None => writeln!(f, "spend path: {:?}, unsatisfiable", path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let wsh = Descriptor::<String>::from_str(
+            "wsh(or_d(pk(A),and_v(v:pk(B),and_v(v:older(144),after(500000)))))",
+        )
+        .unwrap();
+        let summary = wsh.summary();
+        assert_eq!(summary.address_type, Some(AddressType::P2wsh));
+        assert_eq!(
+            summary.keys,
+            vec![
+                ("A".to_string(), KeyRole::Script),
+                ("B".to_string(), KeyRole::Script)
+            ]
+        );
+        assert_eq!(summary.absolute_timelocks, vec![500000]);
+        assert_eq!(summary.relative_timelocks, vec![144]);
+        let weight = match wsh {
+            Descriptor::Wsh(ref wsh) => wsh.max_satisfaction_weight().ok(),
+            _ => unreachable!(),
+        };
+        assert_eq!(summary.spend_paths, vec![(SpendPath::Script, weight)]);
+
+        let tr = Descriptor::<String>::from_str("tr(A,{pk(B),and_v(v:pk(C),older(10))})").unwrap();
+        let summary = tr.summary();
+        assert_eq!(summary.address_type, Some(AddressType::P2tr));
+        assert_eq!(
+            summary.keys,
+            vec![
+                ("A".to_string(), KeyRole::Internal),
+                ("B".to_string(), KeyRole::TapLeaf(0)),
+                ("C".to_string(), KeyRole::TapLeaf(1)),
+            ]
+        );
+        assert_eq!(summary.relative_timelocks, vec![10]);
+        let paths: Vec<_> = summary.spend_paths.iter().map(|&(path, _)| path).collect();
+        assert_eq!(
+            paths,
+            vec![SpendPath::Key, SpendPath::TapLeaf(0), SpendPath::TapLeaf(1)]
+        );
+        // The heaviest path is the whole descriptor's maximum
+        let max = summary.spend_paths.iter().filter_map(|&(_, w)| w).max();
+        match tr {
+            Descriptor::Tr(ref tr) => assert_eq!(max, tr.max_satisfaction_weight().ok()),
+            _ => unreachable!(),
+        }
+        assert!(summary.to_string().contains("key: C (TapLeaf(1))"));
+
+        let wpkh = Descriptor::<String>::from_str("sh(wpkh(A))")
+            .unwrap()
+            .summary();
+        assert_eq!(wpkh.keys, vec![("A".to_string(), KeyRole::Single)]);
+        assert_eq!(wpkh.spend_paths.len(), 1);
+        assert_eq!(wpkh.spend_paths[0].0, SpendPath::Key);
+    }
+}
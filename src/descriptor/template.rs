@@ -0,0 +1,166 @@
+//! Standard Descriptor Templates
+//!
+//! Constructors for the canonical single-signature descriptors described by
+//! BIP44 (`pkh`), BIP49 (`sh(wpkh)`), BIP84 (`wpkh`) and BIP86 (`tr`), built
+//! from an account-level extended public key.
+
+use bitcoin::util::bip32;
+use bitcoin::Network;
+
+use super::{Descriptor, DescriptorPublicKey, DescriptorXKey, Wildcard};
+use crate::Error;
+
+/// Which keychain of an account a template descriptor derives from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeychainKind {
+    /// The receiving keychain, derived at child `0`
+    External,
+    /// The change keychain, derived at child `1`
+    Internal,
+}
+
+impl KeychainKind {
+    /// The unhardened child index of this keychain below the account key
+    pub fn index(self) -> u32 {
+        match self {
+            KeychainKind::External => 0,
+            KeychainKind::Internal => 1,
+        }
+    }
+}
+
+impl Descriptor<DescriptorPublicKey> {
+    /// Create a BIP44 `pkh([fp/44'/coin'/account']xpub/keychain/*)` descriptor
+    ///
+    /// `xpub` must be the account-level key (depth 3) and `fingerprint` that of the master key.
+    /// The coin type is `0'` for mainnet keys and `1'` otherwise.
+    pub fn new_bip44(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        keychain: KeychainKind,
+    ) -> Result<Self, Error> {
+        let key = template_key(44, xpub, fingerprint, keychain)?;
+        Ok(Descriptor::new_pkh(key))
+    }
+
+    /// Create a BIP49 `sh(wpkh([fp/49'/coin'/account']xpub/keychain/*))` descriptor
+    ///
+    /// See [`Descriptor::new_bip44`] for the requirements on `xpub`.
+    pub fn new_bip49(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        keychain: KeychainKind,
+    ) -> Result<Self, Error> {
+        let key = template_key(49, xpub, fingerprint, keychain)?;
+        Descriptor::new_sh_wpkh(key)
+    }
+
+    /// Create a BIP84 `wpkh([fp/84'/coin'/account']xpub/keychain/*)` descriptor
+    ///
+    /// See [`Descriptor::new_bip44`] for the requirements on `xpub`.
+    pub fn new_bip84(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        keychain: KeychainKind,
+    ) -> Result<Self, Error> {
+        let key = template_key(84, xpub, fingerprint, keychain)?;
+        Descriptor::new_wpkh(key)
+    }
+
+    /// Create a BIP86 `tr([fp/86'/coin'/account']xpub/keychain/*)` key-path only descriptor
+    ///
+    /// See [`Descriptor::new_bip44`] for the requirements on `xpub`.
+    pub fn new_bip86(
+        xpub: bip32::ExtendedPubKey,
+        fingerprint: bip32::Fingerprint,
+        keychain: KeychainKind,
+    ) -> Result<Self, Error> {
+        let key = template_key(86, xpub, fingerprint, keychain)?;
+        Descriptor::new_tr(key, None)
+    }
+}
+
+/// Build the ranged key `[fp/purpose'/coin'/account']xpub/keychain/*`
+fn template_key(
+    purpose: u32,
+    xpub: bip32::ExtendedPubKey,
+    fingerprint: bip32::Fingerprint,
+    keychain: KeychainKind,
+) -> Result<DescriptorPublicKey, Error> {
+    if xpub.depth != 3 || !xpub.child_number.is_hardened() {
+        return Err(Error::BadDescriptor(format!(
+            "BIP{} template expects a hardened account-level xpub (depth 3), got depth {} child {}",
+            purpose, xpub.depth, xpub.child_number
+        )));
+    }
+    let coin = match xpub.network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    let origin_path = vec![
+        bip32::ChildNumber::from_hardened_idx(purpose).expect("purpose below 2^31"),
+        bip32::ChildNumber::from_hardened_idx(coin).expect("coin type below 2^31"),
+        xpub.child_number,
+    ];
+    let keychain =
+        bip32::ChildNumber::from_normal_idx(keychain.index()).expect("keychain below 2^31");
+
+    Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+        origin: Some((fingerprint, origin_path.into())),
+        xkey: xpub,
+        derivation_path: vec![keychain].into(),
+        wildcard: Wildcard::Unhardened,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::secp256k1;
+
+    use super::*;
+    use crate::descriptor::DerivationIndex;
+    use crate::prelude::*;
+
+    #[test]
+    fn standard_templates() {
+        // BIP86 test vector for the "abandon ... about" mnemonic
+        let xpub = bip32::ExtendedPubKey::from_str("xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ").unwrap();
+        let fingerprint = bip32::Fingerprint::from_str("73c5da0a").unwrap();
+
+        let bip86 = Descriptor::new_bip86(xpub, fingerprint, KeychainKind::External).unwrap();
+        assert_eq!(
+            bip86.to_string(),
+            Descriptor::<DescriptorPublicKey>::from_str("tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/*)")
+                .unwrap()
+                .to_string()
+        );
+        let secp = secp256k1::Secp256k1::verification_only();
+        assert_eq!(
+            bip86
+                .derived_descriptor(&secp, DerivationIndex::new(0).unwrap())
+                .unwrap()
+                .address(Network::Bitcoin)
+                .unwrap()
+                .to_string(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+
+        let change = Descriptor::new_bip84(xpub, fingerprint, KeychainKind::Internal).unwrap();
+        assert!(change
+            .to_string()
+            .starts_with("wpkh([73c5da0a/84'/0'/0']xpub"));
+        assert!(change.to_string().contains("/1/*)#"));
+        let bip49 = Descriptor::new_bip49(xpub, fingerprint, KeychainKind::External).unwrap();
+        assert!(bip49
+            .to_string()
+            .starts_with("sh(wpkh([73c5da0a/49'/0'/0']"));
+        let bip44 = Descriptor::new_bip44(xpub, fingerprint, KeychainKind::External).unwrap();
+        assert!(bip44.to_string().starts_with("pkh([73c5da0a/44'/0'/0']"));
+
+        // Master keys are rejected
+        let master = bip32::ExtendedPubKey::from_str("xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8").unwrap();
+        assert!(Descriptor::new_bip86(master, fingerprint, KeychainKind::External).is_err());
+    }
+}
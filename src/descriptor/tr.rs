@@ -4,28 +4,33 @@ use core::str::FromStr;
 use core::{fmt, hash};
 
 use bitcoin::blockdata::opcodes;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::psbt;
 use bitcoin::util::taproot::{
-    LeafVersion, TaprootBuilder, TaprootBuilderError, TaprootSpendInfo, TAPROOT_CONTROL_BASE_SIZE,
+    ControlBlock, LeafVersion, TapLeafHash, TaprootBuilder, TaprootBuilderError,
+    TaprootMerkleBranch, TaprootSpendInfo, TAPROOT_ANNEX_PREFIX, TAPROOT_CONTROL_BASE_SIZE,
     TAPROOT_CONTROL_MAX_NODE_COUNT, TAPROOT_CONTROL_NODE_SIZE,
 };
-use bitcoin::{secp256k1, Address, Network, Script};
+use bitcoin::{Address, Network, Script};
 use sync::Arc;
 
-use super::checksum::{desc_checksum, verify_checksum};
-use crate::expression::{self, FromTree};
+use super::checksum::{desc_checksum, verify_checksum_located};
+use crate::expression;
+use crate::miniscript::satisfy::{SatisfactionError, SigSizeAssumptions};
 use crate::miniscript::Miniscript;
+use crate::plan::{tap_key_spend_weight, Assumptions, TapSpendPath, TapSpendPlan};
 use crate::policy::semantic::Policy;
 use crate::policy::Liftable;
 use crate::prelude::*;
-use crate::util::{varint_len, witness_size};
+use crate::util::{varint_len, with_secp, witness_size};
 use crate::{
-    errstr, Error, ForEach, ForEachKey, MiniscriptKey, Satisfier, Tap, ToPublicKey, TranslatePk,
-    Translator,
+    errstr, Error, ForEach, ForEachKey, FromStrParser, KeyParser, MiniscriptKey, Satisfier,
+    ScriptContext, Tap, ToPublicKey, TranslatePk, Translator, MAX_RECURSION_DEPTH,
 };
 
 /// A Taproot Tree representation.
-// Hidden leaves are not yet supported in descriptor spec. Conceptually, it should
-// be simple to integrate those here, but it is best to wait on core for the exact syntax.
+// Opaque leaves are not part of the descriptor spec, they are written as
+// `opaque(<leaf hash>)` until core settles on a syntax.
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum TapTree<Pk: MiniscriptKey> {
     /// A taproot tree structure
@@ -35,9 +40,17 @@ pub enum TapTree<Pk: MiniscriptKey> {
     // in adding a LeafVersion with Leaf type here. All Miniscripts right now
     // are of Leafversion::default
     Leaf(Arc<Miniscript<Pk, Tap>>),
+    /// A leaf known only by its hash, such as the branch of a counterparty
+    /// who does not reveal it. It contributes to the output key, but cannot
+    /// be satisfied.
+    Opaque(TapLeafHash),
 }
 
 /// A taproot descriptor
+///
+/// When parsing, an uncompressed internal key or a hash is rejected with
+/// [`Error::NonXOnlyInternalKey`]. A compressed internal key is accepted, as
+/// Bitcoin Core does, and converted to its x-only form. This is deprecated.
 pub struct Tr<Pk: MiniscriptKey> {
     /// A taproot internal key
     internal_key: Pk,
@@ -108,6 +121,18 @@ impl<Pk: MiniscriptKey> hash::Hash for Tr<Pk> {
 }
 
 impl<Pk: MiniscriptKey> TapTree<Pk> {
+    // Sorts the keys of every `sortedmulti_a` in the leaves of the tree
+    pub(super) fn sort_multi_a_keys(&mut self) {
+        match *self {
+            TapTree::Tree(ref mut left, ref mut right) => {
+                Arc::make_mut(left).sort_multi_a_keys();
+                Arc::make_mut(right).sort_multi_a_keys();
+            }
+            TapTree::Leaf(ref mut ms) => Arc::make_mut(ms).sort_multi_a_keys(),
+            TapTree::Opaque(..) => {}
+        }
+    }
+
     // Helper function to compute height
     // TODO: Instead of computing this every time we add a new leaf, we should
     // add height as a separate field in taptree
@@ -116,11 +141,11 @@ impl<Pk: MiniscriptKey> TapTree<Pk> {
             TapTree::Tree(ref left_tree, ref right_tree) => {
                 1 + max(left_tree.taptree_height(), right_tree.taptree_height())
             }
-            TapTree::Leaf(..) => 0,
+            TapTree::Leaf(..) | TapTree::Opaque(..) => 0,
         }
     }
 
-    /// Iterate over all miniscripts
+    /// Iterate over all miniscripts, skipping opaque leaves
     pub fn iter(&self) -> TapTreeIter<Pk> {
         TapTreeIter {
             stack: vec![(0, self)],
@@ -139,9 +164,127 @@ impl<Pk: MiniscriptKey> TapTree<Pk> {
                 Arc::new(r.translate_helper(t)?),
             ),
             TapTree::Leaf(ms) => TapTree::Leaf(Arc::new(ms.translate_pk(t)?)),
+            TapTree::Opaque(hash) => TapTree::Opaque(*hash),
         };
         Ok(frag)
     }
+
+    /// Whether some leaves of the tree are opaque
+    pub fn has_opaque_leaves(&self) -> bool {
+        match *self {
+            TapTree::Tree(ref left, ref right) => {
+                left.has_opaque_leaves() || right.has_opaque_leaves()
+            }
+            TapTree::Leaf(..) => false,
+            TapTree::Opaque(..) => true,
+        }
+    }
+
+    // Helper function to add all leaves, opaque ones included, to a builder
+    fn add_to_builder(
+        &self,
+        builder: TaprootBuilder,
+        depth: u8,
+    ) -> Result<TaprootBuilder, TaprootBuilderError>
+    where
+        Pk: ToPublicKey,
+    {
+        match *self {
+            TapTree::Tree(ref left, ref right) => {
+                let builder = left.add_to_builder(builder, depth + 1)?;
+                right.add_to_builder(builder, depth + 1)
+            }
+            TapTree::Leaf(ref ms) => builder.add_leaf(depth, ms.encode()),
+            TapTree::Opaque(ref hash) => {
+                builder.add_hidden_node(depth, sha256::Hash::from_inner(hash.into_inner()))
+            }
+        }
+    }
+}
+
+/// Builder for a [`TapTree`] with an arbitrary, possibly unbalanced, shape
+///
+/// Leaves must be added in depth-first order, left to right, each with its depth in the
+/// tree, mirroring [`TaprootBuilder`]. For example, adding leaves at depths `1, 2, 2`
+/// produces `{A,{B,C}}`.
+#[derive(Clone, Debug)]
+pub struct TapTreeBuilder<Pk: MiniscriptKey> {
+    /// Incomplete subtrees, indexed by depth
+    branch: Vec<Option<TapTree<Pk>>>,
+}
+
+impl<Pk: MiniscriptKey> Default for TapTreeBuilder<Pk> {
+    fn default() -> Self {
+        TapTreeBuilder::new()
+    }
+}
+
+impl<Pk: MiniscriptKey> TapTreeBuilder<Pk> {
+    /// Create a new, empty, builder
+    pub fn new() -> Self {
+        TapTreeBuilder { branch: vec![] }
+    }
+
+    /// Add a leaf at `depth`, merging it with its sibling if that completes a subtree
+    pub fn add_leaf(self, depth: u8, ms: Miniscript<Pk, Tap>) -> Result<Self, Error> {
+        self.insert(TapTree::Leaf(Arc::new(ms)), depth)
+    }
+
+    /// Add an opaque leaf with the given hash at `depth`
+    pub fn add_opaque_leaf(self, depth: u8, hash: TapLeafHash) -> Result<Self, Error> {
+        self.insert(TapTree::Opaque(hash), depth)
+    }
+
+    /// Whether the leaves added so far form a complete tree
+    pub fn is_finalizable(&self) -> bool {
+        self.branch.len() == 1 && self.branch[0].is_some()
+    }
+
+    /// Finish building, checking that the leaves form a complete tree
+    pub fn finalize(mut self) -> Result<TapTree<Pk>, Error> {
+        if self.branch.is_empty() {
+            return Err(Error::TapTreeBuilder(TaprootBuilderError::EmptyTree));
+        }
+        if !self.is_finalizable() {
+            return Err(Error::TapTreeBuilder(TaprootBuilderError::IncompleteTree));
+        }
+        Ok(self.branch.pop().unwrap().unwrap())
+    }
+
+    fn insert(mut self, mut node: TapTree<Pk>, mut depth: u8) -> Result<Self, Error> {
+        if depth as usize > TAPROOT_CONTROL_MAX_NODE_COUNT {
+            return Err(Error::TapTreeBuilder(
+                TaprootBuilderError::InvalidMerkleTreeDepth(depth as usize),
+            ));
+        }
+        // Nothing can be added to a complete tree
+        if self.is_finalizable() {
+            return Err(Error::TapTreeBuilder(TaprootBuilderError::OverCompleteTree));
+        }
+        // A shallower node cannot be added while a deeper subtree is still incomplete
+        if depth as usize + 1 < self.branch.len() {
+            return Err(Error::TapTreeBuilder(
+                TaprootBuilderError::NodeNotInDfsOrder,
+            ));
+        }
+        while self.branch.len() == depth as usize + 1 {
+            let sibling = match self.branch.pop() {
+                Some(Some(sibling)) => sibling,
+                Some(None) => {
+                    self.branch.push(None);
+                    break;
+                }
+                None => unreachable!("branch length checked above"),
+            };
+            node = TapTree::Tree(Arc::new(sibling), Arc::new(node));
+            depth -= 1;
+        }
+        if self.branch.len() < depth as usize + 1 {
+            self.branch.resize(depth as usize + 1, None);
+        }
+        self.branch[depth as usize] = Some(node);
+        Ok(self)
+    }
 }
 
 impl<Pk: MiniscriptKey> fmt::Display for TapTree<Pk> {
@@ -149,6 +292,7 @@ impl<Pk: MiniscriptKey> fmt::Display for TapTree<Pk> {
         match self {
             TapTree::Tree(ref left, ref right) => write!(f, "{{{},{}}}", *left, *right),
             TapTree::Leaf(ref script) => write!(f, "{}", *script),
+            TapTree::Opaque(ref hash) => write!(f, "opaque({})", hash),
         }
     }
 }
@@ -158,6 +302,7 @@ impl<Pk: MiniscriptKey> fmt::Debug for TapTree<Pk> {
         match self {
             TapTree::Tree(ref left, ref right) => write!(f, "{{{:?},{:?}}}", *left, *right),
             TapTree::Leaf(ref script) => write!(f, "{:?}", *script),
+            TapTree::Opaque(ref hash) => write!(f, "opaque({})", hash),
         }
     }
 }
@@ -174,7 +319,9 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
                 spend_info: Mutex::new(None),
             })
         } else {
-            Err(Error::MaxRecursiveDepthExceeded)
+            Err(Error::MaxRecursiveDepthExceeded(
+                TAPROOT_CONTROL_MAX_NODE_COUNT as u32,
+            ))
         }
     }
 
@@ -196,8 +343,8 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
         &self.tree
     }
 
-    /// Iterate over all scripts in merkle tree. If there is no script path, the iterator
-    /// yields [`None`]
+    /// Iterate over all scripts in merkle tree, skipping opaque leaves. If there is no
+    /// script path, the iterator yields [`None`]
     pub fn iter_scripts(&self) -> TapTreeIter<Pk> {
         match self.tree {
             Some(ref t) => t.iter(),
@@ -205,6 +352,35 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
         }
     }
 
+    /// Iterate over all leaves in the merkle tree along with their depth, leaf hash and the
+    /// control block (including the merkle path) needed to spend them
+    ///
+    /// This computes and caches the [`TaprootSpendInfo`] if needed.
+    pub fn iter_leaves(&self) -> TapLeafIter<'_, Pk>
+    where
+        Pk: ToPublicKey,
+    {
+        TapLeafIter {
+            scripts: self.iter_scripts(),
+            spend_info: self.spend_info(),
+        }
+    }
+
+    /// The script tree in the form of the `tap_tree` field of PSBT outputs.
+    ///
+    /// Returns `None` if there is no script tree or it has opaque leaves.
+    pub fn psbt_tap_tree(&self) -> Option<psbt::TapTree>
+    where
+        Pk: ToPublicKey,
+    {
+        let builder = self
+            .tree
+            .as_ref()?
+            .add_to_builder(TaprootBuilder::new(), 0)
+            .expect("Taptree is a well formed tree");
+        psbt::TapTree::from_builder(builder).ok()
+    }
+
     /// Compute the [`TaprootSpendInfo`] associated with this descriptor if spend data is `None`.
     ///
     /// If spend data is already computed (i.e it is not `None`), this does not recompute it.
@@ -222,45 +398,40 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
         }
         drop(read_lock);
 
-        // Get a new secp context
-        // This would be cheap operation after static context support from upstream
-        let secp = secp256k1::Secp256k1::verification_only();
         // Key spend path with no merkle root
-        let data = if self.tree.is_none() {
-            TaprootSpendInfo::new_key_spend(&secp, self.internal_key.to_x_only_pubkey(), None)
-        } else {
-            let mut builder = TaprootBuilder::new();
-            for (depth, ms) in self.iter_scripts() {
-                let script = ms.encode();
-                builder = builder
-                    .add_leaf(depth, script)
+        let data = with_secp(|secp| {
+            if let Some(ref tree) = self.tree {
+                let builder = tree
+                    .add_to_builder(TaprootBuilder::new(), 0)
                     .expect("Computing spend data on a valid Tree should always succeed");
+                // Assert builder cannot error here because we have a well formed descriptor
+                match builder.finalize(secp, self.internal_key.to_x_only_pubkey()) {
+                    Ok(data) => data,
+                    Err(e) => match e {
+                        TaprootBuilderError::InvalidMerkleTreeDepth(_) => {
+                            unreachable!("Depth checked in struct construction")
+                        }
+                        TaprootBuilderError::NodeNotInDfsOrder => {
+                            unreachable!("Insertion is called in DFS order")
+                        }
+                        TaprootBuilderError::OverCompleteTree => {
+                            unreachable!("Taptree is a well formed tree")
+                        }
+                        TaprootBuilderError::InvalidInternalKey(_) => {
+                            unreachable!("Internal key checked for validity")
+                        }
+                        TaprootBuilderError::IncompleteTree => {
+                            unreachable!("Taptree is a well formed tree")
+                        }
+                        TaprootBuilderError::EmptyTree => {
+                            unreachable!("Taptree is a well formed tree with atleast 1 element")
+                        }
+                    },
+                }
+            } else {
+                TaprootSpendInfo::new_key_spend(secp, self.internal_key.to_x_only_pubkey(), None)
             }
-            // Assert builder cannot error here because we have a well formed descriptor
-            match builder.finalize(&secp, self.internal_key.to_x_only_pubkey()) {
-                Ok(data) => data,
-                Err(e) => match e {
-                    TaprootBuilderError::InvalidMerkleTreeDepth(_) => {
-                        unreachable!("Depth checked in struct construction")
-                    }
-                    TaprootBuilderError::NodeNotInDfsOrder => {
-                        unreachable!("Insertion is called in DFS order")
-                    }
-                    TaprootBuilderError::OverCompleteTree => {
-                        unreachable!("Taptree is a well formed tree")
-                    }
-                    TaprootBuilderError::InvalidInternalKey(_) => {
-                        unreachable!("Internal key checked for validity")
-                    }
-                    TaprootBuilderError::IncompleteTree => {
-                        unreachable!("Taptree is a well formed tree")
-                    }
-                    TaprootBuilderError::EmptyTree => {
-                        unreachable!("Taptree is a well formed tree with atleast 1 element")
-                    }
-                },
-            }
-        };
+        });
         let spend_info = Arc::new(data);
         *self.spend_info.lock().expect("Lock poisoned") = Some(Arc::clone(&spend_info));
         spend_info
@@ -284,31 +455,121 @@ impl<Pk: MiniscriptKey> Tr<Pk> {
     /// # Errors
     /// When the descriptor is impossible to safisfy (ex: sh(OP_FALSE)).
     pub fn max_satisfaction_weight(&self) -> Result<usize, Error> {
-        let mut max_wieght = Some(65);
+        self.max_satisfaction_weight_helper(SigSizeAssumptions::WORST_CASE, 0)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, like [`Tr::max_satisfaction_weight`] but assuming
+    /// signatures of the given sizes.
+    pub fn max_satisfaction_weight_with_sig_sizes(
+        &self,
+        sig_sizes: SigSizeAssumptions,
+    ) -> Result<usize, Error> {
+        self.max_satisfaction_weight_helper(sig_sizes, 0)
+    }
+
+    /// Computes an upper bound on the weight of a satisfying witness to the
+    /// transaction, when an annex of `annex_len` bytes (including the `0x50`
+    /// prefix) is attached to the witness.
+    ///
+    /// Makes the same assumptions as [`Tr::max_satisfaction_weight`].
+    pub fn max_satisfaction_weight_with_annex(&self, annex_len: usize) -> Result<usize, Error> {
+        self.max_satisfaction_weight_helper(
+            SigSizeAssumptions::WORST_CASE,
+            varint_len(annex_len) + annex_len,
+        )
+    }
+
+    fn max_satisfaction_weight_helper(
+        &self,
+        sig_sizes: SigSizeAssumptions,
+        annex_weight: usize,
+    ) -> Result<usize, Error> {
+        let mut max_wieght = Some(key_spend_weight(sig_sizes));
         for (depth, ms) in self.iter_scripts() {
-            let script_size = ms.script_size();
-            let max_sat_elems = match ms.max_satisfaction_witness_elements() {
-                Ok(elem) => elem,
-                Err(..) => continue,
-            };
-            let max_sat_size = match ms.max_satisfaction_size() {
-                Ok(sz) => sz,
-                Err(..) => continue,
-            };
-            let control_block_sz = control_block_len(depth);
-            let wit_size = 4 + // scriptSig len byte
-            control_block_sz + // first element control block
-            varint_len(script_size) +
-            script_size + // second element script len with prefix
-            varint_len(max_sat_elems) +
-            max_sat_size; // witness
-            max_wieght = cmp::max(max_wieght, Some(wit_size));
+            max_wieght = cmp::max(max_wieght, leaf_spend_weight(depth, ms, sig_sizes));
         }
-        max_wieght.ok_or(Error::ImpossibleSatisfaction)
+        max_wieght
+            .map(|w| w + annex_weight)
+            .ok_or(Error::ImpossibleSatisfaction)
     }
 }
 
+/// Weight of the satisfaction of a key spend
+pub(super) fn key_spend_weight(sig_sizes: SigSizeAssumptions) -> usize {
+    // scriptSig len byte, witness count and the signature with its length prefix
+    4 + 1 + 1 + sig_sizes.schnorr()
+}
+
+/// Upper bound on the weight of the satisfaction of a leaf at `depth`, `None`
+/// if it cannot be satisfied
+pub(super) fn leaf_spend_weight<Pk: MiniscriptKey>(
+    depth: u8,
+    ms: &Miniscript<Pk, Tap>,
+    sig_sizes: SigSizeAssumptions,
+) -> Option<usize> {
+    let script_size = ms.script_size();
+    let max_sat_elems = ms.max_satisfaction_witness_elements().ok()?;
+    let max_sat_size = ms.max_satisfaction_size_with_sig_sizes(sig_sizes).ok()?;
+    let control_block_sz = control_block_len(depth);
+    Some(
+        4 + // scriptSig len byte
+        control_block_sz + // first element control block
+        varint_len(script_size) +
+        script_size + // second element script len with prefix
+        varint_len(max_sat_elems) +
+        max_sat_size, // witness
+    )
+}
+
 impl<Pk: MiniscriptKey + ToPublicKey> Tr<Pk> {
+    /// Selects the cheapest spend path that is satisfiable with `assets`,
+    /// comparing the key path against every satisfiable script path.
+    ///
+    /// The key path is available if the internal key is among the keys
+    /// of `assets`.
+    pub fn best_spend(&self, assets: &Assumptions<Pk>) -> Result<TapSpendPlan<'_, Pk>, Error> {
+        let elem_weight = |len: usize| varint_len(len) + len;
+        let annex_weight = assets.annex.as_ref().map(|a| elem_weight(a.len()));
+        let annex_count = annex_weight.map(|_| 1).unwrap_or(0);
+        let annex_weight = annex_weight.unwrap_or(0);
+
+        let mut best: Option<TapSpendPlan<Pk>> = None;
+        let mut errors = vec![];
+        if assets.keys.contains(&self.internal_key) {
+            best = Some(TapSpendPlan {
+                path: TapSpendPath::KeyPath,
+                witness_weight: tap_key_spend_weight(assets),
+            });
+        } else {
+            errors.push(key_spend_error(&self.internal_key));
+        }
+        for (leaf, (depth, ms)) in self.iter_scripts().enumerate() {
+            let wit = match ms.satisfy(assets) {
+                Ok(wit) => wit,
+                Err(e) => {
+                    push_leaf_errors(&mut errors, leaf, e);
+                    continue;
+                }
+            };
+            let witness_weight = varint_len(wit.len() + 2 + annex_count)
+                + Tap::satisfaction_weight(&wit)
+                + elem_weight(ms.script_size())
+                + elem_weight(control_block_len(depth))
+                + annex_weight;
+            if best
+                .as_ref()
+                .map_or(true, |b| witness_weight < b.witness_weight)
+            {
+                best = Some(TapSpendPlan {
+                    path: TapSpendPath::ScriptPath { depth, leaf: ms },
+                    witness_weight,
+                });
+            }
+        }
+        best.ok_or(Error::Unsatisfiable(errors))
+    }
+
     /// Obtains the corresponding script pubkey for this descriptor.
     pub fn script_pubkey(&self) -> Script {
         let output_key = self.spend_info().output_key();
@@ -378,38 +639,182 @@ where
                     self.stack.push((depth + 1, l));
                 }
                 TapTree::Leaf(ref ms) => return Some((depth, ms)),
+                TapTree::Opaque(..) => {}
             }
         }
         None
     }
 }
 
-#[rustfmt::skip]
-impl_block_str!(
-    Tr<Pk>,
+/// Script path spending data for a single leaf of a [`Tr`] descriptor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TapLeafInfo<'a, Pk: MiniscriptKey> {
+    /// Depth of the leaf in the tree
+    pub depth: u8,
+    /// The leaf miniscript
+    pub miniscript: &'a Miniscript<Pk, Tap>,
+    /// The encoded leaf script
+    pub script: Script,
+    /// The tapleaf hash, as used in signature hashes and PSBT fields
+    pub leaf_hash: TapLeafHash,
+    /// The control block to put in the witness when spending this leaf
+    pub control_block: ControlBlock,
+}
+
+impl<'a, Pk: MiniscriptKey> TapLeafInfo<'a, Pk> {
+    /// The merkle path from this leaf to the tree root
+    pub fn merkle_branch(&self) -> &TaprootMerkleBranch {
+        &self.control_block.merkle_branch
+    }
+}
+
+/// Iterator over the leaves of a [`Tr`] descriptor along with their spending data
+///
+/// Created by [`Tr::iter_leaves`].
+pub struct TapLeafIter<'a, Pk: MiniscriptKey> {
+    scripts: TapTreeIter<'a, Pk>,
+    spend_info: Arc<TaprootSpendInfo>,
+}
+
+impl<'a, Pk> Iterator for TapLeafIter<'a, Pk>
+where
+    Pk: MiniscriptKey + ToPublicKey + 'a,
+{
+    type Item = TapLeafInfo<'a, Pk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, ms) = self.scripts.next()?;
+        let leaf_script = (ms.encode(), LeafVersion::TapScript);
+        let branches = self
+            .spend_info
+            .as_script_map()
+            .get(&leaf_script)
+            .expect("Every leaf of the tree is in the script map");
+        // The same script may appear more than once in the tree, pick the
+        // branch matching this occurrence's depth
+        let merkle_branch = branches
+            .iter()
+            .find(|b| b.as_inner().len() == depth as usize)
+            .or_else(|| branches.iter().next())
+            .expect("Script map entries are never empty")
+            .clone();
+        Some(TapLeafInfo {
+            depth,
+            miniscript: ms,
+            leaf_hash: TapLeafHash::from_script(&leaf_script.0, leaf_script.1),
+            control_block: ControlBlock {
+                leaf_version: leaf_script.1,
+                output_key_parity: self.spend_info.output_key_parity(),
+                internal_key: self.spend_info.internal_key(),
+                merkle_branch,
+            },
+            script: leaf_script.0,
+        })
+    }
+}
+
+impl<Pk: MiniscriptKey> Tr<Pk> {
     // Helper function to parse taproot script path
-    fn parse_tr_script_spend(tree: &expression::Tree,) -> Result<TapTree<Pk>, Error> {
+    fn parse_tr_script_spend<P, E>(
+        tree: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<TapTree<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         match tree {
-            expression::Tree { name, args } if !name.is_empty() && args.is_empty() => {
-                let script = Miniscript::<Pk, Tap>::from_str(name)?;
+            expression::Tree { name, args, pos }
+                if name.starts_with("opaque(") && name.ends_with(')') && args.is_empty() =>
+            {
+                let hash = TapLeafHash::from_str(&name[7..name.len() - 1])
+                    .map_err(|e| Error::Unexpected(e.to_string()).at(*pos + 7))?;
+                Ok(TapTree::Opaque(hash))
+            }
+            expression::Tree { name, args, pos } if !name.is_empty() && args.is_empty() => {
+                // The leaf was not parsed as part of the tree
+                let script =
+                    Miniscript::<Pk, Tap>::parse_with(name, parser).map_err(|e| e.offset(*pos))?;
                 Ok(TapTree::Leaf(Arc::new(script)))
             }
-            expression::Tree { name, args } if name.is_empty() && args.len() == 2 => {
-                let left = Self::parse_tr_script_spend(&args[0])?;
-                let right = Self::parse_tr_script_spend(&args[1])?;
+            expression::Tree { name, args, .. } if name.is_empty() && args.len() == 2 => {
+                let left = Self::parse_tr_script_spend(&args[0], parser)?;
+                let right = Self::parse_tr_script_spend(&args[1], parser)?;
                 Ok(TapTree::Tree(Arc::new(left), Arc::new(right)))
             }
             _ => Err(Error::Unexpected(
                 "unknown format for script spending paths while parsing taproot descriptor"
                     .to_string(),
-            )),
+            )
+            .at(tree.pos)),
         }
     }
-);
+
+    // Helper function to parse the internal key, rejecting keys which are not
+    // x-only before they fail deep in key parsing
+    //
+    // A compressed key is parsed as its x-only form, dropping its parity, as
+    // Bitcoin Core does. This is deprecated: the key is written back x-only,
+    // and a future version may reject it.
+    fn parse_internal_key<P, E>(key: &expression::Tree, parser: &mut P) -> Result<Pk, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        // Keep the origin, if any, for the suggested x-only key
+        let split = key.name.find(']').map(|i| i + 1).unwrap_or(0);
+        let (origin, hex) = key.name.split_at(split);
+        if hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            match (hex.len(), hex.get(..2)) {
+                // A compressed key is converted only when the key type can hold
+                // its x-only form
+                (66, Some("02")) | (66, Some("03")) => {
+                    let x_only = format!("{}{}", origin, &hex[2..]);
+                    if let Ok(pk) = parser.pk(&x_only) {
+                        if key.args.is_empty() {
+                            return Ok(pk);
+                        }
+                    }
+                }
+                (130, _) | (40, _) => {
+                    return Err(Error::NonXOnlyInternalKey(key.name.to_string()).at(key.pos))
+                }
+                _ => {}
+            }
+        }
+        expression::terminal(key, |pk| parser.pk(pk))
+    }
+}
 
 impl_from_tree!(
     Tr<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Self, Error> {
+        Self::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Tr<Pk> {
+    /// Parses a `tr` descriptor from string, with the keys parsed by `parser`
+    pub(crate) fn parse_with<P, E>(s: &str, parser: &mut P) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        let desc_str = verify_checksum_located(s)?;
+        let top = parse_tr_tree(desc_str)?;
+        Self::from_tree_with(&top, parser)
+    }
+
+    /// Parses an expression tree into a `tr` descriptor, with the keys parsed by
+    /// `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Self, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         if top.name == "tr" {
             match top.args.len() {
                 1 => {
@@ -420,7 +825,7 @@ impl_from_tree!(
                             key.args.len()
                         )));
                     }
-                    Tr::new(expression::terminal(key, Pk::from_str)?, None)
+                    Tr::new(Self::parse_internal_key(key, parser)?, None)
                 }
                 2 => {
                     let key = &top.args[0];
@@ -431,34 +836,30 @@ impl_from_tree!(
                         )));
                     }
                     let tree = &top.args[1];
-                    let ret = Self::parse_tr_script_spend(tree)?;
-                    Tr::new(expression::terminal(key, Pk::from_str)?, Some(ret))
-                }
-                _ => {
-                    return Err(Error::Unexpected(format!(
-                        "{}[#{} args] while parsing taproot descriptor",
-                        top.name,
-                        top.args.len()
-                    )));
+                    let ret = Self::parse_tr_script_spend(tree, parser)?;
+                    Tr::new(Self::parse_internal_key(key, parser)?, Some(ret))
                 }
+                _ => Err(Error::Unexpected(format!(
+                    "{}[#{} args] while parsing taproot descriptor",
+                    top.name,
+                    top.args.len()
+                ))),
             }
         } else {
-            return Err(Error::Unexpected(format!(
+            Err(Error::Unexpected(format!(
                 "{}[#{} args] while parsing taproot descriptor",
                 top.name,
                 top.args.len()
-            )));
+            )))
         }
     }
-);
+}
 
 impl_from_str!(
     Tr<Pk>,
     type Err = Error;,
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let desc_str = verify_checksum(s)?;
-        let top = parse_tr_tree(desc_str)?;
-        Self::from_tree(&top)
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Self::parse_with(s, &mut FromStrParser)
     }
 );
 
@@ -481,11 +882,7 @@ impl<Pk: MiniscriptKey> fmt::Display for Tr<Pk> {
 
 // Helper function to parse string into miniscript tree form
 fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
-    for ch in s.bytes() {
-        if !ch.is_ascii() {
-            return Err(Error::Unprintable(ch));
-        }
-    }
+    expression::printable(s)?;
 
     if s.len() > 3 && &s[..3] == "tr(" && s.as_bytes()[s.len() - 1] == b')' {
         let rest = &s[3..s.len() - 1];
@@ -493,10 +890,12 @@ fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
             let internal_key = expression::Tree {
                 name: rest,
                 args: vec![],
+                pos: 3,
             };
             return Ok(expression::Tree {
                 name: "tr",
                 args: vec![internal_key],
+                pos: 0,
             });
         }
         // use str::split_once() method to refactor this when compiler version bumps up
@@ -506,21 +905,26 @@ fn parse_tr_tree(s: &str) -> Result<expression::Tree, Error> {
         let internal_key = expression::Tree {
             name: key,
             args: vec![],
+            pos: 3,
         };
         if script.is_empty() {
             return Ok(expression::Tree {
                 name: "tr",
                 args: vec![internal_key],
+                pos: 0,
             });
         }
-        let (tree, rest) = expression::Tree::from_slice_delim(script, 1, '{')?;
+        let script_pos = 3 + key.len() + 1;
+        let (tree, rest) =
+            expression::Tree::from_slice_delim(script, script_pos, MAX_RECURSION_DEPTH - 1, '{')?;
         if rest.is_empty() {
             Ok(expression::Tree {
                 name: "tr",
                 args: vec![internal_key, tree],
+                pos: 0,
             })
         } else {
-            Err(errstr(rest))
+            Err(errstr(rest).at(script_pos + script.len() - rest.len()))
         }
     } else {
         Err(Error::Unexpected("invalid taproot descriptor".to_string()))
@@ -555,6 +959,7 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for TapTree<Pk> {
                     Ok(Policy::Threshold(1, vec![lift_helper(l)?, lift_helper(r)?]))
                 }
                 TapTree::Leaf(ref leaf) => leaf.lift(),
+                TapTree::Opaque(..) => Err(Error::OpaqueTapLeaf),
             }
         }
 
@@ -631,6 +1036,30 @@ where
     S: Satisfier<Pk>,
 {
     let spend_info = desc.spend_info();
+    let annex = satisfier.lookup_tap_annex();
+    if let Some(ref annex) = annex {
+        if annex.first() != Some(&TAPROOT_ANNEX_PREFIX) {
+            return Err(Error::InvalidTapAnnex);
+        }
+    }
+    let (mut wit, script_sig) = best_tap_spend_helper(desc, &spend_info, satisfier, allow_mall)?;
+    // The annex, if present, is always the last witness element
+    if let Some(annex) = annex {
+        wit.push(annex);
+    }
+    Ok((wit, script_sig))
+}
+
+fn best_tap_spend_helper<Pk, S>(
+    desc: &Tr<Pk>,
+    spend_info: &TaprootSpendInfo,
+    satisfier: S,
+    allow_mall: bool,
+) -> Result<(Vec<Vec<u8>>, Script), Error>
+where
+    Pk: ToPublicKey,
+    S: Satisfier<Pk>,
+{
     // First try the key spend path
     if let Some(sig) = satisfier.lookup_tap_key_spend_sig() {
         Ok((vec![sig.to_vec()], Script::new()))
@@ -638,16 +1067,19 @@ where
         // Since we have the complete descriptor we can ignore the satisfier. We don't use the control block
         // map (lookup_control_block) from the satisfier here.
         let (mut min_wit, mut min_wit_len) = (None, None);
-        for (depth, ms) in desc.iter_scripts() {
-            let mut wit = if allow_mall {
-                match ms.satisfy_malleable(&satisfier) {
-                    Ok(wit) => wit,
-                    Err(..) => continue, // No witness for this script in tr descriptor, look for next one
-                }
+        let mut errors = vec![key_spend_error(desc.internal_key())];
+        for (leaf, (depth, ms)) in desc.iter_scripts().enumerate() {
+            let wit = if allow_mall {
+                ms.satisfy_malleable(&satisfier)
             } else {
-                match ms.satisfy(&satisfier) {
-                    Ok(wit) => wit,
-                    Err(..) => continue, // No witness for this script in tr descriptor, look for next one
+                ms.satisfy(&satisfier)
+            };
+            let mut wit = match wit {
+                Ok(wit) => wit,
+                Err(e) => {
+                    // No witness for this script in tr descriptor, look for next one
+                    push_leaf_errors(&mut errors, leaf, e);
+                    continue;
                 }
             };
             // Compute the final witness size
@@ -675,13 +1107,30 @@ where
         }
         match min_wit {
             Some(wit) => Ok((wit, Script::new())),
-            None => Err(Error::CouldNotSatisfy), // Could not satisfy all miniscripts inside Tr
+            None => Err(Error::Unsatisfiable(errors)), // Could not satisfy all miniscripts inside Tr
         }
     }
 }
 
+// The reason a key spend is not possible
+fn key_spend_error<Pk: MiniscriptKey>(key: &Pk) -> SatisfactionError {
+    SatisfactionError::MissingSignature {
+        path: vec![],
+        key: key.to_string(),
+    }
+}
+
+// Records why the leaf at position `leaf` could not be satisfied
+fn push_leaf_errors(errors: &mut Vec<SatisfactionError>, leaf: usize, e: Error) {
+    if let Error::Unsatisfiable(leaf_errors) = e {
+        errors.extend(leaf_errors.into_iter().map(|e| e.in_leaf(leaf)));
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use bitcoin::secp256k1;
+
     use super::*;
     use crate::ForEachKey;
 
@@ -707,4 +1156,283 @@ mod tests {
             ForEach::Hash(_h) => unreachable!(),
         }));
     }
+
+    #[test]
+    fn annex() {
+        struct KeySpend(Vec<u8>);
+
+        impl Satisfier<bitcoin::PublicKey> for KeySpend {
+            fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+                let sig = secp256k1::schnorr::Signature::from_slice(&[1; 64]).unwrap();
+                Some(bitcoin::SchnorrSig {
+                    sig,
+                    hash_ty: bitcoin::SchnorrSighashType::Default,
+                })
+            }
+
+            fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+                Some(self.0.clone())
+            }
+        }
+
+        let tr = Tr::<bitcoin::PublicKey>::from_str(
+            "tr(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        let (wit, _) = tr.get_satisfaction(KeySpend(vec![0x50, 1, 2])).unwrap();
+        assert_eq!(wit, vec![vec![1; 64], vec![0x50, 1, 2]]);
+        assert_eq!(
+            tr.get_satisfaction(KeySpend(vec![0x51])).unwrap_err(),
+            Error::InvalidTapAnnex
+        );
+        assert_eq!(
+            tr.max_satisfaction_weight_with_annex(3).unwrap(),
+            tr.max_satisfaction_weight().unwrap() + 4
+        );
+    }
+
+    #[test]
+    fn best_spend() {
+        let keys: Vec<_> = [
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+            "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+            "0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8",
+        ]
+        .iter()
+        .map(|k| bitcoin::PublicKey::from_str(k).unwrap())
+        .collect();
+        let tr = Tr::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),and_v(v:pk({}),pk({}))}})",
+            keys[0], keys[1], keys[1], keys[2]
+        ))
+        .unwrap();
+
+        let plan = tr.best_spend(&Assumptions::new().key(keys[0])).unwrap();
+        assert_eq!(plan.path, TapSpendPath::KeyPath);
+        assert_eq!(plan.witness_weight, 1 + 1 + 65);
+
+        // Both leaves are satisfiable, the single-key one is cheaper
+        let plan = tr
+            .best_spend(&Assumptions::new().key(keys[1]).key(keys[2]))
+            .unwrap();
+        match plan.path {
+            TapSpendPath::ScriptPath { depth, leaf } => {
+                assert_eq!(depth, 1);
+                assert_eq!(leaf.to_string(), format!("pk({})", keys[1]));
+            }
+            TapSpendPath::KeyPath => panic!("Key path is not available"),
+        }
+        // count + sig + script + control block
+        assert_eq!(plan.witness_weight, 1 + 66 + 35 + 66);
+
+        // Key path wins over script paths
+        let plan = tr
+            .best_spend(&Assumptions::new().key(keys[0]).key(keys[1]))
+            .unwrap();
+        assert_eq!(plan.path, TapSpendPath::KeyPath);
+
+        // Leaf errors are reported below the position of the leaf
+        let missing =
+            |path: Vec<usize>, key: &bitcoin::PublicKey| SatisfactionError::MissingSignature {
+                path,
+                key: key.to_string(),
+            };
+        let err = Error::Unsatisfiable(vec![
+            missing(vec![], &keys[0]),
+            missing(vec![0, 0], &keys[1]),
+            missing(vec![1, 0, 0, 0], &keys[1]),
+        ]);
+        assert_eq!(
+            tr.best_spend(&Assumptions::new().key(keys[2])).unwrap_err(),
+            err
+        );
+        assert_eq!(
+            crate::Descriptor::Tr(tr)
+                .get_satisfaction(Assumptions::new().key(keys[2]))
+                .unwrap_err(),
+            err
+        );
+    }
+
+    #[test]
+    fn taptree_builder() {
+        let leaf = |s: &str| Miniscript::<String, Tap>::from_str(s).unwrap();
+
+        let tree = TapTreeBuilder::new()
+            .add_leaf(1, leaf("pk(A)"))
+            .unwrap()
+            .add_leaf(2, leaf("pk(B)"))
+            .unwrap()
+            .add_leaf(2, leaf("pk(C)"))
+            .unwrap()
+            .finalize()
+            .unwrap();
+        let tr = Tr::new("K".to_string(), Some(tree)).unwrap();
+        assert_eq!(tr, Tr::from_str("tr(K,{pk(A),{pk(B),pk(C)}})").unwrap());
+
+        // Deep, unbalanced trees are allowed
+        let mut builder = TapTreeBuilder::new();
+        for depth in 1..=128 {
+            builder = builder.add_leaf(depth, leaf("pk(A)")).unwrap();
+        }
+        let tree = builder
+            .add_leaf(128, leaf("pk(B)"))
+            .unwrap()
+            .finalize()
+            .unwrap();
+        assert_eq!(tree.iter().count(), 129);
+
+        assert_eq!(
+            TapTreeBuilder::<String>::new().finalize().unwrap_err(),
+            Error::TapTreeBuilder(TaprootBuilderError::EmptyTree)
+        );
+        assert_eq!(
+            TapTreeBuilder::new()
+                .add_leaf(1, leaf("pk(A)"))
+                .unwrap()
+                .finalize()
+                .unwrap_err(),
+            Error::TapTreeBuilder(TaprootBuilderError::IncompleteTree)
+        );
+        assert_eq!(
+            TapTreeBuilder::new()
+                .add_leaf(2, leaf("pk(A)"))
+                .unwrap()
+                .add_leaf(1, leaf("pk(B)"))
+                .unwrap_err(),
+            Error::TapTreeBuilder(TaprootBuilderError::NodeNotInDfsOrder)
+        );
+        assert_eq!(
+            TapTreeBuilder::new()
+                .add_leaf(0, leaf("pk(A)"))
+                .unwrap()
+                .add_leaf(0, leaf("pk(B)"))
+                .unwrap_err(),
+            Error::TapTreeBuilder(TaprootBuilderError::OverCompleteTree)
+        );
+        assert_eq!(
+            TapTreeBuilder::new()
+                .add_leaf(1, leaf("pk(A)"))
+                .unwrap()
+                .add_leaf(1, leaf("pk(B)"))
+                .unwrap()
+                .add_leaf(1, leaf("pk(C)"))
+                .unwrap_err(),
+            Error::TapTreeBuilder(TaprootBuilderError::OverCompleteTree)
+        );
+        assert_eq!(
+            TapTreeBuilder::new()
+                .add_leaf(129, leaf("pk(A)"))
+                .unwrap_err(),
+            Error::TapTreeBuilder(TaprootBuilderError::InvalidMerkleTreeDepth(129))
+        );
+    }
+
+    #[test]
+    fn iter_leaves() {
+        let keys: Vec<_> = [
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+            "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+            "0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8",
+        ]
+        .iter()
+        .map(|k| bitcoin::PublicKey::from_str(k).unwrap())
+        .collect();
+        let tr = Tr::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),{{pk({}),pk({})}}}})",
+            keys[0], keys[1], keys[2], keys[1]
+        ))
+        .unwrap();
+
+        let secp = secp256k1::Secp256k1::verification_only();
+        let output_key = tr.spend_info().output_key().to_inner();
+        let leaves: Vec<_> = tr.iter_leaves().collect();
+        assert_eq!(
+            leaves.iter().map(|l| l.depth).collect::<Vec<_>>(),
+            vec![1, 2, 2]
+        );
+        for leaf in &leaves {
+            assert_eq!(leaf.script, leaf.miniscript.encode());
+            assert_eq!(leaf.merkle_branch().as_inner().len(), leaf.depth as usize);
+            assert_eq!(
+                leaf.leaf_hash,
+                TapLeafHash::from_script(&leaf.script, LeafVersion::TapScript)
+            );
+            assert!(leaf
+                .control_block
+                .verify_taproot_commitment(&secp, output_key, &leaf.script));
+        }
+        // The repeated leaf gets the merkle path of each of its occurrences
+        assert_eq!(leaves[0].script, leaves[2].script);
+        assert_ne!(leaves[0].control_block, leaves[2].control_block);
+    }
+
+    #[test]
+    fn spend_info_cache() {
+        let tr = Tr::<bitcoin::PublicKey>::from_str(
+            "tr(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443,pk(0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07))",
+        )
+        .unwrap();
+        let spend_info = tr.spend_info();
+        // Computed once, then shared by later calls and clones
+        assert!(Arc::ptr_eq(&spend_info, &tr.spend_info()));
+        assert!(Arc::ptr_eq(&spend_info, &tr.clone().spend_info()));
+        tr.address(Network::Bitcoin);
+        assert!(Arc::ptr_eq(&spend_info, &tr.spend_info()));
+    }
+
+    #[test]
+    fn opaque_leaves() {
+        let keys = [
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+            "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+            "0231c7d3fc85c148717848033ce276ae2b464a4e2c367ed33886cc428b8af48ff8",
+        ];
+        let full = Tr::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),{{pk({}),pk({})}}}})",
+            keys[0], keys[1], keys[2], keys[1]
+        ))
+        .unwrap();
+        let (_, hidden) = full.iter_scripts().nth(1).unwrap();
+        let hash = TapLeafHash::from_script(&hidden.encode(), LeafVersion::TapScript);
+        let partial = Tr::<bitcoin::PublicKey>::from_str(&format!(
+            "tr({},{{pk({}),{{opaque({}),pk({})}}}})",
+            keys[0], keys[1], hash, keys[1]
+        ))
+        .unwrap();
+        assert!(partial.taptree().as_ref().unwrap().has_opaque_leaves());
+        assert_eq!(
+            Tr::<bitcoin::PublicKey>::from_str(&partial.to_string()).unwrap(),
+            partial
+        );
+
+        // The output key and the spending data of the known leaves are those
+        // of the full tree
+        assert_eq!(partial.script_pubkey(), full.script_pubkey());
+        let known: Vec<_> = partial.iter_leaves().collect();
+        let all: Vec<_> = full.iter_leaves().collect();
+        assert_eq!(known.len(), 2);
+        assert_eq!(known[0].control_block, all[0].control_block);
+        assert_eq!(known[1].control_block, all[2].control_block);
+
+        // The spending conditions of the whole tree are unknown
+        assert_eq!(partial.lift().unwrap_err(), Error::OpaqueTapLeaf);
+
+        let built = TapTreeBuilder::new()
+            .add_leaf(
+                1,
+                Miniscript::from_str(&format!("pk({})", keys[1])).unwrap(),
+            )
+            .unwrap()
+            .add_opaque_leaf(2, hash)
+            .unwrap()
+            .add_leaf(
+                2,
+                Miniscript::from_str(&format!("pk({})", keys[1])).unwrap(),
+            )
+            .unwrap()
+            .finalize()
+            .unwrap();
+        assert_eq!(partial.taptree().as_ref(), Some(&built));
+    }
 }
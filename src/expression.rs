@@ -14,18 +14,52 @@
 
 //! # Function-like Expression Language
 //!
+//! Descriptors, miniscripts and policies are all written as nested
+//! expressions of the form `name(arg,...)`. A [`Tree`] is such an expression
+//! parsed from a string, without any meaning attached to its names, so that
+//! tools like formatters, linters or syntax highlighters can work on the
+//! strings of this crate without implementing the grammar again.
+//!
+//! ```
+//! use miniscript::expression::Tree;
+//!
+//! let s = "wsh(or_d(pk(A),and_v(v:pk(B),older(144))))";
+//! let tree = Tree::from_str(s).unwrap();
+//! assert_eq!(tree.args[0].name, "or_d");
+//! // Byte range of an expression in `s`
+//! let older = tree.iter().find(|node| node.name == "older").unwrap();
+//! assert_eq!(&s[older.span()], "older(144)");
+//! // Print the tree back, or indent it with the alternate flag
+//! assert_eq!(tree.to_string(), s);
+//! assert_eq!(format!("{:#}", tree.args[0].args[1]), "and_v(\n  v:pk(B),\n  older(144)\n)");
+//! ```
+//!
+use core::ops::Range;
 use core::str::FromStr;
+use core::{cmp, fmt};
+#[cfg(feature = "std")]
+use std::error;
 
+use crate::descriptor::checksum::verify_checksum_located;
 use crate::prelude::*;
-use crate::{errstr, Error, MAX_RECURSION_DEPTH};
+use crate::{errstr, Error, LocatedError, MAX_RECURSION_DEPTH};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// A token of the form `x(...)` or `x`
+///
+/// The branches of a taproot tree are written `{x,y}`, and parsed as a token
+/// with an empty name.
+///
+/// New fields may be added to the struct, which is built with [`Tree::new`]
+/// outside of this crate.
+#[non_exhaustive]
 pub struct Tree<'a> {
     /// The name `x`
     pub name: &'a str,
     /// The comma-separated contents of the `(...)`, if any
     pub args: Vec<Tree<'a>>,
+    /// Byte offset of the name in the parsed string
+    pub pos: usize,
 }
 // or_b(pk(A),pk(B))
 //
@@ -33,70 +67,137 @@ pub struct Tree<'a> {
 // or_b()
 // pk(A), pk(B)
 
+impl<'a> Tree<'a> {
+    /// Creates a token named `name` with the arguments `args`, whose name is
+    /// at the byte offset `pos` of the string it is parsed from
+    pub fn new(name: &'a str, args: Vec<Tree<'a>>, pos: usize) -> Self {
+        Tree { name, args, pos }
+    }
+
+    /// Byte range of the token in the parsed string, from its name to its
+    /// closing bracket if any
+    pub fn span(&self) -> Range<usize> {
+        let mut last = self;
+        let mut closing = 0;
+        while let Some(arg) = last.args.last() {
+            last = arg;
+            closing += 1;
+        }
+        self.pos..last.pos + last.name.len() + closing
+    }
+
+    /// Iterates over the token and all the tokens it contains, parents first
+    /// and in the order they are written
+    pub fn iter(&self) -> TreeIter<'_, 'a> {
+        TreeIter { stack: vec![self] }
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let (open, close) = if self.name.is_empty() {
+            ("{", "}")
+        } else {
+            ("(", ")")
+        };
+        f.write_str(self.name)?;
+        if self.args.is_empty() {
+            return Ok(());
+        }
+        // Keep the tokens with terminal arguments only on one line
+        let multiline = f.alternate() && self.args.iter().any(|arg| !arg.args.is_empty());
+        f.write_str(open)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            if multiline {
+                write!(f, "\n{:1$}", "", 2 * (depth + 1))?;
+            }
+            arg.fmt_indented(f, depth + 1)?;
+        }
+        if multiline {
+            write!(f, "\n{:1$}", "", 2 * depth)?;
+        }
+        f.write_str(close)
+    }
+}
+
+/// Writes the token as it is parsed or, with the alternate flag `{:#}`, with
+/// one argument per line indented by two spaces for the tokens with nested
+/// arguments
+impl<'a> fmt::Display for Tree<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+/// Iterator over a [`Tree`] and the tokens it contains, see [`Tree::iter`]
+#[derive(Debug, Clone)]
+pub struct TreeIter<'t, 'a> {
+    stack: Vec<&'t Tree<'a>>,
+}
+
+impl<'t, 'a> Iterator for TreeIter<'t, 'a> {
+    type Item = &'t Tree<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.args.iter().rev());
+        Some(node)
+    }
+}
+
 /// A trait for extracting a structure from a Tree representation in token form
 pub trait FromTree: Sized {
     /// Extract a structure from Tree representation
     fn from_tree(top: &Tree) -> Result<Self, Error>;
 }
 
+/// A trait for parsing a structure from a string like
+/// [`FromStr`](core::str::FromStr), with the error located in the string
+///
+/// ```
+/// use miniscript::expression::FromStrLocated;
+/// use miniscript::Descriptor;
+///
+/// let err = Descriptor::<String>::from_str_located("wsh(and_v(v:pk(A),olde(1)))").unwrap_err();
+/// assert_eq!(err.position(), Some(18));
+/// ```
+pub trait FromStrLocated: Sized {
+    /// Parse a structure from a string, returning the error along with the
+    /// byte offset of the expression in which it was found
+    fn from_str_located(s: &str) -> Result<Self, LocatedError>;
+}
+
 enum Found {
     Nothing,
     LBracket(usize), // Either a left ( or {
     Comma(usize),
     RBracket(usize), // Either a right ) or }
+    NonAscii(usize),
 }
 
+// Finds the next delimiter in a single scan of the bytes, which also rejects
+// non-ASCII ones so that `sl` can be sliced at any position found
 fn next_expr(sl: &str, delim: char) -> Found {
-    let mut found = Found::Nothing;
-    if delim == '(' {
-        for (n, ch) in sl.char_indices() {
-            match ch {
-                '(' => {
-                    found = Found::LBracket(n);
-                    break;
-                }
-                ',' => {
-                    found = Found::Comma(n);
-                    break;
-                }
-                ')' => {
-                    found = Found::RBracket(n);
-                    break;
-                }
-                _ => {}
-            }
+    let (open, close) = match delim {
+        '(' => (b'(', b')'),
+        '{' => (b'{', b'}'),
+        _ => unreachable!("{}", "Internal: delimiters in parsing must be '(' or '{'"),
+    };
+    // Round brackets nesting within a taptree leaf
+    let mut leaf_depth = 0i32;
+    for (n, &byte) in sl.as_bytes().iter().enumerate() {
+        match byte {
+            _ if !byte.is_ascii() => return Found::NonAscii(n),
+            _ if byte == open => return Found::LBracket(n),
+            _ if byte == close => return Found::RBracket(n),
+            b',' if leaf_depth == 0 => return Found::Comma(n),
+            b'(' => leaf_depth += 1,
+            b')' => leaf_depth -= 1,
+            _ => {}
         }
-    } else if delim == '{' {
-        let mut new_count = 0;
-        for (n, ch) in sl.char_indices() {
-            match ch {
-                '{' => {
-                    found = Found::LBracket(n);
-                    break;
-                }
-                '(' => {
-                    new_count += 1;
-                }
-                ',' => {
-                    if new_count == 0 {
-                        found = Found::Comma(n);
-                        break;
-                    }
-                }
-                ')' => {
-                    new_count -= 1;
-                }
-                '}' => {
-                    found = Found::RBracket(n);
-                    break;
-                }
-                _ => {}
-            }
-        }
-    } else {
-        unreachable!("{}", "Internal: delimiters in parsing must be '(' or '{'");
     }
-    found
+    Found::Nothing
 }
 
 // Get the corresponding delim
@@ -112,64 +213,92 @@ impl<'a> Tree<'a> {
     /// Parse an expression with round brackets
     pub fn from_slice(sl: &'a str) -> Result<(Tree<'a>, &'a str), Error> {
         // Parsing TapTree or just miniscript
-        Self::from_slice_delim(sl, 0u32, '(')
+        Self::from_slice_delim(sl, 0, MAX_RECURSION_DEPTH, '(').map_err(Error::unlocated)
     }
 
+    /// Parse an expression starting at byte `pos` of the string being parsed,
+    /// the errors of which are located at their byte offset in that string
+    ///
+    /// The tree is built in a single pass over `sl` and without recursion, so
+    /// that its depth is only bounded by `max_depth`, the depth at which nodes
+    /// are rejected. Names are borrowed from `sl`, the only allocations being
+    /// the argument lists.
     pub(crate) fn from_slice_delim(
         mut sl: &'a str,
-        depth: u32,
+        mut pos: usize,
+        max_depth: u32,
         delim: char,
     ) -> Result<(Tree<'a>, &'a str), Error> {
-        if depth >= MAX_RECURSION_DEPTH {
-            return Err(Error::MaxRecursiveDepthExceeded);
-        }
+        // The function calls whose arguments are being parsed
+        let mut open: Vec<Tree<'a>> = vec![];
+        loop {
+            if open.len() as u32 >= max_depth {
+                return Err(Error::MaxRecursiveDepthExceeded(max_depth).at(pos));
+            }
 
-        match next_expr(sl, delim) {
-            // String-ending terminal
-            Found::Nothing => Ok((
-                Tree {
-                    name: sl,
-                    args: vec![],
-                },
-                "",
-            )),
-            // Terminal
-            Found::Comma(n) | Found::RBracket(n) => Ok((
-                Tree {
-                    name: &sl[..n],
-                    args: vec![],
-                },
-                &sl[n..],
-            )),
-            // Function call
-            Found::LBracket(n) => {
-                let mut ret = Tree {
-                    name: &sl[..n],
-                    args: vec![],
+            let mut node = match next_expr(sl, delim) {
+                Found::NonAscii(n) => {
+                    let ch = sl[n..].chars().next().expect("non-ASCII byte");
+                    return Err(Error::NonAscii(ch).at(pos + n));
+                }
+                // String-ending terminal
+                Found::Nothing => {
+                    let node = Tree {
+                        name: sl,
+                        args: vec![],
+                        pos,
+                    };
+                    pos += sl.len();
+                    sl = "";
+                    node
+                }
+                // Terminal
+                Found::Comma(n) | Found::RBracket(n) => {
+                    let node = Tree {
+                        name: &sl[..n],
+                        args: vec![],
+                        pos,
+                    };
+                    sl = &sl[n..];
+                    pos += n;
+                    node
+                }
+                // Function call
+                Found::LBracket(n) => {
+                    open.push(Tree {
+                        name: &sl[..n],
+                        args: vec![],
+                        pos,
+                    });
+                    sl = &sl[n + 1..];
+                    pos += n + 1;
+                    continue;
+                }
+            };
+
+            // Close the function calls which `node` ends
+            loop {
+                let parent = match open.last_mut() {
+                    Some(parent) => parent,
+                    None => return Ok((node, sl)),
                 };
+                parent.args.push(node);
 
-                sl = &sl[n + 1..];
-                loop {
-                    let (arg, new_sl) = Tree::from_slice_delim(sl, depth + 1, delim)?;
-                    ret.args.push(arg);
-
-                    if new_sl.is_empty() {
-                        return Err(Error::ExpectedChar(closing_delim(delim)));
-                    }
-
-                    sl = &new_sl[1..];
-                    match new_sl.as_bytes()[0] {
-                        b',' => {}
-                        last_byte => {
-                            if last_byte == closing_delim(delim) as u8 {
-                                break;
-                            } else {
-                                return Err(Error::ExpectedChar(closing_delim(delim)));
-                            }
-                        }
-                    }
+                let next_byte = match sl.as_bytes().first() {
+                    Some(&byte) => byte,
+                    None => return Err(Error::ExpectedChar(closing_delim(delim)).at(pos)),
+                };
+                if next_byte == b',' {
+                    sl = &sl[1..];
+                    pos += 1;
+                    break;
+                } else if next_byte == closing_delim(delim) as u8 {
+                    sl = &sl[1..];
+                    pos += 1;
+                    node = open.pop().expect("the parent is open");
+                } else {
+                    return Err(Error::ExpectedChar(closing_delim(delim)).at(pos));
                 }
-                Ok((ret, sl))
             }
         }
     }
@@ -177,20 +306,197 @@ impl<'a> Tree<'a> {
     /// Parses a tree from a string
     #[allow(clippy::should_implement_trait)] // Cannot use std::str::FromStr because of lifetimes.
     pub fn from_str(s: &'a str) -> Result<Tree<'a>, Error> {
-        // Filter out non-ASCII because we byte-index strings all over the
-        // place and Rust gets very upset when you splinch a string.
-        for ch in s.bytes() {
-            if !ch.is_ascii() {
-                return Err(Error::Unprintable(ch));
-            }
-        }
+        Tree::parse(s).map_err(Error::unlocated)
+    }
+
+    /// Parses a tree from a string, rejecting expressions nested `max_depth`
+    /// times or more with [`Error::MaxRecursiveDepthExceeded`]
+    ///
+    /// [`Tree::from_str`] allows the nesting of any valid miniscript. A lower
+    /// limit can be used to parse untrusted input, whose tree is then turned
+    /// into a descriptor or policy with [`FromTree::from_tree`].
+    pub fn from_str_with_max_depth(s: &'a str, max_depth: u32) -> Result<Tree<'a>, Error> {
+        Tree::parse_with_max_depth(s, max_depth).map_err(Error::unlocated)
+    }
+
+    /// Same as [`Tree::from_str`], with the error located in `s`
+    pub fn from_str_located(s: &'a str) -> Result<Tree<'a>, LocatedError> {
+        Tree::parse(s).map_err(LocatedError::from)
+    }
+
+    /// Parses a tree from a string, the errors of which are located in `s`
+    pub(crate) fn parse(s: &'a str) -> Result<Tree<'a>, Error> {
+        Tree::parse_with_max_depth(s, MAX_RECURSION_DEPTH)
+    }
 
-        let (top, rem) = Tree::from_slice(s)?;
+    /// Same as [`Tree::from_str_with_max_depth`], with the errors located
+    /// in `s`
+    pub(crate) fn parse_with_max_depth(s: &'a str, max_depth: u32) -> Result<Tree<'a>, Error> {
+        // Non-ASCII bytes are rejected while parsing, because we byte-index
+        // strings all over the place and Rust gets very upset when you
+        // splinch a string.
+        let (top, rem) = Tree::from_slice_delim(s, 0, max_depth, '(')?;
         if rem.is_empty() {
             Ok(top)
         } else {
-            Err(errstr(rem))
+            Err(errstr(rem).at(s.len() - rem.len()))
+        }
+    }
+}
+
+/// Checks that a descriptor or policy is only made of printable ASCII
+/// characters and spaces
+pub fn check_printable(s: &str) -> Result<(), LocatedError> {
+    printable(s).map_err(LocatedError::from)
+}
+
+// Same as `check_printable`, with the position of the error kept in it
+pub(crate) fn printable(s: &str) -> Result<(), Error> {
+    for (pos, ch) in s.char_indices() {
+        if !ch.is_ascii() {
+            return Err(Error::NonAscii(ch).at(pos));
+        } else if !ch.is_ascii_graphic() && ch != ' ' {
+            return Err(Error::Unprintable(ch as u8).at(pos));
+        }
+    }
+    Ok(())
+}
+
+/// How strictly the characters of a descriptor, miniscript or policy are
+/// checked when parsing it with [`ParseMode::parse`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseMode {
+    /// The string is parsed as it is, so that any character other than
+    /// printable ASCII is rejected, like with `from_str`
+    Strict,
+    /// ASCII whitespace, like the newline after a pasted key or the tabs and
+    /// newlines of an indented policy, is ignored, and so are comments
+    ///
+    /// A comment starts with a `#` which is at the beginning of a line or
+    /// after whitespace, and is followed by whitespace or the end of the
+    /// line. It runs to the end of the line. Any other `#` is kept, so that
+    /// the checksum of a descriptor is still verified when it is separated
+    /// from the descriptor, as in `wpkh(A) #checksum`.
+    Lenient,
+}
+
+impl ParseMode {
+    /// Parses `s` with `parse`, e.g. [`FromStrLocated::from_str_located`],
+    /// after removing its whitespace and comments in lenient mode. The errors are located
+    /// in `s` rather than in the string given to `parse`.
+    ///
+    /// ```
+    /// use miniscript::expression::{FromStrLocated, ParseMode};
+    /// use miniscript::Descriptor;
+    ///
+    /// let s = "wsh(or_d(\n\tpk(A), # hot key\n\tand_v(v:pk(B),older(144))\n))\n";
+    /// let err = ParseMode::Strict.parse(s, Descriptor::<String>::from_str_located).unwrap_err();
+    /// assert_eq!(err.position(), Some(9));
+    /// let desc = ParseMode::Lenient.parse(s, Descriptor::<String>::from_str_located).unwrap();
+    /// assert_eq!(desc.to_string(), "wsh(or_d(pk(A),and_v(v:pk(B),older(144))))#uj59xatx");
+    /// ```
+    pub fn parse<T, F>(self, s: &str, parse: F) -> Result<T, LocatedError>
+    where
+        F: FnOnce(&str) -> Result<T, LocatedError>,
+    {
+        if self == ParseMode::Strict {
+            return parse(s);
+        }
+        // The position in `s` of each byte of the stripped string
+        let mut positions = Vec::with_capacity(s.len() + 1);
+        let mut stripped = String::with_capacity(s.len());
+        let mut after_blank = true;
+        let mut in_comment = false;
+        let mut chars = s.char_indices().peekable();
+        while let Some((pos, ch)) = chars.next() {
+            if ch == '\n' {
+                in_comment = false;
+            } else if ch == '#' && after_blank {
+                in_comment = match chars.peek() {
+                    Some(&(_, next)) => next.is_ascii_whitespace(),
+                    None => true,
+                };
+            }
+            after_blank = ch.is_ascii_whitespace();
+            if !in_comment && !after_blank {
+                stripped.push(ch);
+                positions.extend(pos..pos + ch.len_utf8());
+            }
+        }
+        positions.push(s.len());
+        parse(&stripped).map_err(|e| locate(e, &positions))
+    }
+}
+
+/// Legacy names of fragments, still written by some software, along with the
+/// fragments they stand for
+pub const FRAGMENT_ALIASES: &[(&str, &str)] = &[("thresh_m", "multi")];
+
+/// Parses `s` with `parse`, after replacing the legacy names of fragments of
+/// [`FRAGMENT_ALIASES`] with their current names, which are then the ones
+/// written by `Display`. A descriptor checksum is checked against `s` as it
+/// is written, and removed. The errors are located in `s` rather than in the
+/// string given to `parse`.
+///
+/// ```
+/// use miniscript::expression::{self, FromStrLocated};
+/// use miniscript::Descriptor;
+///
+/// let s = "wsh(thresh_m(1,A,B))";
+/// assert!(Descriptor::<String>::from_str_located(s).is_err());
+/// let desc = expression::parse_with_aliases(s, Descriptor::<String>::from_str_located).unwrap();
+/// assert_eq!(desc.to_string(), "wsh(multi(1,A,B))#mjxm4tx0");
+/// ```
+pub fn parse_with_aliases<T, F>(s: &str, parse: F) -> Result<T, LocatedError>
+where
+    F: FnOnce(&str) -> Result<T, LocatedError>,
+{
+    let s = if s.contains('#') {
+        verify_checksum_located(s)?
+    } else {
+        s
+    };
+    // The position in `s` of each byte of the resolved string, the whole name
+    // of a fragment being at the position of its alias
+    let mut positions = Vec::with_capacity(s.len() + 1);
+    let mut resolved = String::with_capacity(s.len());
+    let mut start = 0;
+    for (pos, ch) in s.char_indices() {
+        if !"(),{}:@".contains(ch) {
+            continue;
+        }
+        let name = &s[start..pos];
+        match FRAGMENT_ALIASES
+            .iter()
+            .find(|&&(alias, _)| ch == '(' && alias == name)
+        {
+            Some(&(_, fragment)) => {
+                resolved.push_str(fragment);
+                positions.extend(fragment.bytes().map(|_| start));
+            }
+            None => {
+                resolved.push_str(name);
+                positions.extend(start..pos);
+            }
         }
+        resolved.push(ch);
+        positions.push(pos);
+        start = pos + 1;
+    }
+    resolved.push_str(&s[start..]);
+    positions.extend(start..=s.len());
+    parse(&resolved).map_err(|e| locate(e, &positions))
+}
+
+// Moves the position of an error in a string derived from the string being
+// parsed to its position in the latter, given the position of each byte
+fn locate(err: LocatedError, positions: &[usize]) -> LocatedError {
+    let end = positions.last().copied().unwrap_or(0);
+    LocatedError {
+        position: err
+            .position
+            .map(|pos| positions.get(pos).copied().unwrap_or(end)),
+        ..err
     }
 }
 
@@ -207,6 +513,34 @@ pub fn parse_num(s: &str) -> Result<u32, Error> {
     u32::from_str(s).map_err(|_| errstr(s))
 }
 
+// Same as `parse_num`, but the number may also be written in hex with a `0x`
+// prefix (e.g. `0x400000` for a BIP112 time-based relative lock) and its
+// digits may be grouped with single underscores, as in `1_000_000`. Only
+// policies accept these forms: they are written by hand, while miniscripts and
+// descriptors must keep a single encoding of each number to round-trip.
+pub(crate) fn parse_policy_num(s: &str) -> Result<u32, Error> {
+    let (digits, radix) = if s.starts_with("0x") || s.starts_with("0X") {
+        (&s[2..], 16)
+    } else {
+        (s, 10)
+    };
+    if digits.is_empty()
+        || digits.starts_with('_')
+        || digits.ends_with('_')
+        || digits.contains("__")
+    {
+        return Err(errstr(s));
+    }
+    let digits = digits.replace('_', "");
+    if !digits.chars().all(|ch| ch.is_digit(radix)) {
+        return Err(errstr(s));
+    }
+    if radix == 10 {
+        return parse_num(&digits);
+    }
+    u32::from_str_radix(&digits, radix).map_err(|_| errstr(s))
+}
+
 /// Attempts to parse a terminal expression
 pub fn terminal<T, F, Err>(term: &Tree, convert: F) -> Result<T, Error>
 where
@@ -214,9 +548,9 @@ where
     Err: ToString,
 {
     if term.args.is_empty() {
-        convert(term.name).map_err(|e| Error::Unexpected(e.to_string()))
+        convert(term.name).map_err(|e| Error::Unexpected(e.to_string()).at(term.pos))
     } else {
-        Err(errstr(term.name))
+        Err(errstr(term.name).at(term.pos))
     }
 }
 
@@ -250,10 +584,116 @@ where
     }
 }
 
+/// A fragment which is unknown, or given a number of arguments it does not
+/// take, in the language being parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownFragment {
+    /// The name of the fragment, as written
+    pub name: String,
+    /// The number of arguments it was given
+    pub n_args: usize,
+    /// The language being parsed, e.g. `Segwitv0 miniscript`
+    pub context: String,
+    /// The known fragments it may be a misspelling of, closest first
+    pub suggestions: Vec<&'static str>,
+    /// The fragments of the language
+    pub valid: Vec<&'static str>,
+}
+
+impl fmt::Display for UnknownFragment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown fragment «{}» with {} arguments in {}",
+            self.name, self.n_args, self.context
+        )?;
+        if !self.suggestions.is_empty() {
+            write!(f, ", did you mean {}?", self.suggestions.join(" or "))?;
+        }
+        write!(f, " Valid fragments are {}", self.valid.join(", "))
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for UnknownFragment {}
+
+/// A fragment of a language, for [`UnknownFragment`] errors
+pub(crate) struct Fragment {
+    /// Name of the fragment
+    pub name: &'static str,
+    /// How it is written, e.g. `and_v(X,Y)`
+    pub usage: &'static str,
+}
+
+impl UnknownFragment {
+    /// Builds the error for the fragment `name`, whose wrappers if any were
+    /// stripped into `frag_name`, among the known `fragments`
+    pub(crate) fn new<'f, I>(
+        name: &str,
+        frag_name: &str,
+        n_args: usize,
+        context: String,
+        fragments: I,
+    ) -> Self
+    where
+        I: IntoIterator<Item = &'f Fragment>,
+    {
+        let mut valid = vec![];
+        let mut close = vec![];
+        for frag in fragments {
+            valid.push(frag.usage);
+            let distance = if frag.name == frag_name {
+                // Wrong number of arguments
+                0
+            } else {
+                edit_distance(frag.name, frag_name)
+            };
+            if distance <= cmp::max(frag.name.len(), frag_name.len()) / 2 {
+                close.push((distance, frag.usage));
+            }
+        }
+        close.sort_by_key(|&(distance, _)| distance);
+        if let Some(&(0, _)) = close.first() {
+            close.retain(|&(distance, _)| distance == 0);
+        }
+        UnknownFragment {
+            name: name.to_owned(),
+            n_args,
+            context,
+            suggestions: close.into_iter().map(|(_, usage)| usage).collect(),
+            valid,
+        }
+    }
+}
+
+// Levenshtein distance between two ASCII strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = cmp::min(substitution, cmp::min(prev[j + 1], cur[j]) + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::parse_num;
+    use core::str::FromStr;
+
+    use super::{
+        check_printable, parse_num, parse_policy_num, parse_with_aliases, FromStrLocated,
+        ParseMode, Tree,
+    };
+    use crate::descriptor::checksum::{desc_checksum, ChecksumError};
+    use crate::policy::Concrete;
+    use crate::prelude::*;
+    use crate::{Descriptor, Error, LocatedError};
 
     #[test]
     fn test_parse_num() {
@@ -263,5 +703,209 @@ mod tests {
         assert!(parse_num("06").is_err());
         assert!(parse_num("+6").is_err());
         assert!(parse_num("-6").is_err());
+
+        assert!(parse_num("1_000").is_err());
+        assert!(parse_num("0x10").is_err());
+
+        assert_eq!(parse_policy_num("144").unwrap(), 144);
+        assert_eq!(parse_policy_num("1_000_000").unwrap(), 1_000_000);
+        assert_eq!(parse_policy_num("0x400000").unwrap(), 0x400000);
+        assert_eq!(parse_policy_num("0X0040_00FF").unwrap(), 0x4000ff);
+        assert_eq!(parse_policy_num("0xffffffff").unwrap(), u32::max_value());
+        assert!(parse_policy_num("0x1_0000_0000").is_err());
+        assert!(parse_policy_num("0x").is_err());
+        assert!(parse_policy_num("0x+6").is_err());
+        assert!(parse_policy_num("0x_6").is_err());
+        assert!(parse_policy_num("_6").is_err());
+        assert!(parse_policy_num("6_").is_err());
+        assert!(parse_policy_num("1__000").is_err());
+        assert!(parse_policy_num("0_6").is_err());
+        assert!(parse_policy_num("4_294_967_296").is_err());
+
+        // Miniscripts and descriptors only take plain decimal numbers
+        type Segwitv0Ms = crate::Miniscript<String, crate::Segwitv0>;
+        assert!(Segwitv0Ms::from_str_insane("and_v(v:pk(A),older(0x10))").is_err());
+        assert!(Segwitv0Ms::from_str_insane("multi(0x1,A,B)").is_err());
+        assert!(Descriptor::<String>::from_str("wsh(sortedmulti(1_0,A))").is_err());
+        let policy = Concrete::<String>::from_str("and(pk(A),older(0x0040_0001))").unwrap();
+        assert_eq!(policy.to_string(), "and(pk(A),older(4194305))");
+    }
+
+    #[test]
+    fn test_comments() {
+        let policy = "
+            # Either key A, or key B after a day
+            or(
+                pk(A),   # hot key
+                and(pk(B), older(144))
+            )
+        ";
+        let strip = |s: &str| ParseMode::Lenient.parse(s, |s: &str| Ok(s.to_owned()));
+        assert_eq!(strip(policy).unwrap(), "or(pk(A),and(pk(B),older(144)))");
+        assert_eq!(
+            strip("wpkh(A)#abcdefgh # with checksum\n").unwrap(),
+            "wpkh(A)#abcdefgh"
+        );
+        assert_eq!(strip("wpkh(A) #abcdefgh").unwrap(), "wpkh(A)#abcdefgh");
+        assert_eq!(
+            strip("wpkh(A)\n#abcdefgh\n#\n").unwrap(),
+            "wpkh(A)#abcdefgh"
+        );
+
+        // A checksum separated from the descriptor is still verified
+        let parse = |s: &str| ParseMode::Lenient.parse(s, Descriptor::<String>::from_str_located);
+        let desc = "wsh(or_d(pk(A),and_v(v:pk(B),older(144))))";
+        let checksum = desc_checksum(desc).unwrap();
+        assert!(parse(&format!("{} #{}", desc, checksum)).is_ok());
+        assert!(parse(&format!("{}\n#{}\n", desc, checksum)).is_ok());
+        let err = parse(&format!("{} #abcdefgh", desc)).unwrap_err();
+        match *err.error() {
+            Error::BadChecksum(ChecksumError::Mismatch { ref expected, .. }, _) => {
+                assert_eq!(*expected, checksum)
+            }
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        let err = ParseMode::Lenient.parse(policy, Concrete::<String>::from_str_located);
+        assert!(err.is_ok());
+        let err = ParseMode::Lenient.parse("pk(A) # key\nolder(1)", |s| {
+            Tree::from_str_located(s).map(|_| ())
+        });
+        assert_eq!(err.unwrap_err().position(), Some(12));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let tree = Tree::from_str("or(pk(A),and(pk(B),older(1)))").unwrap();
+        assert_eq!(tree.pos, 0);
+        assert_eq!(tree.args[1].pos, 9);
+        assert_eq!(tree.args[1].args[1].args[0].pos, 25);
+
+        let err = Tree::from_str_located("wsh(pk(A)))").unwrap_err();
+        assert_eq!(err.position(), Some(10));
+        assert_eq!(err.to_string(), "unexpected «)» at byte 10");
+        assert_eq!(
+            Tree::from_str_located("wsh(pk(A)").unwrap_err(),
+            Error::At(9, Box::new(Error::ExpectedChar(')'))).into()
+        );
+        let err = Tree::from_str_located("wsh(pk(\u{e9}))").unwrap_err();
+        assert_eq!(err.position(), Some(7));
+        assert_eq!(err.error(), &Error::NonAscii('\u{e9}'));
+        // `from_str` returns the error alone
+        assert_eq!(
+            Tree::from_str("wsh(pk(A)").unwrap_err(),
+            Error::ExpectedChar(')')
+        );
+
+        // Errors are located at the innermost expression they were found in
+        let err = Descriptor::<String>::from_str_located("wsh(and_v(v:pk(A),olde(1)))");
+        assert_eq!(err.unwrap_err().position(), Some(18));
+        let err = Descriptor::<bitcoin::PublicKey>::from_str_located("wpkh(02abc)");
+        assert_eq!(err.unwrap_err().position(), Some(5));
+        let err = Descriptor::<bitcoin::PublicKey>::from_str("wpkh(02abc)");
+        assert_eq!(LocatedError::from(err.unwrap_err()).position(), None);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let nested = format!("{}pk(A){}", "and_v(".repeat(10), ")".repeat(10));
+        assert!(Tree::from_str(&nested).is_ok());
+        // `A` is nested 11 times
+        assert!(Tree::from_str_with_max_depth(&nested, 12).is_ok());
+        assert_eq!(
+            Tree::from_str_with_max_depth(&nested, 11).unwrap_err(),
+            Error::MaxRecursiveDepthExceeded(11)
+        );
+        assert_eq!(
+            Tree::from_str_with_max_depth(&nested, 11)
+                .unwrap_err()
+                .to_string(),
+            "Recursive depth over 11 not permitted"
+        );
+
+        // Deeply nested input is rejected without exhausting the stack
+        let nested = format!("pk{}", "(".repeat(1_000_000));
+        let err = Tree::from_str_located(&nested).unwrap_err();
+        assert_eq!(err.position(), Some(404));
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        let s = "or(\n\tpk(A),\n\tolder(0x)\n)\n";
+        let err = Concrete::<String>::from_str_located(s).unwrap_err();
+        assert_eq!(
+            err,
+            Error::At(3, Box::new(Error::Unprintable(b'\n'))).into()
+        );
+        assert!(err.to_string().starts_with("unexpected whitespace '\\n'"));
+        assert_eq!(
+            ParseMode::Strict.parse(s, Concrete::<String>::from_str_located),
+            Err(err)
+        );
+        let err = ParseMode::Lenient.parse(s, Concrete::<String>::from_str_located);
+        assert_eq!(err.unwrap_err().position(), s.find("0x"));
+        assert!(ParseMode::Lenient
+            .parse(s, |s| Tree::from_str_located(s).map(|_| ()))
+            .is_ok());
+
+        assert!(check_printable("pk(A) ").is_ok());
+        assert_eq!(
+            check_printable("pk(A\x7f)").unwrap_err().position(),
+            Some(4)
+        );
+        assert_eq!(
+            check_printable("pk(A\x14)").unwrap_err().position(),
+            Some(4)
+        );
+        assert_eq!(
+            check_printable("pk(\u{e9})").unwrap_err(),
+            Error::At(3, Box::new(Error::NonAscii('\u{e9}'))).into()
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        fn parse(s: &str) -> Result<Descriptor<String>, LocatedError> {
+            parse_with_aliases(s, Descriptor::from_str_located)
+        }
+        assert_eq!(
+            parse("sh(or_d(thresh_m(1,A,B),and_v(v:thresh_m(1,C,D),older(1))))").unwrap(),
+            Descriptor::from_str("sh(or_d(multi(1,A,B),and_v(v:multi(1,C,D),older(1))))").unwrap()
+        );
+        // Only fragment names are replaced
+        assert!(parse("wsh(pk(thresh_m))").is_ok());
+        let s = "wsh(and_v(v:thresh_m(1,A,B),olde(1)))";
+        assert_eq!(parse(s).unwrap_err().position(), s.find("olde"));
+        // The checksum is the one of the legacy descriptor
+        let s = "wsh(thresh_m(1,A,B))";
+        let checksum = desc_checksum(s).unwrap();
+        assert!(parse(&format!("{}#{}", s, checksum)).is_ok());
+        assert!(parse("wsh(thresh_m(1,A,B))#mjxm4tx0").is_err());
+    }
+
+    #[test]
+    fn test_tree_api() {
+        let s = "or(pk(A),and(pk(B),older(1)))";
+        let tree = Tree::from_str(s).unwrap();
+        let names: Vec<_> = tree.iter().map(|node| node.name).collect();
+        assert_eq!(names, ["or", "pk", "A", "and", "pk", "B", "older", "1"]);
+        let spans: Vec<_> = tree.iter().map(|node| &s[node.span()]).collect();
+        assert_eq!(spans[0], s);
+        assert_eq!(spans[3], "and(pk(B),older(1))");
+        assert_eq!(spans[5], "B");
+        assert_eq!(tree.to_string(), s);
+        assert_eq!(tree.clone(), tree);
+
+        let leaf = |name, pos| Tree {
+            name,
+            pos,
+            args: vec![],
+        };
+        let branch = Tree {
+            name: "",
+            pos: 0,
+            args: vec![leaf("pk(A)", 1), leaf("pk(B)", 7)],
+        };
+        assert_eq!(branch.to_string(), "{pk(A),pk(B)}");
+        assert_eq!(format!("{:#}", branch), "{pk(A),pk(B)}");
     }
 }
@@ -0,0 +1,180 @@
+//! Interpreter Debugger
+//!
+//! Steps through the execution of a script one fragment at a time, exposing
+//! the stack after each step so that the point where a spend fails can be
+//! located.
+
+use super::stack::Element;
+use super::{Error, Iter, SatisfiedConstraint};
+use crate::miniscript::context::NoChecks;
+use crate::prelude::*;
+use crate::ScriptContext;
+
+/// What was evaluated in a [`DebugStep`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DebugLocation {
+    /// A fragment of the script, along with the number of its children
+    /// evaluated before this step
+    Fragment(String, usize),
+    /// The signature check of a spend without script (`pk`, `pkh`, `wpkh`
+    /// or a taproot key spend), with the key
+    KeySpend(String),
+    /// The report of the annex of a taproot spend
+    Annex,
+    /// The final check that the stack holds a single satisfied element
+    End,
+}
+
+/// A single step of the execution of a script
+#[derive(Debug)]
+pub struct DebugStep<'txin> {
+    /// What was evaluated
+    pub location: DebugLocation,
+    /// The stack after the step, its top last
+    pub stack: Vec<Element<'txin>>,
+    /// The constraint satisfied by the step, if any, or the reason the
+    /// execution failed
+    pub result: Result<Option<SatisfiedConstraint>, Error>,
+}
+
+/// Iterator over the steps of the execution of a script
+///
+/// Created by [`super::Interpreter::debugger_custom`] and
+/// [`super::Interpreter::debugger_assume_sigs`]. The last step is either the
+/// successful [`DebugLocation::End`] or the step which failed.
+pub struct Debugger<'intp, 'txin: 'intp> {
+    iter: Iter<'intp, 'txin>,
+    done: bool,
+}
+
+impl<'intp, 'txin: 'intp> Debugger<'intp, 'txin> {
+    pub(super) fn new(iter: Iter<'intp, 'txin>) -> Self {
+        Debugger { iter, done: false }
+    }
+}
+
+impl<'intp, 'txin: 'intp> Iterator for Debugger<'intp, 'txin>
+where
+    NoChecks: ScriptContext,
+{
+    type Item = DebugStep<'txin>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (location, res) = match self.iter.state.pop() {
+            Some(node_state) => {
+                let location =
+                    DebugLocation::Fragment(node_state.node.to_string(), node_state.n_evaluated);
+                (location, self.iter.eval_node(node_state))
+            }
+            None => {
+                let location = match (self.iter.public_key, self.iter.annex) {
+                    (Some(pk), _) => DebugLocation::KeySpend(pk.to_string()),
+                    (None, Some(_)) => DebugLocation::Annex,
+                    (None, None) => DebugLocation::End,
+                };
+                let res = self.iter.finish();
+                if location == DebugLocation::End {
+                    self.done = true;
+                }
+                (location, res)
+            }
+        };
+        let result = match res {
+            None => Ok(None),
+            Some(Ok(constraint)) => Ok(Some(constraint)),
+            Some(Err(e)) => {
+                self.done = true;
+                Err(e)
+            }
+        };
+        Some(DebugStep {
+            location,
+            stack: self.iter.stack.as_slice().to_vec(),
+            result,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::hashes::{sha256, Hash};
+
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::{Miniscript, Segwitv0};
+
+    #[test]
+    fn debugger() {
+        let preimage1 = [0x01; 32];
+        let preimage2 = [0x02; 32];
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "and_v(v:sha256({}),sha256({}))",
+            sha256::Hash::hash(&preimage1),
+            sha256::Hash::hash(&preimage2)
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+
+        let witness = Witness::from_vec(vec![
+            preimage2.to_vec(),
+            preimage1.to_vec(),
+            script.to_bytes(),
+        ]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let steps: Vec<_> = interpreter.debugger_assume_sigs().collect();
+        assert_eq!(
+            steps[0].location,
+            DebugLocation::Fragment(ms.to_string(), 0)
+        );
+        let last = steps.last().unwrap();
+        assert_eq!(last.location, DebugLocation::End);
+        assert!(last.result.is_ok());
+        assert!(last.stack.is_empty());
+        let constraints = steps
+            .iter()
+            .filter(|s| matches_constraint(&s.result))
+            .count();
+        assert_eq!(constraints, 2);
+
+        // The failing step is the verification of the hashlock with the wrong
+        // preimage, right after the hashlock itself is dissatisfied
+        let witness = Witness::from_vec(vec![
+            preimage2.to_vec(),
+            preimage2.to_vec(),
+            script.to_bytes(),
+        ]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let steps: Vec<_> = interpreter.debugger_assume_sigs().collect();
+        let hashlock = format!("sha256({})", sha256::Hash::hash(&preimage1));
+        let failed = &steps[steps.len() - 2..];
+        assert_eq!(
+            failed[0].location,
+            DebugLocation::Fragment(hashlock.clone(), 0)
+        );
+        assert_eq!(
+            failed[0].stack,
+            vec![Element::Push(&preimage2), Element::Dissatisfied]
+        );
+        assert_eq!(
+            failed[1].location,
+            DebugLocation::Fragment(format!("v:{}", hashlock), 1)
+        );
+        match failed[1].result {
+            Err(Error::VerifyFailed) => {}
+            ref res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    fn matches_constraint(res: &Result<Option<SatisfiedConstraint>, Error>) -> bool {
+        match *res {
+            Ok(Some(..)) => true,
+            _ => false,
+        }
+    }
+}
@@ -21,7 +21,7 @@ use bitcoin::hashes::hex::ToHex;
 use bitcoin::util::taproot;
 use bitcoin::{self, secp256k1};
 
-use super::BitcoinKey;
+use super::{BitcoinKey, UnknownTapLeaf};
 use crate::prelude::*;
 
 /// Detailed Error type for Interpreter
@@ -29,6 +29,8 @@ use crate::prelude::*;
 pub enum Error {
     /// Could not satisfy, absolute locktime not met
     AbsoluteLocktimeNotMet(u32),
+    /// The spent taproot leaf has unknown semantics, anyone can spend it
+    AnyoneCanSpendLeaf(UnknownTapLeaf),
     /// Cannot Infer a taproot descriptor
     /// Key spends cannot infer the internal key of the descriptor
     /// Inferring script spends is possible, but is hidden nodes are currently
@@ -66,6 +68,10 @@ pub enum Error {
     NonStandardSighash(Vec<u8>),
     /// Miniscript error
     Miniscript(crate::Error),
+    /// The script may execute more than 201 non-push opcodes
+    MaxOpCountExceeded,
+    /// The stack may exceed 1000 elements during execution
+    MaxStackSizeExceeded,
     /// MultiSig requires 1 extra zero element apart from the `k` signatures
     MissingExtraZeroMultiSig,
     /// Script abortion because of incorrect dissatisfaction for multisig.
@@ -73,6 +79,8 @@ pub enum Error {
     /// this error. This is network standardness assumption and miniscript only
     /// supports standard scripts
     MultiSigEvaluationError,
+    /// None of the descriptors has the scriptPubKey of the spent output
+    NoMatchingDescriptor,
     ///Witness must be empty for pre-segwit transactions
     NonEmptyWitness,
     ///ScriptSig must be empty for pure segwit transactions
@@ -129,6 +137,9 @@ impl fmt::Display for Error {
                 "required absolute locktime CLTV of {} blocks, not met",
                 n
             ),
+            Error::AnyoneCanSpendLeaf(ref reason) => {
+                write!(f, "anyone can spend taproot leaf: {}", reason)
+            }
             Error::CannotInferTrDescriptors => write!(f, "Cannot infer taproot descriptors"),
             Error::ControlBlockParse(ref e) => write!(f, "Control block parse error {}", e),
             Error::ControlBlockVerificationError => {
@@ -160,10 +171,13 @@ impl fmt::Display for Error {
             Error::NonEmptyWitness => f.write_str("legacy spend had nonempty witness"),
             Error::NonEmptyScriptSig => f.write_str("segwit spend had nonempty scriptsig"),
             Error::Miniscript(ref e) => write!(f, "parse error: {}", e),
+            Error::MaxOpCountExceeded => f.write_str("script may exceed the opcode limit"),
+            Error::MaxStackSizeExceeded => f.write_str("stack may exceed its size limit"),
             Error::MissingExtraZeroMultiSig => f.write_str("CMS missing extra zero"),
             Error::MultiSigEvaluationError => {
                 f.write_str("CMS script aborted, incorrect satisfaction/dissatisfaction")
             }
+            Error::NoMatchingDescriptor => f.write_str("no descriptor matches the spent output"),
             Error::PkEvaluationError(ref key) => write!(f, "Incorrect Signature for pk {}", key),
             Error::PkHashVerifyFail(ref hash) => write!(f, "Pubkey Hash check failed {}", hash),
             Error::PubkeyParseError => f.write_str("could not parse pubkey"),
@@ -193,11 +207,12 @@ impl fmt::Display for Error {
 
 #[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::Error::*;
 
         match self {
             AbsoluteLocktimeNotMet(_)
+            | AnyoneCanSpendLeaf(_)
             | CannotInferTrDescriptors
             | ControlBlockVerificationError
             | CouldNotEvaluate
@@ -212,8 +227,11 @@ impl error::Error for Error {
             | InvalidSchnorrSignature(_)
             | InvalidSchnorrSighashType(_)
             | NonStandardSighash(_)
+            | MaxOpCountExceeded
+            | MaxStackSizeExceeded
             | MissingExtraZeroMultiSig
             | MultiSigEvaluationError
+            | NoMatchingDescriptor
             | NonEmptyWitness
             | NonEmptyScriptSig
             | PubkeyParseError
@@ -0,0 +1,244 @@
+//! Verification Flags
+//!
+//! Chooses the rules enforced by the interpreter on top of the ones needed to
+//! interpret a spend as Miniscript, to emulate either consensus or the
+//! standardness policy of Bitcoin Core.
+
+use super::inner::{Inner, ScriptType};
+use super::stack::Stack;
+use super::Error;
+use crate::miniscript::limits::{MAX_OPS_PER_SCRIPT, MAX_STACK_SIZE};
+
+/// Script verification flags of the [`super::Interpreter`]
+///
+/// The default flags are the historical behavior of the interpreter: minimal
+/// `OP_IF` arguments and standard signatures, without resource limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VerifyFlags {
+    /// Require the `OP_IF` and `OP_NOTIF` arguments taken from the witness to
+    /// be empty or `1`. This is always enforced for tapscript, where it is a
+    /// consensus rule, and is a standardness rule for segwit v0.
+    pub minimal_if: bool,
+    /// Reject legacy and segwit v0 scripts which may execute more than 201
+    /// non-push opcodes
+    pub ops_limit: bool,
+    /// Reject spends whose stack may exceed 1000 elements during execution
+    pub stack_size_limit: bool,
+    /// Reject ECDSA signatures with a non-standard sighash type
+    pub standard_sigs: bool,
+}
+
+impl Default for VerifyFlags {
+    fn default() -> Self {
+        VerifyFlags {
+            minimal_if: true,
+            ops_limit: false,
+            stack_size_limit: false,
+            standard_sigs: true,
+        }
+    }
+}
+
+impl VerifyFlags {
+    /// The rules enforced by consensus
+    pub fn consensus() -> Self {
+        VerifyFlags {
+            minimal_if: false,
+            ops_limit: true,
+            stack_size_limit: true,
+            standard_sigs: false,
+        }
+    }
+
+    /// The rules enforced by the standardness policy of Bitcoin Core
+    pub fn standard() -> Self {
+        VerifyFlags {
+            minimal_if: true,
+            ops_limit: true,
+            stack_size_limit: true,
+            standard_sigs: true,
+        }
+    }
+
+    /// Checks the resource limits, which only depend on the script and the
+    /// initial stack
+    pub(super) fn check_limits(&self, inner: &Inner, stack: &Stack) -> Result<(), Error> {
+        let ms = match *inner {
+            Inner::Script(ref ms, ty) => Some((ms, ty)),
+            Inner::PublicKey(..) => None,
+        };
+        if let Some((ms, ty)) = ms {
+            if self.ops_limit && ty != ScriptType::Tr {
+                if let Some(op_count) = ms.ext.ops.op_count() {
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(Error::MaxOpCountExceeded);
+                    }
+                }
+            }
+        }
+        if self.stack_size_limit {
+            let exec_stack = ms
+                .and_then(|(ms, _)| ms.ext.exec_stack_elem_count_sat)
+                .unwrap_or(0);
+            if stack.as_slice().len() + exec_stack > MAX_STACK_SIZE {
+                return Err(Error::MaxStackSizeExceeded);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::secp256k1;
+
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::prelude::*;
+    use crate::{Miniscript, Segwitv0};
+
+    #[test]
+    fn verify_flags() {
+        let preimage = [0x01; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "or_i(sha256({}),sha256({}))",
+            hash, hash
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        // The branch is chosen with a non-minimal true
+        let witness = Witness::from_vec(vec![preimage.to_vec(), vec![2], script.to_bytes()]);
+        let interpret = |flags| {
+            Interpreter::from_txdata_with_flags(&spk, &script_sig, &witness, 0, 0, flags)
+                .unwrap()
+                .iter_assume_sigs()
+                .collect::<Result<Vec<_>, _>>()
+        };
+        match interpret(VerifyFlags::default()) {
+            Err(Error::UnexpectedStackElementPush) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(interpret(VerifyFlags::consensus()).unwrap().len(), 1);
+
+        // A signature with a non-standard sighash type
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let msg = secp256k1::Message::from_slice(&[1; 32]).unwrap();
+        let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        sig.push(0x04);
+        let script =
+            Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!("pk({})", pk))
+                .unwrap()
+                .encode();
+        let spk = script.to_v0_p2wsh();
+        let witness = Witness::from_vec(vec![sig, script.to_bytes()]);
+        let interpret = |flags| {
+            Interpreter::from_txdata_with_flags(&spk, &script_sig, &witness, 0, 0, flags)
+                .unwrap()
+                .iter_assume_sigs()
+                .collect::<Result<Vec<_>, _>>()
+        };
+        match interpret(VerifyFlags::standard()) {
+            Err(Error::EcdsaSig(bitcoin::EcdsaSigError::NonStandardSighashType(4))) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert_eq!(interpret(VerifyFlags::consensus()).unwrap().len(), 1);
+
+        // Each `a:sha256` executes seven opcodes, along with the OP_ADD
+        let policy = format!(
+            "thresh(1,sha256({}){})",
+            hash,
+            format!(",a:sha256({})", hash).repeat(29)
+        );
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&policy).unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let witness = Witness::from_vec(vec![script.to_bytes()]);
+        assert!(Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).is_ok());
+        match Interpreter::from_txdata_with_flags(
+            &spk,
+            &script_sig,
+            &witness,
+            0,
+            0,
+            VerifyFlags::consensus(),
+        ) {
+            Err(Error::MaxOpCountExceeded) => {}
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(..) => panic!("opcode limit not enforced"),
+        }
+    }
+
+    #[test]
+    fn non_standard_sighash() {
+        use bitcoin::blockdata::script::Builder;
+        use bitcoin::util::sighash::{Prevouts, SighashCache};
+        use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+
+        use crate::interpreter::{KeySigPair, SatisfiedConstraint};
+
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let spk = Script::new_p2pkh(&pk.pubkey_hash());
+        let prevouts = vec![TxOut {
+            value: 10_000,
+            script_pubkey: spk.clone(),
+        }];
+        for &hash_ty in &[0x04u32, 0x84] {
+            let mut tx = Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint::default(),
+                    script_sig: Script::new(),
+                    sequence: 0xffffffff,
+                    witness: Witness::default(),
+                }],
+                output: vec![TxOut {
+                    value: 9_000,
+                    script_pubkey: Script::new(),
+                }],
+            };
+            // The legacy sighash commits to the sighash type as it is
+            let sighash = SighashCache::new(&tx)
+                .legacy_signature_hash(0, &spk, hash_ty)
+                .unwrap();
+            let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+            let mut sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+            sig.push(hash_ty as u8);
+            tx.input[0].script_sig = Builder::new().push_slice(&sig).push_key(&pk).into_script();
+
+            let interpreter = Interpreter::from_txdata_with_flags(
+                &spk,
+                &tx.input[0].script_sig,
+                &tx.input[0].witness,
+                0,
+                0,
+                VerifyFlags::consensus(),
+            )
+            .unwrap();
+            let prevouts = Prevouts::All(&prevouts);
+            let constraints = interpreter
+                .iter(&secp, &tx, 0, &prevouts)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            match constraints[..] {
+                [SatisfiedConstraint::PublicKey {
+                    key_sig: KeySigPair::NonStandardEcdsa(key, _, ty),
+                }] => {
+                    assert_eq!(key, pk);
+                    assert_eq!(ty, hash_ty);
+                }
+                ref res => panic!("unexpected result {:?}", res),
+            }
+            assert_eq!(constraints[0].sighash_type().unwrap().to_u32(), hash_ty);
+        }
+    }
+}
@@ -13,13 +13,18 @@
 //
 
 use bitcoin;
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{read_scriptint, Instruction};
 use bitcoin::blockdata::witness::Witness;
 use bitcoin::hashes::{hash160, sha256, Hash};
 use bitcoin::util::taproot::{ControlBlock, TAPROOT_ANNEX_PREFIX};
 
 use super::{stack, BitcoinKey, Error, Stack, TypedHash160};
+use crate::descriptor::unknown_tap_leaf;
 use crate::miniscript::context::{NoChecks, ScriptContext};
+use crate::miniscript::limits::MAX_PUBKEYS_PER_MULTISIG;
 use crate::prelude::*;
+use crate::util::with_secp;
 use crate::{BareCtx, Legacy, Miniscript, MiniscriptKey, PkTranslator, Segwitv0, Tap};
 
 /// Attempts to parse a slice as a Bitcoin public key, checking compressedness
@@ -66,6 +71,17 @@ fn script_from_stack_elem<Ctx: ScriptContext>(
     }
 }
 
+/// The annex of a taproot witness, its last element if there are at least two
+/// elements and it starts with [`TAPROOT_ANNEX_PREFIX`]
+pub(super) fn taproot_annex(witness: &Witness) -> Option<&[u8]> {
+    match witness.last() {
+        Some(last) if witness.len() >= 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) => {
+            Some(last)
+        }
+        _ => None,
+    }
+}
+
 /// Helper type to indicate the origin of the bare pubkey that the interpereter uses
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum PubkeyType {
@@ -208,17 +224,11 @@ pub(super) fn from_txdata<'txin>(
         } else {
             let output_key = bitcoin::XOnlyPublicKey::from_slice(&spk[2..])
                 .map_err(|_| Error::XOnlyPublicKeyParseError)?;
-            let has_annex = wit_stack
-                .last()
-                .and_then(|x| x.as_push().ok())
-                .map(|x| !x.is_empty() && x[0] == TAPROOT_ANNEX_PREFIX)
-                .unwrap_or(false);
-            let has_annex = has_annex && (wit_stack.len() >= 2);
-            if has_annex {
-                // Annex is non-standard, bitcoin consensus rules ignore it.
-                // Our sighash structure and signature verification
-                // does not support annex, return error
-                return Err(Error::TapAnnexUnsupported);
+            if taproot_annex(witness).is_some() {
+                // Annex is non-standard, bitcoin consensus rules ignore it
+                // apart from committing to it in signatures. The interpreter
+                // keeps it aside and skips it here.
+                wit_stack.pop();
             }
             match wit_stack.len() {
                 0 => Err(Error::UnexpectedStackEnd),
@@ -234,12 +244,24 @@ pub(super) fn from_txdata<'txin>(
                     let tap_script = wit_stack.pop().ok_or(Error::UnexpectedStackEnd)?;
                     let ctrl_blk =
                         ControlBlock::from_slice(ctrl_blk).map_err(Error::ControlBlockParse)?;
+                    // Leaves with unknown semantics are not miniscript, report
+                    // them before failing to parse them
+                    let raw_script = bitcoin::Script::from(tap_script.as_push()?.to_vec());
+                    if let Some(reason) = unknown_tap_leaf(&raw_script, ctrl_blk.leaf_version) {
+                        return if with_secp(|secp| {
+                            ctrl_blk.verify_taproot_commitment(secp, output_key, &raw_script)
+                        }) {
+                            Err(Error::AnyoneCanSpendLeaf(reason))
+                        } else {
+                            Err(Error::ControlBlockVerificationError)
+                        };
+                    }
                     let tap_script = script_from_stack_elem::<Tap>(&tap_script)?;
                     let ms = tap_script.to_no_checks_ms();
-                    // Creating new contexts is cheap
-                    let secp = bitcoin::secp256k1::Secp256k1::verification_only();
                     let tap_script = tap_script.encode();
-                    if ctrl_blk.verify_taproot_commitment(&secp, output_key, &tap_script) {
+                    if with_secp(|secp| {
+                        ctrl_blk.verify_taproot_commitment(secp, output_key, &tap_script)
+                    }) {
                         Ok((
                             Inner::Script(ms, ScriptType::Tr),
                             wit_stack,
@@ -323,7 +345,26 @@ pub(super) fn from_txdata<'txin>(
                     }
                 }
                 // normal p2sh parsed in Legacy context
-                let miniscript = script_from_stack_elem::<Legacy>(&elem)?;
+                let miniscript = match script_from_stack_elem::<Legacy>(&elem) {
+                    Ok(miniscript) => miniscript,
+                    Err(e) => {
+                        let slice = elem.as_push()?;
+                        let script = bitcoin::Script::from(slice.to_owned());
+                        let multi = legacy_multisig(&script).ok_or(e)?;
+                        if !wit_stack.is_empty() {
+                            return Err(Error::NonEmptyWitness);
+                        }
+                        let scripthash = hash160::Hash::hash(slice);
+                        if *spk != bitcoin::Script::new_p2sh(&scripthash.into()) {
+                            return Err(Error::IncorrectScriptHash);
+                        }
+                        return Ok((
+                            Inner::Script(multi, ScriptType::Sh),
+                            ignore_multisig_dummy(ssig_stack),
+                            Some(script),
+                        ));
+                    }
+                };
                 let script = miniscript.encode();
                 let miniscript = miniscript.to_no_checks_ms();
                 if wit_stack.is_empty() {
@@ -347,19 +388,84 @@ pub(super) fn from_txdata<'txin>(
     } else {
         if wit_stack.is_empty() {
             // Bare script parsed in BareCtx
-            let miniscript = Miniscript::<bitcoin::PublicKey, BareCtx>::parse_insane(spk)?;
-            let miniscript = miniscript.to_no_checks_ms();
-            Ok((
-                Inner::Script(miniscript, ScriptType::Bare),
-                ssig_stack,
-                Some(spk.clone()),
-            ))
+            match Miniscript::<bitcoin::PublicKey, BareCtx>::parse_insane(spk) {
+                Ok(miniscript) => Ok((
+                    Inner::Script(miniscript.to_no_checks_ms(), ScriptType::Bare),
+                    ssig_stack,
+                    Some(spk.clone()),
+                )),
+                Err(e) => match legacy_multisig(spk) {
+                    Some(multi) => Ok((
+                        Inner::Script(multi, ScriptType::Bare),
+                        ignore_multisig_dummy(ssig_stack),
+                        Some(spk.clone()),
+                    )),
+                    None => Err(Error::from(e)),
+                },
+            }
         } else {
             Err(Error::NonEmptyWitness)
         }
     }
 }
 
+/// Recognizes a pre-miniscript `OP_CHECKMULTISIG` script, which is not valid
+/// Miniscript as is, and normalizes it into a `multi`
+///
+/// Such scripts were created before Miniscript, e.g. with non-minimal pushes
+/// of the threshold or of the number of keys, and are still found in old bare
+/// and p2sh outputs.
+fn legacy_multisig(script: &bitcoin::Script) -> Option<Miniscript<BitcoinKey, NoChecks>> {
+    fn read_num(ins: &Instruction) -> Option<usize> {
+        let n = match *ins {
+            Instruction::Op(op) => match op.classify(opcodes::ClassifyContext::Legacy) {
+                opcodes::Class::PushNum(n) => n as i64,
+                _ => return None,
+            },
+            Instruction::PushBytes(bytes) => read_scriptint(bytes).ok()?,
+        };
+        if n >= 1 && n <= MAX_PUBKEYS_PER_MULTISIG as i64 {
+            Some(n as usize)
+        } else {
+            None
+        }
+    }
+
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (last, rest) = instructions.split_last()?;
+    if *last != Instruction::Op(opcodes::all::OP_CHECKMULTISIG) || rest.len() < 3 {
+        return None;
+    }
+    let k = read_num(&rest[0])?;
+    let n = read_num(&rest[rest.len() - 1])?;
+    let keys = rest[1..rest.len() - 1]
+        .iter()
+        .map(|ins| match *ins {
+            Instruction::PushBytes(bytes) => pk_from_slice(bytes, false).ok(),
+            Instruction::Op(..) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if keys.len() != n || k > n {
+        return None;
+    }
+    Miniscript::<bitcoin::PublicKey, Legacy>::from_ast(crate::Terminal::Multi(k, keys))
+        .ok()
+        .map(|ms| ms.to_no_checks_ms())
+}
+
+/// Replaces the extra element consumed by `OP_CHECKMULTISIG`, at the bottom of
+/// the stack, with the empty push Miniscript expects
+///
+/// Before BIP147 made it a consensus rule, this dummy element could be any
+/// push, and pre-miniscript multisig spends did not always use an empty one.
+fn ignore_multisig_dummy(stack: Stack) -> Stack {
+    let mut elems = stack.as_slice().to_vec();
+    if let Some(dummy) = elems.first_mut() {
+        *dummy = stack::Element::Dissatisfied;
+    }
+    elems.into()
+}
+
 // Convert a miniscript from a well-defined context to a no checks context.
 // We need to parse insane scripts because these scripts are obtained from already
 // created transaction possibly already confirmed in a block.
@@ -901,4 +1007,73 @@ mod tests {
         assert_eq!(stack, Stack::from(vec![]));
         assert_eq!(script_code, Some(witness_script));
     }
+
+    #[test]
+    fn script_legacy_multisig() {
+        let fixed = fixed_test_data();
+        // The threshold is pushed as a byte rather than with OP_1
+        let script = script::Builder::new()
+            .push_slice(&[1])
+            .push_key(&fixed.pk_comp)
+            .push_key(&fixed.pk_uncomp)
+            .push_opcode(opcodes::all::OP_PUSHNUM_2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert!(Miniscript::<bitcoin::PublicKey, Legacy>::parse_insane(&script).is_err());
+        let multi = Miniscript::<bitcoin::PublicKey, Legacy>::from_str_insane(&format!(
+            "multi(1,{},{})",
+            fixed.pk_comp, fixed.pk_uncomp
+        ))
+        .unwrap()
+        .to_no_checks_ms();
+        let sig = [0x30; 71];
+        let empty_wit = Witness::default();
+
+        // bare, with a non-empty dummy element
+        let script_sig = script::Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_slice(&sig)
+            .into_script();
+        let (inner, stack, script_code) =
+            from_txdata(&script, &script_sig, &empty_wit).expect("parse txdata");
+        assert_eq!(inner, Inner::Script(multi.clone(), ScriptType::Bare));
+        assert_eq!(
+            stack,
+            Stack::from(vec![
+                stack::Element::Dissatisfied,
+                stack::Element::Push(&sig)
+            ])
+        );
+        assert_eq!(script_code, Some(script.clone()));
+
+        // p2sh
+        let spk = Script::new_p2sh(&hash160::Hash::hash(&script[..]).into());
+        let script_sig = script::Builder::new()
+            .push_slice(&[0xff])
+            .push_slice(&sig)
+            .push_slice(&script[..])
+            .into_script();
+        let (inner, stack, script_code) =
+            from_txdata(&spk, &script_sig, &empty_wit).expect("parse txdata");
+        assert_eq!(inner, Inner::Script(multi, ScriptType::Sh));
+        assert_eq!(
+            stack,
+            Stack::from(vec![
+                stack::Element::Dissatisfied,
+                stack::Element::Push(&sig)
+            ])
+        );
+        assert_eq!(script_code, Some(script.clone()));
+
+        // more keys than announced
+        let script = script::Builder::new()
+            .push_slice(&[1])
+            .push_key(&fixed.pk_comp)
+            .push_key(&fixed.pk_uncomp)
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let err = from_txdata(&script, &script_sig, &empty_wit).unwrap_err();
+        assert_eq!(&err.to_string()[0..12], "parse error:");
+    }
 }
@@ -0,0 +1,313 @@
+//! Constraint Locations
+//!
+//! Locates the fragments which produced the constraints satisfied by a spend,
+//! both in the Miniscript AST and in the bytes of the script, so that the
+//! branch of a script which was used on-chain can be highlighted. Failures
+//! are located the same way, along with the witness element being consumed.
+
+use core::ops::Range;
+use core::{fmt, ptr};
+#[cfg(feature = "std")]
+use std::error;
+
+use super::{BitcoinKey, Error, Iter, SatisfiedConstraint};
+use crate::miniscript::context::NoChecks;
+use crate::prelude::*;
+use crate::{Miniscript, ScriptContext, Terminal};
+
+/// Location of a fragment in the script being interpreted
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FragmentLocation {
+    /// Indices of the children leading from the root of the script to the
+    /// fragment, in the order of [`Miniscript::branches`]
+    pub path: Vec<usize>,
+    /// Byte range of the fragment in the script: the scriptCode, or the leaf
+    /// script for taproot script spends
+    pub script_range: Range<usize>,
+}
+
+/// A [`SatisfiedConstraint`] along with the fragment which produced it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocatedConstraint {
+    /// The satisfied constraint
+    pub constraint: SatisfiedConstraint,
+    /// Location of the fragment, `None` for spends without a script
+    pub location: Option<FragmentLocation>,
+}
+
+/// An interpreter [`Error`] along with where it happened
+#[derive(Debug)]
+pub struct LocatedError {
+    /// The error
+    pub error: Error,
+    /// Location of the failing fragment, `None` for spends without a script
+    /// and for failures of the final check of the stack
+    pub location: Option<FragmentLocation>,
+    /// Byte offset in the script of the failing opcode, the last one of the
+    /// failing fragment
+    pub script_offset: Option<usize>,
+    /// Index of the last witness element consumed before failing, or of the
+    /// scriptSig push for legacy spends, `None` if none was consumed
+    pub witness_index: Option<usize>,
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)?;
+        if let Some(offset) = self.script_offset {
+            write!(f, " at script offset {}", offset)?;
+        }
+        if let Some(index) = self.witness_index {
+            write!(f, " consuming witness element {}", index)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for LocatedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Iterator over the satisfied constraints along with their location
+///
+/// Created by [`Iter::located`], yields the same constraints as [`Iter`].
+pub struct LocatedIter<'intp, 'txin: 'intp> {
+    iter: Iter<'intp, 'txin>,
+    // Number of witness elements before evaluating the script
+    n_txin: usize,
+}
+
+impl<'intp, 'txin: 'intp> Iter<'intp, 'txin> {
+    /// Locate every satisfied constraint in the script
+    pub fn located(self) -> LocatedIter<'intp, 'txin> {
+        LocatedIter {
+            n_txin: self.stack.n_txin(),
+            iter: self,
+        }
+    }
+}
+
+impl<'intp, 'txin: 'intp> Iterator for LocatedIter<'intp, 'txin>
+where
+    NoChecks: ScriptContext,
+{
+    type Item = Result<LocatedConstraint, LocatedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.iter.has_errored {
+            return None;
+        }
+        let mut res = None;
+        let mut node = None;
+        while let Some(node_state) = self.iter.state.pop() {
+            let current = node_state.node;
+            res = self.iter.eval_node(node_state);
+            if res.is_some() {
+                node = Some(current);
+                break;
+            }
+        }
+        if res.is_none() {
+            res = self.iter.finish();
+        }
+        let location = match (self.iter.script, node) {
+            (Some(script), Some(node)) => locate(script, node, 0),
+            _ => None,
+        };
+        match res? {
+            Ok(constraint) => Some(Ok(LocatedConstraint {
+                constraint,
+                location,
+            })),
+            Err(error) => {
+                self.iter.has_errored = true;
+                let script_offset = match (&location, node) {
+                    (Some(location), Some(node)) => {
+                        Some(last_instruction_offset(node, &location.script_range))
+                    }
+                    _ => None,
+                };
+                // The witness is consumed from its top, the last element
+                // consumed being the deepest one
+                let witness_index = if self.iter.stack.n_txin() < self.n_txin {
+                    Some(self.iter.stack.n_txin())
+                } else {
+                    None
+                };
+                Some(Err(LocatedError {
+                    error,
+                    location,
+                    script_offset,
+                    witness_index,
+                }))
+            }
+        }
+    }
+}
+
+// Offset of the last instruction of a fragment located at `range`. It is the
+// opcode checking the satisfaction of the fragment, except for `pk_k` which is
+// a single push and cannot fail on its own.
+fn last_instruction_offset(node: &Miniscript<BitcoinKey, NoChecks>, range: &Range<usize>) -> usize {
+    match node.node {
+        Terminal::PkK(..) => range.start,
+        _ => range.end - 1,
+    }
+}
+
+// Finds `target` in the subtree of `node`, which starts at byte `offset`
+fn locate(
+    node: &Miniscript<BitcoinKey, NoChecks>,
+    target: &Miniscript<BitcoinKey, NoChecks>,
+    offset: usize,
+) -> Option<FragmentLocation> {
+    if ptr::eq(node, target) {
+        return Some(FragmentLocation {
+            path: vec![],
+            script_range: offset..offset + node.script_size(),
+        });
+    }
+    let offsets = child_offsets(node);
+    for (i, (child, child_offset)) in node.branches().into_iter().zip(offsets).enumerate() {
+        if let Some(mut location) = locate(child, target, offset + child_offset) {
+            location.path.insert(0, i);
+            return Some(location);
+        }
+    }
+    None
+}
+
+// Offsets of the children of `node` within its encoding, in the order of
+// `Miniscript::branches`
+fn child_offsets(node: &Miniscript<BitcoinKey, NoChecks>) -> Vec<usize> {
+    match node.node {
+        Terminal::Check(..) | Terminal::Verify(..) | Terminal::ZeroNotEqual(..) => vec![0],
+        Terminal::Alt(..) | Terminal::Swap(..) => vec![1],
+        Terminal::DupIf(..) => vec![2],
+        Terminal::NonZero(..) => vec![3],
+        Terminal::AndV(ref l, _) | Terminal::AndB(ref l, _) | Terminal::OrB(ref l, _) => {
+            vec![0, l.script_size()]
+        }
+        Terminal::OrD(ref l, _) => vec![0, l.script_size() + 2],
+        Terminal::OrC(ref l, _) => vec![0, l.script_size() + 1],
+        Terminal::OrI(ref l, _) => vec![1, l.script_size() + 2],
+        Terminal::AndOr(ref a, _, ref c) => vec![
+            0,
+            a.script_size() + c.script_size() + 2,
+            a.script_size() + 1,
+        ],
+        Terminal::Thresh(_, ref subs) => {
+            let mut offsets = vec![0];
+            let mut offset = subs[0].script_size();
+            for sub in &subs[1..] {
+                offsets.push(offset);
+                // Each but the first sub is followed by OP_ADD
+                offset += sub.script_size() + 1;
+            }
+            offsets
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::hashes::{sha256, Hash};
+
+    use super::*;
+    use crate::interpreter::{HashLockType, Interpreter};
+    use crate::Segwitv0;
+
+    #[test]
+    fn located() {
+        let preimages = [[0x01; 32], [0x02; 32], [0x03; 32]];
+        let hashes: Vec<_> = preimages.iter().map(|p| sha256::Hash::hash(p)).collect();
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "and_v(v:sha256({}),or_d(sha256({}),sha256({})))",
+            hashes[0], hashes[1], hashes[2]
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        // Satisfy the first and last hashlocks, dissatisfying the middle one
+        let witness = Witness::from_vec(vec![
+            preimages[2].to_vec(),
+            [0xff; 32].to_vec(),
+            preimages[0].to_vec(),
+            script.to_bytes(),
+        ]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let located: Vec<_> = interpreter
+            .iter_assume_sigs()
+            .located()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(located.len(), 2);
+
+        // The first hashlock ends with the EQUALVERIFY of its `v:` wrapper, so
+        // only the last one can be compared byte for byte with its encoding
+        let expected = [(vec![0, 0], 0..39, 0), (vec![1, 1], 80..119, 2)];
+        for (constraint, &(ref path, ref range, index)) in located.iter().zip(&expected) {
+            match constraint.constraint {
+                SatisfiedConstraint::HashLock {
+                    hash: HashLockType::Sha256(hash),
+                    ..
+                } => assert_eq!(hash, hashes[index]),
+                ref c => panic!("unexpected constraint {:?}", c),
+            }
+            let location = constraint.location.as_ref().unwrap();
+            assert_eq!(location.path, *path);
+            assert_eq!(location.script_range, *range);
+        }
+        let hashlock = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "sha256({})",
+            hashes[2]
+        ))
+        .unwrap();
+        assert_eq!(&script[80..119], &hashlock.encode()[..]);
+    }
+
+    #[test]
+    fn located_error() {
+        let preimage = [0x01; 32];
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "and_v(v:sha256({}),sha256({}))",
+            sha256::Hash::hash(&preimage),
+            sha256::Hash::hash(&preimage)
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        // The first hashlock is dissatisfied, failing its `v:` wrapper
+        let witness = Witness::from_vec(vec![
+            preimage.to_vec(),
+            [0xff; 32].to_vec(),
+            script.to_bytes(),
+        ]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let err = interpreter
+            .iter_assume_sigs()
+            .located()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        match err.error {
+            Error::VerifyFailed => {}
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        let location = err.location.unwrap();
+        assert_eq!(location.path, vec![0]);
+        assert_eq!(location.script_range, 0..39);
+        assert_eq!(err.script_offset, Some(38));
+        assert_eq!(
+            script[38],
+            bitcoin::blockdata::opcodes::all::OP_EQUALVERIFY.into_u8()
+        );
+        assert_eq!(err.witness_index, Some(1));
+    }
+}
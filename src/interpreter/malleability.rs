@@ -0,0 +1,299 @@
+//! Witness Malleability
+//!
+//! Compares the witness of a spend with the canonical satisfaction of its
+//! script, the smallest non-malleable one which can be built from the data
+//! the witness reveals. Spends which differ from it took an unnecessary
+//! branch, revealed more than needed or dissatisfied a fragment in a
+//! non-minimal way, which third parties may use to alter the transaction or
+//! to pin it in mempools.
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::util::taproot::TapLeafHash;
+use bitcoin::{self, XOnlyPublicKey};
+
+use super::inner::{Inner, ScriptType};
+use super::stack::Element;
+use super::{BitcoinKey, Error, HashLockType, Interpreter, SatisfiedConstraint, TypedHash160};
+use crate::miniscript::context::NoChecks;
+use crate::miniscript::satisfy::Preimage32;
+use crate::prelude::*;
+use crate::{Miniscript, MiniscriptKey, PkTranslator, Satisfier, Tap};
+
+/// How the witness of a spend compares with the canonical satisfaction of
+/// its script
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WitnessMalleability {
+    /// The witness is the canonical satisfaction
+    Canonical,
+    /// The witness differs from the canonical satisfaction, given here
+    NonCanonical(Vec<Vec<u8>>),
+    /// No non-malleable satisfaction can be built from the data revealed by
+    /// the witness
+    Malleable,
+}
+
+impl<'txin> Interpreter<'txin> {
+    /// Checks whether the witness is the canonical satisfaction of the script
+    ///
+    /// The canonical satisfaction is built from the signatures and preimages
+    /// revealed by the witness, with the timelocks of the interpreter. The
+    /// signatures are not verified. Spends without a script are always
+    /// canonical.
+    pub fn witness_malleability(&self) -> Result<WitnessMalleability, Error> {
+        let script = match self.inner {
+            Inner::Script(ref ms, ty) => (ms, ty),
+            Inner::PublicKey(..) => return Ok(WitnessMalleability::Canonical),
+        };
+        let mut observed = Observed {
+            age: self.age,
+            lock_time: self.lock_time,
+            ..Default::default()
+        };
+        for constraint in self.iter_assume_sigs() {
+            observed.add(constraint?);
+        }
+        let canonical = match script {
+            (_, ScriptType::Tr) => {
+                let leaf = self.script_code.as_ref().expect("Tapscript is saved");
+                Miniscript::<XOnlyPublicKey, Tap>::parse_insane(leaf)?.satisfy(&observed)
+            }
+            (ms, _) => ms
+                .real_translate_pk::<_, NoChecks, _, _>(&mut ToFullKey)
+                .expect("Only full keys outside taproot")
+                .satisfy(&observed),
+        };
+        let canonical = match canonical {
+            Ok(canonical) => canonical,
+            Err(_) => return Ok(WitnessMalleability::Malleable),
+        };
+        let witness: Vec<Vec<u8>> = self
+            .stack
+            .as_slice()
+            .iter()
+            .map(|elem| match *elem {
+                Element::Push(data) => data.to_vec(),
+                Element::Satisfied => vec![1],
+                Element::Dissatisfied => vec![],
+            })
+            .collect();
+        if witness == canonical {
+            Ok(WitnessMalleability::Canonical)
+        } else {
+            Ok(WitnessMalleability::NonCanonical(canonical))
+        }
+    }
+}
+
+struct ToFullKey;
+
+impl PkTranslator<BitcoinKey, bitcoin::PublicKey, ()> for ToFullKey {
+    fn pk(&mut self, pk: &BitcoinKey) -> Result<bitcoin::PublicKey, ()> {
+        match *pk {
+            BitcoinKey::Fullkey(pk) => Ok(pk),
+            BitcoinKey::XOnlyPublicKey(..) => Err(()),
+        }
+    }
+
+    fn pkh(&mut self, pkh: &TypedHash160) -> Result<hash160::Hash, ()> {
+        match *pkh {
+            TypedHash160::FullKey(hash) => Ok(hash),
+            TypedHash160::XonlyKey(..) => Err(()),
+        }
+    }
+}
+
+// The data revealed by a witness
+#[derive(Default)]
+struct Observed {
+    ecdsa_sigs: Vec<(bitcoin::PublicKey, bitcoin::EcdsaSig)>,
+    schnorr_sigs: Vec<(XOnlyPublicKey, bitcoin::SchnorrSig)>,
+    preimages: Vec<(HashLockType, Preimage32)>,
+    age: u32,
+    lock_time: u32,
+}
+
+impl Observed {
+    fn add(&mut self, constraint: SatisfiedConstraint) {
+        match constraint {
+            SatisfiedConstraint::PublicKey { key_sig }
+            | SatisfiedConstraint::PublicKeyHash { key_sig, .. } => match key_sig {
+                super::KeySigPair::Ecdsa(pk, sig) => self.ecdsa_sigs.push((pk, sig)),
+                // A `Satisfier` only provides signatures of standard types
+                super::KeySigPair::NonStandardEcdsa(..) => {}
+                super::KeySigPair::Schnorr(pk, sig) => self.schnorr_sigs.push((pk, sig)),
+            },
+            SatisfiedConstraint::HashLock { hash, preimage } => {
+                self.preimages.push((hash, preimage))
+            }
+            SatisfiedConstraint::RelativeTimelock { .. }
+            | SatisfiedConstraint::AbsoluteTimelock { .. }
+            | SatisfiedConstraint::Annex { .. } => {}
+        }
+    }
+
+    fn preimage(&self, hash: HashLockType) -> Option<Preimage32> {
+        self.preimages
+            .iter()
+            .find(|&&(h, _)| h == hash)
+            .map(|&(_, preimage)| preimage)
+    }
+
+    fn ecdsa_pkh(&self, hash: &hash160::Hash) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        self.ecdsa_sigs
+            .iter()
+            .find(|&&(pk, _)| pk.to_pubkeyhash() == *hash)
+            .cloned()
+    }
+
+    fn schnorr_pkh(&self, hash: &hash160::Hash) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        self.schnorr_sigs
+            .iter()
+            .find(|&&(pk, _)| pk.to_pubkeyhash() == *hash)
+            .cloned()
+    }
+}
+
+impl Satisfier<bitcoin::PublicKey> for Observed {
+    fn lookup_ecdsa_sig(&self, pk: &bitcoin::PublicKey) -> Option<bitcoin::EcdsaSig> {
+        self.ecdsa_sigs
+            .iter()
+            .find(|&&(key, _)| key == *pk)
+            .map(|&(_, sig)| sig)
+    }
+
+    fn lookup_pkh_pk(&self, hash: &hash160::Hash) -> Option<bitcoin::PublicKey> {
+        self.ecdsa_pkh(hash).map(|(pk, _)| pk)
+    }
+
+    fn lookup_pkh_ecdsa_sig(
+        &self,
+        hash: &hash160::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        self.ecdsa_pkh(hash)
+    }
+
+    fn lookup_sha256(&self, hash: &sha256::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Sha256(*hash))
+    }
+
+    fn lookup_hash256(&self, hash: sha256d::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Hash256(hash))
+    }
+
+    fn lookup_ripemd160(&self, hash: ripemd160::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Ripemd160(hash))
+    }
+
+    fn lookup_hash160(&self, hash: hash160::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Hash160(hash))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.age >= n
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.lock_time >= n
+    }
+}
+
+impl Satisfier<XOnlyPublicKey> for Observed {
+    fn lookup_tap_leaf_script_sig(
+        &self,
+        pk: &XOnlyPublicKey,
+        _: &TapLeafHash,
+    ) -> Option<bitcoin::SchnorrSig> {
+        self.schnorr_sigs
+            .iter()
+            .find(|&&(key, _)| key == *pk)
+            .map(|&(_, sig)| sig)
+    }
+
+    fn lookup_pkh_pk(&self, hash: &hash160::Hash) -> Option<XOnlyPublicKey> {
+        self.schnorr_pkh(hash).map(|(pk, _)| pk)
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        hash_leaf: &(hash160::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        self.schnorr_pkh(&hash_leaf.0)
+    }
+
+    fn lookup_sha256(&self, hash: &sha256::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Sha256(*hash))
+    }
+
+    fn lookup_hash256(&self, hash: sha256d::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Hash256(hash))
+    }
+
+    fn lookup_ripemd160(&self, hash: ripemd160::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Ripemd160(hash))
+    }
+
+    fn lookup_hash160(&self, hash: hash160::Hash) -> Option<Preimage32> {
+        self.preimage(HashLockType::Hash160(hash))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.age >= n
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.lock_time >= n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::secp256k1;
+
+    use super::*;
+    use crate::Segwitv0;
+
+    #[test]
+    fn witness_malleability() {
+        let secp = secp256k1::Secp256k1::new();
+        let msg = secp256k1::Message::from_slice(&[1; 32]).unwrap();
+        let (keys, sigs): (Vec<_>, Vec<_>) = (1..3)
+            .map(|i| {
+                let sk = secp256k1::SecretKey::from_slice(&[i; 32]).unwrap();
+                let pk = bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+                let sig = bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &sk));
+                (pk, sig.to_vec())
+            })
+            .unzip();
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "or_b(pk({}),s:pk({}))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        let malleability = |stack: Vec<Vec<u8>>| {
+            let mut witness = stack;
+            witness.push(script.to_bytes());
+            let witness = Witness::from_vec(witness);
+            Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0)
+                .unwrap()
+                .witness_malleability()
+                .unwrap()
+        };
+
+        // Signing with both keys is valid, but only one signature is needed
+        let canonical = match malleability(vec![sigs[1].clone(), sigs[0].clone()]) {
+            WitnessMalleability::NonCanonical(canonical) => canonical,
+            res => panic!("unexpected result {:?}", res),
+        };
+        assert_eq!(canonical.len(), 2);
+        assert!(canonical.iter().any(Vec::is_empty));
+        assert_eq!(malleability(canonical), WitnessMalleability::Canonical);
+        assert_eq!(
+            malleability(vec![vec![], sigs[0].clone()]),
+            WitnessMalleability::Canonical
+        );
+    }
+}
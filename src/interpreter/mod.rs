@@ -20,25 +20,45 @@
 //!
 
 use core::fmt;
+use core::ops::Deref;
 use core::str::FromStr;
 
 use bitcoin::blockdata::witness::Witness;
-use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::util::psbt::PsbtSighashType;
 use bitcoin::util::{sighash, taproot};
 use bitcoin::{self, secp256k1, TxOut};
 
 use crate::miniscript::context::NoChecks;
 use crate::miniscript::ScriptContext;
+use crate::policy::{Liftable, Semantic};
 use crate::prelude::*;
 use crate::{Descriptor, Miniscript, Terminal, ToPublicKey};
 
+mod debugger;
 mod error;
+mod flags;
 mod inner;
+mod location;
+mod malleability;
 mod stack;
+mod timelock;
+mod tx;
+mod usage;
 
+pub use self::debugger::{DebugLocation, DebugStep, Debugger};
 pub use self::error::Error;
 use self::error::PkEvalErrInner;
+pub use self::flags::VerifyFlags;
+pub use self::location::{FragmentLocation, LocatedConstraint, LocatedError, LocatedIter};
+pub use self::malleability::WitnessMalleability;
+pub use self::stack::Element;
 use self::stack::Stack;
+pub use self::timelock::{ChainTip, Timelock, TimelockStatus};
+pub use self::tx::{verify_transaction, DeferredSchnorrSigs, TxInterpreter, VerifiedInput};
+pub use self::usage::ResourceUsage;
+// Descriptor inference works from the same transaction data as the interpreter
+pub use crate::descriptor::{infer_descriptor, InferredDescriptor, UnknownTapLeaf};
 use crate::MiniscriptKey;
 
 /// An iterable Miniscript-structured representation of the spending of a coin
@@ -48,8 +68,14 @@ pub struct Interpreter<'txin> {
     /// For non-Taproot spends, the scriptCode; for Taproot script-spends, this
     /// is the leaf script; for key-spends it is `None`.
     script_code: Option<bitcoin::Script>,
+    /// The annex of a taproot spend, if any
+    annex: Option<&'txin [u8]>,
     age: u32,
     lock_time: u32,
+    /// The version of the spending transaction, when `age` and `lock_time`
+    /// are its nSequence and nLockTime fields, checked by consensus rules
+    tx_version: Option<i32>,
+    flags: VerifyFlags,
 }
 
 // A type representing functions for checking signatures that accept both
@@ -60,6 +86,10 @@ pub struct Interpreter<'txin> {
 pub enum KeySigPair {
     /// A Full public key and corresponding Ecdsa signature
     Ecdsa(bitcoin::PublicKey, bitcoin::EcdsaSig),
+    /// A Full public key and an Ecdsa signature with a sighash type other
+    /// than the standard ones, only valid by consensus rules
+    /// (see [`VerifyFlags::standard_sigs`])
+    NonStandardEcdsa(bitcoin::PublicKey, secp256k1::ecdsa::Signature, u32),
     /// A x-only key and corresponding Schnorr signature
     Schnorr(bitcoin::XOnlyPublicKey, bitcoin::SchnorrSig),
 }
@@ -69,17 +99,35 @@ impl KeySigPair {
     pub fn as_ecdsa(&self) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
         match self {
             KeySigPair::Ecdsa(pk, sig) => Some((*pk, *sig)),
-            KeySigPair::Schnorr(_, _) => None,
+            KeySigPair::NonStandardEcdsa(..) | KeySigPair::Schnorr(_, _) => None,
         }
     }
 
     /// Obtain a pair of ([`bitcoin::XOnlyPublicKey`], [`bitcoin::SchnorrSig`]) from [`KeySigPair`]
     pub fn as_schnorr(&self) -> Option<(bitcoin::XOnlyPublicKey, bitcoin::SchnorrSig)> {
         match self {
-            KeySigPair::Ecdsa(_, _) => None,
+            KeySigPair::Ecdsa(_, _) | KeySigPair::NonStandardEcdsa(..) => None,
             KeySigPair::Schnorr(pk, sig) => Some((*pk, *sig)),
         }
     }
+
+    /// The sighash type committed to by the signature
+    ///
+    /// Taproot signatures without an explicit type are reported as
+    /// [`bitcoin::SchnorrSighashType::Default`].
+    pub fn sighash_type(&self) -> PsbtSighashType {
+        match self {
+            KeySigPair::Ecdsa(_, sig) => sig.hash_ty.into(),
+            KeySigPair::NonStandardEcdsa(_, _, hash_ty) => PsbtSighashType::from_u32(*hash_ty),
+            KeySigPair::Schnorr(_, sig) => sig.hash_ty.into(),
+        }
+    }
+
+    /// Whether the signature commits only to its own input, letting other
+    /// inputs be added to the transaction (`SIGHASH_ANYONECANPAY`)
+    pub fn is_anyone_can_pay(&self) -> bool {
+        self.sighash_type().to_u32() & 0x80 != 0
+    }
 }
 
 // Internally used enum for different types of bitcoin keys
@@ -151,6 +199,20 @@ impl MiniscriptKey for BitcoinKey {
     type Hash = TypedHash160;
     type Sha256 = bitcoin::hashes::sha256::Hash;
 
+    fn is_uncompressed(&self) -> bool {
+        match self {
+            BitcoinKey::Fullkey(pk) => !pk.compressed,
+            BitcoinKey::XOnlyPublicKey(_) => false,
+        }
+    }
+
+    fn is_x_only_key(&self) -> bool {
+        match self {
+            BitcoinKey::Fullkey(_) => false,
+            BitcoinKey::XOnlyPublicKey(_) => true,
+        }
+    }
+
     fn to_pubkeyhash(&self) -> Self::Hash {
         match self {
             BitcoinKey::Fullkey(pk) => TypedHash160::FullKey(pk.to_pubkeyhash()),
@@ -172,14 +234,60 @@ impl<'txin> Interpreter<'txin> {
         witness: &'txin Witness,
         age: u32,       // CSV, relative lock time.
         lock_time: u32, // CLTV, absolute lock time.
+    ) -> Result<Self, Error> {
+        Self::from_txdata_with_flags(
+            spk,
+            script_sig,
+            witness,
+            age,
+            lock_time,
+            VerifyFlags::default(),
+        )
+    }
+
+    /// Constructs an interpreter from a spend found on chain, without knowing
+    /// its descriptor
+    ///
+    /// The script context and the miniscript are inferred from the scriptPubKey
+    /// and the spending data, as in [`Interpreter::from_txdata`]. A confirmed
+    /// spend necessarily met its timelocks, so they are all reported as
+    /// satisfied rather than checked against the spending transaction.
+    pub fn from_spend(
+        spk: &bitcoin::Script,
+        script_sig: &'txin bitcoin::Script,
+        witness: &'txin Witness,
+    ) -> Result<Self, Error> {
+        Self::from_txdata(spk, script_sig, witness, core::u32::MAX, core::u32::MAX)
+    }
+
+    /// Same as [`Interpreter::from_txdata`], enforcing the rules chosen by
+    /// `flags` rather than the default ones
+    ///
+    /// The resource limits are checked here, the other rules when iterating.
+    pub fn from_txdata_with_flags(
+        spk: &bitcoin::Script,
+        script_sig: &'txin bitcoin::Script,
+        witness: &'txin Witness,
+        age: u32,
+        lock_time: u32,
+        flags: VerifyFlags,
     ) -> Result<Self, Error> {
         let (inner, stack, script_code) = inner::from_txdata(spk, script_sig, witness)?;
+        flags.check_limits(&inner, &stack)?;
+        let annex = if spk.is_v1_p2tr() {
+            inner::taproot_annex(witness)
+        } else {
+            None
+        };
         Ok(Interpreter {
             inner,
             stack,
             script_code,
+            annex,
             age,
             lock_time,
+            tx_version: None,
+            flags,
         })
     }
 
@@ -206,11 +314,24 @@ impl<'txin> Interpreter<'txin> {
             } else {
                 vec![]
             },
+            script: if let inner::Inner::Script(ref script, _) = self.inner {
+                Some(script)
+            } else {
+                None
+            },
             // Cloning the references to elements of stack should be fine as it allows
             // call interpreter.iter() without mutating interpreter
             stack: self.stack.clone(),
+            annex: self.annex,
             age: self.age,
             lock_time: self.lock_time,
+            tx_version: self.tx_version,
+            // Minimal IF is a consensus rule of tapscript
+            flags: VerifyFlags {
+                minimal_if: self.flags.minimal_if || self.is_taproot_v1_script_spend(),
+                ..self.flags
+            },
+            usage: None,
             has_errored: false,
         }
     }
@@ -232,6 +353,66 @@ impl<'txin> Interpreter<'txin> {
         prevouts: &sighash::Prevouts<T>,
         sig: &KeySigPair,
     ) -> bool {
+        let mut cache = sighash::SighashCache::new(tx);
+        self.verify_sig_with_cache(secp, &mut cache, input_idx, prevouts, sig)
+    }
+
+    /// Same as [`Interpreter::verify_sig`], computing the sighash with a
+    /// cache of the transaction
+    ///
+    /// The hashes of the transaction data shared by the segwit v0 and taproot
+    /// sighashes of all its inputs are computed once per cache, so reusing it
+    /// across the inputs of a transaction avoids hashing it again for each of
+    /// them.
+    pub fn verify_sig_with_cache<C, T, R>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        cache: &mut sighash::SighashCache<R>,
+        input_idx: usize,
+        prevouts: &sighash::Prevouts<T>,
+        sig: &KeySigPair,
+    ) -> bool
+    where
+        C: secp256k1::Verification,
+        T: Borrow<TxOut>,
+        R: Deref<Target = bitcoin::Transaction>,
+    {
+        match sig {
+            KeySigPair::Ecdsa(key, ecdsa_sig) => {
+                let msg =
+                    self.ecdsa_sighash_msg(cache, input_idx, prevouts, ecdsa_sig.hash_ty.to_u32());
+                let success =
+                    msg.map(|msg| secp.verify_ecdsa(&msg, &ecdsa_sig.sig, &key.inner).is_ok());
+                success.unwrap_or(false) // unwrap_or checks for errors, while success would have checksig results
+            }
+            KeySigPair::NonStandardEcdsa(key, sig, hash_ty) => {
+                let msg = self.ecdsa_sighash_msg(cache, input_idx, prevouts, *hash_ty);
+                let success = msg.map(|msg| secp.verify_ecdsa(&msg, sig, &key.inner).is_ok());
+                success.unwrap_or(false)
+            }
+            KeySigPair::Schnorr(xpk, schnorr_sig) => {
+                let msg = self.taproot_sighash_msg(cache, input_idx, prevouts, schnorr_sig.hash_ty);
+                let success =
+                    msg.map(|msg| secp.verify_schnorr(&schnorr_sig.sig, &msg, xpk).is_ok());
+                success.unwrap_or(false) // unwrap_or_default checks for errors, while success would have checksig results
+            }
+        }
+    }
+
+    // The message signed by an ECDSA signature of this legacy or segwit v0
+    // spend with the sighash type `hash_ty`, which is hashed as it is even if
+    // it is not one of the standard types. `None` if it cannot be computed.
+    fn ecdsa_sighash_msg<T, R>(
+        &self,
+        cache: &mut sighash::SighashCache<R>,
+        input_idx: usize,
+        prevouts: &sighash::Prevouts<T>,
+        hash_ty: u32,
+    ) -> Option<secp256k1::Message>
+    where
+        T: Borrow<TxOut>,
+        R: Deref<Target = bitcoin::Transaction>,
+    {
         fn get_prevout<'u, T: Borrow<TxOut>>(
             prevouts: &'u sighash::Prevouts<'u, T>,
             input_index: usize,
@@ -247,58 +428,70 @@ impl<'txin> Interpreter<'txin> {
                 sighash::Prevouts::All(prevouts) => prevouts.get(input_index),
             }
         }
-        let mut cache = bitcoin::util::sighash::SighashCache::new(tx);
-        match sig {
-            KeySigPair::Ecdsa(key, ecdsa_sig) => {
-                let script_pubkey = self.script_code.as_ref().expect("Legacy have script code");
-                let sighash = if self.is_legacy() {
-                    let sighash_u32 = ecdsa_sig.hash_ty.to_u32();
-                    cache.legacy_signature_hash(input_idx, script_pubkey, sighash_u32)
-                } else if self.is_segwit_v0() {
-                    let amt = match get_prevout(prevouts, input_idx) {
-                        Some(txout) => txout.borrow().value,
-                        None => return false,
-                    };
-                    cache.segwit_signature_hash(input_idx, script_pubkey, amt, ecdsa_sig.hash_ty)
-                } else {
-                    // taproot(or future) signatures in segwitv0 context
-                    return false;
-                };
-                let msg =
-                    sighash.map(|hash| secp256k1::Message::from_slice(&hash).expect("32 byte"));
-                let success =
-                    msg.map(|msg| secp.verify_ecdsa(&msg, &ecdsa_sig.sig, &key.inner).is_ok());
-                success.unwrap_or(false) // unwrap_or checks for errors, while success would have checksig results
-            }
-            KeySigPair::Schnorr(xpk, schnorr_sig) => {
-                let sighash_msg = if self.is_taproot_v1_key_spend() {
-                    cache.taproot_key_spend_signature_hash(input_idx, prevouts, schnorr_sig.hash_ty)
-                } else if self.is_taproot_v1_script_spend() {
-                    let tap_script = self.script_code.as_ref().expect(
-                        "Internal Hack: Saving leaf script instead\
-                        of script code for script spend",
-                    );
-                    let leaf_hash = taproot::TapLeafHash::from_script(
-                        tap_script,
-                        taproot::LeafVersion::TapScript,
-                    );
-                    cache.taproot_script_spend_signature_hash(
-                        input_idx,
-                        prevouts,
-                        leaf_hash,
-                        schnorr_sig.hash_ty,
-                    )
-                } else {
-                    // schnorr sigs in ecdsa descriptors
-                    return false;
-                };
-                let msg =
-                    sighash_msg.map(|hash| secp256k1::Message::from_slice(&hash).expect("32 byte"));
-                let success =
-                    msg.map(|msg| secp.verify_schnorr(&schnorr_sig.sig, &msg, xpk).is_ok());
-                success.unwrap_or(false) // unwrap_or_default checks for errors, while success would have checksig results
-            }
-        }
+        let script_code = self.script_code.as_ref()?;
+        let sighash = if self.is_legacy() {
+            cache
+                .legacy_signature_hash(input_idx, script_code, hash_ty)
+                .ok()?
+                .into_inner()
+        } else if self.is_segwit_v0() {
+            let amt = get_prevout(prevouts, input_idx)?.borrow().value;
+            // BIP 143 commits to the sighash type as it is, while it selects
+            // the signed data like `from_consensus` does
+            let ty = bitcoin::EcdsaSighashType::from_consensus(hash_ty);
+            let mut data = vec![];
+            cache
+                .segwit_encode_signing_data_to(&mut data, input_idx, script_code, amt, ty)
+                .ok()?;
+            let len = data.len();
+            data[len - 4..].copy_from_slice(&hash_ty.to_le_bytes());
+            sha256d::Hash::hash(&data).into_inner()
+        } else {
+            // taproot(or future) signatures in segwitv0 context
+            return None;
+        };
+        Some(secp256k1::Message::from_slice(&sighash).expect("32 byte"))
+    }
+
+    /// The message signed by a Schnorr signature of this taproot spend, `None`
+    /// if it cannot be computed or for non-taproot spends
+    fn taproot_sighash_msg<T: Borrow<TxOut>, R: Deref<Target = bitcoin::Transaction>>(
+        &self,
+        cache: &mut sighash::SighashCache<R>,
+        input_idx: usize,
+        prevouts: &sighash::Prevouts<T>,
+        hash_ty: bitcoin::SchnorrSighashType,
+    ) -> Option<secp256k1::Message> {
+        let leaf_hash = if self.is_taproot_v1_key_spend() {
+            None
+        } else if self.is_taproot_v1_script_spend() {
+            let tap_script = self.script_code.as_ref().expect(
+                "Internal Hack: Saving leaf script instead\
+                of script code for script spend",
+            );
+            Some(taproot::TapLeafHash::from_script(
+                tap_script,
+                taproot::LeafVersion::TapScript,
+            ))
+        } else {
+            // schnorr sigs in ecdsa descriptors
+            return None;
+        };
+        let annex = self
+            .annex
+            .map(|annex| sighash::Annex::new(annex).expect("Annex prefix checked"));
+        // Signatures commit to the annex, and to the default
+        // OP_CODESEPARATOR position for script spends
+        let sighash = cache.taproot_signature_hash(
+            input_idx,
+            prevouts,
+            annex,
+            leaf_hash.map(|leaf_hash| (leaf_hash, 0xFFFFFFFF)),
+            hash_ty,
+        );
+        sighash
+            .ok()
+            .map(|hash| secp256k1::Message::from_slice(&hash).expect("32 byte"))
     }
 
     /// Creates an iterator over the satisfied spending conditions
@@ -330,11 +523,46 @@ impl<'txin> Interpreter<'txin> {
         }))
     }
 
+    /// Same as [`Interpreter::iter`], computing the sighashes with `cache`,
+    /// see [`Interpreter::verify_sig_with_cache`]
+    pub fn iter_with_cache<'iter, C, T, R>(
+        &'iter self,
+        secp: &'iter secp256k1::Secp256k1<C>,
+        cache: &'iter mut sighash::SighashCache<R>,
+        input_idx: usize,
+        prevouts: &'iter sighash::Prevouts<T>,
+    ) -> Iter<'txin, 'iter>
+    where
+        C: secp256k1::Verification,
+        T: Borrow<TxOut>,
+        R: Deref<Target = bitcoin::Transaction>,
+    {
+        self.iter_custom(Box::new(move |sig| {
+            self.verify_sig_with_cache(secp, cache, input_idx, prevouts, sig)
+        }))
+    }
+
     /// Creates an iterator over the satisfied spending conditions without checking signatures
     pub fn iter_assume_sigs<'iter>(&'iter self) -> Iter<'txin, 'iter> {
         self.iter_custom(Box::new(|_| true))
     }
 
+    /// Creates a [`Debugger`] stepping through the execution one fragment at a
+    /// time, with a custom signature verification function as in
+    /// [`Interpreter::iter_custom`]
+    pub fn debugger_custom<'iter>(
+        &'iter self,
+        verify_sig: Box<dyn FnMut(&KeySigPair) -> bool + 'iter>,
+    ) -> Debugger<'txin, 'iter> {
+        Debugger::new(self.iter_custom(verify_sig))
+    }
+
+    /// Creates a [`Debugger`] stepping through the execution one fragment at a
+    /// time without checking signatures
+    pub fn debugger_assume_sigs<'iter>(&'iter self) -> Debugger<'txin, 'iter> {
+        self.debugger_custom(Box::new(|_| true))
+    }
+
     /// Outputs a "descriptor" string which reproduces the spent coins
     ///
     /// This may not represent the original descriptor used to produce the transaction,
@@ -437,6 +665,14 @@ impl<'txin> Interpreter<'txin> {
         }
     }
 
+    /// The annex of a taproot spend, including its `0x50` prefix
+    ///
+    /// The annex is skipped when interpreting the witness, but signatures
+    /// commit to it and it is reported as [`SatisfiedConstraint::Annex`].
+    pub fn annex(&self) -> Option<&'txin [u8]> {
+        self.annex
+    }
+
     /// Outputs a "descriptor" which reproduces the spent coins
     ///
     /// This may not represent the original descriptor used to produce the transaction,
@@ -495,6 +731,54 @@ pub enum SatisfiedConstraint {
         /// The value of Absolute timelock
         time: u32,
     },
+    ///Annex of a taproot spend, see [`Interpreter::annex`] for its contents
+    Annex {
+        /// The SHA256 hash of the annex, including its `0x50` prefix
+        hash: sha256::Hash,
+        /// The length of the annex
+        len: usize,
+    },
+}
+
+impl SatisfiedConstraint {
+    /// The signature satisfying a key, `None` for hashlocks, timelocks and
+    /// the annex
+    pub fn key_sig(&self) -> Option<&KeySigPair> {
+        match *self {
+            SatisfiedConstraint::PublicKey { ref key_sig }
+            | SatisfiedConstraint::PublicKeyHash { ref key_sig, .. } => Some(key_sig),
+            _ => None,
+        }
+    }
+
+    /// The sighash type of the signature satisfying a key, see
+    /// [`KeySigPair::sighash_type`]
+    pub fn sighash_type(&self) -> Option<PsbtSighashType> {
+        self.key_sig().map(KeySigPair::sighash_type)
+    }
+}
+
+impl Liftable<bitcoin::PublicKey> for SatisfiedConstraint {
+    fn lift(&self) -> Result<Semantic<bitcoin::PublicKey>, crate::Error> {
+        let key_hash = |key_sig: &KeySigPair| match *key_sig {
+            KeySigPair::Ecdsa(pk, _) | KeySigPair::NonStandardEcdsa(pk, ..) => pk.to_pubkeyhash(),
+            KeySigPair::Schnorr(xpk, _) => xpk.to_pubkeyhash(),
+        };
+        Ok(match *self {
+            SatisfiedConstraint::PublicKey { ref key_sig } => Semantic::KeyHash(key_hash(key_sig)),
+            SatisfiedConstraint::PublicKeyHash { keyhash, .. } => Semantic::KeyHash(keyhash),
+            SatisfiedConstraint::HashLock { hash, .. } => match hash {
+                HashLockType::Sha256(hash) => Semantic::Sha256(hash),
+                HashLockType::Hash256(hash) => Semantic::Hash256(hash),
+                HashLockType::Hash160(hash) => Semantic::Hash160(hash),
+                HashLockType::Ripemd160(hash) => Semantic::Ripemd160(hash),
+            },
+            SatisfiedConstraint::RelativeTimelock { time } => Semantic::Older(time),
+            SatisfiedConstraint::AbsoluteTimelock { time } => Semantic::After(time),
+            // The annex is not a spending condition
+            SatisfiedConstraint::Annex { .. } => Semantic::Trivial,
+        })
+    }
 }
 
 ///This is used by the interpreter to know which evaluation state a AstemElem is.
@@ -527,9 +811,15 @@ pub struct Iter<'intp, 'txin: 'intp> {
     verify_sig: Box<dyn FnMut(&KeySigPair) -> bool + 'intp>,
     public_key: Option<&'intp BitcoinKey>,
     state: Vec<NodeEvaluationState<'intp>>,
+    // The whole script, against which fragments are located
+    script: Option<&'intp Miniscript<BitcoinKey, NoChecks>>,
     stack: Stack<'txin>,
+    annex: Option<&'txin [u8]>,
     age: u32,
     lock_time: u32,
+    tx_version: Option<i32>,
+    flags: VerifyFlags,
+    usage: Option<usage::UsageRecorder>,
     has_errored: bool,
 }
 
@@ -558,6 +848,45 @@ impl<'intp, 'txin: 'intp> Iter<'intp, 'txin>
 where
     NoChecks: ScriptContext,
 {
+    /// The semantic policy actually exercised by the spend: the conjunction
+    /// of every satisfied constraint
+    ///
+    /// The spend followed an intended policy if this policy entails it, see
+    /// [`Semantic::entails`]. Keys are identified by their hash, x-only keys
+    /// by the hash of their 32 byte serialization. Constraints which did not
+    /// contribute to the satisfaction are included as well.
+    pub fn spent_policy(self) -> Result<Semantic<bitcoin::PublicKey>, Error> {
+        let mut subs = vec![];
+        for constraint in self {
+            match constraint? {
+                SatisfiedConstraint::Annex { .. } => {}
+                constraint => subs.push(constraint.lift().expect("Lifting cannot fail")),
+            }
+        }
+        Ok(match subs.len() {
+            0 => Semantic::Trivial,
+            1 => subs.pop().unwrap(),
+            n => Semantic::Threshold(n, subs),
+        })
+    }
+}
+
+impl<'intp, 'txin: 'intp> Iter<'intp, 'txin>
+where
+    NoChecks: ScriptContext,
+{
+    // Whether the spending transaction satisfies `lock`: by consensus rules
+    // if its fields are known, otherwise by comparing the given values
+    fn timelock_met(&self, lock: Timelock) -> bool {
+        match (self.tx_version, lock) {
+            (Some(version), _) => {
+                lock.check_fields(version, self.lock_time, self.age) == TimelockStatus::Satisfied
+            }
+            (None, Timelock::After(n)) => self.lock_time >= n,
+            (None, Timelock::Older(n)) => self.age >= n,
+        }
+    }
+
     /// Helper function to push a NodeEvaluationState on state stack
     fn push_evaluation_state(
         &mut self,
@@ -572,409 +901,459 @@ where
         })
     }
 
+    /// Helper function to pop the argument of an `OP_IF` or `OP_NOTIF`, which
+    /// must be empty or `1` unless the minimal IF rule is not enforced
+    fn pop_if_condition(&mut self) -> Result<bool, Error> {
+        match self.stack.pop() {
+            Some(stack::Element::Satisfied) => Ok(true),
+            Some(stack::Element::Dissatisfied) => Ok(false),
+            Some(stack::Element::Push(v)) if !self.flags.minimal_if => {
+                Ok(bitcoin::blockdata::script::read_scriptbool(v))
+            }
+            Some(stack::Element::Push(_v)) => Err(Error::UnexpectedStackElementPush),
+            None => Err(Error::UnexpectedStackEnd),
+        }
+    }
+
     /// Helper function to step the iterator
     fn iter_next(&mut self) -> Option<Result<SatisfiedConstraint, Error>> {
         while let Some(node_state) = self.state.pop() {
             //non-empty stack
-            match node_state.node.node {
-                Terminal::True => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.stack.push(stack::Element::Satisfied);
+            let res = self.eval_node(node_state);
+            if res.is_some() {
+                return res;
+            }
+        }
+        self.finish()
+    }
+
+    /// Helper function to evaluate a single node, `None` if it did not
+    /// satisfy a constraint
+    fn eval_node(
+        &mut self,
+        node_state: NodeEvaluationState<'intp>,
+    ) -> Option<Result<SatisfiedConstraint, Error>> {
+        if let Some(ref mut usage) = self.usage {
+            if node_state.n_evaluated == 0 {
+                usage.enter(node_state.node);
+            }
+        }
+        let res = self.eval_fragment(node_state);
+        if let Some(ref mut usage) = self.usage {
+            usage.step(&self.stack);
+        }
+        res
+    }
+
+    /// Evaluates a node, without recording its resource usage
+    fn eval_fragment(
+        &mut self,
+        node_state: NodeEvaluationState<'intp>,
+    ) -> Option<Result<SatisfiedConstraint, Error>> {
+        match node_state.node.node {
+            Terminal::True => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.stack.push(stack::Element::Satisfied);
+            }
+            Terminal::False => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::PkK(ref pk) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self
+                    .stack
+                    .evaluate_pk(&mut self.verify_sig, &self.flags, pk);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::False => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::PkH(ref pkh) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self
+                    .stack
+                    .evaluate_pkh(&mut self.verify_sig, &self.flags, pkh);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::PkK(ref pk) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_pk(&mut self.verify_sig, pk);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::After(ref n) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let met = self.timelock_met(Timelock::After(*n));
+                let res = self.stack.evaluate_after(n, met);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::PkH(ref pkh) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_pkh(&mut self.verify_sig, pkh);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Older(ref n) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let met = self.timelock_met(Timelock::Older(*n));
+                let res = self.stack.evaluate_older(n, met);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::After(ref n) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_after(n, self.lock_time);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Sha256(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_sha256(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Older(ref n) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_older(n, self.age);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Hash256(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_hash256(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Sha256(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_sha256(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Hash160(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_hash160(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Hash256(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_hash256(hash);
-                    if res.is_some() {
-                        return res;
-                    }
+            }
+            Terminal::Ripemd160(ref hash) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                let res = self.stack.evaluate_ripemd160(hash);
+                if res.is_some() {
+                    return res;
                 }
-                Terminal::Hash160(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_hash160(hash);
-                    if res.is_some() {
-                        return res;
+            }
+            Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::DupIf(ref sub) if node_state.n_evaluated == 0 => {
+                match self.pop_if_condition() {
+                    Ok(false) => self.stack.push(stack::Element::Dissatisfied),
+                    Ok(true) => {
+                        self.push_evaluation_state(node_state.node, 1, 1);
+                        self.push_evaluation_state(sub, 0, 0);
                     }
+                    Err(e) => return Some(Err(e)),
                 }
-                Terminal::Ripemd160(ref hash) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    let res = self.stack.evaluate_ripemd160(hash);
-                    if res.is_some() {
-                        return res;
+            }
+            Terminal::DupIf(ref _sub) if node_state.n_evaluated == 1 => {
+                self.stack.push(stack::Element::Satisfied);
+            }
+            Terminal::ZeroNotEqual(ref sub) | Terminal::Verify(ref sub)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::Verify(ref _sub) if node_state.n_evaluated == 1 => match self.stack.pop() {
+                Some(stack::Element::Satisfied) => (),
+                Some(_) => return Some(Err(Error::VerifyFailed)),
+                None => return Some(Err(Error::UnexpectedStackEnd)),
+            },
+            Terminal::ZeroNotEqual(ref _sub) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
+                    Some(_) => self.stack.push(stack::Element::Satisfied),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Alt(ref sub) | Terminal::Swap(ref sub) | Terminal::Check(ref sub) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.push_evaluation_state(sub, 0, 0);
+            }
+            Terminal::NonZero(ref sub) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                match self.stack.last() {
+                    Some(&stack::Element::Dissatisfied) => (),
+                    Some(_) => self.push_evaluation_state(sub, 0, 0),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::DupIf(ref sub) if node_state.n_evaluated == 0 => match self.stack.pop() {
+            }
+            Terminal::AndV(ref left, ref right) => {
+                debug_assert_eq!(node_state.n_evaluated, 0);
+                debug_assert_eq!(node_state.n_satisfied, 0);
+                self.push_evaluation_state(right, 0, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref left, ref _right) | Terminal::AndB(ref left, ref _right)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref _left, ref right) | Terminal::AndB(ref _left, ref right)
+                if node_state.n_evaluated == 1 =>
+            {
+                match self.stack.pop() {
                     Some(stack::Element::Dissatisfied) => {
-                        self.stack.push(stack::Element::Dissatisfied);
+                        self.push_evaluation_state(node_state.node, 2, 0);
+                        self.push_evaluation_state(right, 0, 0);
                     }
                     Some(stack::Element::Satisfied) => {
-                        self.push_evaluation_state(node_state.node, 1, 1);
-                        self.push_evaluation_state(sub, 0, 0);
+                        self.push_evaluation_state(node_state.node, 2, 1);
+                        self.push_evaluation_state(right, 0, 0);
                     }
                     Some(stack::Element::Push(_v)) => {
                         return Some(Err(Error::UnexpectedStackElementPush))
                     }
                     None => return Some(Err(Error::UnexpectedStackEnd)),
-                },
-                Terminal::DupIf(ref _sub) if node_state.n_evaluated == 1 => {
-                    self.stack.push(stack::Element::Satisfied);
-                }
-                Terminal::ZeroNotEqual(ref sub) | Terminal::Verify(ref sub)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(sub, 0, 0);
                 }
-                Terminal::Verify(ref _sub) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => (),
-                        Some(_) => return Some(Err(Error::VerifyFailed)),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) if node_state.n_satisfied == 1 => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
+                    Some(_) => self.stack.push(stack::Element::Dissatisfied),
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::ZeroNotEqual(ref _sub) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(_) => self.stack.push(stack::Element::Satisfied),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::AndOr(ref left, ref _right, _)
+            | Terminal::OrC(ref left, ref _right)
+            | Terminal::OrD(ref left, ref _right)
+                if node_state.n_evaluated == 0 =>
+            {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::OrB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) if node_state.n_satisfied == 0 => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
-                }
-                Terminal::NonZero(ref sub) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    match self.stack.last() {
-                        Some(&stack::Element::Dissatisfied) => (),
-                        Some(_) => self.push_evaluation_state(sub, 0, 0),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(_) => {
+                        self.stack.push(stack::Element::Satisfied);
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndV(ref left, ref right) => {
-                    debug_assert_eq!(node_state.n_evaluated, 0);
-                    debug_assert_eq!(node_state.n_satisfied, 0);
-                    self.push_evaluation_state(right, 0, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref left, ref _right) | Terminal::AndB(ref left, ref _right)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(left, 0, 0);
-                }
-                Terminal::OrB(ref _left, ref right) | Terminal::AndB(ref _left, ref right)
-                    if node_state.n_evaluated == 1 =>
-                {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(node_state.node, 2, 0);
-                            self.push_evaluation_state(right, 0, 0);
-                        }
-                        Some(stack::Element::Satisfied) => {
-                            self.push_evaluation_state(node_state.node, 2, 1);
-                            self.push_evaluation_state(right, 0, 0);
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::OrC(ref _left, ref right) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => (),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) if node_state.n_satisfied == 1 => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(_) => self.stack.push(stack::Element::Dissatisfied),
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::OrD(ref _left, ref right) if node_state.n_evaluated == 1 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Satisfied) => self.stack.push(stack::Element::Satisfied),
+                    Some(stack::Element::Dissatisfied) => self.push_evaluation_state(right, 0, 0),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::AndOr(ref left, ref _right, _)
-                | Terminal::OrC(ref left, ref _right)
-                | Terminal::OrD(ref left, ref _right)
-                    if node_state.n_evaluated == 0 =>
-                {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(left, 0, 0);
+            }
+            Terminal::AndOr(_, ref left, ref right) | Terminal::OrI(ref left, ref right) => {
+                match self.pop_if_condition() {
+                    Ok(true) => self.push_evaluation_state(left, 0, 0),
+                    Ok(false) => self.push_evaluation_state(right, 0, 0),
+                    Err(e) => return Some(Err(e)),
                 }
-                Terminal::OrB(ref _left, ref _right) if node_state.n_evaluated == 2 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) if node_state.n_satisfied == 0 => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(_) => {
-                            self.stack.push(stack::Element::Satisfied);
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated == 0 => {
+                self.push_evaluation_state(node_state.node, 1, 0);
+                self.push_evaluation_state(&subs[0], 0, 0);
+            }
+            Terminal::Thresh(k, ref subs) if node_state.n_evaluated == subs.len() => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) if node_state.n_satisfied == k => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
-                }
-                Terminal::OrC(ref _left, ref right) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => (),
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Satisfied) if node_state.n_satisfied == k - 1 => {
+                        self.stack.push(stack::Element::Satisfied)
                     }
-                }
-                Terminal::OrD(ref _left, ref right) if node_state.n_evaluated == 1 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Satisfied) | Some(stack::Element::Dissatisfied) => {
+                        self.stack.push(stack::Element::Dissatisfied)
                     }
-                }
-                Terminal::AndOr(_, ref left, ref right) | Terminal::OrI(ref left, ref right) => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Satisfied) => self.push_evaluation_state(left, 0, 0),
-                        Some(stack::Element::Dissatisfied) => {
-                            self.push_evaluation_state(right, 0, 0)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated == 0 => {
-                    self.push_evaluation_state(node_state.node, 1, 0);
-                    self.push_evaluation_state(&subs[0], 0, 0);
-                }
-                Terminal::Thresh(k, ref subs) if node_state.n_evaluated == subs.len() => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) if node_state.n_satisfied == k => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Satisfied) if node_state.n_satisfied == k - 1 => {
-                            self.stack.push(stack::Element::Satisfied)
-                        }
-                        Some(stack::Element::Satisfied) | Some(stack::Element::Dissatisfied) => {
-                            self.stack.push(stack::Element::Dissatisfied)
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
-                        }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+            }
+            Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated != 0 => {
+                match self.stack.pop() {
+                    Some(stack::Element::Dissatisfied) => {
+                        self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied,
+                        );
+                        self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                    }
+                    Some(stack::Element::Satisfied) => {
+                        self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied + 1,
+                        );
+                        self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                    }
+                    Some(stack::Element::Push(_v)) => {
+                        return Some(Err(Error::UnexpectedStackElementPush))
                     }
+                    None => return Some(Err(Error::UnexpectedStackEnd)),
                 }
-                Terminal::Thresh(ref _k, ref subs) if node_state.n_evaluated != 0 => {
-                    match self.stack.pop() {
-                        Some(stack::Element::Dissatisfied) => {
+            }
+            Terminal::MultiA(k, ref subs) => {
+                if node_state.n_evaluated == subs.len() {
+                    if node_state.n_satisfied == k {
+                        self.stack.push(stack::Element::Satisfied);
+                    } else {
+                        self.stack.push(stack::Element::Dissatisfied);
+                    }
+                } else {
+                    // evaluate each key with as a pk
+                    // note that evaluate_pk will error on non-empty incorrect sigs
+                    // push 1 on satisfied sigs and push 0 on empty sigs
+                    match self.stack.evaluate_pk(
+                        &mut self.verify_sig,
+                        &self.flags,
+                        &subs[node_state.n_evaluated],
+                    ) {
+                        Some(Ok(x)) => {
                             self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied,
+                                node_state.n_satisfied + 1,
                             );
-                            self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
+                            match self.stack.pop() {
+                                Some(..) => return Some(Ok(x)),
+                                None => return Some(Err(Error::UnexpectedStackEnd)),
+                            }
                         }
-                        Some(stack::Element::Satisfied) => {
+                        None => {
                             self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied + 1,
+                                node_state.n_satisfied,
                             );
-                            self.push_evaluation_state(&subs[node_state.n_evaluated], 0, 0);
-                        }
-                        Some(stack::Element::Push(_v)) => {
-                            return Some(Err(Error::UnexpectedStackElementPush))
+                            match self.stack.pop() {
+                                Some(..) => {} // not-satisfied, look for next key
+                                None => return Some(Err(Error::UnexpectedStackEnd)),
+                            }
                         }
-                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                        x => return x, //forward errors as is
                     }
                 }
-                Terminal::MultiA(k, ref subs) => {
-                    if node_state.n_evaluated == subs.len() {
-                        if node_state.n_satisfied == k {
-                            self.stack.push(stack::Element::Satisfied);
-                        } else {
-                            self.stack.push(stack::Element::Dissatisfied);
+            }
+            Terminal::Multi(ref k, ref subs) if node_state.n_evaluated == 0 => {
+                let len = self.stack.len();
+                if len < k + 1 {
+                    return Some(Err(Error::InsufficientSignaturesMultiSig));
+                } else {
+                    //Non-sat case. If the first sig is empty, others k elements must
+                    //be empty.
+                    match self.stack.last() {
+                        Some(&stack::Element::Dissatisfied) => {
+                            //Remove the extra zero from multi-sig check
+                            let sigs = self.stack.split_off(len - (k + 1));
+                            let nonsat = sigs
+                                .iter()
+                                .map(|sig| *sig == stack::Element::Dissatisfied)
+                                .filter(|empty| *empty)
+                                .count();
+                            if nonsat == *k + 1 {
+                                self.stack.push(stack::Element::Dissatisfied);
+                            } else {
+                                return Some(Err(Error::MissingExtraZeroMultiSig));
+                            }
                         }
-                    } else {
-                        // evaluate each key with as a pk
-                        // note that evaluate_pk will error on non-empty incorrect sigs
-                        // push 1 on satisfied sigs and push 0 on empty sigs
-                        match self
-                            .stack
-                            .evaluate_pk(&mut self.verify_sig, &subs[node_state.n_evaluated])
-                        {
-                            Some(Ok(x)) => {
-                                self.push_evaluation_state(
-                                    node_state.node,
-                                    node_state.n_evaluated + 1,
-                                    node_state.n_satisfied + 1,
-                                );
-                                match self.stack.pop() {
-                                    Some(..) => return Some(Ok(x)),
-                                    None => return Some(Err(Error::UnexpectedStackEnd)),
+                        None => return Some(Err(Error::UnexpectedStackEnd)),
+                        _ => {
+                            match self.stack.evaluate_multi(
+                                &mut self.verify_sig,
+                                &self.flags,
+                                &subs[subs.len() - 1],
+                            ) {
+                                Some(Ok(x)) => {
+                                    self.push_evaluation_state(
+                                        node_state.node,
+                                        node_state.n_evaluated + 1,
+                                        node_state.n_satisfied + 1,
+                                    );
+                                    return Some(Ok(x));
                                 }
-                            }
-                            None => {
-                                self.push_evaluation_state(
+                                None => self.push_evaluation_state(
                                     node_state.node,
                                     node_state.n_evaluated + 1,
                                     node_state.n_satisfied,
-                                );
-                                match self.stack.pop() {
-                                    Some(..) => {} // not-satisfied, look for next key
-                                    None => return Some(Err(Error::UnexpectedStackEnd)),
-                                }
+                                ),
+                                x => return x, //forward errors as is
                             }
-                            x => return x, //forward errors as is
                         }
                     }
                 }
-                Terminal::Multi(ref k, ref subs) if node_state.n_evaluated == 0 => {
-                    let len = self.stack.len();
-                    if len < k + 1 {
-                        return Some(Err(Error::InsufficientSignaturesMultiSig));
+            }
+            Terminal::Multi(k, ref subs) => {
+                if node_state.n_satisfied == k {
+                    //multi-sig bug: Pop extra 0
+                    if let Some(stack::Element::Dissatisfied) = self.stack.pop() {
+                        self.stack.push(stack::Element::Satisfied);
                     } else {
-                        //Non-sat case. If the first sig is empty, others k elements must
-                        //be empty.
-                        match self.stack.last() {
-                            Some(&stack::Element::Dissatisfied) => {
-                                //Remove the extra zero from multi-sig check
-                                let sigs = self.stack.split_off(len - (k + 1));
-                                let nonsat = sigs
-                                    .iter()
-                                    .map(|sig| *sig == stack::Element::Dissatisfied)
-                                    .filter(|empty| *empty)
-                                    .count();
-                                if nonsat == *k + 1 {
-                                    self.stack.push(stack::Element::Dissatisfied);
-                                } else {
-                                    return Some(Err(Error::MissingExtraZeroMultiSig));
-                                }
-                            }
-                            None => return Some(Err(Error::UnexpectedStackEnd)),
-                            _ => {
-                                match self
-                                    .stack
-                                    .evaluate_multi(&mut self.verify_sig, &subs[subs.len() - 1])
-                                {
-                                    Some(Ok(x)) => {
-                                        self.push_evaluation_state(
-                                            node_state.node,
-                                            node_state.n_evaluated + 1,
-                                            node_state.n_satisfied + 1,
-                                        );
-                                        return Some(Ok(x));
-                                    }
-                                    None => self.push_evaluation_state(
-                                        node_state.node,
-                                        node_state.n_evaluated + 1,
-                                        node_state.n_satisfied,
-                                    ),
-                                    x => return x, //forward errors as is
-                                }
-                            }
-                        }
+                        return Some(Err(Error::MissingExtraZeroMultiSig));
                     }
-                }
-                Terminal::Multi(k, ref subs) => {
-                    if node_state.n_satisfied == k {
-                        //multi-sig bug: Pop extra 0
-                        if let Some(stack::Element::Dissatisfied) = self.stack.pop() {
-                            self.stack.push(stack::Element::Satisfied);
-                        } else {
-                            return Some(Err(Error::MissingExtraZeroMultiSig));
-                        }
-                    } else if node_state.n_evaluated == subs.len() {
-                        return Some(Err(Error::MultiSigEvaluationError));
-                    } else {
-                        match self.stack.evaluate_multi(
-                            &mut self.verify_sig,
-                            &subs[subs.len() - node_state.n_evaluated - 1],
-                        ) {
-                            Some(Ok(x)) => {
-                                self.push_evaluation_state(
-                                    node_state.node,
-                                    node_state.n_evaluated + 1,
-                                    node_state.n_satisfied + 1,
-                                );
-                                return Some(Ok(x));
-                            }
-                            None => self.push_evaluation_state(
+                } else if node_state.n_evaluated == subs.len() {
+                    return Some(Err(Error::MultiSigEvaluationError));
+                } else {
+                    match self.stack.evaluate_multi(
+                        &mut self.verify_sig,
+                        &self.flags,
+                        &subs[subs.len() - node_state.n_evaluated - 1],
+                    ) {
+                        Some(Ok(x)) => {
+                            self.push_evaluation_state(
                                 node_state.node,
                                 node_state.n_evaluated + 1,
-                                node_state.n_satisfied,
-                            ),
-                            x => return x, //forward errors as is
+                                node_state.n_satisfied + 1,
+                            );
+                            return Some(Ok(x));
                         }
+                        None => self.push_evaluation_state(
+                            node_state.node,
+                            node_state.n_evaluated + 1,
+                            node_state.n_satisfied,
+                        ),
+                        x => return x, //forward errors as is
                     }
                 }
-                //All other match patterns should not be reached in any valid
-                //type checked Miniscript
-                _ => return Some(Err(Error::CouldNotEvaluate)),
-            };
+            }
+            //All other match patterns should not be reached in any valid
+            //type checked Miniscript
+            _ => return Some(Err(Error::CouldNotEvaluate)),
+        };
+        None
+    }
+
+    /// Helper function to finish the evaluation once no node is left
+    fn finish(&mut self) -> Option<Result<SatisfiedConstraint, Error>> {
+        let res = self.finish_script();
+        if let Some(ref mut usage) = self.usage {
+            usage.step(&self.stack);
         }
+        res
+    }
 
+    /// Finishes the evaluation, without recording its resource usage
+    fn finish_script(&mut self) -> Option<Result<SatisfiedConstraint, Error>> {
         //state empty implies that either the execution has terminated or we have a
         //Pk based descriptor
         if let Some(pk) = self.public_key {
             if let Some(stack::Element::Push(sig)) = self.stack.pop() {
-                if let Ok(key_sig) = verify_sersig(&mut self.verify_sig, pk, sig) {
+                if let Ok(key_sig) = verify_sersig(&mut self.verify_sig, &self.flags, pk, sig) {
                     //Signature check successful, set public_key to None to
                     //terminate the next() function in the subsequent call
                     self.public_key = None;
@@ -986,6 +1365,12 @@ where
             } else {
                 Some(Err(Error::UnexpectedStackEnd))
             }
+        } else if let Some(annex) = self.annex.take() {
+            //Report the annex once the signatures committing to it are checked
+            Some(Ok(SatisfiedConstraint::Annex {
+                hash: sha256::Hash::hash(annex),
+                len: annex.len(),
+            }))
         } else {
             //All the script has been executed.
             //Check that the stack must contain exactly 1 satisfied element
@@ -1001,13 +1386,27 @@ where
 /// Helper function to verify serialized signature
 fn verify_sersig<'txin>(
     verify_sig: &mut Box<dyn FnMut(&KeySigPair) -> bool + 'txin>,
+    flags: &VerifyFlags,
     pk: &BitcoinKey,
     sigser: &[u8],
 ) -> Result<KeySigPair, Error> {
     match pk {
         BitcoinKey::Fullkey(pk) => {
-            let ecdsa_sig = bitcoin::EcdsaSig::from_slice(sigser)?;
-            let key_sig_pair = KeySigPair::Ecdsa(*pk, ecdsa_sig);
+            let key_sig_pair = if flags.standard_sigs {
+                KeySigPair::Ecdsa(*pk, bitcoin::EcdsaSig::from_slice(sigser)?)
+            } else {
+                // Any sighash type is valid by consensus, and is signed as it
+                // is rather than as the standard type it is treated like
+                let (hash_ty, sig) = sigser
+                    .split_last()
+                    .ok_or(bitcoin::EcdsaSigError::EmptySignature)?;
+                let sig = secp256k1::ecdsa::Signature::from_der(sig)
+                    .map_err(bitcoin::EcdsaSigError::Secp256k1)?;
+                match bitcoin::EcdsaSighashType::from_standard(u32::from(*hash_ty)) {
+                    Ok(hash_ty) => KeySigPair::Ecdsa(*pk, bitcoin::EcdsaSig { sig, hash_ty }),
+                    Err(_) => KeySigPair::NonStandardEcdsa(*pk, sig, u32::from(*hash_ty)),
+                }
+            };
             if verify_sig(&key_sig_pair) {
                 Ok(key_sig_pair)
             } else {
@@ -1112,6 +1511,7 @@ mod tests {
             KeySigPair::Ecdsa(pk, ecdsa_sig) => secp_ref
                 .verify_ecdsa(&sighash, &ecdsa_sig.sig, &pk.inner)
                 .is_ok(),
+            KeySigPair::NonStandardEcdsa(..) => false,
             KeySigPair::Schnorr(xpk, schnorr_sig) => secp_ref
                 .verify_schnorr(&schnorr_sig.sig, &sighash, xpk)
                 .is_ok(),
@@ -1125,14 +1525,19 @@ mod tests {
             Iter {
                 verify_sig: verify_fn,
                 stack: stack,
+                annex: None,
                 public_key: None,
                 state: vec![NodeEvaluationState {
                     node: &ms,
                     n_evaluated: 0,
                     n_satisfied: 0,
                 }],
+                script: Some(ms),
                 age: 1002,
                 lock_time: 1002,
+                tx_version: None,
+                flags: VerifyFlags::default(),
+                usage: None,
                 has_errored: false,
             }
         }
@@ -1595,6 +2000,70 @@ mod tests {
         assert!(multi_a_error.is_err());
     }
 
+    #[test]
+    fn from_spend() {
+        let preimage = [0x01; 32];
+        let ms = Miniscript::<bitcoin::PublicKey, crate::Segwitv0>::from_str_insane(&format!(
+            "and_v(v:sha256({}),after(700000))",
+            sha256::Hash::hash(&preimage)
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        let witness = Witness::from_vec(vec![preimage.to_vec(), script.to_bytes()]);
+
+        let interpreter = Interpreter::from_spend(&spk, &script_sig, &witness).unwrap();
+        assert_eq!(
+            interpreter.inferred_descriptor_string(),
+            format!("wsh({})", ms)
+        );
+        let constraints: Vec<_> = interpreter
+            .iter_assume_sigs()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(constraints.len(), 2);
+        assert_eq!(
+            constraints[1],
+            SatisfiedConstraint::AbsoluteTimelock { time: 700000 }
+        );
+
+        // The timelock is checked when the spending transaction is known
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        assert!(interpreter
+            .iter_assume_sigs()
+            .collect::<Result<Vec<_>, _>>()
+            .is_err());
+    }
+
+    #[test]
+    fn spent_policy() {
+        let preimages = [[0x01; 32], [0x02; 32]];
+        let hashes: Vec<_> = preimages.iter().map(|p| sha256::Hash::hash(p)).collect();
+        let ms = Miniscript::<bitcoin::PublicKey, crate::Segwitv0>::from_str_insane(&format!(
+            "or_d(sha256({}),and_v(v:sha256({}),older(10)))",
+            hashes[0], hashes[1]
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = bitcoin::Script::new();
+        // Spend the second branch
+        let witness = Witness::from_vec(vec![
+            preimages[1].to_vec(),
+            [0xff; 32].to_vec(),
+            script.to_bytes(),
+        ]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 10, 0).unwrap();
+        let spent = interpreter.iter_assume_sigs().spent_policy().unwrap();
+        assert_eq!(
+            spent,
+            Semantic::Threshold(2, vec![Semantic::Sha256(hashes[1]), Semantic::Older(10)])
+        );
+        assert!(spent.clone().entails(ms.lift().unwrap()).unwrap());
+        assert!(!spent.entails(Semantic::Sha256(hashes[0])).unwrap());
+    }
+
     // By design there is no support for parse a miniscript with BitcoinKey
     // because it does not implement FromStr
     fn no_checks_ms(ms: &str) -> Miniscript<BitcoinKey, NoChecks> {
@@ -14,6 +14,8 @@
 
 //! Interpreter stack
 
+use core::cmp;
+
 use bitcoin;
 use bitcoin::blockdata::{opcodes, script};
 use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
@@ -21,6 +23,7 @@ use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
 use super::error::PkEvalErrInner;
 use super::{
     verify_sersig, BitcoinKey, Error, HashLockType, KeySigPair, SatisfiedConstraint, TypedHash160,
+    VerifyFlags,
 };
 use crate::prelude::*;
 
@@ -85,44 +88,65 @@ impl<'txin> Element<'txin> {
 /// Stack Data structure representing the stack input to Miniscript. This Stack
 /// is created from the combination of ScriptSig and Witness stack.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Hash)]
-pub struct Stack<'txin>(Vec<Element<'txin>>);
+pub struct Stack<'txin> {
+    elems: Vec<Element<'txin>>,
+    // Number of elements at the bottom of the stack which come from the
+    // transaction rather than from evaluating the script
+    n_txin: usize,
+}
 
 impl<'txin> From<Vec<Element<'txin>>> for Stack<'txin> {
-    fn from(v: Vec<Element<'txin>>) -> Self {
-        Stack(v)
+    fn from(elems: Vec<Element<'txin>>) -> Self {
+        let n_txin = elems.len();
+        Stack { elems, n_txin }
     }
 }
 
 impl<'txin> Stack<'txin> {
     /// Whether the stack is empty
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.elems.is_empty()
     }
 
     /// Number of elements on the stack
     pub fn len(&mut self) -> usize {
-        self.0.len()
+        self.elems.len()
     }
 
     /// Removes the top stack element, if the stack is nonempty
     pub fn pop(&mut self) -> Option<Element<'txin>> {
-        self.0.pop()
+        let elem = self.elems.pop();
+        self.n_txin = cmp::min(self.n_txin, self.elems.len());
+        elem
     }
 
     /// Pushes an element onto the top of the stack
     pub fn push(&mut self, elem: Element<'txin>) {
-        self.0.push(elem);
+        self.elems.push(elem);
     }
 
     /// Returns a new stack representing the top `k` elements of the stack,
     /// removing these elements from the original
     pub fn split_off(&mut self, k: usize) -> Vec<Element<'txin>> {
-        self.0.split_off(k)
+        let elems = self.elems.split_off(k);
+        self.n_txin = cmp::min(self.n_txin, self.elems.len());
+        elems
+    }
+
+    /// The elements of the stack, its top last
+    pub fn as_slice(&self) -> &[Element<'txin>] {
+        &self.elems
+    }
+
+    /// Number of elements at the bottom of the stack which come from the
+    /// witness or scriptSig and were not consumed yet
+    pub(super) fn n_txin(&self) -> usize {
+        self.n_txin
     }
 
     /// Returns a reference to the top stack element, if the stack is nonempty
     pub fn last(&self) -> Option<&Element<'txin>> {
-        self.0.last()
+        self.elems.last()
     }
 
     /// Helper function to evaluate a Pk Node which takes the
@@ -134,6 +158,7 @@ impl<'txin> Stack<'txin> {
     pub(super) fn evaluate_pk<'intp>(
         &mut self,
         verify_sig: &mut Box<dyn FnMut(&KeySigPair) -> bool + 'intp>,
+        flags: &VerifyFlags,
         pk: &'intp BitcoinKey,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
         if let Some(sigser) = self.pop() {
@@ -143,7 +168,7 @@ impl<'txin> Stack<'txin> {
                     None
                 }
                 Element::Push(sigser) => {
-                    let key_sig = verify_sersig(verify_sig, pk, sigser);
+                    let key_sig = verify_sersig(verify_sig, flags, pk, sigser);
                     match key_sig {
                         Ok(key_sig) => {
                             self.push(Element::Satisfied);
@@ -170,6 +195,7 @@ impl<'txin> Stack<'txin> {
     pub(super) fn evaluate_pkh<'intp>(
         &mut self,
         verify_sig: &mut Box<dyn FnMut(&KeySigPair) -> bool + 'intp>,
+        flags: &VerifyFlags,
         pkh: &'intp TypedHash160,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
         // Parse a bitcoin key from witness data slice depending on hash context
@@ -198,7 +224,7 @@ impl<'txin> Stack<'txin> {
                                 None
                             }
                             Element::Push(sigser) => {
-                                let key_sig = verify_sersig(verify_sig, &pk, sigser);
+                                let key_sig = verify_sersig(verify_sig, flags, &pk, sigser);
                                 match key_sig {
                                     Ok(key_sig) => {
                                         self.push(Element::Satisfied);
@@ -229,12 +255,13 @@ impl<'txin> Stack<'txin> {
     /// The reason we don't need to copy the Script semantics is that
     /// Miniscript never evaluates integers and it is safe to treat them as
     /// booleans
+    /// `met` tells whether the spending transaction satisfies the lock.
     pub(super) fn evaluate_after(
         &mut self,
         n: &u32,
-        lock_time: u32,
+        met: bool,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
-        if lock_time >= *n {
+        if met {
             self.push(Element::Satisfied);
             Some(Ok(SatisfiedConstraint::AbsoluteTimelock { time: *n }))
         } else {
@@ -248,12 +275,13 @@ impl<'txin> Stack<'txin> {
     /// The reason we don't need to copy the Script semantics is that
     /// Miniscript never evaluates integers and it is safe to treat them as
     /// booleans
+    /// `met` tells whether the spending transaction satisfies the lock.
     pub(super) fn evaluate_older(
         &mut self,
         n: &u32,
-        age: u32,
+        met: bool,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
-        if age >= *n {
+        if met {
             self.push(Element::Satisfied);
             Some(Ok(SatisfiedConstraint::RelativeTimelock { time: *n }))
         } else {
@@ -370,11 +398,12 @@ impl<'txin> Stack<'txin> {
     pub(super) fn evaluate_multi<'intp>(
         &mut self,
         verify_sig: &mut Box<dyn FnMut(&KeySigPair) -> bool + 'intp>,
+        flags: &VerifyFlags,
         pk: &'intp BitcoinKey,
     ) -> Option<Result<SatisfiedConstraint, Error>> {
         if let Some(witness_sig) = self.pop() {
             if let Element::Push(sigser) = witness_sig {
-                let key_sig = verify_sersig(verify_sig, pk, sigser);
+                let key_sig = verify_sersig(verify_sig, flags, pk, sigser);
                 match key_sig {
                     Ok(key_sig) => Some(Ok(SatisfiedConstraint::PublicKey { key_sig })),
                     Err(..) => {
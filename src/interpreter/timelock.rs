@@ -0,0 +1,272 @@
+//! Timelock Evaluation
+//!
+//! Checks the `after` and `older` constraints of a script against the
+//! nLockTime and nSequence fields of the spending transaction, and against
+//! the chain on which it is to be mined, so that a spend failing on a
+//! timelock can be told apart from one which only has to wait.
+
+use bitcoin::Transaction;
+
+use super::Interpreter;
+use crate::miniscript::limits::{
+    SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use crate::prelude::*;
+use crate::timelock::{absolute_timelocks_are_same_unit, n_lock_time_is_block_height};
+use crate::Terminal;
+
+/// A block of the chain against which timelocks are evaluated
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChainTip {
+    /// The height of the block
+    pub height: u32,
+    /// The median time past of the block, as defined by BIP 113
+    pub median_time_past: u32,
+}
+
+/// A timelock of a script
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Timelock {
+    /// An absolute timelock, `after(n)`
+    After(u32),
+    /// A relative timelock, `older(n)`
+    Older(u32),
+}
+
+/// Whether a [`Timelock`] is satisfied by a spending transaction
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TimelockStatus {
+    /// The transaction satisfies the lock, which has expired: the transaction
+    /// can be mined on top of the chain tip
+    Satisfied,
+    /// The transaction satisfies the lock, which has not expired yet: the
+    /// transaction cannot be mined on top of the chain tip
+    Pending,
+    /// The transaction does not satisfy the lock: its lock value is too low,
+    /// or it does not enable timelocks
+    Unsatisfied,
+    /// The lock and the transaction field use different units, one being a
+    /// block height and the other a time
+    TypeMismatch,
+}
+
+impl Timelock {
+    /// Checks the lock against the input `input_idx` of `tx`, to be mined on
+    /// top of `tip`
+    ///
+    /// `coin` is only used by relative timelocks: it holds the height of the
+    /// block which confirmed the spent coin, and the median time past of its
+    /// parent block, as defined by BIP 68.
+    ///
+    /// # Panics
+    ///
+    /// If `input_idx` is out of the inputs of `tx`.
+    pub fn status(
+        &self,
+        tx: &Transaction,
+        input_idx: usize,
+        tip: &ChainTip,
+        coin: &ChainTip,
+    ) -> TimelockStatus {
+        let sequence = tx.input[input_idx].sequence;
+        match self.check_fields(tx.version, tx.lock_time, sequence) {
+            TimelockStatus::Satisfied => {}
+            status => return status,
+        }
+        let expired = match *self {
+            Timelock::After(_) => {
+                // The transaction is final in the next block if its lock
+                // time is below the height of that block, or below the
+                // median time past of the tip
+                let final_below = if n_lock_time_is_block_height(tx.lock_time) {
+                    u64::from(tip.height) + 1
+                } else {
+                    u64::from(tip.median_time_past)
+                };
+                u64::from(tx.lock_time) < final_below
+            }
+            Timelock::Older(_) => {
+                let value = u64::from(sequence & SEQUENCE_LOCKTIME_MASK);
+                if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG == 0 {
+                    u64::from(coin.height) + value <= u64::from(tip.height) + 1
+                } else {
+                    u64::from(coin.median_time_past) + (value << 9)
+                        <= u64::from(tip.median_time_past)
+                }
+            }
+        };
+        if expired {
+            TimelockStatus::Satisfied
+        } else {
+            TimelockStatus::Pending
+        }
+    }
+
+    // Checks the lock against the fields of a spending transaction, following
+    // BIP 65 for `after` and BIP 68 and 112 for `older`, regardless of the
+    // chain. Returns `Satisfied` if they meet the lock.
+    pub(super) fn check_fields(
+        &self,
+        version: i32,
+        lock_time: u32,
+        sequence: u32,
+    ) -> TimelockStatus {
+        match *self {
+            Timelock::After(n) => {
+                // A final input disables the nLockTime of the transaction
+                if sequence == 0xffffffff {
+                    TimelockStatus::Unsatisfied
+                } else if !absolute_timelocks_are_same_unit(n, lock_time) {
+                    TimelockStatus::TypeMismatch
+                } else if lock_time < n {
+                    TimelockStatus::Unsatisfied
+                } else {
+                    TimelockStatus::Satisfied
+                }
+            }
+            Timelock::Older(n) => {
+                if version < 2 || sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                    TimelockStatus::Unsatisfied
+                } else if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != n & SEQUENCE_LOCKTIME_TYPE_FLAG
+                {
+                    TimelockStatus::TypeMismatch
+                } else if sequence & SEQUENCE_LOCKTIME_MASK < n & SEQUENCE_LOCKTIME_MASK {
+                    TimelockStatus::Unsatisfied
+                } else {
+                    TimelockStatus::Satisfied
+                }
+            }
+        }
+    }
+}
+
+impl<'txin> Interpreter<'txin> {
+    /// Every timelock of the script, whether or not it is used by the spend
+    ///
+    /// Empty for spends without a script.
+    pub fn timelocks(&self) -> Vec<Timelock> {
+        let ms = match self.inner {
+            super::inner::Inner::Script(ref ms, _) => ms,
+            super::inner::Inner::PublicKey(..) => return vec![],
+        };
+        ms.iter()
+            .filter_map(|node| match node.node {
+                Terminal::After(n) => Some(Timelock::After(n)),
+                Terminal::Older(n) => Some(Timelock::Older(n)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::{OutPoint, Script, TxIn};
+
+    use super::*;
+    use crate::{Miniscript, Segwitv0};
+
+    fn tx(version: i32, lock_time: u32, sequence: u32) -> Transaction {
+        Transaction {
+            version,
+            lock_time,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence,
+                witness: Witness::default(),
+            }],
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn status() {
+        let tip = ChainTip {
+            height: 700_000,
+            median_time_past: 1_600_000_000,
+        };
+        let coin = ChainTip {
+            height: 699_990,
+            median_time_past: 1_599_990_000,
+        };
+        let status = |lock: Timelock, tx: &Transaction| lock.status(tx, 0, &tip, &coin);
+
+        let after = Timelock::After(699_000);
+        assert_eq!(
+            status(after, &tx(2, 700_000, 0xfffffffe)),
+            TimelockStatus::Satisfied
+        );
+        assert_eq!(
+            status(after, &tx(2, 700_001, 0xfffffffe)),
+            TimelockStatus::Pending
+        );
+        assert_eq!(
+            status(after, &tx(2, 698_999, 0xfffffffe)),
+            TimelockStatus::Unsatisfied
+        );
+        assert_eq!(
+            status(after, &tx(2, 700_000, 0xffffffff)),
+            TimelockStatus::Unsatisfied
+        );
+        assert_eq!(
+            status(after, &tx(2, 1_500_000_000, 0xfffffffe)),
+            TimelockStatus::TypeMismatch
+        );
+        let after_time = Timelock::After(1_500_000_000);
+        assert_eq!(
+            status(after_time, &tx(2, 1_599_999_999, 0xfffffffe)),
+            TimelockStatus::Satisfied
+        );
+        assert_eq!(
+            status(after_time, &tx(2, 1_600_000_000, 0xfffffffe)),
+            TimelockStatus::Pending
+        );
+
+        // The coin was confirmed 10 blocks before the tip
+        let older = Timelock::Older(5);
+        assert_eq!(status(older, &tx(2, 0, 11)), TimelockStatus::Satisfied);
+        assert_eq!(status(older, &tx(2, 0, 12)), TimelockStatus::Pending);
+        assert_eq!(status(older, &tx(2, 0, 4)), TimelockStatus::Unsatisfied);
+        assert_eq!(status(older, &tx(1, 0, 11)), TimelockStatus::Unsatisfied);
+        assert_eq!(
+            status(older, &tx(2, 0, SEQUENCE_LOCKTIME_DISABLE_FLAG | 11)),
+            TimelockStatus::Unsatisfied
+        );
+        assert_eq!(
+            status(older, &tx(2, 0, SEQUENCE_LOCKTIME_TYPE_FLAG | 11)),
+            TimelockStatus::TypeMismatch
+        );
+        // 10_000 seconds passed since the coin, that is 19 periods of 512
+        let older_time = Timelock::Older(SEQUENCE_LOCKTIME_TYPE_FLAG | 5);
+        assert_eq!(
+            status(older_time, &tx(2, 0, SEQUENCE_LOCKTIME_TYPE_FLAG | 19)),
+            TimelockStatus::Satisfied
+        );
+        assert_eq!(
+            status(older_time, &tx(2, 0, SEQUENCE_LOCKTIME_TYPE_FLAG | 20)),
+            TimelockStatus::Pending
+        );
+    }
+
+    #[test]
+    fn timelocks() {
+        let preimage = [0x01; 32];
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "and_v(v:sha256({}),or_i(older(144),after(700000)))",
+            sha256::Hash::hash(&preimage)
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let script_sig = Script::new();
+        let witness = Witness::from_vec(vec![preimage.to_vec(), script.to_bytes()]);
+        let interpreter = Interpreter::from_spend(&spk, &script_sig, &witness).unwrap();
+        assert_eq!(
+            interpreter.timelocks(),
+            vec![Timelock::Older(144), Timelock::After(700000)]
+        );
+    }
+}
@@ -0,0 +1,715 @@
+//! Transaction Interpreter
+//!
+//! Interprets an input of a spending transaction given the outputs spent by
+//! every input, computing the legacy, segwit v0 or taproot sighash of each
+//! signature itself. The Schnorr signatures of several inputs can also be
+//! collected while they are interpreted, and verified one by one afterwards.
+
+use core::ops::Deref;
+
+use bitcoin::util::sighash::{self, SighashCache};
+use bitcoin::{secp256k1, Transaction, TxOut, XOnlyPublicKey};
+
+use super::{
+    ChainTip, Error, Interpreter, Iter, KeySigPair, SatisfiedConstraint, Timelock, TimelockStatus,
+};
+use crate::prelude::*;
+use crate::{Descriptor, MiniscriptKey, ToPublicKey};
+
+/// An [`Interpreter`] for an input of a transaction, along with the
+/// transaction and the outputs spent by all of its inputs
+///
+/// Unlike [`Interpreter::iter`], iterating does not need the caller to
+/// provide the sighash data again: signatures are checked against the
+/// sighashes of the transaction.
+pub struct TxInterpreter<'txin> {
+    interpreter: Interpreter<'txin>,
+    tx: &'txin Transaction,
+    input_idx: usize,
+    prevouts: sighash::Prevouts<'txin, TxOut>,
+}
+
+impl<'txin> TxInterpreter<'txin> {
+    /// Constructs an interpreter for the input `input_idx` of `tx`
+    ///
+    /// `prevouts` are the outputs spent by each input of the transaction, in
+    /// order. All of them are needed to compute taproot sighashes. The
+    /// `older` and `after` fragments are checked against the transaction's
+    /// version, locktime and the input's sequence by the consensus rules of
+    /// BIP 65, 68 and 112: for instance `older` is not met by a version 1
+    /// transaction, and a height lock is not met by a time.
+    pub fn new(
+        tx: &'txin Transaction,
+        input_idx: usize,
+        prevouts: &'txin [TxOut],
+    ) -> Result<Self, Error> {
+        let txin = tx
+            .input
+            .get(input_idx)
+            .ok_or(sighash::Error::IndexOutOfInputsBounds {
+                index: input_idx,
+                inputs_size: tx.input.len(),
+            })?;
+        if prevouts.len() != tx.input.len() {
+            return Err(Error::SighashError(sighash::Error::PrevoutsSize));
+        }
+        let mut interpreter = Interpreter::from_txdata(
+            &prevouts[input_idx].script_pubkey,
+            &txin.script_sig,
+            &txin.witness,
+            txin.sequence,
+            tx.lock_time,
+        )?;
+        interpreter.tx_version = Some(tx.version);
+        Ok(TxInterpreter {
+            interpreter,
+            tx,
+            input_idx,
+            prevouts: sighash::Prevouts::All(prevouts),
+        })
+    }
+
+    /// The interpreter of the input, to inspect the spend or to iterate
+    /// with a custom signature verification function
+    pub fn interpreter(&self) -> &Interpreter<'txin> {
+        &self.interpreter
+    }
+
+    /// The index of the interpreted input
+    pub fn input_index(&self) -> usize {
+        self.input_idx
+    }
+
+    /// Creates an iterator over the satisfied spending conditions, verifying
+    /// every signature against the sighash of the transaction
+    ///
+    /// See [`Interpreter::iter`].
+    pub fn iter<'iter, C: secp256k1::Verification>(
+        &'iter self,
+        secp: &'iter secp256k1::Secp256k1<C>,
+    ) -> Iter<'txin, 'iter> {
+        self.interpreter
+            .iter(secp, self.tx, self.input_idx, &self.prevouts)
+    }
+
+    /// Same as [`TxInterpreter::iter`], computing the sighashes with `cache`
+    ///
+    /// `cache` must be a cache of the transaction of the interpreter. Sharing
+    /// it between the interpreters of the inputs of a transaction computes the
+    /// hashes of its data once rather than once per input, see
+    /// [`Interpreter::verify_sig_with_cache`].
+    pub fn iter_with_cache<'iter, C, R>(
+        &'iter self,
+        secp: &'iter secp256k1::Secp256k1<C>,
+        cache: &'iter mut SighashCache<R>,
+    ) -> Iter<'txin, 'iter>
+    where
+        C: secp256k1::Verification,
+        R: Deref<Target = Transaction>,
+    {
+        self.interpreter
+            .iter_with_cache(secp, cache, self.input_idx, &self.prevouts)
+    }
+
+    /// Whether `key` authorized the input with a valid signature
+    ///
+    /// ECDSA keys match regardless of their compression and x-only keys match
+    /// either parity of `key`. The internal key of a taproot key spend, and
+    /// the participants of an aggregate key, cannot be recognized: only the
+    /// key which signed is known. Fails if the input does not satisfy its
+    /// script.
+    pub fn did_key_sign<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        key: &bitcoin::PublicKey,
+    ) -> Result<bool, Error> {
+        let x_only = XOnlyPublicKey::from(key.inner);
+        let mut signed = false;
+        for constraint in self.iter(secp) {
+            signed |= match constraint?.key_sig() {
+                Some(&KeySigPair::Ecdsa(pk, _)) | Some(&KeySigPair::NonStandardEcdsa(pk, ..)) => {
+                    pk.inner == key.inner
+                }
+                Some(&KeySigPair::Schnorr(xpk, _)) => xpk == x_only,
+                None => false,
+            };
+        }
+        Ok(signed)
+    }
+
+    /// Checks every timelock of the script against the transaction, to be
+    /// mined on top of `tip`
+    ///
+    /// See [`Timelock::status`] for the meaning of `coin`.
+    pub fn timelock_status(
+        &self,
+        tip: &ChainTip,
+        coin: &ChainTip,
+    ) -> Vec<(Timelock, TimelockStatus)> {
+        self.interpreter
+            .timelocks()
+            .into_iter()
+            .map(|lock| (lock, lock.status(self.tx, self.input_idx, tip, coin)))
+            .collect()
+    }
+}
+
+/// An input of a transaction verified by [`verify_transaction`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedInput {
+    /// The index of the descriptor of the spent output
+    pub descriptor_index: usize,
+    /// The constraints satisfied by the input
+    pub constraints: Vec<SatisfiedConstraint>,
+}
+
+/// Verifies every input of a transaction spending outputs of `descriptors`
+///
+/// Each input is matched to the first descriptor with the scriptPubKey of
+/// the output it spends, and interpreted checking its signatures. The result
+/// of each input is given in order, failing with
+/// [`Error::NoMatchingDescriptor`] for inputs spending none of the
+/// descriptors. Fails as a whole if `prevouts` does not have one output per
+/// input.
+pub fn verify_transaction<C, Pk>(
+    secp: &secp256k1::Secp256k1<C>,
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    descriptors: &[Descriptor<Pk>],
+) -> Result<Vec<Result<VerifiedInput, Error>>, Error>
+where
+    C: secp256k1::Verification,
+    Pk: MiniscriptKey + ToPublicKey,
+{
+    if prevouts.len() != tx.input.len() {
+        return Err(Error::SighashError(sighash::Error::PrevoutsSize));
+    }
+    let spks: Vec<_> = descriptors.iter().map(Descriptor::script_pubkey).collect();
+    let mut cache = SighashCache::new(tx);
+    let results = prevouts
+        .iter()
+        .enumerate()
+        .map(|(input_idx, prevout)| {
+            let descriptor_index = spks
+                .iter()
+                .position(|spk| *spk == prevout.script_pubkey)
+                .ok_or(Error::NoMatchingDescriptor)?;
+            let interpreter = TxInterpreter::new(tx, input_idx, prevouts)?;
+            let constraints = interpreter
+                .iter_with_cache(secp, &mut cache)
+                .collect::<Result<_, _>>()?;
+            Ok(VerifiedInput {
+                descriptor_index,
+                constraints,
+            })
+        })
+        .collect();
+    Ok(results)
+}
+
+/// Schnorr signatures of taproot inputs, collected to be verified later
+///
+/// The inputs are interpreted with [`DeferredSchnorrSigs::add_input`] and
+/// their Schnorr signatures are then checked with
+/// [`DeferredSchnorrSigs::verify`]. This does not use batch verification,
+/// which rust-secp256k1 does not expose yet: the signatures are still
+/// verified one by one, only after all the inputs were interpreted.
+#[derive(Clone, Debug, Default)]
+pub struct DeferredSchnorrSigs {
+    sigs: Vec<(
+        secp256k1::Message,
+        secp256k1::schnorr::Signature,
+        XOnlyPublicKey,
+    )>,
+}
+
+impl DeferredSchnorrSigs {
+    /// Creates an empty set of signatures
+    pub fn new() -> Self {
+        DeferredSchnorrSigs::default()
+    }
+
+    /// The number of collected signatures
+    pub fn len(&self) -> usize {
+        self.sigs.len()
+    }
+
+    /// Whether no signature was collected
+    pub fn is_empty(&self) -> bool {
+        self.sigs.is_empty()
+    }
+
+    /// Interprets an input, verifying its ECDSA signatures right away and
+    /// collecting its Schnorr signatures
+    ///
+    /// Returns the constraints satisfied by the input, which only hold if the
+    /// collected signatures are then verified successfully. The signatures of
+    /// an input which fails are not collected.
+    pub fn add_input<C: secp256k1::Verification>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        interpreter: &TxInterpreter,
+    ) -> Result<Vec<SatisfiedConstraint>, Error> {
+        let mut cache = SighashCache::new(interpreter.tx);
+        self.add_input_with_cache(secp, interpreter, &mut cache)
+    }
+
+    /// Same as [`DeferredSchnorrSigs::add_input`], computing the sighashes with
+    /// `cache`, see [`TxInterpreter::iter_with_cache`]
+    pub fn add_input_with_cache<C, R>(
+        &mut self,
+        secp: &secp256k1::Secp256k1<C>,
+        interpreter: &TxInterpreter,
+        cache: &mut SighashCache<R>,
+    ) -> Result<Vec<SatisfiedConstraint>, Error>
+    where
+        C: secp256k1::Verification,
+        R: Deref<Target = Transaction>,
+    {
+        let TxInterpreter {
+            ref interpreter,
+            input_idx,
+            ref prevouts,
+            ..
+        } = *interpreter;
+        let mut sigs = vec![];
+        let constraints = {
+            let verify_sig = Box::new(|key_sig: &KeySigPair| match *key_sig {
+                KeySigPair::Ecdsa(..) | KeySigPair::NonStandardEcdsa(..) => {
+                    interpreter.verify_sig_with_cache(secp, cache, input_idx, prevouts, key_sig)
+                }
+                KeySigPair::Schnorr(key, sig) => {
+                    match interpreter.taproot_sighash_msg(cache, input_idx, prevouts, sig.hash_ty) {
+                        Some(msg) => {
+                            sigs.push((msg, sig.sig, key));
+                            true
+                        }
+                        None => false,
+                    }
+                }
+            });
+            interpreter
+                .iter_custom(verify_sig)
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        self.sigs.extend(sigs);
+        Ok(constraints)
+    }
+
+    /// Verifies every collected signature one after the other, returning the
+    /// key of the first invalid one as an error
+    pub fn verify<C: secp256k1::Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+    ) -> Result<(), Error> {
+        for (msg, sig, key) in &self.sigs {
+            if secp.verify_schnorr(sig, msg, key).is_err() {
+                return Err(Error::InvalidSchnorrSignature(*key));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::schnorr::TweakedPublicKey;
+    use bitcoin::{
+        EcdsaSighashType, KeyPair, OutPoint, PublicKey, SchnorrSighashType, Script, TxIn,
+        XOnlyPublicKey,
+    };
+
+    use super::*;
+    use crate::interpreter::error::PkEvalErrInner;
+    use crate::interpreter::SatisfiedConstraint;
+    use crate::{Miniscript, Segwitv0};
+
+    #[test]
+    fn verify_tx() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let keypair = KeyPair::from_secret_key(&secp, sk);
+        let xpk = XOnlyPublicKey::from_keypair(&keypair);
+        let other_sk = secp256k1::SecretKey::from_slice(&[2; 32]).unwrap();
+        let other = PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &other_sk));
+
+        let prevouts = vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: Script::new_v1_p2tr_tweaked(
+                    TweakedPublicKey::dangerous_assume_tweaked(xpk),
+                ),
+            },
+        ];
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Hash::hash(&[]), vout),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: Witness::default(),
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin(0), txin(1)],
+            output: vec![TxOut {
+                value: 29_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+
+        let mut cache = SighashCache::new(&tx);
+        let sighash = cache
+            .segwit_signature_hash(
+                0,
+                &Script::new_p2pkh(&pk.pubkey_hash()),
+                10_000,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+        let mut ecdsa_sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        ecdsa_sig.push(EcdsaSighashType::All as u8);
+        let sighash = cache
+            .taproot_key_spend_signature_hash(
+                1,
+                &sighash::Prevouts::All(&prevouts),
+                SchnorrSighashType::Default,
+            )
+            .unwrap();
+        let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+        let schnorr_sig = secp.sign_schnorr_with_aux_rand(&msg, &keypair, &[0; 32]);
+        tx.input[0].witness = Witness::from_vec(vec![ecdsa_sig, pk.to_bytes()]);
+        tx.input[1].witness = Witness::from_vec(vec![schnorr_sig.as_ref().to_vec()]);
+
+        for idx in 0..2 {
+            let interpreter = TxInterpreter::new(&tx, idx, &prevouts).unwrap();
+            let constraints = interpreter
+                .iter(&secp)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(constraints.len(), 1);
+            assert!(interpreter.did_key_sign(&secp, &pk).unwrap());
+            assert!(!interpreter.did_key_sign(&secp, &other).unwrap());
+        }
+        // The sighash data of the transaction can be shared by its inputs
+        let mut cache = SighashCache::new(&tx);
+        let mut deferred = DeferredSchnorrSigs::new();
+        for idx in 0..2 {
+            let interpreter = TxInterpreter::new(&tx, idx, &prevouts).unwrap();
+            assert!(interpreter
+                .iter_with_cache(&secp, &mut cache)
+                .all(|res| res.is_ok()));
+            deferred
+                .add_input_with_cache(&secp, &interpreter, &mut cache)
+                .unwrap();
+        }
+        assert_eq!(deferred.len(), 1);
+        deferred.verify(&secp).unwrap();
+
+        // Inputs are matched to the descriptors of their spent outputs
+        let wpkh = Descriptor::new_wpkh(pk).unwrap();
+        let rawtr = Descriptor::new_rawtr(pk);
+        let results = verify_transaction(&secp, &tx, &prevouts, &[rawtr, wpkh.clone()]).unwrap();
+        let indices: Vec<_> = results
+            .into_iter()
+            .map(|res| res.unwrap().descriptor_index)
+            .collect();
+        assert_eq!(indices, vec![1, 0]);
+        let results = verify_transaction(&secp, &tx, &prevouts, &[wpkh]).unwrap();
+        assert!(results[0].is_ok());
+        match results[1] {
+            Err(Error::NoMatchingDescriptor) => {}
+            ref res => panic!("unexpected result {:?}", res),
+        }
+
+        // Spending a different amount changes the segwit sighash
+        let mut prevouts_wrong_amount = prevouts.clone();
+        prevouts_wrong_amount[0].value = 10_001;
+        let interpreter = TxInterpreter::new(&tx, 0, &prevouts_wrong_amount).unwrap();
+        assert!(interpreter.iter(&secp).any(|res| res.is_err()));
+        // And changes the taproot sighash of every input
+        let interpreter = TxInterpreter::new(&tx, 1, &prevouts_wrong_amount).unwrap();
+        match interpreter.iter(&secp).next() {
+            Some(Err(Error::PkEvaluationError(PkEvalErrInner::XOnlyKey(key)))) => {
+                assert_eq!(key, xpk)
+            }
+            ref res => panic!("unexpected result {:?}", res),
+        }
+
+        match TxInterpreter::new(&tx, 2, &prevouts) {
+            Err(Error::SighashError(sighash::Error::IndexOutOfInputsBounds {
+                index: 2, ..
+            })) => {}
+            _ => panic!("input index out of bounds"),
+        }
+        match TxInterpreter::new(&tx, 0, &prevouts[..1]) {
+            Err(Error::SighashError(sighash::Error::PrevoutsSize)) => {}
+            _ => panic!("missing prevout"),
+        }
+    }
+
+    #[test]
+    fn consensus_timelocks() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let spends = |ms: &str, version: i32, lock_time: u32, sequence: u32| {
+            let ms = Miniscript::<PublicKey, Segwitv0>::from_str_insane(ms).unwrap();
+            let script = ms.encode();
+            let prevouts = vec![TxOut {
+                value: 10_000,
+                script_pubkey: script.to_v0_p2wsh(),
+            }];
+            let tx = Transaction {
+                version,
+                lock_time,
+                input: vec![TxIn {
+                    previous_output: OutPoint::default(),
+                    script_sig: Script::new(),
+                    sequence,
+                    witness: Witness::from_vec(vec![script.to_bytes()]),
+                }],
+                output: vec![],
+            };
+            let desc = Descriptor::new_wsh(ms).unwrap();
+            let results = verify_transaction(&secp, &tx, &prevouts, &[desc]).unwrap();
+            results[0].is_ok()
+        };
+
+        assert!(spends("older(10)", 2, 0, 10));
+        assert!(!spends("older(10)", 2, 0, 9));
+        // Relative timelocks need a version 2 transaction
+        assert!(!spends("older(10)", 1, 0, 10));
+        // and are disabled by the disable flag, or by a final sequence
+        assert!(!spends("older(10)", 2, 0, (1 << 31) | 10));
+        assert!(!spends("older(10)", 2, 0, 0xffffffff));
+        // A time-based lock is not met by a height, and the other way around
+        assert!(!spends("older(10)", 2, 0, (1 << 22) | 10));
+        assert!(!spends("older(4194314)", 2, 0, 10));
+        assert!(spends("older(4194314)", 2, 0, (1 << 22) | 10));
+
+        assert!(spends("after(100)", 2, 100, 0xfffffffe));
+        assert!(!spends("after(100)", 2, 99, 0xfffffffe));
+        // A final sequence disables the locktime of the transaction
+        assert!(!spends("after(100)", 2, 100, 0xffffffff));
+        assert!(!spends("after(100)", 2, 500_000_100, 0xfffffffe));
+        assert!(!spends("after(500000000)", 2, 600_000, 0xfffffffe));
+        assert!(spends("after(500000000)", 1, 500_000_000, 0));
+    }
+
+    #[test]
+    fn non_default_sighash() {
+        let secp = secp256k1::Secp256k1::new();
+        let sk = secp256k1::SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk = PublicKey::new(secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let keypair = KeyPair::from_secret_key(&secp, sk);
+        let xpk = XOnlyPublicKey::from_keypair(&keypair);
+
+        let prevouts = vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: Script::new_v1_p2tr_tweaked(
+                    TweakedPublicKey::dangerous_assume_tweaked(xpk),
+                ),
+            },
+        ];
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Hash::hash(&[]), vout),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: Witness::default(),
+        };
+        let txout = TxOut {
+            value: 14_000,
+            script_pubkey: Script::new(),
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin(0), txin(1)],
+            output: vec![txout.clone(), txout],
+        };
+
+        let ecdsa_ty = EcdsaSighashType::AllPlusAnyoneCanPay;
+        let schnorr_ty = SchnorrSighashType::SinglePlusAnyoneCanPay;
+        let mut cache = SighashCache::new(&tx);
+        let sighash = cache
+            .segwit_signature_hash(0, &Script::new_p2pkh(&pk.pubkey_hash()), 10_000, ecdsa_ty)
+            .unwrap();
+        let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+        let mut ecdsa_sig = secp.sign_ecdsa(&msg, &sk).serialize_der().to_vec();
+        ecdsa_sig.push(ecdsa_ty as u8);
+        let sighash = cache
+            .taproot_key_spend_signature_hash(1, &sighash::Prevouts::All(&prevouts), schnorr_ty)
+            .unwrap();
+        let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+        let mut schnorr_sig = secp
+            .sign_schnorr_with_aux_rand(&msg, &keypair, &[0; 32])
+            .as_ref()
+            .to_vec();
+        schnorr_sig.push(schnorr_ty as u8);
+        tx.input[0].witness = Witness::from_vec(vec![ecdsa_sig, pk.to_bytes()]);
+        tx.input[1].witness = Witness::from_vec(vec![schnorr_sig]);
+
+        let expected = [ecdsa_ty.into(), schnorr_ty.into()];
+        for (idx, sighash_ty) in expected.iter().enumerate() {
+            let interpreter = TxInterpreter::new(&tx, idx, &prevouts).unwrap();
+            let constraints = interpreter
+                .iter(&secp)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(constraints.len(), 1);
+            assert_eq!(constraints[0].sighash_type(), Some(*sighash_ty));
+            assert!(constraints[0].key_sig().unwrap().is_anyone_can_pay());
+        }
+
+        // Neither signature commits to the other inputs
+        let mut prevouts = prevouts;
+        prevouts.push(prevouts[0].clone());
+        tx.input.push(txin(2));
+        for idx in 0..2 {
+            let interpreter = TxInterpreter::new(&tx, idx, &prevouts).unwrap();
+            assert!(interpreter.iter(&secp).all(|res| res.is_ok()));
+        }
+    }
+
+    #[test]
+    fn annex() {
+        let secp = secp256k1::Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+        let xpk = XOnlyPublicKey::from_keypair(&keypair);
+        let prevouts = vec![TxOut {
+            value: 20_000,
+            script_pubkey: Script::new_v1_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+                xpk,
+            )),
+        }];
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Hash::hash(&[]), 0),
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: Witness::default(),
+            }],
+            output: vec![],
+        };
+        let annex = vec![0x50, 0x01, 0x02];
+        let sign = |tx: &Transaction, annex: Option<&[u8]>| {
+            let sighash = SighashCache::new(tx)
+                .taproot_signature_hash(
+                    0,
+                    &sighash::Prevouts::All(&prevouts),
+                    annex.map(|annex| sighash::Annex::new(annex).unwrap()),
+                    None,
+                    SchnorrSighashType::Default,
+                )
+                .unwrap();
+            let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+            secp.sign_schnorr_with_aux_rand(&msg, &keypair, &[0; 32])
+                .as_ref()
+                .to_vec()
+        };
+
+        let sig = sign(&tx, Some(&annex));
+        tx.input[0].witness = Witness::from_vec(vec![sig, annex.clone()]);
+        let interpreter = TxInterpreter::new(&tx, 0, &prevouts).unwrap();
+        assert_eq!(interpreter.interpreter().annex(), Some(&annex[..]));
+        assert!(interpreter.interpreter().is_taproot_v1_key_spend());
+        let constraints = interpreter
+            .iter(&secp)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(constraints.len(), 2);
+        assert_eq!(
+            constraints[1],
+            SatisfiedConstraint::Annex {
+                hash: sha256::Hash::hash(&annex),
+                len: 3,
+            }
+        );
+
+        // Signatures commit to the annex
+        let sig = sign(&tx, None);
+        tx.input[0].witness = Witness::from_vec(vec![sig, annex.clone()]);
+        let interpreter = TxInterpreter::new(&tx, 0, &prevouts).unwrap();
+        assert!(interpreter.iter(&secp).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn deferred_schnorr_sigs() {
+        let secp = secp256k1::Secp256k1::new();
+        let keypairs: Vec<_> = (1..3)
+            .map(|i| KeyPair::from_seckey_slice(&secp, &[i; 32]).unwrap())
+            .collect();
+        let xpks: Vec<_> = keypairs.iter().map(XOnlyPublicKey::from_keypair).collect();
+        let prevouts: Vec<_> = xpks
+            .iter()
+            .map(|xpk| TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_v1_p2tr_tweaked(
+                    TweakedPublicKey::dangerous_assume_tweaked(*xpk),
+                ),
+            })
+            .collect();
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Hash::hash(&[]), vout),
+            script_sig: Script::new(),
+            sequence: 0xffffffff,
+            witness: Witness::default(),
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![txin(0), txin(1)],
+            output: vec![],
+        };
+        let mut cache = SighashCache::new(&tx);
+        let sigs: Vec<_> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(idx, keypair)| {
+                let sighash = cache
+                    .taproot_key_spend_signature_hash(
+                        idx,
+                        &sighash::Prevouts::All(&prevouts),
+                        SchnorrSighashType::Default,
+                    )
+                    .unwrap();
+                let msg = secp256k1::Message::from_slice(&sighash).unwrap();
+                secp.sign_schnorr_with_aux_rand(&msg, keypair, &[0; 32])
+                    .as_ref()
+                    .to_vec()
+            })
+            .collect();
+        tx.input[0].witness = Witness::from_vec(vec![sigs[0].clone()]);
+        tx.input[1].witness = Witness::from_vec(vec![sigs[1].clone()]);
+
+        let mut deferred = DeferredSchnorrSigs::new();
+        for idx in 0..2 {
+            let interpreter = TxInterpreter::new(&tx, idx, &prevouts).unwrap();
+            assert_eq!(deferred.add_input(&secp, &interpreter).unwrap().len(), 1);
+        }
+        assert_eq!(deferred.len(), 2);
+        deferred.verify(&secp).unwrap();
+
+        // Swapped signatures are only detected when verifying the deferred signatures
+        tx.input[0].witness = Witness::from_vec(vec![sigs[1].clone()]);
+        let interpreter = TxInterpreter::new(&tx, 0, &prevouts).unwrap();
+        deferred.add_input(&secp, &interpreter).unwrap();
+        match deferred.verify(&secp) {
+            Err(Error::InvalidSchnorrSignature(key)) => assert_eq!(key, xpks[0]),
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Resource Usage
+//!
+//! Records the resources used by the execution of a script with a real
+//! witness, so that they can be compared to the consensus limits.
+
+use core::cmp;
+
+use super::stack::{Element, Stack};
+use super::{BitcoinKey, Error, Iter, SatisfiedConstraint};
+use crate::miniscript::context::NoChecks;
+use crate::prelude::*;
+use crate::{Miniscript, ScriptContext, Terminal};
+
+/// Resources used by the execution of a script
+///
+/// Recorded by iterators created with [`Iter::record_usage`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ResourceUsage {
+    /// The largest number of elements on the stack, the witness included
+    pub peak_stack_depth: usize,
+    /// The number of non-push opcodes executed, the keys checked by an
+    /// `OP_CHECKMULTISIG` counting as opcodes as in the consensus limit
+    pub ops_executed: usize,
+    /// The total size of the witness elements consumed by the script
+    pub witness_bytes: usize,
+}
+
+// Records the resource usage of an iterator as it steps
+pub(super) struct UsageRecorder {
+    usage: ResourceUsage,
+    // Sizes of the elements of the initial stack, its bottom first
+    elem_sizes: Vec<usize>,
+    // Number of elements of the initial stack not consumed yet
+    n_txin: usize,
+}
+
+impl UsageRecorder {
+    pub(super) fn new(stack: &Stack) -> Self {
+        let elem_sizes = stack
+            .as_slice()
+            .iter()
+            .map(|elem| match *elem {
+                Element::Push(data) => data.len(),
+                Element::Satisfied => 1,
+                Element::Dissatisfied => 0,
+            })
+            .collect();
+        UsageRecorder {
+            usage: ResourceUsage {
+                peak_stack_depth: stack.as_slice().len(),
+                ..Default::default()
+            },
+            elem_sizes,
+            n_txin: stack.n_txin(),
+        }
+    }
+
+    // Counts the opcodes of a fragment entered for the first time, its
+    // children being counted when they are entered
+    pub(super) fn enter(&mut self, node: &Miniscript<BitcoinKey, NoChecks>) {
+        let children: usize = node.branches().iter().map(|ms| ms.ext.ops.count).sum();
+        self.usage.ops_executed += node.ext.ops.count - children;
+        if let Terminal::Multi(_, ref keys) = node.node {
+            self.usage.ops_executed += keys.len();
+        }
+    }
+
+    // Updates the usage after a step of the execution
+    pub(super) fn step(&mut self, stack: &Stack) {
+        self.usage.peak_stack_depth = cmp::max(self.usage.peak_stack_depth, stack.as_slice().len());
+        let n_txin = stack.n_txin();
+        self.usage.witness_bytes += self.elem_sizes[n_txin..self.n_txin].iter().sum::<usize>();
+        self.n_txin = n_txin;
+    }
+}
+
+impl<'intp, 'txin: 'intp> Iter<'intp, 'txin>
+where
+    NoChecks: ScriptContext,
+{
+    /// Record the resources used by the execution, see [`Iter::usage`]
+    pub fn record_usage(mut self) -> Self {
+        self.usage = Some(UsageRecorder::new(&self.stack));
+        self
+    }
+
+    /// The resources used so far, `None` unless recorded with
+    /// [`Iter::record_usage`]
+    pub fn usage(&self) -> Option<ResourceUsage> {
+        self.usage.as_ref().map(|recorder| recorder.usage)
+    }
+
+    /// Collects every satisfied constraint along with the resources used by
+    /// the whole execution
+    pub fn collect_with_usage(self) -> Result<(Vec<SatisfiedConstraint>, ResourceUsage), Error> {
+        let mut iter = self.record_usage();
+        let constraints = iter.by_ref().collect::<Result<_, _>>()?;
+        let usage = iter.usage().expect("Usage recorded");
+        Ok((constraints, usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::blockdata::witness::Witness;
+    use bitcoin::hashes::{sha256, Hash};
+
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::Segwitv0;
+
+    #[test]
+    fn usage() {
+        let preimages = [[0x01; 32], [0x02; 32]];
+        let hashes: Vec<_> = preimages.iter().map(|p| sha256::Hash::hash(p)).collect();
+        let script_sig = bitcoin::Script::new();
+
+        // Each hashlock is SIZE <32> EQUALVERIFY SHA256 <hash> EQUAL(VERIFY)
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "and_v(v:sha256({}),sha256({}))",
+            hashes[0], hashes[1]
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let witness = Witness::from_vec(vec![
+            preimages[1].to_vec(),
+            preimages[0].to_vec(),
+            script.to_bytes(),
+        ]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let (constraints, usage) = interpreter.iter_assume_sigs().collect_with_usage().unwrap();
+        assert_eq!(constraints.len(), 2);
+        assert_eq!(
+            usage,
+            ResourceUsage {
+                peak_stack_depth: 2,
+                ops_executed: 8,
+                witness_bytes: 64,
+            }
+        );
+        assert!(interpreter.iter_assume_sigs().usage().is_none());
+
+        // Only the executed branch of an IF counts
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::from_str_insane(&format!(
+            "or_i(sha256({}),sha256({}))",
+            hashes[0], hashes[1]
+        ))
+        .unwrap();
+        let script = ms.encode();
+        let spk = script.to_v0_p2wsh();
+        let witness = Witness::from_vec(vec![preimages[1].to_vec(), vec![], script.to_bytes()]);
+        let interpreter = Interpreter::from_txdata(&spk, &script_sig, &witness, 0, 0).unwrap();
+        let (_, usage) = interpreter.iter_assume_sigs().collect_with_usage().unwrap();
+        assert_eq!(ms.ext.ops.count, 11);
+        assert_eq!(
+            usage,
+            ResourceUsage {
+                peak_stack_depth: 2,
+                ops_executed: 7,
+                witness_bytes: 32,
+            }
+        );
+    }
+}
@@ -78,7 +78,7 @@
 //! ```
 //!
 
-#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![cfg_attr(all(test, feature = "unstable"), feature(test))]
 // Coding conventions
 #![deny(unsafe_code)]
@@ -102,6 +102,11 @@ extern crate hashbrown;
 #[cfg(any(feature = "std", test))]
 extern crate core;
 
+// The test harness needs std, while the crate itself is tested with the
+// `alloc` based prelude
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
 #[cfg(feature = "serde")]
 pub use serde;
 #[cfg(all(test, feature = "unstable"))]
@@ -114,8 +119,11 @@ pub mod descriptor;
 pub mod expression;
 pub mod interpreter;
 pub mod miniscript;
+pub mod plan;
 pub mod policy;
 pub mod psbt;
+#[cfg(feature = "test-utils")]
+pub mod testing;
 pub mod timelock;
 
 #[cfg(test)]
@@ -134,7 +142,7 @@ pub use crate::descriptor::{Descriptor, DescriptorPublicKey};
 pub use crate::interpreter::Interpreter;
 pub use crate::miniscript::context::{BareCtx, Legacy, ScriptContext, Segwitv0, Tap};
 pub use crate::miniscript::decode::Terminal;
-pub use crate::miniscript::satisfy::{Preimage32, Satisfier};
+pub use crate::miniscript::satisfy::{DynSatisfier, Preimage32, PreimageProvider, Satisfier};
 pub use crate::miniscript::Miniscript;
 use crate::prelude::*;
 
@@ -458,6 +466,50 @@ where
         T: Translator<P, Q, E>;
 }
 
+/// Describes an object that can parse the keys and hashes of a string into
+/// the types associated with a key. Used by the `from_str_with` methods of
+/// [`Miniscript`], policies and [`Descriptor`] to parse keys which are not
+/// [`FromStr`], or which are written as aliases or URIs.
+pub trait KeyParser<Pk, E>
+where
+    Pk: MiniscriptKey,
+{
+    /// Parses a public key
+    fn pk(&mut self, s: &str) -> Result<Pk, E>;
+
+    /// Parses a public key hash
+    fn pkh(&mut self, s: &str) -> Result<Pk::Hash, E>;
+
+    /// Parses a sha256 hash
+    fn sha256(&mut self, s: &str) -> Result<Pk::Sha256, E>;
+}
+
+/// The [`KeyParser`] used by [`FromStr`], which parses each key and hash
+/// with its [`FromStr`] implementation
+pub(crate) struct FromStrParser;
+
+impl<Pk> KeyParser<Pk, String> for FromStrParser
+where
+    Pk: MiniscriptKey + FromStr,
+    Pk::Hash: FromStr,
+    Pk::Sha256: FromStr,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+{
+    fn pk(&mut self, s: &str) -> Result<Pk, String> {
+        Pk::from_str(s).map_err(|e| e.to_string())
+    }
+
+    fn pkh(&mut self, s: &str) -> Result<Pk::Hash, String> {
+        Pk::Hash::from_str(s).map_err(|e| e.to_string())
+    }
+
+    fn sha256(&mut self, s: &str) -> Result<Pk::Sha256, String> {
+        Pk::Sha256::from_str(s).map_err(|e| e.to_string())
+    }
+}
+
 /// Either a key or a keyhash
 pub enum ForEach<'a, Pk: MiniscriptKey> {
     /// A key
@@ -497,8 +549,11 @@ pub trait ForEachKey<Pk: MiniscriptKey> {
 }
 
 /// Miniscript
-
+///
+/// Use [`Error::kind`] to handle whole categories of errors. The enum is
+/// `non_exhaustive`, so new variants are not a breaking change.
 #[derive(Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Error {
     /// Opcode appeared which is not part of the script subset
     InvalidOpcode(opcodes::All),
@@ -517,6 +572,8 @@ pub enum Error {
     MultiATooManyKeys(u32),
     /// Encountered unprintable character in descriptor
     Unprintable(u8),
+    /// Encountered a character which is not ASCII in descriptor
+    NonAscii(char),
     /// expected character while parsing descriptor; didn't find one
     ExpectedChar(char),
     /// While parsing backward, hit beginning of script
@@ -549,6 +606,12 @@ pub enum Error {
     AbsoluteLocktimeNotMet(u32),
     /// General failure to satisfy
     CouldNotSatisfy,
+    /// Failure to satisfy, with the reasons for each offending fragment
+    Unsatisfiable(Vec<miniscript::satisfy::SatisfactionError>),
+    /// General failure to dissatisfy
+    CouldNotDissatisfy,
+    /// The fragment does not have a unique, non-malleable dissatisfaction
+    NoCanonicalDissatisfaction,
     /// Typechecking failed
     TypeCheck(String),
     /// General error in creating descriptor
@@ -564,8 +627,9 @@ pub enum Error {
     LiftError(policy::LiftError),
     /// Forward script context related errors
     ContextError(miniscript::context::ScriptContextError),
-    /// Recursion depth exceeded when parsing policy/miniscript from string
-    MaxRecursiveDepthExceeded,
+    /// Recursion depth over the given limit when parsing policy/miniscript
+    /// from string, or taproot tree too deep
+    MaxRecursiveDepthExceeded(u32),
     /// Script size too large
     ScriptSizeTooLarge,
     /// Anything but c:pk(key) (P2PK), c:pk_h(key) (P2PKH), and thresh_m(k,...)
@@ -585,6 +649,31 @@ pub enum Error {
     TrNoScriptCode,
     /// No explicit script for Tr descriptors
     TrNoExplicitScript,
+    /// Annex that does not start with `0x50`, or used outside of taproot
+    InvalidTapAnnex,
+    /// Signature sizes which no ECDSA or Schnorr signature can have
+    InvalidSigSizes(usize, usize),
+    /// Leaves given to a [`descriptor::TapTreeBuilder`] do not form a valid tree
+    TapTreeBuilder(bitcoin::util::taproot::TaprootBuilderError),
+    /// raw() and addr() descriptors only describe a scriptPubKey and cannot be spent from
+    WatchOnlyDescriptor,
+    /// The address of an addr() descriptor is for another network than the
+    /// requested one
+    AddrNetworkMismatch(bitcoin::Network, bitcoin::Network),
+    /// The checksum of a descriptor is wrong, along with the descriptor with the
+    /// correct checksum if the rest of it is valid
+    BadChecksum(descriptor::checksum::ChecksumError, Option<String>),
+    /// The spending conditions of a taproot tree with opaque leaves are unknown
+    OpaqueTapLeaf,
+    /// The internal key of a tr() descriptor, as written, is an uncompressed
+    /// key or a hash rather than an x-only key
+    NonXOnlyInternalKey(String),
+    /// An error found in the expression at the given byte offset of the
+    /// parsed string, returned by [`expression::FromTree`]. Parsing from a
+    /// string returns the error alone, see [`LocatedError`] for its position.
+    At(usize, Box<Error>),
+    /// A fragment is unknown, or has the wrong number of arguments
+    UnknownFragment(expression::UnknownFragment),
 }
 
 // https://github.com/sipa/miniscript/pull/5 for discussion on this number
@@ -592,6 +681,121 @@ const MAX_RECURSION_DEPTH: u32 = 402;
 // https://github.com/bitcoin/bips/blob/master/bip-0141.mediawiki
 const MAX_SCRIPT_SIZE: u32 = 10000;
 
+/// The category of an [`Error`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A descriptor, miniscript or policy string is malformed
+    Parse,
+    /// A script could not be decoded as a miniscript
+    Decode,
+    /// A miniscript is not well-typed, or fails the sanity checks
+    TypeCheck,
+    /// A miniscript is not valid in its script context, e.g. it is too large
+    Context,
+    /// A policy is invalid or could not be lifted
+    Policy,
+    /// A policy could not be compiled
+    Compiler,
+    /// A miniscript could not be satisfied or dissatisfied
+    Satisfaction,
+    /// The descriptor does not support the operation, e.g. it has no address
+    Descriptor,
+    /// Any other error
+    Other,
+}
+
+impl Error {
+    /// The category of the error
+    pub fn kind(&self) -> ErrorKind {
+        use self::Error::*;
+
+        match *self {
+            Unprintable(_)
+            | NonAscii(_)
+            | ExpectedChar(_)
+            | Unexpected(_)
+            | MultiColon(_)
+            | MultiAt(_)
+            | AtOutsideOr(_)
+            | LikelyFalse
+            | UnknownWrapper(_)
+            | MaxRecursiveDepthExceeded(_)
+            | BadDescriptor(_)
+            | BadChecksum(..)
+            | NonXOnlyInternalKey(..)
+            | UnknownFragment(_) => ErrorKind::Parse,
+            InvalidOpcode(_) | NonMinimalVerify(_) | InvalidPush(_) | Script(_)
+            | UnexpectedStart | Trailing(_) | BadPubkey(_) | PubKeyCtxError(..) => {
+                ErrorKind::Decode
+            }
+            TypeCheck(_) | NonTopLevel(_) | AnalysisError(_) => ErrorKind::TypeCheck,
+            ContextError(_)
+            | CmsTooManyKeys(_)
+            | MultiATooManyKeys(_)
+            | ScriptSizeTooLarge
+            | NonStandardBareScript => ErrorKind::Context,
+            PolicyError(_) | LiftError(_) => ErrorKind::Policy,
+            #[cfg(feature = "compiler")]
+            CompilerError(_) => ErrorKind::Compiler,
+            MissingHash(_)
+            | MissingSig(_)
+            | RelativeLocktimeNotMet(_)
+            | AbsoluteLocktimeNotMet(_)
+            | CouldNotSatisfy
+            | Unsatisfiable(_)
+            | CouldNotDissatisfy
+            | NoCanonicalDissatisfaction
+            | ImpossibleSatisfaction
+            | InvalidTapAnnex
+            | InvalidSigSizes(..) => ErrorKind::Satisfaction,
+            AddrError(_)
+            | BareDescriptorAddr
+            | TaprootSpendInfoUnavialable
+            | TrNoScriptCode
+            | TrNoExplicitScript
+            | WatchOnlyDescriptor
+            | AddrNetworkMismatch(..)
+            | OpaqueTapLeaf
+            | TapTreeBuilder(_) => ErrorKind::Descriptor,
+            Secp(_) => ErrorKind::Other,
+            At(_, ref e) => e.kind(),
+        }
+    }
+
+    // The byte offset of the expression in which the error was found, if known
+    pub(crate) fn position(&self) -> Option<usize> {
+        match *self {
+            Error::At(pos, _) => Some(pos),
+            _ => None,
+        }
+    }
+
+    // The error without its position
+    pub(crate) fn unlocated(self) -> Error {
+        match self {
+            Error::At(_, e) => *e,
+            e => e,
+        }
+    }
+
+    // Locates the error at byte `pos`, unless an inner expression already did
+    pub(crate) fn at(self, pos: usize) -> Error {
+        match self {
+            Error::At(..) => self,
+            e => Error::At(pos, Box::new(e)),
+        }
+    }
+
+    // Locates the error in a string parsed from byte `offset` of another one
+    pub(crate) fn offset(self, offset: usize) -> Error {
+        match self {
+            Error::At(pos, e) => Error::At(offset + pos, e),
+            e => Error::At(offset, Box::new(e)),
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -601,7 +805,13 @@ impl fmt::Display for Error {
             Error::Script(ref e) => fmt::Display::fmt(e, f),
             Error::AddrError(ref e) => fmt::Display::fmt(e, f),
             Error::CmsTooManyKeys(n) => write!(f, "checkmultisig with {} keys", n),
+            Error::Unprintable(x) if x.is_ascii_whitespace() => write!(
+                f,
+                "unexpected whitespace {:?}, use expression::ParseMode::Lenient to ignore it",
+                x as char
+            ),
             Error::Unprintable(x) => write!(f, "unprintable character 0x{:02x}", x),
+            Error::NonAscii(c) => write!(f, "non-ASCII character {:?}", c),
             Error::ExpectedChar(c) => write!(f, "expected {}", c),
             Error::UnexpectedStart => f.write_str("unexpected start of script"),
             Error::Unexpected(ref s) => write!(f, "unexpected «{}»", s),
@@ -623,6 +833,16 @@ impl fmt::Display for Error {
                 n
             ),
             Error::CouldNotSatisfy => f.write_str("could not satisfy"),
+            Error::Unsatisfiable(ref errs) => {
+                f.write_str("could not satisfy")?;
+                for (i, e) in errs.iter().enumerate() {
+                    f.write_str(if i == 0 { ": " } else { "; " })?;
+                    fmt::Display::fmt(e, f)?;
+                }
+                Ok(())
+            }
+            Error::CouldNotDissatisfy => f.write_str("could not dissatisfy"),
+            Error::NoCanonicalDissatisfaction => f.write_str("no canonical dissatisfaction exists"),
             Error::BadPubkey(ref e) => fmt::Display::fmt(e, f),
             Error::TypeCheck(ref e) => write!(f, "typecheck: {}", e),
             Error::BadDescriptor(ref e) => write!(f, "Invalid descriptor: {}", e),
@@ -632,11 +852,9 @@ impl fmt::Display for Error {
             Error::CompilerError(ref e) => fmt::Display::fmt(e, f),
             Error::PolicyError(ref e) => fmt::Display::fmt(e, f),
             Error::LiftError(ref e) => fmt::Display::fmt(e, f),
-            Error::MaxRecursiveDepthExceeded => write!(
-                f,
-                "Recursive depth over {} not permitted",
-                MAX_RECURSION_DEPTH
-            ),
+            Error::MaxRecursiveDepthExceeded(max) => {
+                write!(f, "Recursive depth over {} not permitted", max)
+            }
             Error::ScriptSizeTooLarge => write!(
                 f,
                 "Standardness rules imply bitcoin than {} bytes",
@@ -658,13 +876,43 @@ impl fmt::Display for Error {
             Error::TaprootSpendInfoUnavialable => write!(f, "Taproot Spend Info not computed."),
             Error::TrNoScriptCode => write!(f, "No script code for Tr descriptors"),
             Error::TrNoExplicitScript => write!(f, "No script code for Tr descriptors"),
+            Error::InvalidTapAnnex => write!(
+                f,
+                "Annex must start with 0x50 and is only allowed in taproot spends"
+            ),
+            Error::InvalidSigSizes(ecdsa, schnorr) => write!(
+                f,
+                "Invalid signature sizes: {} bytes for ECDSA (9 to 72), {} bytes for Schnorr (64 or 65)",
+                ecdsa, schnorr
+            ),
+            Error::TapTreeBuilder(ref e) => write!(f, "Invalid taptree: {}", e),
+            Error::WatchOnlyDescriptor => {
+                f.write_str("raw() and addr() descriptors are watch-only")
+            }
+            Error::AddrNetworkMismatch(found, requested) => write!(
+                f,
+                "addr() descriptor for {} used on {}",
+                found, requested
+            ),
+            Error::OpaqueTapLeaf => {
+                f.write_str("taproot tree has opaque leaves with unknown spending conditions")
+            }
+            Error::NonXOnlyInternalKey(ref key) => {
+                write!(f, "tr() internal key {} is not an x-only key", key)
+            }
+            Error::BadChecksum(ref e, None) => write!(f, "Invalid descriptor: {}", e),
+            Error::BadChecksum(ref e, Some(ref suggestion)) => {
+                write!(f, "Invalid descriptor: {}, did you mean {}?", e, suggestion)
+            }
+            Error::At(pos, ref e) => write!(f, "{} at byte {}", e, pos),
+            Error::UnknownFragment(ref e) => fmt::Display::fmt(e, f),
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::Error::*;
 
         match self {
@@ -674,6 +922,7 @@ impl error::Error for Error {
             | CmsTooManyKeys(_)
             | MultiATooManyKeys(_)
             | Unprintable(_)
+            | NonAscii(_)
             | ExpectedChar(_)
             | UnexpectedStart
             | Unexpected(_)
@@ -689,16 +938,25 @@ impl error::Error for Error {
             | RelativeLocktimeNotMet(_)
             | AbsoluteLocktimeNotMet(_)
             | CouldNotSatisfy
+            | Unsatisfiable(_)
+            | CouldNotDissatisfy
+            | NoCanonicalDissatisfaction
             | TypeCheck(_)
             | BadDescriptor(_)
-            | MaxRecursiveDepthExceeded
+            | MaxRecursiveDepthExceeded(_)
             | ScriptSizeTooLarge
             | NonStandardBareScript
             | ImpossibleSatisfaction
             | BareDescriptorAddr
             | TaprootSpendInfoUnavialable
             | TrNoScriptCode
-            | TrNoExplicitScript => None,
+            | WatchOnlyDescriptor
+            | AddrNetworkMismatch(..)
+            | OpaqueTapLeaf
+            | NonXOnlyInternalKey(..)
+            | TrNoExplicitScript
+            | InvalidTapAnnex
+            | InvalidSigSizes(..) => None,
             Script(e) => Some(e),
             AddrError(e) => Some(e),
             BadPubkey(e) => Some(e),
@@ -710,10 +968,74 @@ impl error::Error for Error {
             ContextError(e) => Some(e),
             AnalysisError(e) => Some(e),
             PubKeyCtxError(e, _) => Some(e),
+            TapTreeBuilder(e) => Some(e),
+            BadChecksum(e, _) => Some(e),
+            At(_, e) => Some(e.as_ref()),
+            UnknownFragment(e) => Some(e),
         }
     }
 }
 
+/// An [`Error`] found while parsing a string, along with the byte offset in
+/// the string of the expression in which it was found, if known
+///
+/// Returned by [`expression::FromStrLocated`], the `FromStr` implementations
+/// returning the [`Error`] alone.
+#[derive(Debug, PartialEq)]
+pub struct LocatedError {
+    error: Error,
+    position: Option<usize>,
+}
+
+impl LocatedError {
+    /// The error
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// The byte offset in the parsed string of the expression in which the
+    /// error was found, if known
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// Converts into the error, without its position
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+}
+
+impl From<Error> for LocatedError {
+    fn from(e: Error) -> LocatedError {
+        match e {
+            Error::At(pos, e) => LocatedError {
+                error: *e,
+                position: Some(pos),
+            },
+            e => LocatedError {
+                error: e,
+                position: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(pos) => write!(f, "{} at byte {}", self.error, pos),
+            None => fmt::Display::fmt(&self.error, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for LocatedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 #[doc(hidden)]
 impl<Pk, Ctx> From<miniscript::types::Error<Pk, Ctx>> for Error
 where
@@ -753,6 +1075,13 @@ impl From<bitcoin::secp256k1::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<bitcoin::util::taproot::TaprootBuilderError> for Error {
+    fn from(e: bitcoin::util::taproot::TaprootBuilderError) -> Error {
+        Error::TapTreeBuilder(e)
+    }
+}
+
 #[doc(hidden)]
 impl From<bitcoin::util::address::Error> for Error {
     fn from(e: bitcoin::util::address::Error) -> Error {
@@ -868,12 +1197,39 @@ mod tests {
         let hash = pk.to_pubkeyhash();
         assert_eq!(hash, pk)
     }
+
+    #[test]
+    fn error_kinds() {
+        let err = Miniscript::<String, Segwitv0>::from_str("and_v(v:pk(A),oldr(1))").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        let err = Miniscript::<String, Segwitv0>::from_str("and_v(pk(A),pk(B))").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeCheck);
+        let err = Miniscript::<String, Segwitv0>::from_str("multi_a(1,A)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Context);
+
+        #[cfg(feature = "std")]
+        {
+            use std::error::Error as _;
+
+            use crate::expression::FromStrLocated;
+
+            // The location of the error is chained to the error itself
+            let err = Miniscript::<String, Segwitv0>::from_str_located("multi_a(1,A)").unwrap_err();
+            assert_eq!(err.position(), Some(0));
+            let source = err.source().unwrap();
+            assert_eq!(
+                source.to_string(),
+                "Multi a(CHECKSIGADD) only allowed post tapscript"
+            );
+            assert!(source.source().is_some());
+        }
+    }
 }
 
 mod prelude {
     // Mutex implementation from LDK
     // https://github.com/lightningdevkit/rust-lightning/blob/9bdce47f0e0516e37c89c09f1975dfc06b5870b1/lightning-invoice/src/sync.rs
-    #[cfg(all(not(feature = "std"), not(test)))]
+    #[cfg(not(feature = "std"))]
     mod mutex {
         use core::cell::{RefCell, RefMut};
         use core::ops::{Deref, DerefMut};
@@ -921,21 +1277,21 @@ mod prelude {
         }
     }
 
-    #[cfg(all(not(feature = "std"), not(test)))]
+    #[cfg(not(feature = "std"))]
     pub use alloc::{
         borrow::{Borrow, Cow, ToOwned},
         boxed::Box,
-        collections::{vec_deque::VecDeque, BTreeMap, BinaryHeap},
+        collections::{vec_deque::VecDeque, BTreeMap, BTreeSet, BinaryHeap},
         rc, slice,
         string::{String, ToString},
         sync,
         vec::Vec,
     };
-    #[cfg(any(feature = "std", test))]
+    #[cfg(feature = "std")]
     pub use std::{
         borrow::{Borrow, Cow, ToOwned},
         boxed::Box,
-        collections::{vec_deque::VecDeque, BTreeMap, BinaryHeap, HashMap, HashSet},
+        collections::{vec_deque::VecDeque, BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
         rc, slice,
         string::{String, ToString},
         sync,
@@ -943,9 +1299,9 @@ mod prelude {
         vec::Vec,
     };
 
-    #[cfg(all(not(feature = "std"), not(test)))]
+    #[cfg(not(feature = "std"))]
     pub use hashbrown::{HashMap, HashSet};
 
-    #[cfg(all(not(feature = "std"), not(test)))]
+    #[cfg(not(feature = "std"))]
     pub use self::mutex::Mutex;
 }
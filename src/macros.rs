@@ -45,6 +45,10 @@ macro_rules! impl_from_tree {
 
 /// Macro for implementing FromStr trait. This avoids copying all the Pk::Associated type bounds
 /// throughout the codebase.
+///
+/// The body returns its errors located in the string, as they are returned by
+/// the `FromStrLocated` implementation also generated, while `FromStr` returns
+/// them without their position.
 macro_rules! impl_from_str {
     ($(;$gen:ident; $gen_con:ident, )* $name: ty,
         type Err = $err_ty:ty;,
@@ -52,6 +56,22 @@ macro_rules! impl_from_str {
         fn $fn:ident ( $($arg:ident : $type:ty),* ) -> $ret:ty
         $body:block
     ) => {
+        impl<Pk $(, $gen)*> $crate::expression::FromStrLocated for $name
+        where
+            Pk: MiniscriptKey + core::str::FromStr,
+            Pk::Hash: core::str::FromStr,
+            Pk::Sha256: core::str::FromStr,
+            <Pk as core::str::FromStr>::Err: $crate::prelude::ToString,
+            <<Pk as MiniscriptKey>::Hash as core::str::FromStr>::Err: $crate::prelude::ToString,
+            <<Pk as MiniscriptKey>::Sha256 as core::str::FromStr>::Err: $crate::prelude::ToString,
+            $($gen : $gen_con,)*
+            {
+                fn from_str_located($($arg: $type)* ) -> Result<Self, $crate::LocatedError> {
+                    let parse = |$($arg: $type)*| -> $ret { $body };
+                    parse($($arg)*).map_err($crate::LocatedError::from)
+                }
+            }
+
         impl<Pk $(, $gen)*> core::str::FromStr for $name
         where
             Pk: MiniscriptKey + core::str::FromStr,
@@ -66,7 +86,8 @@ macro_rules! impl_from_str {
 
                 $(#[$meta])*
                 fn $fn($($arg: $type)* ) -> $ret {
-                    $body
+                    <Self as $crate::expression::FromStrLocated>::from_str_located($($arg)*)
+                        .map_err($crate::LocatedError::into_error)
                 }
             }
     };
@@ -70,7 +70,7 @@ impl fmt::Display for AnalysisError {
 
 #[cfg(feature = "std")]
 impl error::Error for AnalysisError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::AnalysisError::*;
 
         match self {
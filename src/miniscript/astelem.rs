@@ -20,7 +20,6 @@
 //! are given on the Miniscript website.
 
 use core::fmt;
-use core::str::FromStr;
 
 use bitcoin::blockdata::{opcodes, script};
 use bitcoin::hashes::hex::FromHex;
@@ -28,13 +27,14 @@ use bitcoin::hashes::{hash160, ripemd160, sha256d, Hash};
 use sync::Arc;
 
 use crate::miniscript::context::SigType;
+use crate::miniscript::subs::Subs;
 use crate::miniscript::types::{self, Property};
 use crate::miniscript::ScriptContext;
 use crate::prelude::*;
-use crate::util::MsKeyBuilder;
+use crate::util::{sort_x_only_keys, MsKeyBuilder};
 use crate::{
-    errstr, expression, script_num_size, Error, ForEach, ForEachKey, Miniscript, MiniscriptKey,
-    Terminal, ToPublicKey, TranslatePk, Translator,
+    errstr, expression, script_num_size, Error, ForEach, ForEachKey, FromStrParser, KeyParser,
+    Miniscript, MiniscriptKey, Terminal, ToPublicKey, TranslatePk, Translator,
 };
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
@@ -116,9 +116,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                     && c.real_for_each_key(pred)
             }
             Terminal::Thresh(_, ref subs) => subs.iter().all(|sub| sub.real_for_each_key(pred)),
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => {
-                keys.iter().all(|key| pred(ForEach::Key(key)))
-            }
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys.iter().all(|key| pred(ForEach::Key(key))),
         }
     }
 
@@ -178,7 +178,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                 Arc::new(right.real_translate_pk(t)?),
             ),
             Terminal::Thresh(k, ref subs) => {
-                let subs: Result<Vec<Arc<Miniscript<Q, _>>>, _> = subs
+                let subs: Result<Subs<Arc<Miniscript<Q, _>>>, _> = subs
                     .iter()
                     .map(|s| s.real_translate_pk(t).map(Arc::new))
                     .collect();
@@ -192,6 +192,10 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                 let keys: Result<Vec<Q>, _> = keys.iter().map(|k| t.pk(k)).collect();
                 Terminal::MultiA(k, keys?)
             }
+            Terminal::SortedMultiA(k, ref keys) => {
+                let keys: Result<Vec<Q>, _> = keys.iter().map(|k| t.pk(k)).collect();
+                Terminal::SortedMultiA(k, keys?)
+            }
         };
         Ok(frag)
     }
@@ -302,6 +306,13 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Debug for Terminal<Pk, Ctx> {
                     }
                     f.write_str(")")
                 }
+                Terminal::SortedMultiA(k, ref keys) => {
+                    write!(f, "sortedmulti_a({}", k)?;
+                    for k in keys {
+                        write!(f, ",{}", k)?;
+                    }
+                    f.write_str(")")
+                }
                 _ => unreachable!(),
             }
         }
@@ -365,6 +376,13 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
                 }
                 f.write_str(")")
             }
+            Terminal::SortedMultiA(k, ref keys) => {
+                write!(f, "sortedmulti_a({}", k)?;
+                for k in keys {
+                    write!(f, ",{}", k)?;
+                }
+                f.write_str(")")
+            }
             // wrappers
             _ => {
                 if let Some((ch, sub)) = self.wrap_char() {
@@ -400,6 +418,35 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Terminal<Pk, Ctx> {
     }
 }
 
+// The fragments of miniscript, with the multisig ones depending on the context
+#[rustfmt::skip]
+const FRAGMENTS: &[expression::Fragment] = &[
+    expression::Fragment { name: "pk", usage: "pk(key)" },
+    expression::Fragment { name: "pkh", usage: "pkh(key)" },
+    expression::Fragment { name: "pk_k", usage: "pk_k(key)" },
+    expression::Fragment { name: "pk_h", usage: "pk_h(key)" },
+    expression::Fragment { name: "after", usage: "after(n)" },
+    expression::Fragment { name: "older", usage: "older(n)" },
+    expression::Fragment { name: "sha256", usage: "sha256(h)" },
+    expression::Fragment { name: "hash256", usage: "hash256(h)" },
+    expression::Fragment { name: "ripemd160", usage: "ripemd160(h)" },
+    expression::Fragment { name: "hash160", usage: "hash160(h)" },
+    expression::Fragment { name: "1", usage: "1" },
+    expression::Fragment { name: "0", usage: "0" },
+    expression::Fragment { name: "and_v", usage: "and_v(X,Y)" },
+    expression::Fragment { name: "and_b", usage: "and_b(X,Y)" },
+    expression::Fragment { name: "and_n", usage: "and_n(X,Y)" },
+    expression::Fragment { name: "andor", usage: "andor(X,Y,Z)" },
+    expression::Fragment { name: "or_b", usage: "or_b(X,Z)" },
+    expression::Fragment { name: "or_d", usage: "or_d(X,Z)" },
+    expression::Fragment { name: "or_c", usage: "or_c(X,Z)" },
+    expression::Fragment { name: "or_i", usage: "or_i(X,Z)" },
+    expression::Fragment { name: "thresh", usage: "thresh(k,X,...)" },
+    expression::Fragment { name: "multi", usage: "multi(k,key,...)" },
+    expression::Fragment { name: "multi_a", usage: "multi_a(k,key,...)" },
+    expression::Fragment { name: "sortedmulti_a", usage: "sortedmulti_a(k,key,...)" },
+];
+
 impl_from_tree!(
     ;Ctx; ScriptContext,
     Arc<Terminal<Pk, Ctx>>,
@@ -412,6 +459,21 @@ impl_from_tree!(
     ;Ctx; ScriptContext,
     Terminal<Pk, Ctx>,
     fn from_tree(top: &expression::Tree) -> Result<Terminal<Pk, Ctx>, Error> {
+        Terminal::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
+    /// Parses an expression tree into a fragment, with the keys and hashes
+    /// parsed by `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Terminal<Pk, Ctx>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         let mut aliased_wrap;
         let frag_name;
         let frag_wrap;
@@ -457,21 +519,17 @@ impl_from_tree!(
             }
         }
         let mut unwrapped = match (frag_name, top.args.len()) {
-            ("pk_k", 1) => {
-                expression::terminal(&top.args[0], |x| Pk::from_str(x).map(Terminal::PkK))
-            }
-            ("pk_h", 1) => {
-                expression::terminal(&top.args[0], |x| Pk::Hash::from_str(x).map(Terminal::PkH))
-            }
+            ("pk_k", 1) => expression::terminal(&top.args[0], |x| parser.pk(x).map(Terminal::PkK)),
+            ("pk_h", 1) => expression::terminal(&top.args[0], |x| parser.pkh(x).map(Terminal::PkH)),
             ("after", 1) => expression::terminal(&top.args[0], |x| {
                 expression::parse_num(x).map(Terminal::After)
             }),
             ("older", 1) => expression::terminal(&top.args[0], |x| {
                 expression::parse_num(x).map(Terminal::Older)
             }),
-            ("sha256", 1) => expression::terminal(&top.args[0], |x| {
-                Pk::Sha256::from_str(x).map(Terminal::Sha256)
-            }),
+            ("sha256", 1) => {
+                expression::terminal(&top.args[0], |x| parser.sha256(x).map(Terminal::Sha256))
+            }
             ("hash256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256d::Hash::from_hex(x)
                     .map(|x| x.into_inner())
@@ -489,22 +547,40 @@ impl_from_tree!(
             }),
             ("1", 0) => Ok(Terminal::True),
             ("0", 0) => Ok(Terminal::False),
-            ("and_v", 2) => expression::binary(top, Terminal::AndV),
-            ("and_b", 2) => expression::binary(top, Terminal::AndB),
+            ("and_v", 2) => Ok(Terminal::AndV(
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
+            )),
+            ("and_b", 2) => Ok(Terminal::AndB(
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
+            )),
             ("and_n", 2) => Ok(Terminal::AndOr(
-                expression::FromTree::from_tree(&top.args[0])?,
-                expression::FromTree::from_tree(&top.args[1])?,
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
                 Arc::new(Miniscript::from_ast(Terminal::False)?),
             )),
             ("andor", 3) => Ok(Terminal::AndOr(
-                expression::FromTree::from_tree(&top.args[0])?,
-                expression::FromTree::from_tree(&top.args[1])?,
-                expression::FromTree::from_tree(&top.args[2])?,
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
+                sub(&top.args[2], parser)?,
+            )),
+            ("or_b", 2) => Ok(Terminal::OrB(
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
+            )),
+            ("or_d", 2) => Ok(Terminal::OrD(
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
+            )),
+            ("or_c", 2) => Ok(Terminal::OrC(
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
+            )),
+            ("or_i", 2) => Ok(Terminal::OrI(
+                sub(&top.args[0], parser)?,
+                sub(&top.args[1], parser)?,
             )),
-            ("or_b", 2) => expression::binary(top, Terminal::OrB),
-            ("or_d", 2) => expression::binary(top, Terminal::OrD),
-            ("or_c", 2) => expression::binary(top, Terminal::OrC),
-            ("or_i", 2) => expression::binary(top, Terminal::OrI),
             ("thresh", n) => {
                 if n == 0 {
                     return Err(errstr("no arguments given"));
@@ -517,14 +593,12 @@ impl_from_tree!(
                     return Err(errstr("empty thresholds not allowed in descriptors"));
                 }
 
-                let subs: Result<Vec<Arc<Miniscript<Pk, Ctx>>>, _> = top.args[1..]
-                    .iter()
-                    .map(expression::FromTree::from_tree)
-                    .collect();
+                let subs: Result<Subs<Arc<Miniscript<Pk, Ctx>>>, _> =
+                    top.args[1..].iter().map(|arg| sub(arg, parser)).collect();
 
                 Ok(Terminal::Thresh(k, subs?))
             }
-            ("multi", n) | ("multi_a", n) => {
+            ("multi", n) | ("multi_a", n) | ("sortedmulti_a", n) => {
                 if n == 0 {
                     return Err(errstr("no arguments given"));
                 }
@@ -535,21 +609,31 @@ impl_from_tree!(
 
                 let pks: Result<Vec<Pk>, _> = top.args[1..]
                     .iter()
-                    .map(|sub| expression::terminal(sub, Pk::from_str))
+                    .map(|arg| expression::terminal(arg, |x| parser.pk(x)))
                     .collect();
 
-                if frag_name == "multi" {
-                    pks.map(|pks| Terminal::Multi(k, pks))
-                } else {
-                    // must be multi_a
-                    pks.map(|pks| Terminal::MultiA(k, pks))
+                match frag_name {
+                    "multi" => pks.map(|pks| Terminal::Multi(k, pks)),
+                    "multi_a" => pks.map(|pks| Terminal::MultiA(k, pks)),
+                    // must be sortedmulti_a
+                    _ => pks.map(|pks| Terminal::SortedMultiA(k, pks)),
                 }
             }
-            _ => Err(Error::Unexpected(format!(
-                "{}({} args) while parsing Miniscript",
-                top.name,
-                top.args.len(),
-            ))),
+            _ => {
+                // Only suggest the multisig fragments of the context
+                let is_ecdsa = Ctx::sig_type() == SigType::Ecdsa;
+                let fragments = FRAGMENTS.iter().filter(|frag| {
+                    !frag.name.contains("multi") || (frag.name == "multi") == is_ecdsa
+                });
+                let written = top.name.rsplit(':').next().unwrap_or("");
+                Err(Error::UnknownFragment(expression::UnknownFragment::new(
+                    top.name,
+                    written,
+                    top.args.len(),
+                    format!("{} miniscript", Ctx::name_str()),
+                    fragments,
+                )))
+            }
         }?;
         for ch in frag_wrap.chars().rev() {
             // Check whether the wrapper is valid under the current context
@@ -590,139 +674,213 @@ impl_from_tree!(
         // Check whether the unwrapped miniscript is valid under the current context
         let ms = Miniscript::from_ast(unwrapped)?;
         Ctx::check_global_validity(&ms)?;
-        Ok(ms.node)
+        Ok(ms.into_inner())
     }
-);
+}
 
-/// Helper trait to add a `push_astelem` method to `script::Builder`
-trait PushAstElem<Pk: MiniscriptKey, Ctx: ScriptContext> {
-    fn push_astelem(self, ast: &Miniscript<Pk, Ctx>) -> Self
-    where
-        Pk: ToPublicKey;
+/// Parses a subexpression of a fragment, see [`Terminal::from_tree_with`]
+fn sub<Pk, Ctx, P, E>(
+    top: &expression::Tree,
+    parser: &mut P,
+) -> Result<Arc<Miniscript<Pk, Ctx>>, Error>
+where
+    Pk: MiniscriptKey,
+    Ctx: ScriptContext,
+    P: KeyParser<Pk, E>,
+    E: ToString,
+{
+    Miniscript::from_tree_with(top, parser).map(Arc::new)
 }
 
-impl<Pk: MiniscriptKey, Ctx: ScriptContext> PushAstElem<Pk, Ctx> for script::Builder {
-    fn push_astelem(self, ast: &Miniscript<Pk, Ctx>) -> Self
-    where
-        Pk: ToPublicKey,
-    {
-        ast.node.encode(self)
+/// A pending step of the explicit-stack traversal done by [`Terminal::encode`]
+enum EncodeStep<'a, Pk: MiniscriptKey, Ctx: ScriptContext> {
+    /// A subexpression, which is expanded into further steps
+    Node(&'a Terminal<Pk, Ctx>),
+    /// A single opcode
+    Opcode(opcodes::All),
+    /// A `VERIFY`, merged into the previous opcode where possible
+    Verify,
+    /// A number
+    Int(i64),
+}
+
+// Derived impls would needlessly require `Pk: Copy`
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Clone for EncodeStep<'a, Pk, Ctx> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
+impl<'a, Pk: MiniscriptKey, Ctx: ScriptContext> Copy for EncodeStep<'a, Pk, Ctx> {}
+
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
     /// Encode the element as a fragment of Bitcoin Script. The inverse
     /// function, from Script to an AST element, is implemented in the
     /// `parse` module.
+    ///
+    /// The AST is walked with an explicit stack rather than by recursion,
+    /// so arbitrarily deep scripts cannot overflow the call stack.
     pub fn encode(&self, mut builder: script::Builder) -> script::Builder
     where
         Pk: ToPublicKey,
     {
+        let mut stack = vec![EncodeStep::Node(self)];
+        while let Some(step) = stack.pop() {
+            builder = match step {
+                EncodeStep::Node(node) => node.encode_step(builder, &mut stack),
+                EncodeStep::Opcode(op) => builder.push_opcode(op),
+                EncodeStep::Verify => builder.push_verify(),
+                EncodeStep::Int(n) => builder.push_int(n),
+            };
+        }
+        builder
+    }
+
+    // Encodes a leaf fragment directly. For any other fragment, schedules the
+    // steps encoding it on `stack`, in the order they have to be executed.
+    fn encode_step<'a>(
+        &'a self,
+        mut builder: script::Builder,
+        stack: &mut Vec<EncodeStep<'a, Pk, Ctx>>,
+    ) -> script::Builder
+    where
+        Pk: ToPublicKey,
+    {
+        use self::EncodeStep::{Int, Node, Opcode, Verify};
+
+        let start = stack.len();
         match *self {
-            Terminal::PkK(ref pk) => builder.push_ms_key::<_, Ctx>(pk),
-            Terminal::PkH(ref hash) => builder
-                .push_opcode(opcodes::all::OP_DUP)
-                .push_opcode(opcodes::all::OP_HASH160)
-                .push_slice(&Pk::hash_to_hash160(hash)[..])
-                .push_opcode(opcodes::all::OP_EQUALVERIFY),
-            Terminal::After(t) => builder
-                .push_int(t as i64)
-                .push_opcode(opcodes::all::OP_CLTV),
-            Terminal::Older(t) => builder.push_int(t as i64).push_opcode(opcodes::all::OP_CSV),
-            Terminal::Sha256(ref h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_SHA256)
-                .push_slice(&Pk::to_sha256(&h))
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Hash256(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_HASH256)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Ripemd160(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_RIPEMD160)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::Hash160(h) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_int(32)
-                .push_opcode(opcodes::all::OP_EQUALVERIFY)
-                .push_opcode(opcodes::all::OP_HASH160)
-                .push_slice(&h[..])
-                .push_opcode(opcodes::all::OP_EQUAL),
-            Terminal::True => builder.push_opcode(opcodes::OP_TRUE),
-            Terminal::False => builder.push_opcode(opcodes::OP_FALSE),
-            Terminal::Alt(ref sub) => builder
-                .push_opcode(opcodes::all::OP_TOALTSTACK)
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_FROMALTSTACK),
-            Terminal::Swap(ref sub) => builder.push_opcode(opcodes::all::OP_SWAP).push_astelem(sub),
-            Terminal::Check(ref sub) => builder
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_CHECKSIG),
-            Terminal::DupIf(ref sub) => builder
-                .push_opcode(opcodes::all::OP_DUP)
-                .push_opcode(opcodes::all::OP_IF)
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::Verify(ref sub) => builder.push_astelem(sub).push_verify(),
-            Terminal::NonZero(ref sub) => builder
-                .push_opcode(opcodes::all::OP_SIZE)
-                .push_opcode(opcodes::all::OP_0NOTEQUAL)
-                .push_opcode(opcodes::all::OP_IF)
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::ZeroNotEqual(ref sub) => builder
-                .push_astelem(sub)
-                .push_opcode(opcodes::all::OP_0NOTEQUAL),
-            Terminal::AndV(ref left, ref right) => builder.push_astelem(left).push_astelem(right),
-            Terminal::AndB(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_BOOLAND),
-            Terminal::AndOr(ref a, ref b, ref c) => builder
-                .push_astelem(a)
-                .push_opcode(opcodes::all::OP_NOTIF)
-                .push_astelem(c)
-                .push_opcode(opcodes::all::OP_ELSE)
-                .push_astelem(b)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::OrB(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_BOOLOR),
-            Terminal::OrD(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_opcode(opcodes::all::OP_IFDUP)
-                .push_opcode(opcodes::all::OP_NOTIF)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::OrC(ref left, ref right) => builder
-                .push_astelem(left)
-                .push_opcode(opcodes::all::OP_NOTIF)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_ENDIF),
-            Terminal::OrI(ref left, ref right) => builder
-                .push_opcode(opcodes::all::OP_IF)
-                .push_astelem(left)
-                .push_opcode(opcodes::all::OP_ELSE)
-                .push_astelem(right)
-                .push_opcode(opcodes::all::OP_ENDIF),
+            Terminal::PkK(ref pk) => return builder.push_ms_key::<_, Ctx>(pk),
+            Terminal::PkH(ref hash) => {
+                return builder
+                    .push_opcode(opcodes::all::OP_DUP)
+                    .push_opcode(opcodes::all::OP_HASH160)
+                    .push_slice(&Pk::hash_to_hash160(hash)[..])
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+            }
+            Terminal::After(t) => {
+                return builder
+                    .push_int(t as i64)
+                    .push_opcode(opcodes::all::OP_CLTV)
+            }
+            Terminal::Older(t) => {
+                return builder.push_int(t as i64).push_opcode(opcodes::all::OP_CSV)
+            }
+            Terminal::Sha256(ref h) => {
+                return builder
+                    .push_opcode(opcodes::all::OP_SIZE)
+                    .push_int(32)
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .push_opcode(opcodes::all::OP_SHA256)
+                    .push_slice(&Pk::to_sha256(h))
+                    .push_opcode(opcodes::all::OP_EQUAL)
+            }
+            Terminal::Hash256(h) => {
+                return builder
+                    .push_opcode(opcodes::all::OP_SIZE)
+                    .push_int(32)
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .push_opcode(opcodes::all::OP_HASH256)
+                    .push_slice(&h[..])
+                    .push_opcode(opcodes::all::OP_EQUAL)
+            }
+            Terminal::Ripemd160(h) => {
+                return builder
+                    .push_opcode(opcodes::all::OP_SIZE)
+                    .push_int(32)
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .push_opcode(opcodes::all::OP_RIPEMD160)
+                    .push_slice(&h[..])
+                    .push_opcode(opcodes::all::OP_EQUAL)
+            }
+            Terminal::Hash160(h) => {
+                return builder
+                    .push_opcode(opcodes::all::OP_SIZE)
+                    .push_int(32)
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .push_opcode(opcodes::all::OP_HASH160)
+                    .push_slice(&h[..])
+                    .push_opcode(opcodes::all::OP_EQUAL)
+            }
+            Terminal::True => return builder.push_opcode(opcodes::OP_TRUE),
+            Terminal::False => return builder.push_opcode(opcodes::OP_FALSE),
+            Terminal::Alt(ref sub) => stack.extend_from_slice(&[
+                Opcode(opcodes::all::OP_TOALTSTACK),
+                Node(&sub.node),
+                Opcode(opcodes::all::OP_FROMALTSTACK),
+            ]),
+            Terminal::Swap(ref sub) => {
+                stack.extend_from_slice(&[Opcode(opcodes::all::OP_SWAP), Node(&sub.node)])
+            }
+            Terminal::Check(ref sub) => {
+                stack.extend_from_slice(&[Node(&sub.node), Opcode(opcodes::all::OP_CHECKSIG)])
+            }
+            Terminal::DupIf(ref sub) => stack.extend_from_slice(&[
+                Opcode(opcodes::all::OP_DUP),
+                Opcode(opcodes::all::OP_IF),
+                Node(&sub.node),
+                Opcode(opcodes::all::OP_ENDIF),
+            ]),
+            Terminal::Verify(ref sub) => stack.extend_from_slice(&[Node(&sub.node), Verify]),
+            Terminal::NonZero(ref sub) => stack.extend_from_slice(&[
+                Opcode(opcodes::all::OP_SIZE),
+                Opcode(opcodes::all::OP_0NOTEQUAL),
+                Opcode(opcodes::all::OP_IF),
+                Node(&sub.node),
+                Opcode(opcodes::all::OP_ENDIF),
+            ]),
+            Terminal::ZeroNotEqual(ref sub) => {
+                stack.extend_from_slice(&[Node(&sub.node), Opcode(opcodes::all::OP_0NOTEQUAL)])
+            }
+            Terminal::AndV(ref left, ref right) => {
+                stack.extend_from_slice(&[Node(&left.node), Node(&right.node)])
+            }
+            Terminal::AndB(ref left, ref right) => stack.extend_from_slice(&[
+                Node(&left.node),
+                Node(&right.node),
+                Opcode(opcodes::all::OP_BOOLAND),
+            ]),
+            Terminal::AndOr(ref a, ref b, ref c) => stack.extend_from_slice(&[
+                Node(&a.node),
+                Opcode(opcodes::all::OP_NOTIF),
+                Node(&c.node),
+                Opcode(opcodes::all::OP_ELSE),
+                Node(&b.node),
+                Opcode(opcodes::all::OP_ENDIF),
+            ]),
+            Terminal::OrB(ref left, ref right) => stack.extend_from_slice(&[
+                Node(&left.node),
+                Node(&right.node),
+                Opcode(opcodes::all::OP_BOOLOR),
+            ]),
+            Terminal::OrD(ref left, ref right) => stack.extend_from_slice(&[
+                Node(&left.node),
+                Opcode(opcodes::all::OP_IFDUP),
+                Opcode(opcodes::all::OP_NOTIF),
+                Node(&right.node),
+                Opcode(opcodes::all::OP_ENDIF),
+            ]),
+            Terminal::OrC(ref left, ref right) => stack.extend_from_slice(&[
+                Node(&left.node),
+                Opcode(opcodes::all::OP_NOTIF),
+                Node(&right.node),
+                Opcode(opcodes::all::OP_ENDIF),
+            ]),
+            Terminal::OrI(ref left, ref right) => stack.extend_from_slice(&[
+                Opcode(opcodes::all::OP_IF),
+                Node(&left.node),
+                Opcode(opcodes::all::OP_ELSE),
+                Node(&right.node),
+                Opcode(opcodes::all::OP_ENDIF),
+            ]),
             Terminal::Thresh(k, ref subs) => {
-                builder = builder.push_astelem(&subs[0]);
+                stack.push(Node(&subs[0].node));
                 for sub in &subs[1..] {
-                    builder = builder.push_astelem(sub).push_opcode(opcodes::all::OP_ADD);
+                    stack.push(Node(&sub.node));
+                    stack.push(Opcode(opcodes::all::OP_ADD));
                 }
-                builder
-                    .push_int(k as i64)
-                    .push_opcode(opcodes::all::OP_EQUAL)
+                stack.push(Int(k as i64));
+                stack.push(Opcode(opcodes::all::OP_EQUAL));
             }
             Terminal::Multi(k, ref keys) => {
                 debug_assert!(Ctx::sig_type() == SigType::Ecdsa);
@@ -730,9 +888,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                 for pk in keys {
                     builder = builder.push_key(&pk.to_public_key());
                 }
-                builder
+                return builder
                     .push_int(keys.len() as i64)
-                    .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+                    .push_opcode(opcodes::all::OP_CHECKMULTISIG);
             }
             Terminal::MultiA(k, ref keys) => {
                 debug_assert!(Ctx::sig_type() == SigType::Schnorr);
@@ -743,11 +901,17 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                     builder = builder.push_ms_key::<_, Ctx>(pk);
                     builder = builder.push_opcode(opcodes::all::OP_CHECKSIGADD);
                 }
-                builder
+                return builder
                     .push_int(k as i64)
-                    .push_opcode(opcodes::all::OP_NUMEQUAL)
+                    .push_opcode(opcodes::all::OP_NUMEQUAL);
+            }
+            Terminal::SortedMultiA(k, ref keys) => {
+                return Terminal::<Pk, Ctx>::MultiA(k, sort_x_only_keys(keys)).encode(builder)
             }
         }
+        // The steps were pushed in execution order, but are popped from the end
+        stack[start..].reverse();
+        builder
     }
 
     /// Size, in bytes of the script-pubkey. If this Miniscript is used outside
@@ -758,6 +922,17 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
     /// to instead call the corresponding function on a `Descriptor`, which
     /// will handle the segwit/non-segwit technicalities for you.
     pub fn script_size(&self) -> usize {
+        let mut size = 0;
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            size += node.script_size_step(&mut stack);
+        }
+        size
+    }
+
+    // Size of the fragment excluding its subexpressions, which are pushed
+    // onto `stack` to be accounted for separately
+    fn script_size_step<'a>(&'a self, stack: &mut Vec<&'a Self>) -> usize {
         match *self {
             Terminal::PkK(ref pk) => Ctx::pk_len(pk),
             Terminal::PkH(..) => 24,
@@ -769,29 +944,57 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
             Terminal::Hash160(..) => 21 + 6,
             Terminal::True => 1,
             Terminal::False => 1,
-            Terminal::Alt(ref sub) => sub.node.script_size() + 2,
-            Terminal::Swap(ref sub) => sub.node.script_size() + 1,
-            Terminal::Check(ref sub) => sub.node.script_size() + 1,
-            Terminal::DupIf(ref sub) => sub.node.script_size() + 3,
+            Terminal::Alt(ref sub) => {
+                stack.push(&sub.node);
+                2
+            }
+            Terminal::Swap(ref sub)
+            | Terminal::Check(ref sub)
+            | Terminal::ZeroNotEqual(ref sub) => {
+                stack.push(&sub.node);
+                1
+            }
+            Terminal::DupIf(ref sub) => {
+                stack.push(&sub.node);
+                3
+            }
             Terminal::Verify(ref sub) => {
-                sub.node.script_size() + if sub.ext.has_free_verify { 0 } else { 1 }
+                stack.push(&sub.node);
+                if sub.ext.has_free_verify {
+                    0
+                } else {
+                    1
+                }
+            }
+            Terminal::NonZero(ref sub) => {
+                stack.push(&sub.node);
+                4
+            }
+            Terminal::AndV(ref l, ref r) => {
+                stack.extend_from_slice(&[&l.node, &r.node]);
+                0
+            }
+            Terminal::AndB(ref l, ref r) | Terminal::OrB(ref l, ref r) => {
+                stack.extend_from_slice(&[&l.node, &r.node]);
+                1
             }
-            Terminal::NonZero(ref sub) => sub.node.script_size() + 4,
-            Terminal::ZeroNotEqual(ref sub) => sub.node.script_size() + 1,
-            Terminal::AndV(ref l, ref r) => l.node.script_size() + r.node.script_size(),
-            Terminal::AndB(ref l, ref r) => l.node.script_size() + r.node.script_size() + 1,
             Terminal::AndOr(ref a, ref b, ref c) => {
-                a.node.script_size() + b.node.script_size() + c.node.script_size() + 3
+                stack.extend_from_slice(&[&a.node, &b.node, &c.node]);
+                3
+            }
+            Terminal::OrD(ref l, ref r) | Terminal::OrI(ref l, ref r) => {
+                stack.extend_from_slice(&[&l.node, &r.node]);
+                3
+            }
+            Terminal::OrC(ref l, ref r) => {
+                stack.extend_from_slice(&[&l.node, &r.node]);
+                2
             }
-            Terminal::OrB(ref l, ref r) => l.node.script_size() + r.node.script_size() + 1,
-            Terminal::OrD(ref l, ref r) => l.node.script_size() + r.node.script_size() + 3,
-            Terminal::OrC(ref l, ref r) => l.node.script_size() + r.node.script_size() + 2,
-            Terminal::OrI(ref l, ref r) => l.node.script_size() + r.node.script_size() + 3,
             Terminal::Thresh(k, ref subs) => {
                 assert!(!subs.is_empty(), "threshold must be nonempty");
+                stack.extend(subs.iter().map(|s| &s.node));
                 script_num_size(k) // k
                     + 1 // EQUAL
-                    + subs.iter().map(|s| s.node.script_size()).sum::<usize>()
                     + subs.len() // ADD
                     - 1 // no ADD on first element
             }
@@ -801,7 +1004,7 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Terminal<Pk, Ctx> {
                     + script_num_size(pks.len())
                     + pks.iter().map(|pk| Ctx::pk_len(pk)).sum::<usize>()
             }
-            Terminal::MultiA(k, ref pks) => {
+            Terminal::MultiA(k, ref pks) | Terminal::SortedMultiA(k, ref pks) => {
                 script_num_size(k)
                     + 1 // NUMEQUAL
                     + pks.iter().map(|pk| Ctx::pk_len(pk)).sum::<usize>() // n keys
@@ -27,7 +27,7 @@ use crate::miniscript::limits::{
 };
 use crate::miniscript::types;
 use crate::prelude::*;
-use crate::util::witness_to_scriptsig;
+use crate::util::{varint_len, witness_to_scriptsig};
 use crate::{Error, Miniscript, MiniscriptKey, Terminal};
 
 /// Error for Script Context
@@ -80,7 +80,7 @@ pub enum ScriptContextError {
 
 #[cfg(feature = "std")]
 impl error::Error for ScriptContextError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::ScriptContextError::*;
 
         match self {
@@ -219,6 +219,17 @@ where
 
     /// Depending on script context, the size of a satifaction witness may slightly differ.
     fn max_satisfaction_size<Pk: MiniscriptKey>(ms: &Miniscript<Pk, Self>) -> Option<usize>;
+
+    /// The weight of the given satisfaction once serialized into the spending
+    /// transaction. Segwit contexts count each witness element with its length
+    /// prefix, pre-segwit contexts count the scriptSig bytes at four weight
+    /// units each. Neither includes the element count or scriptSig length.
+    fn satisfaction_weight(witness: &[Vec<u8>]) -> usize {
+        witness
+            .iter()
+            .map(|elem| varint_len(elem.len()) + elem.len())
+            .sum()
+    }
     /// Depending on script Context, some of the Terminals might not
     /// be valid under the current consensus rules.
     /// Or some of the script resource limits may have been exceeded.
@@ -398,7 +409,7 @@ impl ScriptContext for Legacy {
                     }
                 }
             }
-            Terminal::MultiA(..) => {
+            Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
                 return Err(ScriptContextError::MultiANotAllowed);
             }
             _ => {}
@@ -446,6 +457,10 @@ impl ScriptContext for Legacy {
         }
     }
 
+    fn satisfaction_weight(witness: &[Vec<u8>]) -> usize {
+        4 * witness_to_scriptsig(witness).len()
+    }
+
     fn name_str() -> &'static str {
         "Legacy/p2sh"
     }
@@ -512,7 +527,9 @@ impl ScriptContext for Segwitv0 {
                 }
                 Ok(())
             }
-            Terminal::MultiA(..) => Err(ScriptContextError::MultiANotAllowed),
+            Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                Err(ScriptContextError::MultiANotAllowed)
+            }
             _ => Ok(()),
         }
     }
@@ -727,7 +744,9 @@ impl ScriptContext for BareCtx {
                 }
                 Ok(())
             }
-            Terminal::MultiA(..) => Err(ScriptContextError::MultiANotAllowed),
+            Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                Err(ScriptContextError::MultiANotAllowed)
+            }
             _ => Ok(()),
         }
     }
@@ -769,6 +788,10 @@ impl ScriptContext for BareCtx {
         }
     }
 
+    fn satisfaction_weight(witness: &[Vec<u8>]) -> usize {
+        4 * witness_to_scriptsig(witness).len()
+    }
+
     fn name_str() -> &'static str {
         "BareCtx"
     }
@@ -822,8 +845,15 @@ impl ScriptContext for NoChecks {
         panic!("Tried to compute a satisfaction size bound on a no-checks ecdsa miniscript")
     }
 
-    fn pk_len<Pk: MiniscriptKey>(_pk: &Pk) -> usize {
-        panic!("Tried to compute a pk len bound on a no-checks ecdsa miniscript")
+    fn pk_len<Pk: MiniscriptKey>(pk: &Pk) -> usize {
+        // The interpreter's keys know their encoding
+        if pk.is_uncompressed() {
+            66
+        } else if pk.is_x_only_key() {
+            33
+        } else {
+            34
+        }
     }
 
     fn name_str() -> &'static str {
@@ -28,6 +28,7 @@ use sync::Arc;
 
 use crate::miniscript::lex::{Token as Tk, TokenIter};
 use crate::miniscript::limits::MAX_PUBKEYS_PER_MULTISIG;
+use crate::miniscript::subs::Subs;
 use crate::miniscript::types::extra_props::ExtData;
 use crate::miniscript::types::{Property, Type};
 use crate::miniscript::ScriptContext;
@@ -77,7 +78,7 @@ impl fmt::Display for KeyParseError {
 
 #[cfg(feature = "std")]
 impl error::Error for KeyParseError {
-    fn cause(&self) -> Option<&(dyn error::Error + 'static)> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             KeyParseError::FullKeyParseError(e) => Some(e),
             KeyParseError::XonlyKeyParseError(e) => Some(e),
@@ -186,11 +187,13 @@ pub enum Terminal<Pk: MiniscriptKey, Ctx: ScriptContext> {
     OrI(Arc<Miniscript<Pk, Ctx>>, Arc<Miniscript<Pk, Ctx>>),
     // Thresholds
     /// [E] ([W] ADD)* k EQUAL
-    Thresh(usize, Vec<Arc<Miniscript<Pk, Ctx>>>),
+    Thresh(usize, Subs<Arc<Miniscript<Pk, Ctx>>>),
     /// k (<key>)* n CHECKMULTISIG
     Multi(usize, Vec<Pk>),
     /// <key> CHECKSIG (<key> CHECKSIGADD)*(n-1) k NUMEQUAL
     MultiA(usize, Vec<Pk>),
+    /// Same as `MultiA`, with the keys sorted by their x-only serialization when encoded
+    SortedMultiA(usize, Vec<Pk>),
 }
 
 macro_rules! match_token {
@@ -578,7 +581,7 @@ pub fn parse<Ctx: ScriptContext>(
                 );
             }
             Some(NonTerm::ThreshE { n, k }) => {
-                let mut subs = Vec::with_capacity(n);
+                let mut subs = Subs::with_capacity(n);
                 for _ in 0..n {
                     subs.push(Arc::new(term.pop().unwrap()));
                 }
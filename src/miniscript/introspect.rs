@@ -0,0 +1,431 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Witness Introspection
+//!
+//! The inverse of satisfaction: given a Miniscript and a witness stack,
+//! work out which fragment consumes each witness element and what role the
+//! element plays there. Intended for debugging and forensic analysis; no
+//! signature is verified.
+//!
+
+use core::fmt;
+
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+
+use super::context::SigType;
+use crate::prelude::*;
+use crate::util::sort_x_only_keys;
+use crate::{Miniscript, ScriptContext, Terminal, ToPublicKey};
+
+/// The role a witness element plays in the satisfaction of a fragment
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum WitnessLabel {
+    /// A signature for the given key (or key hash, for `pk_h`)
+    Signature(String),
+    /// A signature for one of the keys of a `multi` fragment. Attributing it
+    /// to a particular key requires verifying it against the transaction.
+    MultiSignature,
+    /// The public key hashing to the given `pk_h` hash
+    PublicKey(String),
+    /// The preimage of the given hash
+    Preimage(String),
+    /// An element dissatisfying a key or hash fragment
+    Dissatisfaction,
+    /// A push selecting a branch of an `OP_IF`: `true` for the first one
+    BranchSelector(bool),
+    /// The extra element consumed by `OP_CHECKMULTISIG`
+    MultisigDummy,
+}
+
+impl fmt::Display for WitnessLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WitnessLabel::Signature(ref key) => write!(f, "signature for {}", key),
+            WitnessLabel::MultiSignature => f.write_str("multisig signature"),
+            WitnessLabel::PublicKey(ref hash) => write!(f, "public key for {}", hash),
+            WitnessLabel::Preimage(ref hash) => write!(f, "preimage of {}", hash),
+            WitnessLabel::Dissatisfaction => f.write_str("dissatisfaction"),
+            WitnessLabel::BranchSelector(true) => f.write_str("branch selector (first)"),
+            WitnessLabel::BranchSelector(false) => f.write_str("branch selector (second)"),
+            WitnessLabel::MultisigDummy => f.write_str("multisig dummy"),
+        }
+    }
+}
+
+/// A witness element together with the fragment that consumes it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LabelledElement {
+    /// Path of child indices (as in [`Miniscript::get_nth_child`]) leading
+    /// from the root to the consuming fragment
+    pub path: Vec<usize>,
+    /// The role of the element within that fragment
+    pub label: WitnessLabel,
+}
+
+/// A partial parse: the number of witness elements not yet consumed and the
+/// labels of the consumed ones, topmost first.
+type Parse = (usize, Vec<LabelledElement>);
+
+/// Label the elements of `witness`, bottom of the stack first.
+///
+/// Returns `None` if the witness neither satisfies nor dissatisfies `ms`.
+/// If several parses exist the first one found is returned, preferring
+/// satisfactions over dissatisfactions.
+pub(super) fn label_witness<Pk, Ctx>(
+    ms: &Miniscript<Pk, Ctx>,
+    witness: &[Vec<u8>],
+) -> Option<Vec<LabelledElement>>
+where
+    Pk: ToPublicKey,
+    Ctx: ScriptContext,
+{
+    let parses = parse(ms, witness, witness.len(), true, &[])
+        .into_iter()
+        .chain(parse(ms, witness, witness.len(), false, &[]));
+    for (end, mut labels) in parses {
+        if end == 0 {
+            labels.reverse();
+            return Some(labels);
+        }
+    }
+    None
+}
+
+/// Consume the topmost element, if `label` accepts it
+fn take<F>(witness: &[Vec<u8>], end: usize, path: &[usize], label: F) -> Vec<Parse>
+where
+    F: FnOnce(&[u8]) -> Option<WitnessLabel>,
+{
+    if end == 0 {
+        return vec![];
+    }
+    match label(&witness[end - 1]) {
+        Some(label) => vec![(
+            end - 1,
+            vec![LabelledElement {
+                path: path.to_vec(),
+                label,
+            }],
+        )],
+        None => vec![],
+    }
+}
+
+/// Continue every parse in `prev` with the parses produced by `next`
+fn then<F>(prev: Vec<Parse>, next: F) -> Vec<Parse>
+where
+    F: Fn(usize) -> Vec<Parse>,
+{
+    let mut ret = vec![];
+    for (end, labels) in prev {
+        for (next_end, next_labels) in next(end) {
+            let mut labels = labels.clone();
+            labels.extend(next_labels);
+            ret.push((next_end, labels));
+        }
+    }
+    ret
+}
+
+fn is_sig<Ctx: ScriptContext>(elem: &[u8]) -> bool {
+    match Ctx::sig_type() {
+        SigType::Ecdsa => bitcoin::EcdsaSig::from_slice(elem).is_ok(),
+        SigType::Schnorr => bitcoin::SchnorrSig::from_slice(elem).is_ok(),
+    }
+}
+
+/// Label a signature, or the empty dissatisfaction, for `key`
+fn sig_label<Ctx: ScriptContext>(elem: &[u8], sat: bool, key: String) -> Option<WitnessLabel> {
+    if sat && is_sig::<Ctx>(elem) {
+        Some(WitnessLabel::Signature(key))
+    } else if !sat && elem.is_empty() {
+        Some(WitnessLabel::Dissatisfaction)
+    } else {
+        None
+    }
+}
+
+/// Label a 32-byte push which is a preimage iff `matches` says so
+fn preimage_label<F>(elem: &[u8], sat: bool, hash: String, matches: F) -> Option<WitnessLabel>
+where
+    F: FnOnce(&[u8]) -> bool,
+{
+    if elem.len() != 32 {
+        None
+    } else if matches(elem) == sat {
+        Some(if sat {
+            WitnessLabel::Preimage(hash)
+        } else {
+            WitnessLabel::Dissatisfaction
+        })
+    } else {
+        None
+    }
+}
+
+/// Label a push of `1` (first branch) or of the empty vector (second branch)
+fn selector(elem: &[u8], first: bool) -> Option<WitnessLabel> {
+    let expected: &[u8] = if first { &[1] } else { &[] };
+    if elem == expected {
+        Some(WitnessLabel::BranchSelector(first))
+    } else {
+        None
+    }
+}
+
+/// All the ways the topmost elements of `witness[..end]` can (dis)satisfy `ms`
+fn parse<Pk, Ctx>(
+    ms: &Miniscript<Pk, Ctx>,
+    witness: &[Vec<u8>],
+    end: usize,
+    sat: bool,
+    path: &[usize],
+) -> Vec<Parse>
+where
+    Pk: ToPublicKey,
+    Ctx: ScriptContext,
+{
+    let sub = |i: usize, end: usize, sat: bool| -> Vec<Parse> {
+        let mut child_path = path.to_vec();
+        child_path.push(i);
+        match ms.get_nth_child(i) {
+            Some(child) => parse(child, witness, end, sat, &child_path),
+            None => vec![],
+        }
+    };
+    let nothing = |possible: bool| {
+        if possible {
+            vec![(end, vec![])]
+        } else {
+            vec![]
+        }
+    };
+
+    match ms.node {
+        Terminal::True => nothing(sat),
+        Terminal::False => nothing(!sat),
+        Terminal::After(_) | Terminal::Older(_) => nothing(sat),
+        Terminal::PkK(ref pk) => take(witness, end, path, |elem| {
+            sig_label::<Ctx>(elem, sat, pk.to_string())
+        }),
+        Terminal::PkH(ref pkh) => {
+            let key = take(witness, end, path, |elem| {
+                if hash160::Hash::hash(elem) == Pk::hash_to_hash160(pkh) {
+                    Some(WitnessLabel::PublicKey(pkh.to_string()))
+                } else {
+                    None
+                }
+            });
+            then(key, |end| {
+                take(witness, end, path, |elem| {
+                    sig_label::<Ctx>(elem, sat, pkh.to_string())
+                })
+            })
+        }
+        Terminal::Sha256(ref h) => take(witness, end, path, |elem| {
+            preimage_label(elem, sat, h.to_string(), |pre| {
+                sha256::Hash::hash(pre) == Pk::to_sha256(h)
+            })
+        }),
+        Terminal::Hash256(h) => take(witness, end, path, |elem| {
+            preimage_label(elem, sat, h.to_string(), |pre| {
+                sha256d::Hash::hash(pre) == h
+            })
+        }),
+        Terminal::Ripemd160(h) => take(witness, end, path, |elem| {
+            preimage_label(elem, sat, h.to_string(), |pre| {
+                ripemd160::Hash::hash(pre) == h
+            })
+        }),
+        Terminal::Hash160(h) => take(witness, end, path, |elem| {
+            preimage_label(elem, sat, h.to_string(), |pre| {
+                hash160::Hash::hash(pre) == h
+            })
+        }),
+        Terminal::Alt(..) | Terminal::Swap(..) | Terminal::Check(..) => sub(0, end, sat),
+        Terminal::ZeroNotEqual(..) => sub(0, end, sat),
+        Terminal::Verify(..) => {
+            if sat {
+                sub(0, end, true)
+            } else {
+                vec![]
+            }
+        }
+        Terminal::NonZero(..) => {
+            if sat {
+                sub(0, end, true)
+            } else {
+                take(witness, end, path, |elem| {
+                    if elem.is_empty() {
+                        Some(WitnessLabel::Dissatisfaction)
+                    } else {
+                        None
+                    }
+                })
+            }
+        }
+        Terminal::DupIf(..) => {
+            let sel = take(witness, end, path, |elem| selector(elem, sat));
+            if sat {
+                then(sel, |end| sub(0, end, true))
+            } else {
+                sel
+            }
+        }
+        Terminal::AndV(..) => then(sub(0, end, true), |end| sub(1, end, sat)),
+        Terminal::AndB(..) => {
+            let combos: &[(bool, bool)] = if sat {
+                &[(true, true)]
+            } else {
+                &[(false, false), (true, false), (false, true)]
+            };
+            combos
+                .iter()
+                .flat_map(|&(l, r)| then(sub(0, end, l), |end| sub(1, end, r)))
+                .collect()
+        }
+        Terminal::AndOr(..) => {
+            let mut ret = then(sub(0, end, true), |end| sub(1, end, sat));
+            ret.extend(then(sub(0, end, false), |end| sub(2, end, sat)));
+            ret
+        }
+        Terminal::OrB(..) => {
+            let combos: &[(bool, bool)] = if sat {
+                &[(true, false), (false, true), (true, true)]
+            } else {
+                &[(false, false)]
+            };
+            combos
+                .iter()
+                .flat_map(|&(l, r)| then(sub(0, end, l), |end| sub(1, end, r)))
+                .collect()
+        }
+        Terminal::OrD(..) => {
+            let mut ret = if sat { sub(0, end, true) } else { vec![] };
+            ret.extend(then(sub(0, end, false), |end| sub(1, end, sat)));
+            ret
+        }
+        Terminal::OrC(..) => {
+            if sat {
+                let mut ret = sub(0, end, true);
+                ret.extend(then(sub(0, end, false), |end| sub(1, end, true)));
+                ret
+            } else {
+                vec![]
+            }
+        }
+        Terminal::OrI(..) => {
+            let mut ret = vec![];
+            for (i, first) in [(0, true), (1, false)].iter().cloned() {
+                let sel = take(witness, end, path, |elem| selector(elem, first));
+                ret.extend(then(sel, |end| sub(i, end, sat)));
+            }
+            ret
+        }
+        Terminal::Thresh(k, ref subs) => {
+            let mut states = vec![(end, vec![], 0)];
+            for i in 0..subs.len() {
+                let mut next = vec![];
+                for (end, labels, count) in states {
+                    for &sub_sat in &[true, false] {
+                        for (sub_end, sub_labels) in sub(i, end, sub_sat) {
+                            let mut labels: Vec<LabelledElement> = labels.clone();
+                            labels.extend(sub_labels);
+                            next.push((sub_end, labels, count + sub_sat as usize));
+                        }
+                    }
+                }
+                states = next;
+            }
+            states
+                .into_iter()
+                .filter(|&(_, _, count)| (count == k) == sat)
+                .map(|(end, labels, _)| (end, labels))
+                .collect()
+        }
+        Terminal::Multi(k, ref keys) => {
+            let mut ret = vec![(end, vec![])];
+            for i in 0..k {
+                // Signatures are in key order, so the topmost one belongs to
+                // the last signing key
+                let key = if k == keys.len() {
+                    Some(keys[k - 1 - i].to_string())
+                } else {
+                    None
+                };
+                ret = then(ret, |end| {
+                    take(witness, end, path, |elem| match key {
+                        Some(ref key) => sig_label::<Ctx>(elem, sat, key.clone()),
+                        None if sat && is_sig::<Ctx>(elem) => Some(WitnessLabel::MultiSignature),
+                        None if !sat && elem.is_empty() => Some(WitnessLabel::Dissatisfaction),
+                        None => None,
+                    })
+                });
+            }
+            then(ret, |end| {
+                take(witness, end, path, |elem| {
+                    if elem.is_empty() {
+                        Some(WitnessLabel::MultisigDummy)
+                    } else {
+                        None
+                    }
+                })
+            })
+        }
+        Terminal::MultiA(k, ref keys) => parse_multi_a::<_, Ctx>(k, keys, witness, end, sat, path),
+        Terminal::SortedMultiA(k, ref keys) => {
+            parse_multi_a::<_, Ctx>(k, &sort_x_only_keys(keys), witness, end, sat, path)
+        }
+    }
+}
+
+/// All the ways the topmost elements of `witness[..end]` can (dis)satisfy a
+/// `multi_a` of the given keys
+fn parse_multi_a<Pk, Ctx>(
+    k: usize,
+    keys: &[Pk],
+    witness: &[Vec<u8>],
+    end: usize,
+    sat: bool,
+    path: &[usize],
+) -> Vec<Parse>
+where
+    Pk: ToPublicKey,
+    Ctx: ScriptContext,
+{
+    // The first key's signature is on top; every key consumes
+    // exactly one element
+    let mut ret = vec![(end, vec![], 0)];
+    for key in keys {
+        let mut next = vec![];
+        for (end, labels, count) in ret {
+            for &key_sat in &[true, false] {
+                let taken = take(witness, end, path, |elem| {
+                    sig_label::<Ctx>(elem, key_sat, key.to_string())
+                });
+                for (end, key_labels) in taken {
+                    let mut labels: Vec<LabelledElement> = labels.clone();
+                    labels.extend(key_labels);
+                    next.push((end, labels, count + key_sat as usize));
+                }
+            }
+        }
+        ret = next;
+    }
+    ret.into_iter()
+        .filter(|&(_, _, count)| (count == k) == sat)
+        .map(|(end, labels, _)| (end, labels))
+        .collect()
+}
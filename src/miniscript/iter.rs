@@ -124,7 +124,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     pub fn get_leapk(&self) -> Vec<Pk> {
         match self.node {
             Terminal::PkK(ref key) => vec![key.clone()],
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => keys.clone(),
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys.clone(),
             _ => vec![],
         }
     }
@@ -142,9 +144,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match self.node {
             Terminal::PkH(ref hash) => vec![hash.clone()],
             Terminal::PkK(ref key) => vec![key.to_pubkeyhash()],
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => {
-                keys.iter().map(Pk::to_pubkeyhash).collect()
-            }
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys.iter().map(Pk::to_pubkeyhash).collect(),
             _ => vec![],
         }
     }
@@ -160,7 +162,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match self.node {
             Terminal::PkH(ref hash) => vec![PkPkh::HashedPubkey(hash.clone())],
             Terminal::PkK(ref key) => vec![PkPkh::PlainPubkey(key.clone())],
-            Terminal::Multi(_, ref keys) | Terminal::MultiA(_, ref keys) => keys
+            Terminal::Multi(_, ref keys)
+            | Terminal::MultiA(_, ref keys)
+            | Terminal::SortedMultiA(_, ref keys) => keys
                 .iter()
                 .map(|key| PkPkh::PlainPubkey(key.clone()))
                 .collect(),
@@ -175,9 +179,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     pub fn get_nth_pk(&self, n: usize) -> Option<Pk> {
         match (&self.node, n) {
             (&Terminal::PkK(ref key), 0) => Some(key.clone()),
-            (&Terminal::Multi(_, ref keys), _) | (&Terminal::MultiA(_, ref keys), _) => {
-                keys.get(n).cloned()
-            }
+            (&Terminal::Multi(_, ref keys), _)
+            | (&Terminal::MultiA(_, ref keys), _)
+            | (&Terminal::SortedMultiA(_, ref keys), _) => keys.get(n).cloned(),
             _ => None,
         }
     }
@@ -193,9 +197,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match (&self.node, n) {
             (&Terminal::PkH(ref hash), 0) => Some(hash.clone()),
             (&Terminal::PkK(ref key), 0) => Some(key.to_pubkeyhash()),
-            (&Terminal::Multi(_, ref keys), _) | (&Terminal::MultiA(_, ref keys), _) => {
-                keys.get(n).map(Pk::to_pubkeyhash)
-            }
+            (&Terminal::Multi(_, ref keys), _)
+            | (&Terminal::MultiA(_, ref keys), _)
+            | (&Terminal::SortedMultiA(_, ref keys), _) => keys.get(n).map(Pk::to_pubkeyhash),
             _ => None,
         }
     }
@@ -208,7 +212,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         match (&self.node, n) {
             (&Terminal::PkH(ref hash), 0) => Some(PkPkh::HashedPubkey(hash.clone())),
             (&Terminal::PkK(ref key), 0) => Some(PkPkh::PlainPubkey(key.clone())),
-            (&Terminal::Multi(_, ref keys), _) | (&Terminal::MultiA(_, ref keys), _) => {
+            (&Terminal::Multi(_, ref keys), _)
+            | (&Terminal::MultiA(_, ref keys), _)
+            | (&Terminal::SortedMultiA(_, ref keys), _) => {
                 keys.get(n).map(|key| PkPkh::PlainPubkey(key.clone()))
             }
             _ => None,
@@ -458,6 +464,7 @@ pub mod test {
 
     use super::{Miniscript, PkPkh};
     use crate::miniscript::context::Segwitv0;
+    use crate::prelude::*;
 
     pub type TestData = (
         Miniscript<bitcoin::PublicKey, Segwitv0>,
@@ -34,6 +34,10 @@ pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
 // https://github.com/bitcoin/bips/blob/master/bip-0112.mediawiki
 pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
 
+/// Mask of the relative lock-time value in a sequence number
+// https://github.com/bitcoin/bips/blob/master/bip-0068.mediawiki
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+
 /// Maximum script element size allowed by consensus rules
 // https://github.com/bitcoin/bitcoin/blob/42b66a6b814bca130a9ccf0a3f747cf33d628232/src/script/script.h#L23
 pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
@@ -46,6 +50,9 @@ pub const MAX_SCRIPTSIG_SIZE: usize = 1650;
 pub const MAX_STACK_SIZE: usize = 1000;
 /** The maximum allowed weight for a block, see BIP 141 (network rule) */
 pub const MAX_BLOCK_WEIGHT: usize = 4000000;
+/// Maximum weight of a transaction allowed by standardness rules
+// https://github.com/bitcoin/bitcoin/blob/42b66a6b814bca130a9ccf0a3f747cf33d628232/src/policy/policy.h#L24
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
 
 /// Maximum pubkeys as arguments to CHECKMULTISIG
 // https://github.com/bitcoin/bitcoin/blob/6acda4b00b3fc1bfac02f5de590e1a5386cbc779/src/script/script.h#L30
@@ -25,7 +25,7 @@
 //!
 
 use core::marker::PhantomData;
-use core::{fmt, hash, str};
+use core::{fmt, hash, mem, str};
 
 use bitcoin::blockdata::script;
 use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
@@ -37,10 +37,12 @@ pub mod analyzable;
 pub mod astelem;
 pub(crate) mod context;
 pub mod decode;
+pub mod introspect;
 pub mod iter;
 pub mod lex;
 pub mod limits;
 pub mod satisfy;
+pub mod subs;
 pub mod types;
 
 use core::cmp;
@@ -54,7 +56,8 @@ use crate::miniscript::decode::Terminal;
 use crate::miniscript::types::extra_props::ExtData;
 use crate::miniscript::types::Type;
 use crate::{
-    expression, Error, ForEach, ForEachKey, MiniscriptKey, ToPublicKey, TranslatePk, Translator,
+    expression, Error, ForEach, ForEachKey, FromStrParser, KeyParser, MiniscriptKey, ToPublicKey,
+    TranslatePk, Translator,
 };
 #[cfg(test)]
 mod ms_tests;
@@ -72,6 +75,74 @@ pub struct Miniscript<Pk: MiniscriptKey, Ctx: ScriptContext> {
     pub(crate) phantom: PhantomData<Ctx>,
 }
 
+/// Drops deep trees without recursion, so that dropping adversarially deep
+/// scripts cannot overflow the stack. Children that are uniquely owned have
+/// their own subexpressions detached and moved onto an explicit stack before
+/// being dropped as leaves.
+///
+/// Since 8.0.0, this means the fields of a `Miniscript` can no longer be
+/// moved out of it; [`Miniscript::into_inner`] takes its root fragment.
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Drop for Miniscript<Pk, Ctx> {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        take_children(&mut self.node, &mut stack);
+        while let Some(child) = stack.pop() {
+            if let Ok(mut child) = Arc::try_unwrap(child) {
+                take_children(&mut child.node, &mut stack);
+            }
+        }
+    }
+}
+
+// Replaces a fragment having subexpressions with a leaf, moving the
+// subexpressions onto `stack`
+fn take_children<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    node: &mut Terminal<Pk, Ctx>,
+    stack: &mut Vec<Arc<Miniscript<Pk, Ctx>>>,
+) {
+    match *node {
+        Terminal::PkK(..)
+        | Terminal::PkH(..)
+        | Terminal::After(..)
+        | Terminal::Older(..)
+        | Terminal::Sha256(..)
+        | Terminal::Hash256(..)
+        | Terminal::Ripemd160(..)
+        | Terminal::Hash160(..)
+        | Terminal::True
+        | Terminal::False
+        | Terminal::Multi(..)
+        | Terminal::MultiA(..)
+        | Terminal::SortedMultiA(..) => return,
+        _ => {}
+    }
+    match mem::replace(node, Terminal::True) {
+        Terminal::Alt(sub)
+        | Terminal::Swap(sub)
+        | Terminal::Check(sub)
+        | Terminal::DupIf(sub)
+        | Terminal::Verify(sub)
+        | Terminal::NonZero(sub)
+        | Terminal::ZeroNotEqual(sub) => stack.push(sub),
+        Terminal::AndV(l, r)
+        | Terminal::AndB(l, r)
+        | Terminal::OrB(l, r)
+        | Terminal::OrD(l, r)
+        | Terminal::OrC(l, r)
+        | Terminal::OrI(l, r) => {
+            stack.push(l);
+            stack.push(r);
+        }
+        Terminal::AndOr(a, b, c) => {
+            stack.push(a);
+            stack.push(b);
+            stack.push(c);
+        }
+        Terminal::Thresh(_, subs) => stack.extend(subs),
+        _ => unreachable!("leaves are returned early"),
+    }
+}
+
 /// `PartialOrd` of `Miniscript` must depend only on node and not the type information.
 /// The type information and extra_properties can be deterministically determined
 /// by the ast.
@@ -138,14 +209,51 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> fmt::Display for Miniscript<Pk, Ctx>
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     /// Extracts the `AstElem` representing the root of the miniscript
-    pub fn into_inner(self) -> Terminal<Pk, Ctx> {
-        self.node
+    pub fn into_inner(mut self) -> Terminal<Pk, Ctx> {
+        mem::replace(&mut self.node, Terminal::True)
     }
 
     /// Get a reference to the inner `AstElem` representing the root of miniscript
     pub fn as_inner(&self) -> &Terminal<Pk, Ctx> {
         &self.node
     }
+
+    /// Sorts the keys of every `sortedmulti_a` fragment, which does not
+    /// change the script
+    pub(crate) fn sort_multi_a_keys(&mut self) {
+        // Like `Drop`, walk the fragments without recursing
+        let mut stack = vec![self];
+        while let Some(ms) = stack.pop() {
+            match ms.node {
+                Terminal::SortedMultiA(_, ref mut keys) => keys.sort(),
+                Terminal::Alt(ref mut sub)
+                | Terminal::Swap(ref mut sub)
+                | Terminal::Check(ref mut sub)
+                | Terminal::DupIf(ref mut sub)
+                | Terminal::Verify(ref mut sub)
+                | Terminal::NonZero(ref mut sub)
+                | Terminal::ZeroNotEqual(ref mut sub) => stack.push(Arc::make_mut(sub)),
+                Terminal::AndV(ref mut left, ref mut right)
+                | Terminal::AndB(ref mut left, ref mut right)
+                | Terminal::OrB(ref mut left, ref mut right)
+                | Terminal::OrD(ref mut left, ref mut right)
+                | Terminal::OrC(ref mut left, ref mut right)
+                | Terminal::OrI(ref mut left, ref mut right) => {
+                    stack.push(Arc::make_mut(left));
+                    stack.push(Arc::make_mut(right));
+                }
+                Terminal::AndOr(ref mut a, ref mut b, ref mut c) => {
+                    stack.push(Arc::make_mut(a));
+                    stack.push(Arc::make_mut(b));
+                    stack.push(Arc::make_mut(c));
+                }
+                Terminal::Thresh(_, ref mut subs) => {
+                    stack.extend(subs.iter_mut().map(Arc::make_mut));
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
 impl<Ctx: ScriptContext> Miniscript<Ctx::Key, Ctx> {
@@ -268,6 +376,18 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     pub fn max_satisfaction_size(&self) -> Result<usize, Error> {
         Ctx::max_satisfaction_size(self).ok_or(Error::ImpossibleSatisfaction)
     }
+
+    /// Maximum size, in bytes, of a satisfying witness, like
+    /// [`Miniscript::max_satisfaction_size`] but assuming signatures of the
+    /// given sizes.
+    pub fn max_satisfaction_size_with_sig_sizes(
+        &self,
+        sig_sizes: satisfy::SigSizeAssumptions,
+    ) -> Result<usize, Error> {
+        let mut ms = self.clone();
+        ms.ext = ExtData::with_sig_sizes(self, sig_sizes);
+        Ctx::max_satisfaction_size(&ms).ok_or(Error::ImpossibleSatisfaction)
+    }
 }
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> ForEachKey<Pk> for Miniscript<Pk, Ctx> {
@@ -340,10 +460,59 @@ impl_block_str!(
     /// insane scripts. In general, in a multi-party setting users should only
     /// accept sane scripts.
     pub fn from_str_insane(s: &str,) -> Result<Miniscript<Pk, Ctx>, Error>
+    {
+        Miniscript::from_str_insane_with(s, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
+    /// Parse a Miniscript from string and perform sanity checks, with the
+    /// keys and hashes parsed by `parser` rather than by their [`FromStr`]
+    /// implementations.
+    ///
+    /// [`FromStr`]: core::str::FromStr
+    pub fn from_str_with<P, E>(s: &str, parser: &mut P) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        Miniscript::parse_with(s, parser).map_err(Error::unlocated)
+    }
+
+    /// Same as [`Miniscript::from_str_insane`], with the keys and hashes
+    /// parsed by `parser`
+    pub fn from_str_insane_with<P, E>(s: &str, parser: &mut P) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        Miniscript::parse_insane_with(s, parser).map_err(Error::unlocated)
+    }
+
+    /// Same as [`Miniscript::from_str_with`], with the errors located in `s`
+    pub(crate) fn parse_with<P, E>(s: &str, parser: &mut P) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        let ms = Miniscript::parse_insane_with(s, parser)?;
+        ms.sanity_check()?;
+        Ok(ms)
+    }
+
+    /// Same as [`Miniscript::from_str_insane_with`], with the errors located
+    /// in `s`
+    pub(crate) fn parse_insane_with<P, E>(
+        s: &str,
+        parser: &mut P,
+    ) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
     {
         // This checks for invalid ASCII chars
-        let top = expression::Tree::from_str(s)?;
-        let ms: Miniscript<Pk, Ctx> = expression::FromTree::from_tree(&top)?;
+        let top = expression::Tree::parse(s)?;
+        let ms = Miniscript::from_tree_with(&top, parser)?;
 
         if ms.ty.corr.base != types::Base::B {
             Err(Error::NonTopLevel(format!("{:?}", ms)))
@@ -351,11 +520,35 @@ impl_block_str!(
             Ok(ms)
         }
     }
-);
+
+    /// Parse an expression tree into a Miniscript, with the keys and hashes
+    /// parsed by `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Miniscript<Pk, Ctx>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        let inner = Terminal::from_tree_with(top, parser).map_err(|e| e.at(top.pos))?;
+        Ok(Miniscript {
+            ty: Type::type_check(&inner, |_| None).map_err(|e| Error::from(e).at(top.pos))?,
+            ext: ExtData::type_check(&inner, |_| None).map_err(|e| Error::from(e).at(top.pos))?,
+            node: inner,
+            phantom: PhantomData,
+        })
+    }
+}
 
 impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
     /// Attempt to produce non-malleable satisfying witness for the
     /// witness script represented by the parse tree
+    ///
+    /// On failure, `Error::Unsatisfiable` lists the fragments whose
+    /// requirements the satisfier could not meet. If a malleable satisfaction
+    /// exists, it instead holds a single `MalleableOnly` error pointing at the
+    /// fragment to blame.
     pub fn satisfy<S: satisfy::Satisfier<Pk>>(&self, satisfier: S) -> Result<Vec<Vec<u8>>, Error>
     where
         Pk: ToPublicKey,
@@ -364,17 +557,106 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         let leaf_hash = TapLeafHash::from_script(&self.encode(), LeafVersion::TapScript);
         match satisfy::Satisfaction::satisfy(&self.node, &satisfier, self.ty.mall.safe, &leaf_hash)
             .stack
+        {
+            satisfy::Witness::Stack(stack) => {
+                Ctx::check_witness::<Pk>(&stack).map_err(|error| {
+                    Error::Unsatisfiable(vec![satisfy::SatisfactionError::ResourceLimitExceeded {
+                        path: vec![],
+                        error,
+                    }])
+                })?;
+                Ok(stack)
+            }
+            satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
+                let malleable_path = satisfy::SatisfactionError::malleable_path(
+                    self,
+                    &satisfier,
+                    self.ty.mall.safe,
+                    &leaf_hash,
+                );
+                Err(Error::Unsatisfiable(match malleable_path {
+                    Some(path) => vec![satisfy::SatisfactionError::MalleableOnly { path }],
+                    None => satisfy::SatisfactionError::collect(self, &satisfier, &leaf_hash),
+                }))
+            }
+        }
+    }
+
+    /// Attempt to produce the canonical dissatisfying witness for the
+    /// witness script represented by the parse tree.
+    ///
+    /// Only fragments with a unique dissatisfaction (`Dissat::Unique`) have a
+    /// canonical one; for all others `Error::NoCanonicalDissatisfaction` is
+    /// returned. The satisfier is only consulted for data that is needed to
+    /// dissatisfy, such as the public key behind a `pk_h`.
+    pub fn dissatisfy<S: satisfy::Satisfier<Pk>>(&self, satisfier: S) -> Result<Vec<Vec<u8>>, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        if self.ty.mall.dissat != types::Dissat::Unique {
+            return Err(Error::NoCanonicalDissatisfaction);
+        }
+        let leaf_hash = TapLeafHash::from_script(&self.encode(), LeafVersion::TapScript);
+        match satisfy::Satisfaction::dissatisfy(
+            &self.node,
+            &satisfier,
+            self.ty.mall.safe,
+            &leaf_hash,
+        )
+        .stack
         {
             satisfy::Witness::Stack(stack) => {
                 Ctx::check_witness::<Pk>(&stack)?;
                 Ok(stack)
             }
             satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
-                Err(Error::CouldNotSatisfy)
+                Err(Error::CouldNotDissatisfy)
             }
         }
     }
 
+    /// Estimate the weight of the non-malleable satisfaction that would be
+    /// produced once everything declared in `assumptions` is available.
+    ///
+    /// No keys or signatures are needed: signatures are assumed to have the
+    /// sizes given by `assumptions.sig_sizes` (by default 72 bytes for ECDSA
+    /// and 65 bytes for Schnorr, including the sighash byte), and preimages
+    /// 32 bytes. The result is counted as described in
+    /// [`ScriptContext::satisfaction_weight`], and does not include the
+    /// witness script or any other descriptor-level overhead.
+    ///
+    /// Returns `Error::Unsatisfiable` if the assumptions are insufficient.
+    pub fn estimate_satisfaction_weight(
+        &self,
+        assumptions: &satisfy::Assumptions<Pk>,
+    ) -> Result<usize, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        let stack = self.satisfy(assumptions)?;
+        Ok(Ctx::satisfaction_weight(&stack))
+    }
+
+    /// Label every element of a witness stack (bottom first) with the
+    /// fragment consuming it and its role there, such as the signature for a
+    /// key or an `or_i` branch selector.
+    ///
+    /// This is the inverse of [`Miniscript::satisfy`]. Signatures are only
+    /// checked to be well-formed, not verified, so a `multi` signature can
+    /// only be attributed to its key when all keys sign. Returns
+    /// `Error::Unexpected` if the witness neither satisfies nor dissatisfies
+    /// the script.
+    pub fn label_witness(
+        &self,
+        witness: &[Vec<u8>],
+    ) -> Result<Vec<introspect::LabelledElement>, Error>
+    where
+        Pk: ToPublicKey,
+    {
+        introspect::label_witness(self, witness)
+            .ok_or_else(|| Error::Unexpected("witness does not (dis)satisfy the script".to_owned()))
+    }
+
     /// Attempt to produce a malleable satisfying witness for the
     /// witness script represented by the parse tree
     pub fn satisfy_malleable<S: satisfy::Satisfier<Pk>>(
@@ -394,11 +676,18 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Miniscript<Pk, Ctx> {
         .stack
         {
             satisfy::Witness::Stack(stack) => {
-                Ctx::check_witness::<Pk>(&stack)?;
+                Ctx::check_witness::<Pk>(&stack).map_err(|error| {
+                    Error::Unsatisfiable(vec![satisfy::SatisfactionError::ResourceLimitExceeded {
+                        path: vec![],
+                        error,
+                    }])
+                })?;
                 Ok(stack)
             }
             satisfy::Witness::Unavailable | satisfy::Witness::Impossible => {
-                Err(Error::CouldNotSatisfy)
+                Err(Error::Unsatisfiable(satisfy::SatisfactionError::collect(
+                    self, &satisfier, &leaf_hash,
+                )))
             }
         }
     }
@@ -418,13 +707,7 @@ impl_from_tree!(
     /// Parse an expression tree into a Miniscript. As a general rule, this
     /// should not be called directly; rather go through the descriptor API.
     fn from_tree(top: &expression::Tree) -> Result<Miniscript<Pk, Ctx>, Error> {
-        let inner: Terminal<Pk, Ctx> = expression::FromTree::from_tree(top)?;
-        Ok(Miniscript {
-            ty: Type::type_check(&inner, |_| None)?,
-            ext: ExtData::type_check(&inner, |_| None)?,
-            node: inner,
-            phantom: PhantomData,
-        })
+        Miniscript::from_tree_with(top, &mut FromStrParser)
     }
 );
 
@@ -436,9 +719,7 @@ impl_from_str!(
     /// See [Miniscript::from_str_insane] to parse scripts from string that
     /// do not clear the [Miniscript::sanity_check] checks.
     fn from_str(s: &str) -> Result<Miniscript<Pk, Ctx>, Error> {
-        let ms = Self::from_str_insane(s)?;
-        ms.sanity_check()?;
-        Ok(ms)
+        Miniscript::parse_with(s, &mut FromStrParser)
     }
 );
 
@@ -458,12 +739,13 @@ mod tests {
     use sync::Arc;
 
     use super::{Miniscript, ScriptContext, Segwitv0, Tap};
+    use crate::expression::FromStrLocated;
     use crate::miniscript::types::{self, ExtData, Property, Type};
     use crate::miniscript::Terminal;
     use crate::policy::Liftable;
     use crate::prelude::*;
     use crate::test_utils::{StrKeyTranslator, StrXOnlyKeyTranslator};
-    use crate::{hex_script, DummyKey, DummyKeyHash, Satisfier, ToPublicKey, TranslatePk};
+    use crate::{hex_script, DummyKey, DummyKeyHash, Error, Satisfier, ToPublicKey, TranslatePk};
 
     type Segwitv0Script = Miniscript<bitcoin::PublicKey, Segwitv0>;
     type Tapscript = Miniscript<bitcoin::secp256k1::XOnlyPublicKey, Tap>;
@@ -943,7 +1225,65 @@ mod tests {
         assert!(Segwitv0Script::from_str_insane("🌏")
             .unwrap_err()
             .to_string()
-            .contains("unprintable character"));
+            .contains("non-ASCII character '🌏'"));
+    }
+
+    #[test]
+    fn unknown_fragment() {
+        fn suggestions<Ctx: ScriptContext>(ms: &str) -> Vec<&'static str> {
+            match Miniscript::<String, Ctx>::from_str_insane(ms).unwrap_err() {
+                Error::UnknownFragment(e) => e.suggestions,
+                e => panic!("unexpected error {}", e),
+            }
+        }
+
+        assert_eq!(
+            suggestions::<Segwitv0>("treshold(1,pk(A),pk(B))"),
+            vec!["thresh(k,X,...)"]
+        );
+        // The wrong number of arguments
+        assert_eq!(
+            suggestions::<Segwitv0>("and_v(v:pk(A))"),
+            vec!["and_v(X,Y)"]
+        );
+        assert_eq!(suggestions::<Segwitv0>("v:pk_k(A,B)"), vec!["pk_k(key)"]);
+        // Only the multisig of the context is suggested
+        assert_eq!(
+            suggestions::<Segwitv0>("mult(1,A)"),
+            vec!["multi(k,key,...)"]
+        );
+        assert_eq!(suggestions::<Tap>("mult(1,A)"), vec!["multi_a(k,key,...)"]);
+        assert_eq!(suggestions::<Segwitv0>("xyz"), Vec::<&str>::new());
+
+        let err =
+            Miniscript::<String, Segwitv0>::from_str_located("and_v(v:pk(A),oldr(1))").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown fragment «oldr» with 1 arguments in Segwitv0 miniscript, did you mean \
+             older(n)? Valid fragments are pk(key), pkh(key), pk_k(key), pk_h(key), after(n), \
+             older(n), sha256(h), hash256(h), ripemd160(h), hash160(h), 1, 0, and_v(X,Y), \
+             and_b(X,Y), and_n(X,Y), andor(X,Y,Z), or_b(X,Z), or_d(X,Z), or_c(X,Z), or_i(X,Z), \
+             thresh(k,X,...), multi(k,key,...) at byte 14"
+        );
+    }
+
+    #[test]
+    fn thresh_subs() {
+        // Small thresholds are built the same way as large ones, whether
+        // their subexpressions are stored inline or not
+        for n in 1..8 {
+            let keys: Vec<String> = (0..n).map(|i| format!("K{}", i)).collect();
+            let subs: Vec<String> = keys.iter().map(|k| format!("s:pk({})", k)).collect();
+            let ms = format!("thresh(1,pk(K),{})", subs.join(","));
+            let ms = Miniscript::<String, Segwitv0>::from_str(&ms).unwrap();
+            let subs = match ms.node {
+                Terminal::Thresh(1, ref subs) => subs.clone(),
+                ref node => panic!("unexpected fragment {:?}", node),
+            };
+            assert_eq!(subs.len(), n + 1);
+            let rebuilt = Terminal::Thresh(1, subs.into_vec().into());
+            assert_eq!(Miniscript::from_ast(rebuilt).unwrap(), ms);
+        }
     }
 
     #[test]
@@ -1067,4 +1407,339 @@ mod tests {
         let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::parse_insane(&enc).unwrap();
         assert_eq!(ms_trans.encode(), ms.encode());
     }
+
+    #[test]
+    fn estimate_satisfaction_weight() {
+        use crate::miniscript::satisfy::Assumptions;
+        use crate::Legacy;
+
+        let keys = pubkeys(2);
+        let ms = Segwitv0Script::from_str(&format!(
+            "or_d(pk({}),and_v(v:pk({}),older(10)))",
+            keys[0], keys[1]
+        ))
+        .unwrap();
+
+        // Nothing available
+        assert!(ms
+            .estimate_satisfaction_weight(&Assumptions::new())
+            .is_err());
+        // Timelock not met
+        assert!(ms
+            .estimate_satisfaction_weight(&Assumptions::new().key(keys[1]).sequence(9))
+            .is_err());
+        // Signature of the first key
+        assert_eq!(
+            ms.estimate_satisfaction_weight(&Assumptions::new().key(keys[0]))
+                .unwrap(),
+            73
+        );
+        // Second branch, which needs a dissatisfaction of the first key
+        let weight = ms
+            .estimate_satisfaction_weight(&Assumptions::new().key(keys[1]).sequence(10))
+            .unwrap();
+        assert_eq!(weight, 74);
+        assert_eq!(weight, ms.max_satisfaction_size().unwrap());
+
+        let ms = Miniscript::<bitcoin::PublicKey, Legacy>::from_str(&format!("pk({})", keys[0]))
+            .unwrap();
+        assert_eq!(
+            ms.estimate_satisfaction_weight(&Assumptions::new().key(keys[0]))
+                .unwrap(),
+            4 * 73
+        );
+
+        let ms = Tapscript::from_str(&format!("pk({})", keys[0].to_x_only_pubkey())).unwrap();
+        assert_eq!(
+            ms.estimate_satisfaction_weight(&Assumptions::new().key(keys[0].to_x_only_pubkey()))
+                .unwrap(),
+            66
+        );
+    }
+
+    #[test]
+    fn dissatisfy() {
+        let keys = pubkeys(2);
+        let ms =
+            Segwitv0Script::from_str(&format!("or_b(pk({}),s:pk({}))", keys[0], keys[1])).unwrap();
+        assert_eq!(ms.dissatisfy(()).unwrap(), vec![vec![], vec![]]);
+
+        let ms =
+            Segwitv0Script::from_str(&format!("and_v(v:pk({}),pk({}))", keys[0], keys[1])).unwrap();
+        assert_eq!(
+            ms.dissatisfy(()).unwrap_err().to_string(),
+            "no canonical dissatisfaction exists"
+        );
+    }
+
+    #[test]
+    fn satisfaction_errors() {
+        use crate::miniscript::satisfy::{Assumptions, SatisfactionError};
+        use crate::Error;
+
+        let keys = pubkeys(2);
+        let hash = sha256::Hash::hash(&[0x42; 32]);
+        let ms = Segwitv0Script::from_str_insane(&format!(
+            "and_v(v:pk({}),or_d(sha256({}),older(10)))",
+            keys[0], hash
+        ))
+        .unwrap();
+
+        let assets = Assumptions::new().key(keys[0]).sequence(5);
+        assert_eq!(
+            ms.satisfy(&assets).unwrap_err(),
+            Error::Unsatisfiable(vec![
+                SatisfactionError::MissingPreimage {
+                    path: vec![1, 0],
+                    hash: hash.to_string(),
+                },
+                SatisfactionError::ImmatureTimelock {
+                    path: vec![1, 1],
+                    relative: true,
+                    value: 10,
+                },
+            ])
+        );
+
+        // Unmet requirements of branches which can be satisfied are not
+        // reported
+        let assets = Assumptions::new().key(keys[1]).sequence(10);
+        let err = ms.satisfy(&assets).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Unsatisfiable(vec![SatisfactionError::MissingSignature {
+                path: vec![0, 0, 0],
+                key: keys[0].to_string(),
+            }])
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "could not satisfy: missing signature for {} at fragment [0, 0, 0]",
+                keys[0]
+            )
+        );
+
+        // Without a signature, a third party could switch between the hash
+        // and the timelock branches
+        let ms = Segwitv0Script::from_str_insane(&format!(
+            "and_v(v:older(5),or_d(sha256({}),older(10)))",
+            hash
+        ))
+        .unwrap();
+        let assets = Assumptions::new().sha256(hash).sequence(10);
+        assert_eq!(
+            ms.satisfy(&assets).unwrap_err(),
+            Error::Unsatisfiable(vec![SatisfactionError::MalleableOnly { path: vec![1] }])
+        );
+        assert!(ms.satisfy_malleable(&assets).is_ok());
+    }
+
+    #[test]
+    fn dyn_satisfier() {
+        use crate::miniscript::satisfy::{After, Assumptions, Older};
+        use crate::DynSatisfier;
+
+        let ms = Segwitv0Script::from_str_insane("and_v(v:older(10),after(100))").unwrap();
+        let satisfiers: Vec<DynSatisfier<bitcoin::PublicKey>> = vec![
+            Box::new(Older(10)),
+            Box::new((Older(10), After(100))),
+            Box::new(Assumptions::new().sequence(10).lock_time(100)),
+        ];
+        assert!(ms.satisfy(&satisfiers[0]).is_err());
+        assert_eq!(ms.satisfy(&satisfiers[1]).unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(ms.satisfy(&*satisfiers[2]).unwrap(), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn label_witness() {
+        use crate::miniscript::introspect::{LabelledElement, WitnessLabel};
+        use crate::miniscript::satisfy::Assumptions;
+        use crate::MiniscriptKey;
+
+        let keys = pubkeys(2);
+        let preimage = [0x42; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let pkh = keys[1].to_pubkeyhash().to_string();
+        let ms = Segwitv0Script::from_str(&format!(
+            "or_i(and_v(v:pk({}),sha256({})),c:pk_h({}))",
+            keys[0], hash, pkh
+        ))
+        .unwrap();
+        let label = |path: Vec<usize>, label| LabelledElement { path, label };
+
+        let witness = ms.satisfy(Assumptions::new().key(keys[1])).unwrap();
+        assert_eq!(
+            ms.label_witness(&witness).unwrap(),
+            vec![
+                label(vec![1, 0], WitnessLabel::Signature(pkh.clone())),
+                label(vec![1, 0], WitnessLabel::PublicKey(pkh)),
+                label(vec![], WitnessLabel::BranchSelector(false)),
+            ]
+        );
+
+        let sig = witness[0].clone();
+        let witness = vec![preimage.to_vec(), sig, vec![1]];
+        assert_eq!(
+            ms.label_witness(&witness).unwrap(),
+            vec![
+                label(vec![0, 1], WitnessLabel::Preimage(hash.to_string())),
+                label(
+                    vec![0, 0, 0, 0],
+                    WitnessLabel::Signature(keys[0].to_string())
+                ),
+                label(vec![], WitnessLabel::BranchSelector(true)),
+            ]
+        );
+
+        // A wrong preimage dissatisfies the script
+        let witness = vec![vec![0; 32], witness[1].clone(), vec![1]];
+        assert_eq!(
+            ms.label_witness(&witness).unwrap()[0],
+            label(vec![0, 1], WitnessLabel::Dissatisfaction)
+        );
+        assert!(ms.label_witness(&[vec![2]]).is_err());
+    }
+
+    #[test]
+    fn key_preference() {
+        use crate::miniscript::satisfy::PreferKeys;
+
+        let secp = secp256k1::Secp256k1::new();
+        let keys = pubkeys(3);
+        let msg = secp256k1::Message::from_slice(&[0x11; 32]).unwrap();
+        let mut sigs = HashMap::new();
+        for (i, pk) in keys.iter().enumerate() {
+            let mut sk = [0; 32];
+            sk[0] = i as u8 + 1;
+            let sk = secp256k1::SecretKey::from_slice(&sk).unwrap();
+            let sig = bitcoin::EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &sk));
+            sigs.insert(*pk, sig);
+        }
+        let sig = |i: usize| sigs[&keys[i]].to_vec();
+
+        let ms = Segwitv0Script::from_str(&format!("multi(2,{},{},{})", keys[0], keys[1], keys[2]))
+            .unwrap();
+        let prefer = PreferKeys(vec![keys[2], keys[0]]);
+        assert_eq!(
+            ms.satisfy((&sigs, prefer)).unwrap(),
+            vec![vec![], sig(0), sig(2)]
+        );
+
+        let ms = Segwitv0Script::from_str(&format!(
+            "thresh(2,pk({}),s:pk({}),s:pk({}))",
+            keys[0], keys[1], keys[2]
+        ))
+        .unwrap();
+        let prefer = PreferKeys(vec![keys[2], keys[1]]);
+        assert_eq!(
+            ms.satisfy((&sigs, prefer)).unwrap(),
+            vec![sig(2), sig(1), vec![]]
+        );
+        let prefer = PreferKeys(vec![keys[0], keys[1]]);
+        assert_eq!(
+            ms.satisfy((&sigs, prefer)).unwrap(),
+            vec![vec![], sig(1), sig(0)]
+        );
+    }
+
+    #[test]
+    fn sig_size_assumptions() {
+        use crate::miniscript::satisfy::{Assumptions, SigSizeAssumptions};
+        use crate::MiniscriptKey;
+
+        let keys = pubkeys(4);
+        let ms = Segwitv0Script::from_str(&format!(
+            "or_d(multi(2,{},{},{}),and_v(v:pkh({}),older(10)))",
+            keys[0],
+            keys[1],
+            keys[2],
+            keys[3].to_pubkeyhash()
+        ))
+        .unwrap();
+        assert_eq!(
+            ms.max_satisfaction_size_with_sig_sizes(SigSizeAssumptions::WORST_CASE)
+                .unwrap(),
+            ms.max_satisfaction_size().unwrap()
+        );
+        // The 2-of-3 is the largest satisfaction, with two signatures
+        assert_eq!(
+            ms.max_satisfaction_size_with_sig_sizes(SigSizeAssumptions::COMPACT)
+                .unwrap(),
+            ms.max_satisfaction_size().unwrap() - 2
+        );
+
+        // Dummy signatures have exactly the assumed size
+        let ms = Segwitv0Script::from_str(&format!("pk({})", keys[0])).unwrap();
+        for ecdsa in 9..73 {
+            let sig_sizes = SigSizeAssumptions::new(ecdsa, 64).unwrap();
+            let assets = Assumptions::new().key(keys[0]).sig_sizes(sig_sizes);
+            assert_eq!(ms.satisfy(&assets).unwrap()[0].len(), ecdsa);
+            assert_eq!(
+                ms.estimate_satisfaction_weight(&assets).unwrap(),
+                ms.max_satisfaction_size_with_sig_sizes(sig_sizes).unwrap()
+            );
+        }
+        for &(ecdsa, schnorr) in &[(8, 64), (73, 64), (72, 63), (72, 66)] {
+            assert_eq!(
+                SigSizeAssumptions::new(ecdsa, schnorr),
+                Err(Error::InvalidSigSizes(ecdsa, schnorr))
+            );
+        }
+    }
+
+    #[test]
+    fn preimage_provider() {
+        use crate::miniscript::satisfy::Preimages;
+        use crate::PreimageProvider;
+
+        struct HtlcDb(HashMap<sha256::Hash, [u8; 32]>);
+
+        impl PreimageProvider<bitcoin::PublicKey> for HtlcDb {
+            fn sha256_preimage(&self, h: &sha256::Hash) -> Option<[u8; 32]> {
+                self.0.get(h).cloned()
+            }
+        }
+
+        let preimage = [0x42; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let ms = Segwitv0Script::from_str_insane(&format!("sha256({})", hash)).unwrap();
+
+        let mut db = HtlcDb(HashMap::new());
+        assert!(ms.satisfy(Preimages(&db)).is_err());
+        db.0.insert(hash, preimage);
+        assert_eq!(ms.satisfy(Preimages(&db)).unwrap(), vec![preimage.to_vec()]);
+        // Can be combined with other satisfiers
+        assert_eq!(
+            ms.satisfy(((), Preimages(&db))).unwrap(),
+            vec![preimage.to_vec()]
+        );
+    }
+
+    #[test]
+    fn deep_tree_no_recursion() {
+        // Deep enough that recursively encoding, sizing, sorting or dropping
+        // it would overflow the default test thread stack
+        let depth = 200_000;
+        let mut ms = Segwitv0Script::from_ast(Terminal::True).unwrap();
+        for _ in 0..depth {
+            ms = Segwitv0Script::from_ast(Terminal::ZeroNotEqual(Arc::new(ms))).unwrap();
+        }
+        assert_eq!(ms.script_size(), depth + 1);
+        let script = ms.encode();
+        assert_eq!(script.len(), depth + 1);
+        assert_eq!(script[0], bitcoin::blockdata::opcodes::OP_TRUE.into_u8());
+        assert_eq!(
+            script[depth],
+            bitcoin::blockdata::opcodes::all::OP_0NOTEQUAL.into_u8()
+        );
+        ms.sort_multi_a_keys();
+
+        // Shared subtrees are left intact when another owner remains
+        let shared = Arc::new(ms);
+        let outer = Segwitv0Script::from_ast(Terminal::Verify(Arc::clone(&shared))).unwrap();
+        drop(outer);
+        assert_eq!(shared.script_size(), depth + 1);
+        drop(shared);
+    }
 }
@@ -21,6 +21,7 @@ mod tests {
     use core::fmt;
 
     use crate::miniscript::types;
+    use crate::prelude::*;
     use crate::{Miniscript, Segwitv0};
 
     struct TestType(types::Type);
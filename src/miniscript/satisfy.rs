@@ -18,7 +18,9 @@
 //! scriptpubkeys.
 //!
 
-use core::{cmp, i64, mem};
+use core::{cmp, fmt, i64, mem, u32};
+#[cfg(feature = "std")]
+use std::error;
 
 use bitcoin;
 use bitcoin::hashes::{hash160, ripemd160, sha256d};
@@ -26,12 +28,13 @@ use bitcoin::secp256k1::XOnlyPublicKey;
 use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapLeafHash};
 use sync::Arc;
 
+use crate::miniscript::context::ScriptContextError;
 use crate::miniscript::limits::{
     LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
 };
 use crate::prelude::*;
-use crate::util::witness_size;
-use crate::{Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
+use crate::util::{sort_x_only_keys, witness_size};
+use crate::{Error, Miniscript, MiniscriptKey, ScriptContext, Terminal, ToPublicKey};
 
 /// Type alias for 32 byte Preimage.
 pub type Preimage32 = [u8; 32];
@@ -62,6 +65,12 @@ pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
         None
     }
 
+    /// Lookup the annex to attach to taproot witnesses. The annex must
+    /// start with `0x50`.
+    fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+        None
+    }
+
     /// Given a `Pkh`, lookup corresponding `Pk`
     fn lookup_pkh_pk(&self, _: &Pk::Hash) -> Option<Pk> {
         None
@@ -118,6 +127,384 @@ pub trait Satisfier<Pk: MiniscriptKey + ToPublicKey> {
     fn check_after(&self, _: u32) -> bool {
         false
     }
+
+    /// Rank of a key when more signatures are available for a threshold
+    /// (`thresh`, `multi` or `multi_a`) than it needs. Keys with a lower
+    /// rank are used first, keys without a rank last, and among equally
+    /// ranked choices the smallest witness is picked.
+    fn key_preference(&self, _: &Pk) -> Option<u32> {
+        None
+    }
+}
+
+/// Trait describing a lookup table for hash preimages only.
+///
+/// This is separate from [`Satisfier`] so that hash-lock storage (e.g. an
+/// HTLC database) can be implemented independently of any keystore. Wrap a
+/// provider in [`Preimages`] to use it as a [`Satisfier`], typically in a
+/// tuple together with the signature satisfier.
+pub trait PreimageProvider<Pk: MiniscriptKey> {
+    /// Given a SHA256 hash, look up its preimage
+    fn sha256_preimage(&self, _: &Pk::Sha256) -> Option<Preimage32> {
+        None
+    }
+
+    /// Given a HASH256 hash, look up its preimage
+    fn hash256_preimage(&self, _: sha256d::Hash) -> Option<Preimage32> {
+        None
+    }
+
+    /// Given a RIPEMD160 hash, look up its preimage
+    fn ripemd160_preimage(&self, _: ripemd160::Hash) -> Option<Preimage32> {
+        None
+    }
+
+    /// Given a HASH160 hash, look up its preimage
+    fn hash160_preimage(&self, _: hash160::Hash) -> Option<Preimage32> {
+        None
+    }
+}
+
+impl<Pk: MiniscriptKey, P: PreimageProvider<Pk>> PreimageProvider<Pk> for &P {
+    fn sha256_preimage(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        (**self).sha256_preimage(h)
+    }
+
+    fn hash256_preimage(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        (**self).hash256_preimage(h)
+    }
+
+    fn ripemd160_preimage(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        (**self).ripemd160_preimage(h)
+    }
+
+    fn hash160_preimage(&self, h: hash160::Hash) -> Option<Preimage32> {
+        (**self).hash160_preimage(h)
+    }
+}
+
+/// Newtype around a [`PreimageProvider`] which implements `Satisfier`
+/// by answering the preimage lookups only
+pub struct Preimages<P>(pub P);
+
+impl<Pk: MiniscriptKey + ToPublicKey, P: PreimageProvider<Pk>> Satisfier<Pk> for Preimages<P> {
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        self.0.sha256_preimage(h)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.0.hash256_preimage(h)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.0.ripemd160_preimage(h)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.0.hash160_preimage(h)
+    }
+}
+
+/// Sizes assumed for signatures when estimating the size of satisfactions,
+/// in bytes including the sighash byte but not the push opcode or length
+/// prefix.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SigSizeAssumptions {
+    ecdsa: usize,
+    schnorr: usize,
+}
+
+impl SigSizeAssumptions {
+    /// Assume ECDSA signatures of `ecdsa` bytes, between 9 and 72, and
+    /// Schnorr signatures of `schnorr` bytes: 64 with the default sighash
+    /// type, 65 otherwise
+    pub fn new(ecdsa: usize, schnorr: usize) -> Result<Self, Error> {
+        if (9..=72).contains(&ecdsa) && (schnorr == 64 || schnorr == 65) {
+            Ok(SigSizeAssumptions { ecdsa, schnorr })
+        } else {
+            Err(Error::InvalidSigSizes(ecdsa, schnorr))
+        }
+    }
+
+    /// Size of ECDSA signatures
+    pub fn ecdsa(&self) -> usize {
+        self.ecdsa
+    }
+
+    /// Size of Schnorr signatures
+    pub fn schnorr(&self) -> usize {
+        self.schnorr
+    }
+
+    /// The largest possible signatures: 72-byte ECDSA signatures and 65-byte
+    /// Schnorr signatures with an explicit sighash type. This is what the
+    /// `max_satisfaction_*` family of methods assumes.
+    pub const WORST_CASE: Self = SigSizeAssumptions {
+        ecdsa: 72,
+        schnorr: 65,
+    };
+
+    /// ECDSA signatures with a low R value (71 bytes), as produced by
+    /// signers grinding the nonce, and Schnorr signatures using the default
+    /// sighash type (64 bytes).
+    pub const COMPACT: Self = SigSizeAssumptions {
+        ecdsa: 71,
+        schnorr: 64,
+    };
+}
+
+impl Default for SigSizeAssumptions {
+    fn default() -> Self {
+        SigSizeAssumptions::WORST_CASE
+    }
+}
+
+/// Declaration of the signatures, hash preimages and timelocks that *will*
+/// be available when a Miniscript is eventually satisfied.
+///
+/// Implements [`Satisfier`] by answering every lookup for a declared item
+/// with a dummy value: signatures of the sizes given by `sig_sizes` (by
+/// default 72-byte ECDSA and 65-byte Schnorr signatures, the convention
+/// used by the `max_satisfaction_*` family of methods) and 32-byte
+/// preimages. This lets callers estimate the cost of a satisfaction without
+/// having access to any signer material.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Assumptions<Pk: MiniscriptKey> {
+    /// Keys for which a signature will be available
+    pub keys: BTreeSet<Pk>,
+    /// Sha256 hashes whose preimages will be available
+    pub sha256_preimages: BTreeSet<Pk::Sha256>,
+    /// Hash256 hashes whose preimages will be available
+    pub hash256_preimages: BTreeSet<sha256d::Hash>,
+    /// Ripemd160 hashes whose preimages will be available
+    pub ripemd160_preimages: BTreeSet<ripemd160::Hash>,
+    /// Hash160 hashes whose preimages will be available
+    pub hash160_preimages: BTreeSet<hash160::Hash>,
+    /// The `nSequence` value of the spending input, if relative
+    /// timelocks should be considered satisfiable
+    pub sequence: Option<u32>,
+    /// The `nLockTime` value of the spending transaction, if absolute
+    /// timelocks should be considered satisfiable
+    pub lock_time: Option<u32>,
+    /// The annex that will be attached to taproot witnesses, if any
+    pub annex: Option<Vec<u8>>,
+    /// The sizes of the dummy signatures
+    pub sig_sizes: SigSizeAssumptions,
+}
+
+impl<Pk: MiniscriptKey> Default for Assumptions<Pk> {
+    fn default() -> Self {
+        Assumptions {
+            keys: BTreeSet::new(),
+            sha256_preimages: BTreeSet::new(),
+            hash256_preimages: BTreeSet::new(),
+            ripemd160_preimages: BTreeSet::new(),
+            hash160_preimages: BTreeSet::new(),
+            sequence: None,
+            lock_time: None,
+            annex: None,
+            sig_sizes: SigSizeAssumptions::default(),
+        }
+    }
+}
+
+impl<Pk: MiniscriptKey> Assumptions<Pk> {
+    /// Create an empty set of assumptions, under which nothing is available
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare that a signature for `pk` will be available
+    pub fn key(mut self, pk: Pk) -> Self {
+        self.keys.insert(pk);
+        self
+    }
+
+    /// Declare that the preimage of the sha256 hash `h` will be available
+    pub fn sha256(mut self, h: Pk::Sha256) -> Self {
+        self.sha256_preimages.insert(h);
+        self
+    }
+
+    /// Declare that the preimage of the hash256 hash `h` will be available
+    pub fn hash256(mut self, h: sha256d::Hash) -> Self {
+        self.hash256_preimages.insert(h);
+        self
+    }
+
+    /// Declare that the preimage of the ripemd160 hash `h` will be available
+    pub fn ripemd160(mut self, h: ripemd160::Hash) -> Self {
+        self.ripemd160_preimages.insert(h);
+        self
+    }
+
+    /// Declare that the preimage of the hash160 hash `h` will be available
+    pub fn hash160(mut self, h: hash160::Hash) -> Self {
+        self.hash160_preimages.insert(h);
+        self
+    }
+
+    /// Declare the `nSequence` the spending input will use
+    pub fn sequence(mut self, n: u32) -> Self {
+        self.sequence = Some(n);
+        self
+    }
+
+    /// Declare the `nLockTime` the spending transaction will use
+    pub fn lock_time(mut self, n: u32) -> Self {
+        self.lock_time = Some(n);
+        self
+    }
+
+    /// Declare the annex that will be attached to taproot witnesses
+    pub fn annex(mut self, annex: Vec<u8>) -> Self {
+        self.annex = Some(annex);
+        self
+    }
+
+    /// Assume signatures of the given sizes
+    pub fn sig_sizes(mut self, sig_sizes: SigSizeAssumptions) -> Self {
+        self.sig_sizes = sig_sizes;
+        self
+    }
+}
+
+/// An ECDSA signature of `size` bytes (clamped to the range 9..=72),
+/// including the sighash byte
+fn dummy_ecdsa_sig(size: usize) -> bitcoin::EcdsaSig {
+    // The DER encoding takes 6 bytes besides `r` and `s`
+    let rs_len = size - 7;
+    // `s` must be low, so it takes at most 32 bytes; a 33-byte `r` is a
+    // 32-byte one with the high bit set
+    let s_len = cmp::min(rs_len - 1, 32);
+    let r_len = rs_len - s_len;
+    let mut compact = [0; 64];
+    for byte in compact[cmp::max(32, r_len) - r_len..32].iter_mut() {
+        *byte = 0x7f;
+    }
+    if r_len == 33 {
+        compact[0] = 0x80;
+    }
+    for byte in compact[64 - s_len..].iter_mut() {
+        *byte = 0x7f;
+    }
+    let sig = bitcoin::secp256k1::ecdsa::Signature::from_compact(&compact)
+        .expect("Valid compact signature");
+    bitcoin::EcdsaSig::sighash_all(sig)
+}
+
+/// A Schnorr signature, with an explicit sighash byte unless `size` is 64
+pub(crate) fn dummy_schnorr_sig(size: usize) -> bitcoin::SchnorrSig {
+    let sig = bitcoin::secp256k1::schnorr::Signature::from_slice(&[0x01; 64])
+        .expect("Valid schnorr signature");
+    bitcoin::SchnorrSig {
+        sig,
+        hash_ty: if size == 64 {
+            bitcoin::SchnorrSighashType::Default
+        } else {
+            bitcoin::SchnorrSighashType::All
+        },
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for Assumptions<Pk> {
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        if self.keys.contains(pk) {
+            Some(dummy_ecdsa_sig(self.sig_sizes.ecdsa()))
+        } else {
+            None
+        }
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, _: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
+        if self.keys.contains(pk) {
+            Some(dummy_schnorr_sig(self.sig_sizes.schnorr()))
+        } else {
+            None
+        }
+    }
+
+    fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+        self.annex.clone()
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.keys
+            .iter()
+            .find(|pk| pk.to_pubkeyhash() == *pkh)
+            .cloned()
+    }
+
+    fn lookup_pkh_ecdsa_sig(
+        &self,
+        pkh: &Pk::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        self.keys
+            .iter()
+            .find(|pk| pk.to_pubkeyhash() == *pkh)
+            .map(|pk| (pk.to_public_key(), dummy_ecdsa_sig(self.sig_sizes.ecdsa())))
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        pkh: &(Pk::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        self.keys
+            .iter()
+            .find(|pk| pk.to_pubkeyhash() == pkh.0)
+            .map(|pk| {
+                (
+                    pk.to_x_only_pubkey(),
+                    dummy_schnorr_sig(self.sig_sizes.schnorr()),
+                )
+            })
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        if self.sha256_preimages.contains(h) {
+            Some([0; 32])
+        } else {
+            None
+        }
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        if self.hash256_preimages.contains(&h) {
+            Some([0; 32])
+        } else {
+            None
+        }
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        if self.ripemd160_preimages.contains(&h) {
+            Some([0; 32])
+        } else {
+            None
+        }
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        if self.hash160_preimages.contains(&h) {
+            Some([0; 32])
+        } else {
+            None
+        }
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        match self.sequence {
+            Some(seq) => <Older as Satisfier<Pk>>::check_older(&Older(seq), n),
+            None => false,
+        }
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        match self.lock_time {
+            Some(lock_time) => <After as Satisfier<Pk>>::check_after(&After(lock_time), n),
+            None => false,
+        }
+    }
 }
 
 // Allow use of `()` as a "no conditions available" satisfier
@@ -163,6 +550,18 @@ impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for After {
     }
 }
 
+/// Newtype around a list of keys which implements `Satisfier` by ranking
+/// each key by its position in the list, so that e.g. our own keys are
+/// used before cosigner keys. Combine it with the actual satisfier in a
+/// tuple.
+pub struct PreferKeys<Pk>(pub Vec<Pk>);
+
+impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PreferKeys<Pk> {
+    fn key_preference(&self, pk: &Pk) -> Option<u32> {
+        self.0.iter().position(|k| k == pk).map(|i| i as u32)
+    }
+}
+
 impl<Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for HashMap<Pk, bitcoin::EcdsaSig> {
     fn lookup_ecdsa_sig(&self, key: &Pk) -> Option<bitcoin::EcdsaSig> {
         self.get(key).copied()
@@ -220,129 +619,101 @@ where
     }
 }
 
-impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a S {
-    fn lookup_ecdsa_sig(&self, p: &Pk) -> Option<bitcoin::EcdsaSig> {
-        (**self).lookup_ecdsa_sig(p)
-    }
-
-    fn lookup_tap_leaf_script_sig(&self, p: &Pk, h: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
-        (**self).lookup_tap_leaf_script_sig(p, h)
-    }
-
-    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
-        (**self).lookup_pkh_pk(pkh)
-    }
-
-    fn lookup_pkh_ecdsa_sig(
-        &self,
-        pkh: &Pk::Hash,
-    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
-        (**self).lookup_pkh_ecdsa_sig(pkh)
-    }
-
-    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
-        (**self).lookup_tap_key_spend_sig()
-    }
-
-    fn lookup_pkh_tap_leaf_script_sig(
-        &self,
-        pkh: &(Pk::Hash, TapLeafHash),
-    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
-        (**self).lookup_pkh_tap_leaf_script_sig(pkh)
-    }
-
-    fn lookup_tap_control_block_map(
-        &self,
-    ) -> Option<&BTreeMap<ControlBlock, (bitcoin::Script, LeafVersion)>> {
-        (**self).lookup_tap_control_block_map()
-    }
-
-    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
-        (**self).lookup_sha256(h)
-    }
-
-    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
-        (**self).lookup_hash256(h)
-    }
-
-    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
-        (**self).lookup_ripemd160(h)
-    }
+/// Forwards every `Satisfier` method through a pointer to a (possibly
+/// unsized) satisfier, so that `&dyn Satisfier<Pk>` and
+/// `Box<dyn Satisfier<Pk>>` can be passed wherever a satisfier is expected.
+macro_rules! impl_deref_satisfier {
+    ($ty:ty) => {
+        impl<'a, Pk, S> Satisfier<Pk> for $ty
+        where
+            Pk: MiniscriptKey + ToPublicKey,
+            S: Satisfier<Pk> + ?Sized,
+        {
+            fn lookup_ecdsa_sig(&self, p: &Pk) -> Option<bitcoin::EcdsaSig> {
+                (**self).lookup_ecdsa_sig(p)
+            }
 
-    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
-        (**self).lookup_hash160(h)
-    }
+            fn lookup_tap_leaf_script_sig(
+                &self,
+                p: &Pk,
+                h: &TapLeafHash,
+            ) -> Option<bitcoin::SchnorrSig> {
+                (**self).lookup_tap_leaf_script_sig(p, h)
+            }
 
-    fn check_older(&self, t: u32) -> bool {
-        (**self).check_older(t)
-    }
+            fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+                (**self).lookup_pkh_pk(pkh)
+            }
 
-    fn check_after(&self, t: u32) -> bool {
-        (**self).check_after(t)
-    }
-}
+            fn lookup_pkh_ecdsa_sig(
+                &self,
+                pkh: &Pk::Hash,
+            ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+                (**self).lookup_pkh_ecdsa_sig(pkh)
+            }
 
-impl<'a, Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for &'a mut S {
-    fn lookup_ecdsa_sig(&self, p: &Pk) -> Option<bitcoin::EcdsaSig> {
-        (**self).lookup_ecdsa_sig(p)
-    }
+            fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+                (**self).lookup_tap_key_spend_sig()
+            }
 
-    fn lookup_tap_leaf_script_sig(&self, p: &Pk, h: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
-        (**self).lookup_tap_leaf_script_sig(p, h)
-    }
+            fn lookup_pkh_tap_leaf_script_sig(
+                &self,
+                pkh: &(Pk::Hash, TapLeafHash),
+            ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+                (**self).lookup_pkh_tap_leaf_script_sig(pkh)
+            }
 
-    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
-        (**self).lookup_tap_key_spend_sig()
-    }
+            fn lookup_tap_control_block_map(
+                &self,
+            ) -> Option<&BTreeMap<ControlBlock, (bitcoin::Script, LeafVersion)>> {
+                (**self).lookup_tap_control_block_map()
+            }
 
-    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
-        (**self).lookup_pkh_pk(pkh)
-    }
+            fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+                (**self).lookup_tap_annex()
+            }
 
-    fn lookup_pkh_ecdsa_sig(
-        &self,
-        pkh: &Pk::Hash,
-    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
-        (**self).lookup_pkh_ecdsa_sig(pkh)
-    }
+            fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+                (**self).lookup_sha256(h)
+            }
 
-    fn lookup_pkh_tap_leaf_script_sig(
-        &self,
-        pkh: &(Pk::Hash, TapLeafHash),
-    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
-        (**self).lookup_pkh_tap_leaf_script_sig(pkh)
-    }
+            fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+                (**self).lookup_hash256(h)
+            }
 
-    fn lookup_tap_control_block_map(
-        &self,
-    ) -> Option<&BTreeMap<ControlBlock, (bitcoin::Script, LeafVersion)>> {
-        (**self).lookup_tap_control_block_map()
-    }
+            fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+                (**self).lookup_ripemd160(h)
+            }
 
-    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
-        (**self).lookup_sha256(h)
-    }
+            fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+                (**self).lookup_hash160(h)
+            }
 
-    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
-        (**self).lookup_hash256(h)
-    }
+            fn check_older(&self, t: u32) -> bool {
+                (**self).check_older(t)
+            }
 
-    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
-        (**self).lookup_ripemd160(h)
-    }
+            fn check_after(&self, t: u32) -> bool {
+                (**self).check_after(t)
+            }
 
-    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
-        (**self).lookup_hash160(h)
-    }
+            fn key_preference(&self, pk: &Pk) -> Option<u32> {
+                (**self).key_preference(pk)
+            }
+        }
+    };
+}
 
-    fn check_older(&self, t: u32) -> bool {
-        (**self).check_older(t)
-    }
+impl_deref_satisfier!(&'a S);
+impl_deref_satisfier!(&'a mut S);
+impl_deref_satisfier!(Box<S>);
 
-    fn check_after(&self, t: u32) -> bool {
-        (**self).check_after(t)
-    }
-}
+/// A type-erased satisfier.
+///
+/// `Satisfier` is object safe, so satisfiers of different types (for example
+/// ones provided by plugins or across an FFI boundary) can be boxed and used
+/// interchangeably wherever a satisfier is expected.
+pub type DynSatisfier<'a, Pk> = Box<dyn Satisfier<Pk> + 'a>;
 
 macro_rules! impl_tuple_satisfier {
     ($($ty:ident),*) => {
@@ -433,6 +804,16 @@ macro_rules! impl_tuple_satisfier {
                 None
             }
 
+            fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.lookup_tap_annex() {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
+
             fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
                 let &($(ref $ty,)*) = self;
                 $(
@@ -492,6 +873,16 @@ macro_rules! impl_tuple_satisfier {
                 )*
                 false
             }
+
+            fn key_preference(&self, key: &Pk) -> Option<u32> {
+                let &($(ref $ty,)*) = self;
+                $(
+                    if let Some(result) = $ty.key_preference(key) {
+                        return Some(result);
+                    }
+                )*
+                None
+            }
         }
     }
 }
@@ -505,6 +896,291 @@ impl_tuple_satisfier!(A, B, C, D, E, F);
 impl_tuple_satisfier!(A, B, C, D, E, F, G);
 impl_tuple_satisfier!(A, B, C, D, E, F, G, H);
 
+/// The reason a particular fragment of a Miniscript could not be satisfied.
+///
+/// Every variant carries the path of the offending fragment, given as the
+/// sequence of child indices (as in [`Miniscript::get_nth_child`]) leading
+/// from the root to it. An empty path designates the root itself. For `tr`
+/// descriptors, the path starts with the position of the leaf in
+/// [`Tr::iter_scripts`](crate::descriptor::Tr::iter_scripts) order, and an
+/// empty path designates the key spend.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SatisfactionError {
+    /// No signature is available for the given key
+    MissingSignature {
+        /// Path to the fragment requiring the signature
+        path: Vec<usize>,
+        /// The key (or key hash, for `pk_h`) which needs to sign
+        key: String,
+    },
+    /// No preimage is available for the given hash
+    MissingPreimage {
+        /// Path to the hash fragment
+        path: Vec<usize>,
+        /// The hash whose preimage is needed
+        hash: String,
+    },
+    /// An `after` or `older` timelock is not satisfied by the transaction
+    ImmatureTimelock {
+        /// Path to the timelock fragment
+        path: Vec<usize>,
+        /// Whether the timelock is relative (`older`) or absolute (`after`)
+        relative: bool,
+        /// The timelock value
+        value: u32,
+    },
+    /// A satisfaction exists but violates the limits of the script context
+    ResourceLimitExceeded {
+        /// Path to the fragment exceeding the limits
+        path: Vec<usize>,
+        /// The violated limit
+        error: ScriptContextError,
+    },
+    /// The fragment can never be satisfied, either because it is `0` or
+    /// because its satisfiable branches cannot be combined
+    ImpossibleBranch {
+        /// Path to the unsatisfiable fragment
+        path: Vec<usize>,
+    },
+    /// The fragment can only be satisfied malleably, so that a third party
+    /// could change the witness and thus the wtxid
+    MalleableOnly {
+        /// Path to the deepest fragment without a non-malleable satisfaction
+        path: Vec<usize>,
+    },
+}
+
+impl SatisfactionError {
+    /// The path of child indices leading to the offending fragment
+    pub fn path(&self) -> &[usize] {
+        match *self {
+            SatisfactionError::MissingSignature { ref path, .. }
+            | SatisfactionError::MissingPreimage { ref path, .. }
+            | SatisfactionError::ImmatureTimelock { ref path, .. }
+            | SatisfactionError::ResourceLimitExceeded { ref path, .. }
+            | SatisfactionError::ImpossibleBranch { ref path }
+            | SatisfactionError::MalleableOnly { ref path } => path,
+        }
+    }
+
+    /// The same error, for the fragment at the same path below the taproot
+    /// leaf at position `leaf`
+    pub(crate) fn in_leaf(mut self, leaf: usize) -> Self {
+        match self {
+            SatisfactionError::MissingSignature { ref mut path, .. }
+            | SatisfactionError::MissingPreimage { ref mut path, .. }
+            | SatisfactionError::ImmatureTimelock { ref mut path, .. }
+            | SatisfactionError::ResourceLimitExceeded { ref mut path, .. }
+            | SatisfactionError::ImpossibleBranch { ref mut path }
+            | SatisfactionError::MalleableOnly { ref mut path } => path.insert(0, leaf),
+        }
+        self
+    }
+
+    /// If `ms` can only be satisfied malleably, the path to the deepest
+    /// fragment which has a malleable satisfaction but no non-malleable one
+    pub(super) fn malleable_path<Pk, Ctx, Sat>(
+        ms: &Miniscript<Pk, Ctx>,
+        stfr: &Sat,
+        root_has_sig: bool,
+        leaf_hash: &TapLeafHash,
+    ) -> Option<Vec<usize>>
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+    {
+        let mall = Satisfaction::satisfy_mall(&ms.node, stfr, root_has_sig, leaf_hash);
+        let non_mall = Satisfaction::satisfy(&ms.node, stfr, root_has_sig, leaf_hash);
+        match (mall.stack, non_mall.stack) {
+            (Witness::Stack(_), Witness::Stack(_)) => return None,
+            (Witness::Stack(_), _) => {}
+            _ => return None,
+        }
+        for (i, child) in ms.branches().into_iter().enumerate() {
+            if let Some(mut path) =
+                SatisfactionError::malleable_path(child, stfr, root_has_sig, leaf_hash)
+            {
+                path.insert(0, i);
+                return Some(path);
+            }
+        }
+        Some(vec![])
+    }
+
+    /// Collect the reasons why `ms` cannot be satisfied by `stfr`.
+    ///
+    /// Only unsatisfiable fragments are descended into: the unmet leaf
+    /// requirements of branches which could be satisfied, and so do not
+    /// prevent the satisfaction, are not reported. A fragment whose children
+    /// are all satisfiable but which is not is reported as an impossible
+    /// branch.
+    pub(super) fn collect<Pk, Ctx, Sat>(
+        ms: &Miniscript<Pk, Ctx>,
+        stfr: &Sat,
+        leaf_hash: &TapLeafHash,
+    ) -> Vec<SatisfactionError>
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+    {
+        let mut errors = vec![];
+        SatisfactionError::collect_helper(ms, stfr, leaf_hash, &mut vec![], &mut errors);
+        if errors.is_empty() {
+            errors.push(SatisfactionError::ImpossibleBranch { path: vec![] });
+        }
+        errors
+    }
+
+    fn collect_helper<Pk, Ctx, Sat>(
+        ms: &Miniscript<Pk, Ctx>,
+        stfr: &Sat,
+        leaf_hash: &TapLeafHash,
+        path: &mut Vec<usize>,
+        errors: &mut Vec<SatisfactionError>,
+    ) where
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+    {
+        let missing_sig = |pk: &Pk| match Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash) {
+            Witness::Stack(_) => None,
+            _ => Some(pk.to_string()),
+        };
+        let mut missing_hash = |preimage: Option<Preimage32>, hash: String| {
+            if preimage.is_none() {
+                errors.push(SatisfactionError::MissingPreimage {
+                    path: path.clone(),
+                    hash,
+                });
+            }
+        };
+        match ms.node {
+            Terminal::False => {
+                errors.push(SatisfactionError::ImpossibleBranch { path: path.clone() })
+            }
+            Terminal::PkK(ref pk) => {
+                if let Some(key) = missing_sig(pk) {
+                    errors.push(SatisfactionError::MissingSignature {
+                        path: path.clone(),
+                        key,
+                    });
+                }
+            }
+            Terminal::PkH(ref pkh) => {
+                if let Witness::Stack(_) = Witness::pkh_signature(stfr, pkh) {
+                } else {
+                    errors.push(SatisfactionError::MissingSignature {
+                        path: path.clone(),
+                        key: pkh.to_string(),
+                    });
+                }
+            }
+            Terminal::Multi(k, ref keys)
+            | Terminal::MultiA(k, ref keys)
+            | Terminal::SortedMultiA(k, ref keys) => {
+                let missing: Vec<String> = keys.iter().filter_map(missing_sig).collect();
+                if keys.len() - missing.len() < k {
+                    errors.extend(missing.into_iter().map(|key| {
+                        SatisfactionError::MissingSignature {
+                            path: path.clone(),
+                            key,
+                        }
+                    }));
+                }
+            }
+            Terminal::After(t) => {
+                if !stfr.check_after(t) {
+                    errors.push(SatisfactionError::ImmatureTimelock {
+                        path: path.clone(),
+                        relative: false,
+                        value: t,
+                    });
+                }
+            }
+            Terminal::Older(t) => {
+                if !stfr.check_older(t) {
+                    errors.push(SatisfactionError::ImmatureTimelock {
+                        path: path.clone(),
+                        relative: true,
+                        value: t,
+                    });
+                }
+            }
+            Terminal::Sha256(ref h) => missing_hash(stfr.lookup_sha256(h), h.to_string()),
+            Terminal::Hash256(h) => missing_hash(stfr.lookup_hash256(h), h.to_string()),
+            Terminal::Ripemd160(h) => missing_hash(stfr.lookup_ripemd160(h), h.to_string()),
+            Terminal::Hash160(h) => missing_hash(stfr.lookup_hash160(h), h.to_string()),
+            _ => {
+                let n_errors = errors.len();
+                for (i, child) in ms.branches().into_iter().enumerate() {
+                    let sat = Satisfaction::satisfy_mall(
+                        &child.node,
+                        stfr,
+                        child.ty.mall.safe,
+                        leaf_hash,
+                    );
+                    if let Witness::Stack(_) = sat.stack {
+                        continue;
+                    }
+                    path.push(i);
+                    SatisfactionError::collect_helper(child, stfr, leaf_hash, path, errors);
+                    path.pop();
+                }
+                if errors.len() == n_errors {
+                    errors.push(SatisfactionError::ImpossibleBranch { path: path.clone() });
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for SatisfactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SatisfactionError::MissingSignature { ref key, .. } => {
+                write!(f, "missing signature for {}", key)?
+            }
+            SatisfactionError::MissingPreimage { ref hash, .. } => {
+                write!(f, "missing preimage of {}", hash)?
+            }
+            SatisfactionError::ImmatureTimelock {
+                relative: true,
+                value,
+                ..
+            } => write!(f, "relative timelock older({}) not met", value)?,
+            SatisfactionError::ImmatureTimelock {
+                relative: false,
+                value,
+                ..
+            } => write!(f, "absolute timelock after({}) not met", value)?,
+            SatisfactionError::ResourceLimitExceeded { ref error, .. } => {
+                write!(f, "resource limit exceeded: {}", error)?
+            }
+            SatisfactionError::ImpossibleBranch { .. } => f.write_str("impossible branch")?,
+            SatisfactionError::MalleableOnly { .. } => {
+                f.write_str("only a malleable satisfaction exists")?
+            }
+        }
+        write!(f, " at fragment {:?}", self.path())
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for SatisfactionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            SatisfactionError::ResourceLimitExceeded { ref error, .. } => Some(error),
+            SatisfactionError::MissingSignature { .. }
+            | SatisfactionError::MissingPreimage { .. }
+            | SatisfactionError::ImmatureTimelock { .. }
+            | SatisfactionError::ImpossibleBranch { .. }
+            | SatisfactionError::MalleableOnly { .. } => None,
+        }
+    }
+}
+
 /// A witness, if available, for a Miniscript fragment
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Witness {
@@ -725,8 +1401,15 @@ impl Satisfaction {
             // First consider the candidates that are not impossible to satisfy
             // by any party. Among those first consider the ones that have no sig
             // because third party can malleate them if they are not chosen.
-            // Lastly, choose by weight.
-            (is_impossible, sats[i].has_sig, stack_weight)
+            // Then consider the ones we must (or cannot) satisfy, and lastly
+            // choose by key preference and weight.
+            (
+                is_impossible,
+                sats[i].has_sig,
+                Self::thresh_tier(stack_weight),
+                Self::key_preference(&subs[i], stfr),
+                stack_weight,
+            )
         });
 
         for i in 0..k {
@@ -831,14 +1514,20 @@ impl Satisfaction {
         let mut sat_indices = (0..subs.len()).collect::<Vec<_>>();
         sat_indices.sort_by_key(|&i| {
             // For malleable satifactions, directly choose smallest weights
-            match (&sats[i].stack, &ret_stack[i].stack) {
+            // among the preferred keys
+            let stack_weight = match (&sats[i].stack, &ret_stack[i].stack) {
                 (&Witness::Unavailable, _) | (&Witness::Impossible, _) => i64::MAX,
                 // This is only possible when one of the branches has PkH
                 (_, &Witness::Unavailable) | (_, &Witness::Impossible) => i64::MIN,
                 (&Witness::Stack(ref s), &Witness::Stack(ref d)) => {
                     witness_size(s) as i64 - witness_size(d) as i64
                 }
-            }
+            };
+            (
+                Self::thresh_tier(stack_weight),
+                Self::key_preference(&subs[i], stfr),
+                stack_weight,
+            )
         });
 
         // swap the satisfactions
@@ -856,6 +1545,30 @@ impl Satisfaction {
         }
     }
 
+    /// Split the thresh sort weights into the subs which must be satisfied
+    /// (no dissatisfaction), the optional ones and the unsatisfiable ones, so
+    /// that key preferences only reorder the optional subs.
+    fn thresh_tier(stack_weight: i64) -> u8 {
+        match stack_weight {
+            i64::MIN => 0,
+            i64::MAX => 2,
+            _ => 1,
+        }
+    }
+
+    /// The best preference the satisfier gives to any key of `ms`
+    fn key_preference<Pk, Ctx, Sat>(ms: &Miniscript<Pk, Ctx>, stfr: &Sat) -> u32
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+    {
+        ms.iter_pk()
+            .filter_map(|pk| stfr.key_preference(&pk))
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
     fn minimum(sat1: Self, sat2: Self) -> Self {
         // If there is only one available satisfaction, we must choose that
         // regardless of has_sig marker.
@@ -1142,7 +1855,8 @@ impl Satisfaction {
                 for pk in keys {
                     match Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash) {
                         Witness::Stack(sig) => {
-                            sigs.push(sig);
+                            let pref = stfr.key_preference(pk).unwrap_or(u32::MAX);
+                            sigs.push((pref, sig));
                             sig_count += 1;
                         }
                         Witness::Impossible => {}
@@ -1158,19 +1872,19 @@ impl Satisfaction {
                         has_sig: false,
                     }
                 } else {
-                    // Throw away the most expensive ones
+                    // Throw away the least preferred, then the most expensive ones
                     for _ in 0..sig_count - k {
                         let max_idx = sigs
                             .iter()
                             .enumerate()
-                            .max_by_key(|&(_, v)| v.len())
+                            .max_by_key(|&(_, &(pref, ref v))| (pref, v.len()))
                             .unwrap()
                             .0;
-                        sigs[max_idx] = vec![];
+                        sigs[max_idx] = (0, vec![]);
                     }
 
                     Satisfaction {
-                        stack: sigs.into_iter().fold(Witness::push_0(), |acc, sig| {
+                        stack: sigs.into_iter().fold(Witness::push_0(), |acc, (_, sig)| {
                             Witness::combine(acc, Witness::Stack(sig))
                         }),
                         has_sig: true,
@@ -1181,7 +1895,10 @@ impl Satisfaction {
                 // Collect all available signatures
                 let mut sig_count = 0;
                 let mut sigs = vec![vec![vec![]]; keys.len()];
-                for (i, pk) in keys.iter().rev().enumerate() {
+                // Try the preferred keys first
+                let mut order = keys.iter().rev().enumerate().collect::<Vec<_>>();
+                order.sort_by_key(|&(_, pk)| stfr.key_preference(pk).unwrap_or(u32::MAX));
+                for (i, pk) in order {
                     match Witness::signature::<_, _, Ctx>(stfr, pk, leaf_hash) {
                         Witness::Stack(sig) => {
                             sigs[i] = sig;
@@ -1215,6 +1932,10 @@ impl Satisfaction {
                     }
                 }
             }
+            Terminal::SortedMultiA(k, ref keys) => {
+                let multi_a = Terminal::MultiA(k, sort_x_only_keys(keys));
+                Self::satisfy_helper(&multi_a, stfr, root_has_sig, leaf_hash, min_fn, thresh_fn)
+            }
         }
     }
 
@@ -1381,7 +2102,7 @@ impl Satisfaction {
                 stack: Witness::Stack(vec![vec![]; k + 1]),
                 has_sig: false,
             },
-            Terminal::MultiA(_, ref pks) => Satisfaction {
+            Terminal::MultiA(_, ref pks) | Terminal::SortedMultiA(_, ref pks) => Satisfaction {
                 stack: Witness::Stack(vec![vec![]; pks.len()]),
                 has_sig: false,
             },
@@ -1409,6 +2130,27 @@ impl Satisfaction {
         )
     }
 
+    /// Produce a non-malleable dissatisfaction
+    pub(super) fn dissatisfy<
+        Pk: MiniscriptKey + ToPublicKey,
+        Ctx: ScriptContext,
+        Sat: Satisfier<Pk>,
+    >(
+        term: &Terminal<Pk, Ctx>,
+        stfr: &Sat,
+        root_has_sig: bool,
+        leaf_hash: &TapLeafHash,
+    ) -> Self {
+        Self::dissatisfy_helper(
+            term,
+            stfr,
+            root_has_sig,
+            leaf_hash,
+            &mut Satisfaction::minimum,
+            &mut Satisfaction::thresh,
+        )
+    }
+
     /// Produce a satisfaction(possibly malleable)
     pub(super) fn satisfy_mall<
         Pk: MiniscriptKey + ToPublicKey,
@@ -0,0 +1,161 @@
+//! Thresh Subexpressions
+//!
+//! The subexpressions of a `thresh` fragment are stored in a [`Subs`]. Most
+//! thresholds have only a few of them, so with the `smallvec` feature up to
+//! four are stored inline rather than in a separate heap allocation. This
+//! does not grow `Terminal` for real keys, whose `pk_k` fragment is already
+//! larger. The API is the same with or without the feature.
+//!
+//! The keys of `multi` and `multi_a` stay in a `Vec`: stored inline, they
+//! would make every node of a miniscript several times larger.
+
+use core::iter::FromIterator;
+use core::ops::{Deref, DerefMut};
+use core::{fmt, slice};
+
+use crate::prelude::*;
+
+#[cfg(feature = "smallvec")]
+type Inner<T> = smallvec::SmallVec<[T; 4]>;
+#[cfg(not(feature = "smallvec"))]
+type Inner<T> = Vec<T>;
+
+#[cfg(feature = "smallvec")]
+type InnerIntoIter<T> = smallvec::IntoIter<[T; 4]>;
+#[cfg(not(feature = "smallvec"))]
+type InnerIntoIter<T> = <Vec<T> as IntoIterator>::IntoIter;
+
+/// The subexpressions of a `thresh` fragment
+///
+/// It dereferences to a slice, and is built from a `Vec` or an iterator.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Subs<T>(Inner<T>);
+
+impl<T> Subs<T> {
+    /// Creates an empty list of subexpressions
+    pub fn new() -> Self {
+        Subs(Inner::new())
+    }
+
+    /// Creates an empty list with room for `n` subexpressions
+    pub fn with_capacity(n: usize) -> Self {
+        Subs(Inner::with_capacity(n))
+    }
+
+    /// Appends a subexpression
+    pub fn push(&mut self, sub: T) {
+        self.0.push(sub)
+    }
+
+    /// Converts the list into a `Vec`
+    #[cfg(feature = "smallvec")]
+    pub fn into_vec(self) -> Vec<T> {
+        self.0.into_vec()
+    }
+
+    /// Converts the list into a `Vec`
+    #[cfg(not(feature = "smallvec"))]
+    pub fn into_vec(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<T> Default for Subs<T> {
+    fn default() -> Self {
+        Subs::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Subs<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self[..], f)
+    }
+}
+
+impl<T> Deref for Subs<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Subs<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+}
+
+impl<T> From<Vec<T>> for Subs<T> {
+    #[cfg(feature = "smallvec")]
+    fn from(v: Vec<T>) -> Self {
+        Subs(Inner::from_vec(v))
+    }
+
+    #[cfg(not(feature = "smallvec"))]
+    fn from(v: Vec<T>) -> Self {
+        Subs(v)
+    }
+}
+
+impl<T> FromIterator<T> for Subs<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Subs(iter.into_iter().collect())
+    }
+}
+
+impl<T> Extend<T> for Subs<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+/// Owning iterator over the subexpressions of a [`Subs`]
+pub struct IntoIter<T>(InnerIntoIter<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<T> {
+        self.0.next_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+impl<T> IntoIterator for Subs<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter(self.0.into_iter())
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Subs<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Subs<T> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> slice::IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
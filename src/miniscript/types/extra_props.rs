@@ -9,8 +9,9 @@ use crate::miniscript::context::SigType;
 use crate::miniscript::limits::{
     LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_TYPE_FLAG,
 };
+use crate::miniscript::satisfy::SigSizeAssumptions;
 use crate::prelude::*;
-use crate::{script_num_size, MiniscriptKey, Terminal};
+use crate::{script_num_size, Miniscript, MiniscriptKey, Terminal};
 
 /// Timelock information for satisfaction of a fragment.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Default, Hash)]
@@ -911,7 +912,9 @@ impl Property for ExtData {
             Terminal::False => Ok(Self::from_false()),
             Terminal::PkK(..) => Ok(Self::from_pk_k::<Ctx>()),
             Terminal::PkH(..) => Ok(Self::from_pk_h::<Ctx>()),
-            Terminal::Multi(k, ref pks) | Terminal::MultiA(k, ref pks) => {
+            Terminal::Multi(k, ref pks)
+            | Terminal::MultiA(k, ref pks)
+            | Terminal::SortedMultiA(k, ref pks) => {
                 if k == 0 {
                     return Err(Error {
                         fragment: fragment.clone(),
@@ -926,7 +929,9 @@ impl Property for ExtData {
                 }
                 match *fragment {
                     Terminal::Multi(..) => Ok(Self::from_multi(k, pks.len())),
-                    Terminal::MultiA(..) => Ok(Self::from_multi_a(k, pks.len())),
+                    Terminal::MultiA(..) | Terminal::SortedMultiA(..) => {
+                        Ok(Self::from_multi_a(k, pks.len()))
+                    }
                     _ => unreachable!(),
                 }
             }
@@ -1027,6 +1032,68 @@ impl Property for ExtData {
     }
 }
 
+impl ExtData {
+    /// Recompute the satisfaction size bounds of `ms` assuming signatures of
+    /// the given sizes, instead of the worst case assumed when type-checking.
+    pub(crate) fn with_sig_sizes<Pk, Ctx>(
+        ms: &Miniscript<Pk, Ctx>,
+        sig_sizes: SigSizeAssumptions,
+    ) -> Self
+    where
+        Pk: MiniscriptKey,
+        Ctx: ScriptContext,
+    {
+        // Signatures including the push opcode or length prefix
+        let sig = match Ctx::sig_type() {
+            SigType::Ecdsa => sig_sizes.ecdsa() + 1,
+            SigType::Schnorr => sig_sizes.schnorr() + 1,
+        };
+        let mut ext = ms.ext;
+        match ms.node {
+            Terminal::PkK(..) => ext.max_sat_size = Some((sig, sig)),
+            Terminal::PkH(..) => {
+                let pk = match Ctx::sig_type() {
+                    SigType::Ecdsa => 34,
+                    SigType::Schnorr => 33,
+                };
+                ext.max_sat_size = Some((pk + sig, pk + sig));
+            }
+            Terminal::Multi(k, _) => ext.max_sat_size = Some((1 + sig * k, 1 + sig * k)),
+            Terminal::MultiA(k, ref pks) | Terminal::SortedMultiA(k, ref pks) => {
+                let size = (pks.len() - k) + sig * k;
+                ext.max_sat_size = Some((size, size));
+            }
+            _ => {
+                let sub = |n: usize| {
+                    let child = ms.get_nth_child(n).expect("child of a combinator");
+                    Self::with_sig_sizes(child, sig_sizes)
+                };
+                let ret = match ms.node {
+                    Terminal::Alt(..) => Self::cast_alt(sub(0)),
+                    Terminal::Swap(..) => Self::cast_swap(sub(0)),
+                    Terminal::Check(..) => Self::cast_check(sub(0)),
+                    Terminal::DupIf(..) => Self::cast_dupif(sub(0)),
+                    Terminal::Verify(..) => Self::cast_verify(sub(0)),
+                    Terminal::NonZero(..) => Self::cast_nonzero(sub(0)),
+                    Terminal::ZeroNotEqual(..) => Self::cast_zeronotequal(sub(0)),
+                    Terminal::AndB(..) => Self::and_b(sub(0), sub(1)),
+                    Terminal::AndV(..) => Self::and_v(sub(0), sub(1)),
+                    Terminal::OrB(..) => Self::or_b(sub(0), sub(1)),
+                    Terminal::OrD(..) => Self::or_d(sub(0), sub(1)),
+                    Terminal::OrC(..) => Self::or_c(sub(0), sub(1)),
+                    Terminal::OrI(..) => Self::or_i(sub(0), sub(1)),
+                    Terminal::AndOr(..) => Self::and_or(sub(0), sub(1), sub(2)),
+                    Terminal::Thresh(k, ref subs) => Self::threshold(k, subs.len(), |n| Ok(sub(n))),
+                    // Other leaves do not depend on signature sizes
+                    _ => Ok(ext),
+                };
+                ext = ret.expect("typechecked before");
+            }
+        }
+        ext
+    }
+}
+
 // Function to pass to sort_by. Sort by (satisfaction cost - dissatisfaction cost).
 //
 // We sort by (satisfaction cost - dissatisfaction cost) to make a worst-case (the most
@@ -0,0 +1,1259 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Spend planning
+//!
+//! Tools to decide how an output will be spent before any signature exists.
+//! A spend plan is described by [`Assumptions`], listing the keys, preimages
+//! and timelocks that will be available at signing time.
+//!
+
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::{fmt, hash};
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::secp256k1::{self, XOnlyPublicKey};
+use bitcoin::util::bip32::KeySource;
+use bitcoin::util::psbt::PsbtSighashType;
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::{EcdsaSighashType, SchnorrSighashType, Script};
+
+use crate::descriptor::DerivedDescriptorKey;
+pub use crate::miniscript::satisfy::{Assumptions, SigSizeAssumptions};
+use crate::miniscript::satisfy::{Preimage32, SatisfactionError};
+use crate::policy::{semantic, Liftable};
+use crate::prelude::*;
+use crate::util::{varint_len, witness_to_scriptsig};
+use crate::{
+    Descriptor, Error, ForEachKey, Miniscript, MiniscriptKey, PkTranslator, Satisfier,
+    ScriptContext, Segwitv0, Tap, ToPublicKey, TranslatePk,
+};
+
+/// Keys that may know where they were derived from
+pub trait KeyOrigin {
+    /// The master fingerprint and derivation path of this key, if known
+    fn key_origin(&self) -> Option<KeySource>;
+}
+
+impl KeyOrigin for bitcoin::PublicKey {
+    fn key_origin(&self) -> Option<KeySource> {
+        None
+    }
+}
+
+impl KeyOrigin for secp256k1::PublicKey {
+    fn key_origin(&self) -> Option<KeySource> {
+        None
+    }
+}
+
+impl KeyOrigin for XOnlyPublicKey {
+    fn key_origin(&self) -> Option<KeySource> {
+        None
+    }
+}
+
+impl KeyOrigin for DerivedDescriptorKey {
+    fn key_origin(&self) -> Option<KeySource> {
+        Some((self.master_fingerprint(), self.full_derivation_path()))
+    }
+}
+
+/// A single signature that has to be produced to execute a spend plan
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigningRequest<Pk: MiniscriptKey> {
+    /// The key that has to sign
+    pub key: Pk,
+    /// The master fingerprint and derivation path of the key, if known
+    pub origin: Option<KeySource>,
+    /// The leaf the signature commits to, for taproot script spends.
+    /// `None` for taproot key spends and pre-taproot signatures.
+    pub leaf_hash: Option<TapLeafHash>,
+    /// The sighash type the signature should use
+    pub sighash_type: PsbtSighashType,
+}
+
+// Satisfier answering the signature lookups `inner` can answer with dummy
+// signatures that encode the index of the lookup, so the signatures used by
+// the final witness can be mapped back to the lookups that produced them.
+// All other lookups are forwarded to `inner`.
+pub(crate) struct SigRecorder<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> {
+    inner: S,
+    // The internal key, if the taproot key spend path is available
+    key_spend: Option<Pk>,
+    schnorr: bool,
+    lookups: RefCell<Vec<(Pk, Option<TapLeafHash>)>>,
+    // The preimages handed out, and the timelocks found to be satisfied
+    preimages: RefCell<Vec<(PreimageHash, Preimage32)>>,
+    older: RefCell<Vec<u32>>,
+    after: RefCell<Vec<u32>>,
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> SigRecorder<Pk, S> {
+    pub(crate) fn new(inner: S, key_spend: Option<Pk>, schnorr: bool) -> Self {
+        SigRecorder {
+            inner,
+            key_spend,
+            schnorr,
+            lookups: RefCell::new(vec![]),
+            preimages: RefCell::new(vec![]),
+            older: RefCell::new(vec![]),
+            after: RefCell::new(vec![]),
+        }
+    }
+
+    fn record_preimage(
+        &self,
+        hash: PreimageHash,
+        preimage: Option<Preimage32>,
+    ) -> Option<Preimage32> {
+        let preimage = preimage?;
+        self.preimages.borrow_mut().push((hash, preimage));
+        Some(preimage)
+    }
+
+    fn record(&self, pk: Pk, leaf_hash: Option<TapLeafHash>) -> u16 {
+        let mut lookups = self.lookups.borrow_mut();
+        lookups.push((pk, leaf_hash));
+        (lookups.len() - 1) as u16
+    }
+
+    fn ecdsa_sig(idx: u16) -> bitcoin::EcdsaSig {
+        let mut compact = [0x7f; 64];
+        compact[0] = 0x80;
+        compact[1] = (idx >> 8) as u8;
+        compact[2] = idx as u8;
+        let sig =
+            secp256k1::ecdsa::Signature::from_compact(&compact).expect("Valid compact signature");
+        bitcoin::EcdsaSig::sighash_all(sig)
+    }
+
+    fn schnorr_sig(idx: u16) -> bitcoin::SchnorrSig {
+        let mut bytes = [0x01; 64];
+        bytes[0] = (idx >> 8) as u8;
+        bytes[1] = idx as u8;
+        let sig =
+            secp256k1::schnorr::Signature::from_slice(&bytes).expect("Valid schnorr signature");
+        bitcoin::SchnorrSig {
+            sig,
+            hash_ty: SchnorrSighashType::Default,
+        }
+    }
+
+    /// The serialized dummy signature returned for the lookup `idx`
+    fn sig_bytes(&self, idx: usize) -> Vec<u8> {
+        if self.schnorr {
+            Self::schnorr_sig(idx as u16).to_vec()
+        } else {
+            Self::ecdsa_sig(idx as u16).to_vec()
+        }
+    }
+
+    /// The lookup whose dummy signature is `elem`, if any
+    fn lookup_for(&self, elem: &[u8]) -> Option<(Pk, Option<TapLeafHash>)> {
+        let lookups = self.lookups.borrow();
+        (0..lookups.len())
+            .find(|idx| self.sig_bytes(*idx) == elem)
+            .map(|idx| lookups[idx].clone())
+    }
+
+    /// Find the lookups whose signatures made it into the final witness,
+    /// which are to be made with `sighash_type` or the default type
+    pub(crate) fn requests(
+        &self,
+        witness: &[Vec<u8>],
+        script_sig: &bitcoin::Script,
+        sighash_type: Option<PsbtSighashType>,
+    ) -> Vec<SigningRequest<Pk>>
+    where
+        Pk: KeyOrigin,
+    {
+        let sighash_type = match sighash_type {
+            Some(ty) => ty,
+            None if self.schnorr => PsbtSighashType::from(SchnorrSighashType::Default),
+            None => PsbtSighashType::from(EcdsaSighashType::All),
+        };
+        witness
+            .iter()
+            .cloned()
+            .chain(scriptsig_elements(script_sig))
+            .filter_map(|elem| self.lookup_for(&elem))
+            .map(|(pk, leaf_hash)| SigningRequest {
+                origin: pk.key_origin(),
+                key: pk,
+                leaf_hash,
+                sighash_type,
+            })
+            .collect()
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey, S: Satisfier<Pk>> Satisfier<Pk> for SigRecorder<Pk, S> {
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        self.inner.lookup_ecdsa_sig(pk)?;
+        Some(Self::ecdsa_sig(self.record(pk.clone(), None)))
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        let ik = self.key_spend.clone()?;
+        Some(Self::schnorr_sig(self.record(ik, None)))
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, h: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
+        self.inner.lookup_tap_leaf_script_sig(pk, h)?;
+        Some(Self::schnorr_sig(self.record(pk.clone(), Some(*h))))
+    }
+
+    fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+        self.inner.lookup_tap_annex()
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.inner.lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_ecdsa_sig(
+        &self,
+        pkh: &Pk::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        let (key, _) = self.inner.lookup_pkh_ecdsa_sig(pkh)?;
+        let pk = self.inner.lookup_pkh_pk(pkh)?;
+        Some((key, Self::ecdsa_sig(self.record(pk, None))))
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        pkh: &(Pk::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        let (key, _) = self.inner.lookup_pkh_tap_leaf_script_sig(pkh)?;
+        let pk = self.inner.lookup_pkh_pk(&pkh.0)?;
+        Some((key, Self::schnorr_sig(self.record(pk, Some(pkh.1)))))
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        self.record_preimage(
+            PreimageHash::Sha256(Pk::to_sha256(h)),
+            self.inner.lookup_sha256(h),
+        )
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.record_preimage(PreimageHash::Hash256(h), self.inner.lookup_hash256(h))
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.record_preimage(PreimageHash::Ripemd160(h), self.inner.lookup_ripemd160(h))
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.record_preimage(PreimageHash::Hash160(h), self.inner.lookup_hash160(h))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        let ret = self.inner.check_older(n);
+        if ret {
+            self.older.borrow_mut().push(n);
+        }
+        ret
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        let ret = self.inner.check_after(n);
+        if ret {
+            self.after.borrow_mut().push(n);
+        }
+        ret
+    }
+
+    fn key_preference(&self, pk: &Pk) -> Option<u32> {
+        self.inner.key_preference(pk)
+    }
+}
+
+// The pushes of a scriptSig, in the form they would have on a witness stack
+pub(crate) fn scriptsig_elements(script_sig: &Script) -> Vec<Vec<u8>> {
+    script_sig
+        .instructions()
+        .flatten()
+        .map(|ins| match ins {
+            Instruction::PushBytes(bytes) => bytes.to_vec(),
+            Instruction::Op(op) => {
+                let n = op.into_u8();
+                if n >= opcodes::all::OP_PUSHNUM_1.into_u8()
+                    && n <= opcodes::all::OP_PUSHNUM_16.into_u8()
+                {
+                    vec![n - opcodes::all::OP_PUSHNUM_1.into_u8() + 1]
+                } else if op == opcodes::all::OP_PUSHNUM_NEG1 {
+                    vec![0x81]
+                } else {
+                    vec![n]
+                }
+            }
+        })
+        .collect()
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey + KeyOrigin> Descriptor<Pk> {
+    /// Lists the signatures needed to spend an output controlled by this
+    /// descriptor, following the spend path that would be chosen by
+    /// [`Descriptor::get_satisfaction`] if everything declared in `assets`
+    /// were available.
+    ///
+    /// Each [`SigningRequest`] carries the key origin and, for taproot
+    /// script spends, the leaf hash, so it can be routed to the right
+    /// signing device directly. The signatures are requested with
+    /// `sighash_type`, typically the `sighash_type` of the PSBT input, or
+    /// with `SIGHASH_DEFAULT` for taproot and `SIGHASH_ALL` otherwise if it
+    /// is `None`.
+    pub fn signing_requests(
+        &self,
+        assets: &Assumptions<Pk>,
+        sighash_type: Option<PsbtSighashType>,
+    ) -> Result<Vec<SigningRequest<Pk>>, Error> {
+        let recorder = match *self {
+            Descriptor::Tr(ref tr) => {
+                let ik = tr.internal_key();
+                let key_spend = if assets.keys.contains(ik) {
+                    Some(ik.clone())
+                } else {
+                    None
+                };
+                SigRecorder::new(assets, key_spend, true)
+            }
+            Descriptor::RawTr(ref rawtr) => {
+                let ok = rawtr.output_key();
+                let key_spend = if assets.keys.contains(ok) {
+                    Some(ok.clone())
+                } else {
+                    None
+                };
+                SigRecorder::new(assets, key_spend, true)
+            }
+            _ => SigRecorder::new(assets, None, false),
+        };
+        let (witness, script_sig) = self.get_satisfaction(&recorder)?;
+        Ok(recorder.requests(&witness, &script_sig, sighash_type))
+    }
+}
+
+/// The way a taproot output is spent
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TapSpendPath<'a, Pk: MiniscriptKey> {
+    /// Spend with a signature by the (tweaked) internal key
+    KeyPath,
+    /// Spend by satisfying the given leaf
+    ScriptPath {
+        /// Depth of the leaf in the tree
+        depth: u8,
+        /// The leaf script
+        leaf: &'a Miniscript<Pk, Tap>,
+    },
+}
+
+/// The cheapest way to spend a taproot output with the given assets
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TapSpendPlan<'a, Pk: MiniscriptKey> {
+    /// The selected spend path
+    pub path: TapSpendPath<'a, Pk>,
+    /// Weight of the complete witness, including the element count, the
+    /// leaf script, the control block and the annex if any. Schnorr
+    /// signatures are assumed to be 65 bytes, including the sighash byte.
+    pub witness_weight: usize,
+}
+
+// Weight of the witness of a taproot key path spend, including the annex if any
+pub(crate) fn tap_key_spend_weight<Pk: MiniscriptKey>(assets: &Assumptions<Pk>) -> usize {
+    let elem_weight = |len: usize| varint_len(len) + len;
+    match assets.annex {
+        Some(ref annex) => {
+            varint_len(2) + elem_weight(assets.sig_sizes.schnorr()) + elem_weight(annex.len())
+        }
+        None => varint_len(1) + elem_weight(assets.sig_sizes.schnorr()),
+    }
+}
+
+/// Weight of the outpoint and `nSequence` of a transaction input
+pub(crate) const TXIN_BASE_WEIGHT: usize = 4 * (32 + 4 + 4);
+
+/// The result of planning the inputs of a transaction, see [`plan_transaction`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TransactionPlan {
+    /// Weight of each input, including its outpoint, `nSequence`, scriptSig
+    /// and witness. `None` for inputs that cannot be spent with their assets.
+    pub input_weights: Vec<Option<usize>>,
+    /// Indices of the inputs that cannot be spent with their assets
+    pub unspendable: Vec<usize>,
+    /// Total weight of the spendable inputs, including the segwit marker
+    /// and flag and the empty witnesses of non-segwit inputs if needed
+    pub total_weight: usize,
+    /// Fee paid by the spendable inputs at the requested fee rate
+    pub fee: u64,
+}
+
+// Weight of the spend of a single input, and whether it has a witness
+fn plan_input<Pk: MiniscriptKey + ToPublicKey>(
+    desc: &Descriptor<Pk>,
+    assets: &Assumptions<Pk>,
+) -> Result<(usize, bool), Error> {
+    let (witness_weight, script_sig_len) = match *desc {
+        Descriptor::Tr(ref tr) => (tr.best_spend(assets)?.witness_weight, 0),
+        Descriptor::RawTr(ref rawtr) => {
+            if !assets.keys.contains(rawtr.output_key()) {
+                return Err(Error::Unsatisfiable(vec![
+                    SatisfactionError::MissingSignature {
+                        path: vec![],
+                        key: rawtr.output_key().to_string(),
+                    },
+                ]));
+            }
+            (tap_key_spend_weight(assets), 0)
+        }
+        _ => {
+            let (witness, script_sig) = desc.get_satisfaction(assets)?;
+            let witness_weight = if witness.is_empty() {
+                0
+            } else {
+                varint_len(witness.len()) + Segwitv0::satisfaction_weight(&witness)
+            };
+            (witness_weight, script_sig.len())
+        }
+    };
+    let weight = TXIN_BASE_WEIGHT + 4 * (varint_len(script_sig_len) + script_sig_len);
+    Ok((weight + witness_weight, witness_weight > 0))
+}
+
+/// Plans the spend of every input, given the descriptor controlling it and
+/// the assets that will be available to satisfy it.
+///
+/// The weights only cover the inputs; the transaction header and outputs
+/// have to be accounted for separately. The fee rate is in satoshis per
+/// virtual byte, and the fee is rounded up.
+pub fn plan_transaction<Pk: MiniscriptKey + ToPublicKey>(
+    inputs: &[(Descriptor<Pk>, Assumptions<Pk>)],
+    sat_per_vb: u64,
+) -> TransactionPlan {
+    let mut input_weights = Vec::with_capacity(inputs.len());
+    let mut unspendable = vec![];
+    let mut total_weight = 0;
+    let (mut has_witness, mut no_witness_count) = (false, 0);
+    for (idx, (desc, assets)) in inputs.iter().enumerate() {
+        match plan_input(desc, assets) {
+            Ok((weight, witness)) => {
+                total_weight += weight;
+                has_witness |= witness;
+                if !witness {
+                    no_witness_count += 1;
+                }
+                input_weights.push(Some(weight));
+            }
+            Err(..) => {
+                unspendable.push(idx);
+                input_weights.push(None);
+            }
+        }
+    }
+    if has_witness {
+        // Segwit marker and flag, plus the empty witness of non-segwit inputs
+        total_weight += 2 + no_witness_count;
+    }
+    let fee = ((total_weight as u64 + 3) / 4) * sat_per_vb;
+    TransactionPlan {
+        input_weights,
+        unspendable,
+        total_weight,
+        fee,
+    }
+}
+
+/// A spend which becomes valid once the transaction lock time reaches
+/// `available_at`, as listed by [`Descriptor::spend_timeline`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TimedSpend {
+    /// The earliest lock time (block height or timestamp) at which the spend
+    /// is valid, or `None` if it is valid under the assumed lock time
+    pub available_at: Option<u32>,
+    /// Weight of the input, as in [`TransactionPlan::input_weights`]
+    pub weight: usize,
+}
+
+impl TimedSpend {
+    /// Virtual size of the input, rounded up
+    pub fn vsize(&self) -> usize {
+        (self.weight + 3) / 4
+    }
+}
+
+impl<Pk: MiniscriptKey + ToPublicKey> Descriptor<Pk> {
+    /// Lists when this descriptor can be spent with `assets`, and at what
+    /// cost.
+    ///
+    /// Each absolute timelock of the descriptor is tried as lock time. The
+    /// first entry is the earliest possible spend; every later entry
+    /// requires a later lock time and is strictly cheaper than the ones
+    /// before it, so only spends worth waiting for are listed. The result is
+    /// empty if the descriptor cannot be spent with `assets` at any time.
+    pub fn spend_timeline(&self, assets: &Assumptions<Pk>) -> Vec<TimedSpend> {
+        let mut candidates = vec![None];
+        if let Ok(policy) = self.lift() {
+            candidates.extend(
+                policy
+                    .absolute_timelocks()
+                    .into_iter()
+                    .filter(|&t| assets.lock_time.map_or(true, |lock_time| t > lock_time))
+                    .map(Some),
+            );
+        }
+
+        let mut ret: Vec<TimedSpend> = vec![];
+        for available_at in candidates {
+            let mut assets = assets.clone();
+            if available_at.is_some() {
+                assets.lock_time = available_at;
+            }
+            if let Ok((weight, _)) = plan_input(self, &assets) {
+                if ret.last().map_or(true, |last| weight < last.weight) {
+                    ret.push(TimedSpend {
+                        available_at,
+                        weight,
+                    });
+                }
+            }
+        }
+        ret
+    }
+}
+
+// Element of a cached witness or scriptSig
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum TemplateElem {
+    // A signature by the key at the given position in `for_each_key` order,
+    // committing to the leaf at the given position for taproot script spends
+    Sig { key: usize, leaf: Option<usize> },
+    // A descriptor-specific blob (key, script, control block), see `blobs`
+    Blob(usize),
+    // The preimage of the given hash
+    Preimage(PreimageHash),
+    // Data that is the same for every descriptor of the same structure
+    Data(Vec<u8>),
+}
+
+// A hash whose preimage was looked up while satisfying
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PreimageHash {
+    Sha256(sha256::Hash),
+    Hash256(sha256d::Hash),
+    Ripemd160(ripemd160::Hash),
+    Hash160(hash160::Hash),
+}
+
+impl fmt::Display for PreimageHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PreimageHash::Sha256(ref h) => fmt::Display::fmt(h, f),
+            PreimageHash::Hash256(ref h) => fmt::Display::fmt(h, f),
+            PreimageHash::Ripemd160(ref h) => fmt::Display::fmt(h, f),
+            PreimageHash::Hash160(ref h) => fmt::Display::fmt(h, f),
+        }
+    }
+}
+
+/// The spend path chosen for a descriptor, with the signatures, preimages
+/// and descriptor-specific data left as placeholders.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SatisfactionTemplate {
+    witness: Vec<TemplateElem>,
+    script_sig: Vec<TemplateElem>,
+    // Timelocks which were satisfiable when the spend path was chosen
+    older: Vec<u32>,
+    after: Vec<u32>,
+}
+
+// The keys of a descriptor, in `for_each_key` order
+fn template_keys<Pk: MiniscriptKey<Hash = Pk>>(desc: &Descriptor<Pk>) -> Vec<Pk> {
+    let mut keys = vec![];
+    desc.for_each_key(|k| {
+        keys.push(k.as_key().clone());
+        true
+    });
+    keys
+}
+
+// The taproot leaf hashes of a descriptor, in `iter_scripts` order
+fn template_leaves<Pk: MiniscriptKey + ToPublicKey>(desc: &Descriptor<Pk>) -> Vec<TapLeafHash> {
+    match *desc {
+        Descriptor::Tr(ref tr) => tr
+            .iter_scripts()
+            .map(|(_, ms)| TapLeafHash::from_script(&ms.encode(), LeafVersion::TapScript))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// The sha256 hashes of a descriptor, to look preimages up by plain hash
+fn template_sha256s<Pk: MiniscriptKey + ToPublicKey>(desc: &Descriptor<Pk>) -> Vec<Pk::Sha256> {
+    fn collect<Pk: MiniscriptKey>(policy: &semantic::Policy<Pk>, hashes: &mut Vec<Pk::Sha256>) {
+        match *policy {
+            semantic::Policy::Sha256(ref h) => hashes.push(h.clone()),
+            semantic::Policy::Threshold(_, ref subs) => {
+                for sub in subs {
+                    collect(sub, hashes);
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut hashes = vec![];
+    if let Ok(policy) = desc.lift() {
+        collect(&policy, &mut hashes);
+    }
+    hashes
+}
+
+// Every witness element that depends on the actual keys of a descriptor.
+// Two descriptors with the same structure yield the same number of blobs
+// in the same order.
+fn template_blobs<Pk: MiniscriptKey<Hash = Pk> + ToPublicKey>(
+    desc: &Descriptor<Pk>,
+) -> Vec<Vec<u8>> {
+    let mut blobs = vec![];
+    for pk in template_keys(desc) {
+        blobs.push(pk.to_public_key().to_bytes());
+        blobs.push(pk.to_x_only_pubkey().serialize().to_vec());
+    }
+    if let Ok(script) = desc.explicit_script() {
+        blobs.push(script.into_bytes());
+    }
+    blobs.extend(scriptsig_elements(&desc.unsigned_script_sig()));
+    if let Descriptor::Tr(ref tr) = *desc {
+        let spend_info = tr.spend_info();
+        for (_, ms) in tr.iter_scripts() {
+            let leaf_script = (ms.encode(), LeafVersion::TapScript);
+            let control_block = spend_info
+                .control_block(&leaf_script)
+                .expect("Control block must exist in script map for every known leaf");
+            blobs.push(leaf_script.0.into_bytes());
+            blobs.push(control_block.serialize());
+        }
+    }
+    blobs
+}
+
+impl SatisfactionTemplate {
+    /// Satisfies `desc` using `satisfier`, remembering the chosen spend path
+    /// so that it can be reused for other descriptors with the same
+    /// structure.
+    pub fn new<Pk, S>(desc: &Descriptor<Pk>, satisfier: S) -> Result<Self, Error>
+    where
+        Pk: MiniscriptKey<Hash = Pk> + ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        let recorder = match *desc {
+            Descriptor::Tr(ref tr) => {
+                let key_spend = if satisfier.lookup_tap_key_spend_sig().is_some() {
+                    Some(tr.internal_key().clone())
+                } else {
+                    None
+                };
+                SigRecorder::new(PkhKeys(satisfier), key_spend, true)
+            }
+            Descriptor::RawTr(ref rawtr) => {
+                let key_spend = if satisfier.lookup_tap_key_spend_sig().is_some() {
+                    Some(rawtr.output_key().clone())
+                } else {
+                    None
+                };
+                SigRecorder::new(PkhKeys(satisfier), key_spend, true)
+            }
+            _ => SigRecorder::new(PkhKeys(satisfier), None, false),
+        };
+        let (witness, script_sig) = desc.get_satisfaction(&recorder)?;
+
+        let keys = template_keys(desc);
+        let leaves = template_leaves(desc);
+        let blobs = template_blobs(desc);
+        let preimages = recorder.preimages.borrow();
+        let to_template = |elem: Vec<u8>| {
+            if let Some((pk, leaf_hash)) = recorder.lookup_for(&elem) {
+                TemplateElem::Sig {
+                    key: keys
+                        .iter()
+                        .position(|k| *k == pk)
+                        .expect("Signatures are only looked up for descriptor keys"),
+                    leaf: leaf_hash.map(|h| {
+                        leaves
+                            .iter()
+                            .position(|l| *l == h)
+                            .expect("Leaf hashes are only computed for descriptor leaves")
+                    }),
+                }
+            } else if let Some(idx) = blobs.iter().position(|b| *b == elem) {
+                TemplateElem::Blob(idx)
+            } else if let Some(&(hash, _)) = preimages.iter().find(|p| p.1[..] == elem[..]) {
+                TemplateElem::Preimage(hash)
+            } else {
+                TemplateElem::Data(elem)
+            }
+        };
+        let template = SatisfactionTemplate {
+            witness: witness.into_iter().map(&to_template).collect(),
+            script_sig: scriptsig_elements(&script_sig)
+                .into_iter()
+                .map(&to_template)
+                .collect(),
+            older: recorder.older.borrow().clone(),
+            after: recorder.after.borrow().clone(),
+        };
+        Ok(template)
+    }
+
+    /// Fills in the template for `desc`, which must have the same structure
+    /// as the descriptor the template was created from, returning the
+    /// witness and scriptSig. Signatures and preimages are looked up from
+    /// `satisfier`, and every timelock that was satisfiable when the template
+    /// was created must still be. Fails if any of them is not available, even
+    /// if `desc` could be satisfied through another spend path.
+    pub fn instantiate<Pk, S>(
+        &self,
+        desc: &Descriptor<Pk>,
+        satisfier: S,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        Pk: MiniscriptKey<Hash = Pk> + ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        let keys = template_keys(desc);
+        let leaves = template_leaves(desc);
+        let blobs = template_blobs(desc);
+        if let Some(n) = self.older.iter().find(|n| !satisfier.check_older(**n)) {
+            return Err(Error::RelativeLocktimeNotMet(*n));
+        }
+        if let Some(n) = self.after.iter().find(|n| !satisfier.check_after(**n)) {
+            return Err(Error::AbsoluteLocktimeNotMet(*n));
+        }
+        let mismatch = || Error::BadDescriptor("descriptor does not match the template".to_owned());
+        let is_tr = match *desc {
+            Descriptor::Tr(..) | Descriptor::RawTr(..) => true,
+            _ => false,
+        };
+        let fill = |elem: &TemplateElem| -> Result<Vec<u8>, Error> {
+            match *elem {
+                TemplateElem::Sig { key, leaf } => {
+                    let pk = keys.get(key).ok_or_else(mismatch)?;
+                    let sig = match (is_tr, leaf) {
+                        (true, None) => satisfier.lookup_tap_key_spend_sig().map(|s| s.to_vec()),
+                        (true, Some(leaf)) => {
+                            let h = leaves.get(leaf).ok_or_else(mismatch)?;
+                            satisfier
+                                .lookup_tap_leaf_script_sig(pk, h)
+                                .or_else(|| {
+                                    satisfier
+                                        .lookup_pkh_tap_leaf_script_sig(&(pk.clone(), *h))
+                                        .map(|(_, sig)| sig)
+                                })
+                                .map(|s| s.to_vec())
+                        }
+                        (false, _) => satisfier
+                            .lookup_ecdsa_sig(pk)
+                            .or_else(|| satisfier.lookup_pkh_ecdsa_sig(pk).map(|(_, sig)| sig))
+                            .map(|s| s.to_vec()),
+                    };
+                    sig.ok_or_else(|| Error::MissingSig(pk.to_public_key()))
+                }
+                TemplateElem::Blob(idx) => blobs.get(idx).cloned().ok_or_else(mismatch),
+                TemplateElem::Preimage(hash) => {
+                    let preimage = match hash {
+                        PreimageHash::Sha256(h) => template_sha256s(desc)
+                            .into_iter()
+                            .find(|pk_h| Pk::to_sha256(pk_h) == h)
+                            .and_then(|pk_h| satisfier.lookup_sha256(&pk_h)),
+                        PreimageHash::Hash256(h) => satisfier.lookup_hash256(h),
+                        PreimageHash::Ripemd160(h) => satisfier.lookup_ripemd160(h),
+                        PreimageHash::Hash160(h) => satisfier.lookup_hash160(h),
+                    };
+                    preimage.map(|p| p.to_vec()).ok_or_else(|| {
+                        Error::Unsatisfiable(vec![SatisfactionError::MissingPreimage {
+                            path: vec![],
+                            hash: hash.to_string(),
+                        }])
+                    })
+                }
+                TemplateElem::Data(ref data) => Ok(data.clone()),
+            }
+        };
+        let witness = self
+            .witness
+            .iter()
+            .map(&fill)
+            .collect::<Result<Vec<_>, _>>()?;
+        let script_sig = self
+            .script_sig
+            .iter()
+            .map(&fill)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((witness, witness_to_scriptsig(&script_sig)))
+    }
+}
+
+// Keys whose hash type is the key itself can always answer `lookup_pkh_pk`
+struct PkhKeys<S>(S);
+
+impl<Pk, S> Satisfier<Pk> for PkhKeys<S>
+where
+    Pk: MiniscriptKey<Hash = Pk> + ToPublicKey,
+    S: Satisfier<Pk>,
+{
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        self.0.lookup_ecdsa_sig(pk)
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        self.0.lookup_tap_key_spend_sig()
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, h: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
+        self.0.lookup_tap_leaf_script_sig(pk, h)
+    }
+
+    fn lookup_tap_annex(&self) -> Option<Vec<u8>> {
+        self.0.lookup_tap_annex()
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk) -> Option<Pk> {
+        Some(pkh.clone())
+    }
+
+    fn lookup_pkh_ecdsa_sig(&self, pkh: &Pk) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        self.0
+            .lookup_pkh_ecdsa_sig(pkh)
+            .or_else(|| Some((pkh.to_public_key(), self.0.lookup_ecdsa_sig(pkh)?)))
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        pkh: &(Pk, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        self.0.lookup_pkh_tap_leaf_script_sig(pkh).or_else(|| {
+            Some((
+                pkh.0.to_x_only_pubkey(),
+                self.0.lookup_tap_leaf_script_sig(&pkh.0, &pkh.1)?,
+            ))
+        })
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        self.0.lookup_sha256(h)
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        self.0.lookup_hash256(h)
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        self.0.lookup_ripemd160(h)
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        self.0.lookup_hash160(h)
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.0.check_older(n)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.0.check_after(n)
+    }
+
+    fn key_preference(&self, pk: &Pk) -> Option<u32> {
+        self.0.key_preference(pk)
+    }
+}
+
+// Placeholder for the key at the given position in `for_each_key` order
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+struct KeyPosition<H>(usize, PhantomData<H>);
+
+impl<H> fmt::Display for KeyPosition<H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<H: Clone + Eq + Ord + fmt::Display + fmt::Debug + hash::Hash> MiniscriptKey
+    for KeyPosition<H>
+{
+    type Hash = Self;
+    type Sha256 = H;
+
+    fn to_pubkeyhash(&self) -> Self {
+        self.clone()
+    }
+}
+
+struct StructureTranslator<Pk>(Vec<Pk>);
+
+impl<Pk: MiniscriptKey<Hash = Pk>> PkTranslator<Pk, KeyPosition<Pk::Sha256>, ()>
+    for StructureTranslator<Pk>
+{
+    fn pk(&mut self, pk: &Pk) -> Result<KeyPosition<Pk::Sha256>, ()> {
+        let idx = self
+            .0
+            .iter()
+            .position(|k| k == pk)
+            .expect("Only descriptor keys are translated");
+        Ok(KeyPosition(idx, PhantomData))
+    }
+
+    fn pkh(&mut self, pkh: &Pk) -> Result<KeyPosition<Pk::Sha256>, ()> {
+        self.pk(pkh)
+    }
+}
+
+// Hash of the descriptor with its keys replaced by their positions, so that
+// descriptors with the same structure have the same hash
+fn structural_hash<Pk: MiniscriptKey<Hash = Pk>>(desc: &Descriptor<Pk>) -> sha256::Hash {
+    let structure = desc
+        .translate_pk(&mut StructureTranslator(template_keys(desc)))
+        .expect("Structure translation never fails");
+    sha256::Hash::hash(structure.to_string().as_bytes())
+}
+
+/// Cache of [`SatisfactionTemplate`]s, keyed by a hash of the descriptor
+/// structure.
+///
+/// Wallets often spend many inputs controlled by the same (derived)
+/// descriptor. The first input of a given structure is satisfied normally
+/// and its spend path is remembered; for the following ones only the
+/// signatures and preimages of that path are looked up. Inputs for which the
+/// remembered path is not available are satisfied normally.
+#[derive(Clone, Debug, Default)]
+pub struct TemplateCache {
+    templates: BTreeMap<sha256::Hash, SatisfactionTemplate>,
+}
+
+impl TemplateCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of cached templates
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    /// Returns the witness and scriptSig spending an output controlled by
+    /// `desc`, reusing the cached spend path of a descriptor with the same
+    /// structure if there is one.
+    pub fn satisfy<Pk, S>(
+        &mut self,
+        desc: &Descriptor<Pk>,
+        satisfier: S,
+    ) -> Result<(Vec<Vec<u8>>, Script), Error>
+    where
+        Pk: MiniscriptKey<Hash = Pk> + ToPublicKey,
+        S: Satisfier<Pk>,
+    {
+        let template = match self.templates.get(&structural_hash(desc)) {
+            Some(template) => template,
+            None => {
+                let template = SatisfactionTemplate::new(desc, &satisfier)?;
+                self.templates
+                    .entry(structural_hash(desc))
+                    .or_insert(template)
+            }
+        };
+        template
+            .instantiate(desc, &satisfier)
+            .or_else(|_| desc.get_satisfaction(&satisfier))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::util::bip32;
+
+    use super::*;
+    use crate::descriptor::DerivationIndex;
+    use crate::DescriptorPublicKey;
+
+    fn keys(desc: &Descriptor<DerivedDescriptorKey>) -> Vec<DerivedDescriptorKey> {
+        let mut keys = vec![];
+        desc.for_each_key(|k| {
+            keys.push(k.as_key().clone());
+            true
+        });
+        keys
+    }
+
+    #[test]
+    fn signing_requests() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(or_d(pk([d34db33f/48'/0'/0'/2']xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*),and_v(v:pk(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/1/*),older(10))))",
+        )
+        .unwrap();
+        let derived = desc.derive(DerivationIndex::new(7).unwrap());
+        let keys = keys(&derived);
+
+        // Only the first key: the first branch is used
+        let reqs = derived
+            .signing_requests(&Assumptions::new().key(keys[0].clone()), None)
+            .unwrap();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].key, keys[0]);
+        assert_eq!(
+            reqs[0].origin,
+            Some((
+                bip32::Fingerprint::from_str("d34db33f").unwrap(),
+                bip32::DerivationPath::from_str("m/48'/0'/0'/2'/0/7").unwrap()
+            ))
+        );
+        assert_eq!(reqs[0].leaf_hash, None);
+        assert_eq!(
+            reqs[0].sighash_type,
+            PsbtSighashType::from(EcdsaSighashType::All)
+        );
+
+        // Only the timelocked branch
+        let reqs = derived
+            .signing_requests(&Assumptions::new().key(keys[1].clone()).sequence(10), None)
+            .unwrap();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].key, keys[1]);
+
+        // The sighash type of the caller
+        let sighash_type = PsbtSighashType::from(EcdsaSighashType::NonePlusAnyoneCanPay);
+        let reqs = derived
+            .signing_requests(&Assumptions::new().key(keys[0].clone()), Some(sighash_type))
+            .unwrap();
+        assert_eq!(reqs[0].sighash_type, sighash_type);
+
+        assert!(derived.signing_requests(&Assumptions::new(), None).is_err());
+    }
+
+    #[test]
+    fn signing_requests_tr() {
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(
+            "tr(xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*,pk(xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/1/*))",
+        )
+        .unwrap();
+        let derived = desc.derive(DerivationIndex::new(0).unwrap());
+        // Leaf keys come first, the internal key last
+        let keys = keys(&derived);
+
+        // Key spend
+        let reqs = derived
+            .signing_requests(&Assumptions::new().key(keys[1].clone()), None)
+            .unwrap();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].leaf_hash, None);
+        assert_eq!(
+            reqs[0].sighash_type,
+            PsbtSighashType::from(SchnorrSighashType::Default)
+        );
+
+        // Script spend
+        let reqs = derived
+            .signing_requests(&Assumptions::new().key(keys[0].clone()), None)
+            .unwrap();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].key, keys[0]);
+        assert!(reqs[0].leaf_hash.is_some());
+    }
+
+    #[test]
+    fn template_cache() {
+        let xpub_a = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*";
+        let xpub_b = "xpub6AHA9hZDN11k2ijHMeS5QqHx2KP9aMBRhTDqANMnwVtdyw2TDYRmF8PjpvwUFcL1Et8Hj59S3gTSMcUQ5gAqTz3Wd8EsMTmF3DChhqPQBnU/1/*";
+        let descs = [
+            format!(
+                "wsh(or_d(pk({}),and_v(v:pk({}),older(10))))",
+                xpub_a, xpub_b
+            ),
+            format!(
+                "sh(wsh(or_d(pk({}),and_v(v:pk({}),older(10)))))",
+                xpub_a, xpub_b
+            ),
+            format!(
+                "sh(or_d(pk({}),and_v(v:pkh({}),older(10))))",
+                xpub_a, xpub_b
+            ),
+            format!("pkh({})", xpub_b),
+            format!(
+                "tr({},{{pk({}),and_v(v:pk({}),older(10))}})",
+                xpub_a, xpub_a, xpub_b
+            ),
+        ];
+
+        let mut cache = TemplateCache::new();
+        for (n, desc) in descs.iter().enumerate() {
+            let desc = Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+            for idx in 0..3 {
+                let derived = desc.derive(DerivationIndex::new(idx).unwrap());
+                let keys = keys(&derived);
+                // Only the timelocked path is available
+                let b = keys
+                    .iter()
+                    .find(|k| k.to_string().starts_with("xpub6AHA"))
+                    .unwrap()
+                    .clone();
+                let assets = Assumptions::new().key(b.clone()).sequence(10);
+                assert_eq!(
+                    cache.satisfy(&derived, &assets).unwrap(),
+                    derived.get_satisfaction(&assets).unwrap()
+                );
+                assert_eq!(cache.len(), n + 1);
+
+                // Without the timelock, the cached path can't be used
+                let assets = Assumptions::new().key(keys[0].clone()).key(b);
+                assert_eq!(
+                    cache.satisfy(&derived, &assets),
+                    derived.get_satisfaction(&assets)
+                );
+                assert_eq!(
+                    cache.satisfy(&derived, Assumptions::new()),
+                    derived.get_satisfaction(Assumptions::new())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn template_cache_preimages() {
+        let xpub = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL/0/*";
+        let h = sha256::Hash::hash(&[1; 32]);
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),sha256({})))",
+            xpub, h
+        ))
+        .unwrap();
+
+        let mut cache = TemplateCache::new();
+        for idx in 0..3 {
+            let derived = desc.derive(DerivationIndex::new(idx).unwrap());
+            let key = Assumptions::new().key(keys(&derived)[0].clone());
+            let both = key.clone().sha256(h);
+            assert_eq!(
+                cache.satisfy(&derived, &both).unwrap(),
+                derived.get_satisfaction(&both).unwrap()
+            );
+            // The preimage is looked up again rather than copied
+            assert!(derived.get_satisfaction(&key).is_err());
+            assert_eq!(
+                cache.satisfy(&derived, &key),
+                derived.get_satisfaction(&key)
+            );
+        }
+        assert_eq!(cache.len(), 1);
+
+        // Descriptors differing only by hash have a different structure
+        let h = sha256::Hash::hash(&[2; 32]);
+        let other = Descriptor::<DescriptorPublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),sha256({})))",
+            xpub, h
+        ))
+        .unwrap()
+        .derive(DerivationIndex::new(0).unwrap());
+        let both = Assumptions::new().key(keys(&other)[0].clone()).sha256(h);
+        assert_eq!(
+            cache.satisfy(&other, &both).unwrap(),
+            other.get_satisfaction(&both).unwrap()
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn plan_transaction() {
+        let pks: Vec<bitcoin::PublicKey> = [
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+            "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+        ]
+        .iter()
+        .map(|k| bitcoin::PublicKey::from_str(k).unwrap())
+        .collect();
+        let wpkh = Descriptor::new_wpkh(pks[0]).unwrap();
+        let pkh = Descriptor::new_pkh(pks[0]);
+        let tr = Descriptor::new_tr(pks[1], None).unwrap();
+
+        let plan = super::plan_transaction(
+            &[
+                (wpkh.clone(), Assumptions::new().key(pks[0])),
+                (pkh, Assumptions::new().key(pks[0])),
+                (tr, Assumptions::new().key(pks[1])),
+                (wpkh, Assumptions::new()),
+            ],
+            2,
+        );
+        // count + sig + key
+        let wpkh_weight = TXIN_BASE_WEIGHT + 4 + 1 + 73 + 34;
+        // scriptSig len + sig + key
+        let pkh_weight = TXIN_BASE_WEIGHT + 4 * (1 + 73 + 34);
+        // count + sig
+        let tr_weight = TXIN_BASE_WEIGHT + 4 + 1 + 66;
+        assert_eq!(
+            plan.input_weights,
+            vec![Some(wpkh_weight), Some(pkh_weight), Some(tr_weight), None]
+        );
+        assert_eq!(plan.unspendable, vec![3]);
+        let total = wpkh_weight + pkh_weight + tr_weight + 2 + 1;
+        assert_eq!(plan.total_weight, total);
+        assert_eq!(plan.fee, 2 * ((total as u64 + 3) / 4));
+    }
+
+    #[test]
+    fn spend_timeline() {
+        let pks: Vec<bitcoin::PublicKey> = [
+            "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443",
+            "0384526253c27c7aef56c7b71a5cd25bebb66dddda437826defc5b2568bde81f07",
+        ]
+        .iter()
+        .map(|k| bitcoin::PublicKey::from_str(k).unwrap())
+        .collect();
+        // The recovery path is cheaper than the 2-of-2, but only available
+        // from block 840000 on
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(or_d(multi(2,{0},{1}),and_v(v:pk({0}),after(840000))))",
+            pks[0], pks[1]
+        ))
+        .unwrap();
+
+        let timeline = desc.spend_timeline(&Assumptions::new().key(pks[0]).key(pks[1]));
+        assert_eq!(timeline.len(), 2);
+        assert_eq!(timeline[0].available_at, None);
+        assert_eq!(timeline[1].available_at, Some(840000));
+        assert!(timeline[1].weight < timeline[0].weight);
+
+        let timeline = desc.spend_timeline(&Assumptions::new().key(pks[0]));
+        assert_eq!(timeline.len(), 1);
+        assert_eq!(timeline[0].available_at, Some(840000));
+        // Recovery is not delayed once the lock time is reached
+        let assets = Assumptions::new().key(pks[0]).lock_time(850000);
+        assert_eq!(desc.spend_timeline(&assets)[0].available_at, None);
+        assert_eq!(desc.spend_timeline(&assets)[0].vsize(), timeline[0].vsize());
+
+        assert!(desc
+            .spend_timeline(&Assumptions::new().key(pks[1]))
+            .is_empty());
+    }
+}
@@ -27,6 +27,7 @@ use sync::Arc;
 
 use crate::miniscript::context::SigType;
 use crate::miniscript::limits::MAX_PUBKEYS_PER_MULTISIG;
+use crate::miniscript::subs::Subs;
 use crate::miniscript::types::{self, ErrorKind, ExtData, Property, Type};
 use crate::miniscript::ScriptContext;
 use crate::policy::Concrete;
@@ -83,7 +84,7 @@ impl fmt::Display for CompilerError {
 
 #[cfg(feature = "std")]
 impl error::Error for CompilerError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::CompilerError::*;
 
         match self {
@@ -970,7 +971,7 @@ where
             let n = subs.len();
             let k_over_n = k as f64 / n as f64;
 
-            let mut sub_ast = Vec::with_capacity(n);
+            let mut sub_ast = Subs::with_capacity(n);
             let mut sub_ext_data = Vec::with_capacity(n);
 
             let mut best_es = Vec::with_capacity(n);
@@ -15,6 +15,7 @@
 //! Concrete Policies
 //!
 
+use core::convert::TryFrom;
 use core::{fmt, str};
 #[cfg(feature = "std")]
 use std::error;
@@ -36,11 +37,16 @@ use {
 };
 
 use super::ENTAILMENT_MAX_TERMINALS;
-use crate::expression::{self, FromTree};
-use crate::miniscript::limits::{LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_TYPE_FLAG};
+use crate::expression;
+use crate::miniscript::limits::{
+    LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
 use crate::miniscript::types::extra_props::TimelockInfo;
 use crate::prelude::*;
-use crate::{errstr, Error, ForEach, ForEachKey, MiniscriptKey, Translator};
+use crate::{
+    errstr, Error, ForEach, ForEachKey, FromStrParser, KeyParser, LocatedError, MiniscriptKey,
+    Translator,
+};
 
 /// Concrete policy which corresponds directly to a Miniscript structure,
 /// and whose disjunctions are annotated with satisfaction probabilities
@@ -55,7 +61,9 @@ pub enum Policy<Pk: MiniscriptKey> {
     Key(Pk),
     /// An absolute locktime restriction
     After(u32),
-    /// A relative locktime restriction
+    /// A relative locktime restriction, as an `nSequence` value. In the string
+    /// form, it can also be written as a number of blocks, hours or days, see
+    /// [`parse_older`].
     Older(u32),
     /// A SHA256 whose preimage must be provided to satisfy the descriptor
     Sha256(Pk::Sha256),
@@ -98,6 +106,15 @@ pub enum PolicyError {
     HeightTimelockCombination,
     /// Duplicate Public Keys
     DuplicatePubKeys,
+    /// `older` fragment in hours or days which is not a multiple of 512 seconds,
+    /// the granularity of time-based relative timelocks
+    InexactRelativeTime(u64),
+    /// `older` fragment in blocks, hours or days which does not fit the 16
+    /// bits of a relative timelock
+    RelativeTimelockTooLarge,
+    /// Probability of an `or` branch which is neither a whole number nor a
+    /// decimal with at most 9 decimals, or too large once made whole
+    InvalidProbability,
 }
 
 impl fmt::Display for PolicyError {
@@ -129,13 +146,24 @@ impl fmt::Display for PolicyError {
                 f.write_str("Cannot lift policies that have a heightlock and timelock combination")
             }
             PolicyError::DuplicatePubKeys => f.write_str("Policy contains duplicate keys"),
+            PolicyError::InexactRelativeTime(secs) => write!(
+                f,
+                "Relative timelock of {} seconds is not a multiple of 512 seconds",
+                secs
+            ),
+            PolicyError::RelativeTimelockTooLarge => {
+                f.write_str("Relative timelock must be at most 65535 blocks or 65535 * 512 seconds")
+            }
+            PolicyError::InvalidProbability => f.write_str(
+                "Probability must be a whole number or a decimal like 0.95, of at most 9 decimals",
+            ),
         }
     }
 }
 
 #[cfg(feature = "std")]
 impl error::Error for PolicyError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::PolicyError::*;
 
         match self {
@@ -148,7 +176,10 @@ impl error::Error for PolicyError {
             | InsufficientArgsforOr
             | EntailmentMaxTerminals
             | HeightTimelockCombination
-            | DuplicatePubKeys => None,
+            | DuplicatePubKeys
+            | InexactRelativeTime(_)
+            | RelativeTimelockTooLarge
+            | InvalidProbability => None,
         }
     }
 }
@@ -711,46 +742,283 @@ impl_from_str!(
     Policy<Pk>,
     type Err = Error;,
     fn from_str(s: &str) -> Result<Policy<Pk>, Error> {
-        for ch in s.as_bytes() {
-            if *ch < 20 || *ch > 127 {
-                return Err(Error::Unprintable(*ch));
+        Policy::parse_with(s, &mut FromStrParser)
+    }
+);
+
+#[rustfmt::skip]
+impl_block_str!(
+    Policy<Pk>,
+    /// Parse a policy from string like [`FromStr`](core::str::FromStr), but
+    /// keep going after the first invalid fragment and return the errors of
+    /// all of them, each with its byte position. See
+    /// [`Policy::parse_all_errors_with`].
+    pub fn parse_all_errors(s: &str,) -> Result<Policy<Pk>, Vec<LocatedError>>
+    {
+        Policy::parse_all_errors_with(s, &mut FromStrParser)
+    }
+);
+
+serde_string_impl_pk!(Policy, "a miniscript concrete policy");
+
+/// Parses the argument of an `older` fragment
+///
+/// A plain number is the `nSequence` value. A number of blocks, hours or days
+/// is written with a `b`, `h` or `d` suffix and converted to a height-based
+/// relative timelock, or a time-based one of 512 seconds units. For instance
+/// `older(144b)` is `older(144)`, and `older(4d)`, which is 675 units, is
+/// `older(4194979)` with the type flag `1 << 22` set. A time which is not a
+/// multiple of 512 seconds, like `older(1d)`, is rejected rather than rounded.
+pub fn parse_older(arg: &expression::Tree) -> Result<u32, Error> {
+    let (num, unit) = arg.name.split_at(arg.name.len().saturating_sub(1));
+    let secs_per_unit: u64 = match unit {
+        "b" => 0,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return expression::terminal(arg, expression::parse_policy_num),
+    };
+    if !arg.args.is_empty() {
+        return Err(errstr(arg.name).at(arg.pos));
+    }
+    // Decimal only, so that `0x10b` is not read as `0x10` blocks
+    let num = expression::parse_num(num).map_err(|e| e.at(arg.pos))?;
+    let (units, flag) = if secs_per_unit == 0 {
+        (u64::from(num), 0)
+    } else {
+        let secs = u64::from(num) * secs_per_unit;
+        if secs % 512 != 0 {
+            return Err(Error::PolicyError(PolicyError::InexactRelativeTime(secs)).at(arg.pos));
+        }
+        (secs / 512, SEQUENCE_LOCKTIME_TYPE_FLAG)
+    };
+    if units == 0 {
+        // Also rejects `older(0d)`, which would pass as a non-zero `nSequence`
+        // because of its type flag
+        return Err(Error::PolicyError(PolicyError::ZeroTime).at(arg.pos));
+    } else if units > u64::from(SEQUENCE_LOCKTIME_MASK) {
+        return Err(Error::PolicyError(PolicyError::RelativeTimelockTooLarge).at(arg.pos));
+    }
+    Ok(flag | units as u32)
+}
+
+/// The odds of a branch of `or`, written as a whole number or as a decimal,
+/// in units of `10^-decimals`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Odds {
+    units: u64,
+    decimals: u32,
+}
+
+impl Odds {
+    const ONE: Odds = Odds {
+        units: 1,
+        decimals: 0,
+    };
+
+    fn parse(s: &str) -> Result<Odds, Error> {
+        let invalid = || Error::PolicyError(PolicyError::InvalidProbability);
+        let mut split = s.splitn(2, '.');
+        let whole = expression::parse_num(split.next().unwrap_or(""))?;
+        let frac = match split.next() {
+            None => {
+                return Ok(Odds {
+                    units: u64::from(whole),
+                    decimals: 0,
+                })
             }
+            Some(frac) => frac,
+        };
+        if frac.is_empty() || frac.len() > 9 || !frac.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
         }
+        // Cannot overflow with at most 9 decimals
+        let decimals = frac.len() as u32;
+        let units =
+            u64::from(whole) * 10u64.pow(decimals) + frac.parse::<u64>().map_err(|_| invalid())?;
+        Ok(Odds { units, decimals })
+    }
+
+    /// Turns the odds of the branches into whole numbers, in lowest terms if
+    /// any of them is a decimal
+    fn normalize<T>(subs: Vec<(Odds, T)>) -> Result<Vec<(usize, T)>, Error> {
+        let decimals = subs
+            .iter()
+            .map(|(odds, _)| odds.decimals)
+            .max()
+            .unwrap_or(0);
+        let scaled: Vec<u64> = subs
+            .iter()
+            .map(|(odds, _)| odds.units * 10u64.pow(decimals - odds.decimals))
+            .collect();
+        let divisor = if decimals == 0 {
+            1
+        } else {
+            scaled.iter().fold(0, |a, &b| gcd(a, b)).max(1)
+        };
+        subs.into_iter()
+            .zip(scaled)
+            .map(|((_, sub), units)| {
+                let units = usize::try_from(units / divisor)
+                    .map_err(|_| Error::PolicyError(PolicyError::InvalidProbability))?;
+                Ok((units, sub))
+            })
+            .collect()
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
 
-        let tree = expression::Tree::from_str(s)?;
-        let policy: Policy<Pk> = FromTree::from_tree(&tree)?;
+// The fragments of the policy language
+#[rustfmt::skip]
+const FRAGMENTS: &[expression::Fragment] = &[
+    expression::Fragment { name: "UNSATISFIABLE", usage: "UNSATISFIABLE" },
+    expression::Fragment { name: "TRIVIAL", usage: "TRIVIAL" },
+    expression::Fragment { name: "pk", usage: "pk(key)" },
+    expression::Fragment { name: "after", usage: "after(n)" },
+    expression::Fragment { name: "older", usage: "older(n)" },
+    expression::Fragment { name: "sha256", usage: "sha256(h)" },
+    expression::Fragment { name: "hash256", usage: "hash256(h)" },
+    expression::Fragment { name: "ripemd160", usage: "ripemd160(h)" },
+    expression::Fragment { name: "hash160", usage: "hash160(h)" },
+    expression::Fragment { name: "and", usage: "and(X,Y)" },
+    expression::Fragment { name: "or", usage: "or(X,Y)" },
+    expression::Fragment { name: "thresh", usage: "thresh(k,X,...)" },
+];
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Parse a policy from string, with the keys and hashes parsed by
+    /// `parser` rather than by their [`FromStr`](core::str::FromStr)
+    /// implementations
+    pub fn from_str_with<P, E>(s: &str, parser: &mut P) -> Result<Policy<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        Policy::parse_with(s, parser).map_err(Error::unlocated)
+    }
+
+    /// Same as [`Policy::from_str_with`], with the errors located in `s`
+    pub(crate) fn parse_with<P, E>(s: &str, parser: &mut P) -> Result<Policy<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        expression::printable(s)?;
+
+        let tree = expression::Tree::parse(s)?;
+        let policy = Policy::from_tree_with(&tree, parser)?;
         policy.check_timelocks()?;
         Ok(policy)
     }
-);
 
-serde_string_impl_pk!(Policy, "a miniscript concrete policy");
+    /// Parse a policy from string, with the keys and hashes parsed by
+    /// `parser`, and return the errors of all the invalid fragments rather
+    /// than only the first one.
+    ///
+    /// A fragment which cannot be parsed on its own, like a `thresh` with a
+    /// threshold higher than its number of subpolicies, is reported along
+    /// with the errors of its subpolicies. The errors are sorted by position.
+    /// Errors in the syntax of the expression, like an unclosed bracket, stop
+    /// the parsing and are returned alone.
+    pub fn parse_all_errors_with<P, E>(
+        s: &str,
+        parser: &mut P,
+    ) -> Result<Policy<Pk>, Vec<LocatedError>>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        let tree = expression::printable(s)
+            .and_then(|_| expression::Tree::parse(s))
+            .map_err(|e| vec![e.into()])?;
+        let mut errors = vec![];
+        Policy::collect_errors(&tree, false, parser, &mut errors);
+        if errors.is_empty() {
+            Policy::parse_with(s, parser).map_err(|e| vec![e.into()])
+        } else {
+            Err(errors.into_iter().map(LocatedError::from).collect())
+        }
+    }
+
+    /// Pushes the errors of a fragment and of its subpolicies to `errors`
+    fn collect_errors<P, E>(
+        top: &expression::Tree,
+        allow_prob: bool,
+        parser: &mut P,
+        errors: &mut Vec<Error>,
+    ) where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        let err = match Policy::from_tree_prob(top, allow_prob, parser) {
+            Ok(_) => return,
+            Err(e) => e.at(top.pos),
+        };
+        let frag_name = top.name.rsplit('@').next().unwrap_or("");
+        let subs = match frag_name {
+            "and" | "or" => &top.args[..],
+            "thresh" if !top.args.is_empty() => &top.args[1..],
+            _ => &[],
+        };
+        // The fragment is checked before its subpolicies, so an error at the
+        // fragment itself hides the errors of its subpolicies
+        if subs.is_empty() || err.position() == Some(top.pos) {
+            errors.push(err);
+        }
+        for sub in subs {
+            Policy::collect_errors(sub, frag_name == "or", parser, errors);
+        }
+    }
+
+    /// Parses an expression tree into a policy, with the keys and hashes
+    /// parsed by `parser`
+    pub(crate) fn from_tree_with<P, E>(
+        top: &expression::Tree,
+        parser: &mut P,
+    ) -> Result<Policy<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        Policy::from_tree_prob(top, false, parser)
+            .map(|(_, result)| result)
+            .map_err(|e| e.at(top.pos))
+    }
 
-#[rustfmt::skip]
-impl_block_str!(
-    Policy<Pk>,
     /// Helper function for `from_tree` to parse subexpressions with
     /// names of the form x@y
-    fn from_tree_prob(top: &expression::Tree, allow_prob: bool,)
-        -> Result<(usize, Policy<Pk>), Error>
+    fn from_tree_prob<P, E>(
+        top: &expression::Tree,
+        allow_prob: bool,
+        parser: &mut P,
+    ) -> Result<(Odds, Policy<Pk>), Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
     {
         let frag_prob;
         let frag_name;
         let mut name_split = top.name.split('@');
         match (name_split.next(), name_split.next(), name_split.next()) {
             (None, _, _) => {
-                frag_prob = 1;
+                frag_prob = Odds::ONE;
                 frag_name = "";
             }
             (Some(name), None, _) => {
-                frag_prob = 1;
+                frag_prob = Odds::ONE;
                 frag_name = name;
             }
             (Some(prob), Some(name), None) => {
                 if !allow_prob {
                     return Err(Error::AtOutsideOr(top.name.to_owned()));
                 }
-                frag_prob = expression::parse_num(prob)? as usize;
+                frag_prob = Odds::parse(prob)?;
                 frag_name = name;
             }
             (Some(_), Some(_), Some(_)) => {
@@ -760,9 +1028,9 @@ impl_block_str!(
         match (frag_name, top.args.len() as u32) {
             ("UNSATISFIABLE", 0) => Ok(Policy::Unsatisfiable),
             ("TRIVIAL", 0) => Ok(Policy::Trivial),
-            ("pk", 1) => expression::terminal(&top.args[0], |pk| Pk::from_str(pk).map(Policy::Key)),
+            ("pk", 1) => expression::terminal(&top.args[0], |pk| parser.pk(pk).map(Policy::Key)),
             ("after", 1) => {
-                let num = expression::terminal(&top.args[0], expression::parse_num)?;
+                let num = expression::terminal(&top.args[0], expression::parse_policy_num)?;
                 if num > 2u32.pow(31) {
                     return Err(Error::PolicyError(PolicyError::TimeTooFar));
                 } else if num == 0 {
@@ -771,7 +1039,7 @@ impl_block_str!(
                 Ok(Policy::After(num))
             }
             ("older", 1) => {
-                let num = expression::terminal(&top.args[0], expression::parse_num)?;
+                let num = parse_older(&top.args[0])?;
                 if num > 2u32.pow(31) {
                     return Err(Error::PolicyError(PolicyError::TimeTooFar));
                 } else if num == 0 {
@@ -779,9 +1047,9 @@ impl_block_str!(
                 }
                 Ok(Policy::Older(num))
             }
-            ("sha256", 1) => expression::terminal(&top.args[0], |x| {
-                <Pk::Sha256 as core::str::FromStr>::from_str(x).map(Policy::Sha256)
-            }),
+            ("sha256", 1) => {
+                expression::terminal(&top.args[0], |x| parser.sha256(x).map(Policy::Sha256))
+            }
             ("hash256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256d::Hash::from_hex(x).map(Policy::Hash256)
             }),
@@ -797,7 +1065,7 @@ impl_block_str!(
                 }
                 let mut subs = Vec::with_capacity(top.args.len());
                 for arg in &top.args {
-                    subs.push(Policy::from_tree(arg)?);
+                    subs.push(Policy::from_tree_with(arg, parser)?);
                 }
                 Ok(Policy::And(subs))
             }
@@ -807,36 +1075,46 @@ impl_block_str!(
                 }
                 let mut subs = Vec::with_capacity(top.args.len());
                 for arg in &top.args {
-                    subs.push(Policy::from_tree_prob(arg, true)?);
+                    subs.push(
+                        Policy::from_tree_prob(arg, true, parser).map_err(|e| e.at(arg.pos))?,
+                    );
                 }
-                Ok(Policy::Or(subs))
+                Ok(Policy::Or(
+                    Odds::normalize(subs).map_err(|e| e.at(top.pos))?,
+                ))
             }
             ("thresh", nsubs) => {
                 if top.args.is_empty() || !top.args[0].args.is_empty() {
                     return Err(Error::PolicyError(PolicyError::IncorrectThresh));
                 }
 
-                let thresh = expression::parse_num(top.args[0].name)?;
+                let thresh = expression::parse_policy_num(top.args[0].name)?;
                 if thresh >= nsubs || thresh == 0 {
                     return Err(Error::PolicyError(PolicyError::IncorrectThresh));
                 }
 
                 let mut subs = Vec::with_capacity(top.args.len() - 1);
                 for arg in &top.args[1..] {
-                    subs.push(Policy::from_tree(arg)?);
+                    subs.push(Policy::from_tree_with(arg, parser)?);
                 }
                 Ok(Policy::Threshold(thresh as usize, subs))
             }
-            _ => Err(errstr(top.name)),
+            _ => Err(Error::UnknownFragment(expression::UnknownFragment::new(
+                top.name,
+                frag_name,
+                top.args.len(),
+                "policy".to_owned(),
+                FRAGMENTS,
+            ))),
         }
         .map(|res| (frag_prob, res))
     }
-);
+}
 
 impl_from_tree!(
     Policy<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Policy<Pk>, Error> {
-        Policy::from_tree_prob(top, false).map(|(_, result)| result)
+        Policy::from_tree_with(top, &mut FromStrParser)
     }
 );
 
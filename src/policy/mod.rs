@@ -83,7 +83,7 @@ impl fmt::Display for LiftError {
 
 #[cfg(feature = "std")]
 impl error::Error for LiftError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::LiftError::*;
 
         match self {
@@ -161,7 +161,9 @@ impl<Pk: MiniscriptKey, Ctx: ScriptContext> Liftable<Pk> for Terminal<Pk, Ctx> {
                 let semantic_subs: Result<_, Error> = subs.iter().map(|s| s.node.lift()).collect();
                 Semantic::Threshold(k, semantic_subs?)
             }
-            Terminal::Multi(k, ref keys) | Terminal::MultiA(k, ref keys) => Semantic::Threshold(
+            Terminal::Multi(k, ref keys)
+            | Terminal::MultiA(k, ref keys)
+            | Terminal::SortedMultiA(k, ref keys) => Semantic::Threshold(
                 k,
                 keys.iter()
                     .map(|k| Semantic::KeyHash(k.to_pubkeyhash()))
@@ -182,6 +184,8 @@ impl<Pk: MiniscriptKey> Liftable<Pk> for Descriptor<Pk> {
             Descriptor::Wsh(ref wsh) => wsh.lift(),
             Descriptor::Sh(ref sh) => sh.lift(),
             Descriptor::Tr(ref tr) => tr.lift(),
+            Descriptor::RawTr(ref rawtr) => rawtr.lift(),
+            Descriptor::Raw(_) | Descriptor::Addr(_) => Err(Error::WatchOnlyDescriptor),
         }
     }
 }
@@ -236,11 +240,13 @@ mod tests {
 
     use super::super::miniscript::context::Segwitv0;
     use super::super::miniscript::Miniscript;
+    use super::concrete::PolicyError;
     use super::{Concrete, Liftable, Semantic};
+    use crate::expression::FromStrLocated;
     use crate::prelude::*;
-    use crate::DummyKey;
     #[cfg(feature = "compiler")]
     use crate::{descriptor::TapTree, Descriptor, Tap};
+    use crate::{DummyKey, Error};
 
     type ConcretePol = Concrete<DummyKey>;
     type SemanticPol = Semantic<DummyKey>;
@@ -272,6 +278,90 @@ mod tests {
         // thresh with k = 2
         assert!(ConcretePol::from_str("thresh(2,after(1000000000),after(100),pk())").is_err());
     }
+
+    #[test]
+    fn older_units() {
+        let older = |s: &str| ConcretePol::from_str(&format!("older({})", s));
+        assert_eq!(older("144b"), Ok(ConcretePol::Older(144)));
+        // 4 days are 675 units of 512 seconds
+        assert_eq!(older("4d"), Ok(ConcretePol::Older((1 << 22) | 675)));
+        assert_eq!(older("128h"), Ok(ConcretePol::Older((1 << 22) | 900)));
+        assert_eq!(
+            SemanticPol::from_str("older(4d)"),
+            Ok(SemanticPol::Older((1 << 22) | 675))
+        );
+
+        assert_eq!(
+            older("1d").unwrap_err().to_string(),
+            "Relative timelock of 86400 seconds is not a multiple of 512 seconds"
+        );
+        assert_eq!(
+            older("65536b").unwrap_err().to_string(),
+            "Relative timelock must be at most 65535 blocks or 65535 * 512 seconds"
+        );
+        assert!(older("0b").is_err());
+        assert!(older("1w").is_err());
+        // A zero time is rejected in spite of the type flag
+        let zero_time = || Error::PolicyError(PolicyError::ZeroTime);
+        assert_eq!(older("0d"), Err(zero_time()));
+        assert_eq!(older("0h"), Err(zero_time()));
+        assert_eq!(SemanticPol::from_str("older(0d)"), Err(zero_time()));
+    }
+
+    #[test]
+    fn decimal_odds() {
+        let odds = |s: &str| ConcretePol::from_str(s).map(|pol| pol.to_string());
+        assert_eq!(
+            odds("or(0.95@pk(),0.05@pk())").unwrap(),
+            "or(19@pk(),1@pk())"
+        );
+        assert_eq!(odds("or(0.5@pk(),1@pk())").unwrap(), "or(1@pk(),2@pk())");
+        assert_eq!(odds("or(0.10@pk(),0.2@pk())").unwrap(), "or(1@pk(),2@pk())");
+        // Whole odds are kept as they are written
+        assert_eq!(odds("or(2@pk(),2@pk())").unwrap(), "or(2@pk(),2@pk())");
+
+        assert!(odds("or(0.@pk(),1@pk())").is_err());
+        assert!(odds("or(.5@pk(),1@pk())").is_err());
+        assert!(odds("or(0.5.5@pk(),1@pk())").is_err());
+        assert!(odds("or(0.1234567891@pk(),1@pk())").is_err());
+        let s = "or(0.95@pk(),0.0e5@pk())";
+        assert_eq!(
+            ConcretePol::from_str_located(s).unwrap_err().position(),
+            s.find("0.0e5")
+        );
+    }
+
+    #[test]
+    fn parse_all_errors() {
+        let s = "or(and(pk(),thresh(3,pk(),pk())),and(after(0),hash160(zz)))";
+        let errors = ConcretePol::parse_all_errors(s).unwrap_err();
+        let positions: Vec<_> = errors.iter().map(|e| e.position()).collect();
+        assert_eq!(
+            positions,
+            [s.find("thresh"), s.find("after"), s.find("zz"),]
+        );
+        assert_eq!(errors[0], ConcretePol::from_str_located(s).unwrap_err());
+
+        // The errors of subpolicies are found under a fragment which is wrong
+        let s = "or(9@pk(),1@and(pk(),after(0),pk()))";
+        let errors = ConcretePol::parse_all_errors(s).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].position(), s.find("1@and"));
+        assert_eq!(errors[1].position(), s.find("after"));
+
+        let s = "or(pk(),and(pk(),older(144)))";
+        assert_eq!(
+            ConcretePol::parse_all_errors(s),
+            Ok(ConcretePol::from_str(s).unwrap())
+        );
+        // Syntax errors stop the parsing
+        assert_eq!(
+            ConcretePol::parse_all_errors("or(pk(),after(0)")
+                .unwrap_err()
+                .len(),
+            1
+        );
+    }
     #[test]
     fn policy_rtt_tests() {
         concrete_policy_rtt("pk()");
@@ -306,6 +396,13 @@ mod tests {
                 .to_string(),
             "Threshold k must be greater than 0 and less than or equal to n 0<k<=n"
         );
+        assert!(ConcretePol::from_str("or(pk(),treshold(1,pk()))")
+            .unwrap_err()
+            .to_string()
+            .starts_with(
+                "unknown fragment «treshold» with 2 arguments in policy, did you mean \
+                 thresh(k,X,...)?"
+            ));
         assert_eq!(
             ConcretePol::from_str("and(pk())").unwrap_err().to_string(),
             "And policy fragment must take 2 arguments"
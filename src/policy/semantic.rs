@@ -14,16 +14,18 @@
 
 //! Abstract Policies
 
-use core::str::FromStr;
 use core::{fmt, str};
 
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::{hash160, ripemd160, sha256d};
 
-use super::concrete::PolicyError;
+use super::concrete::{self, PolicyError};
 use super::ENTAILMENT_MAX_TERMINALS;
 use crate::prelude::*;
-use crate::{errstr, expression, timelock, Error, ForEach, ForEachKey, MiniscriptKey, Translator};
+use crate::{
+    errstr, expression, timelock, Error, ForEach, ForEachKey, FromStrParser, KeyParser,
+    MiniscriptKey, Translator,
+};
 
 /// Abstract policy which corresponds to the semantics of a Miniscript
 /// and which allows complex forms of analysis, e.g. filtering and
@@ -311,14 +313,7 @@ impl_from_str!(
     Policy<Pk>,
     type Err = Error;,
     fn from_str(s: &str) -> Result<Policy<Pk>, Error> {
-        for ch in s.as_bytes() {
-            if *ch < 20 || *ch > 127 {
-                return Err(Error::Unprintable(*ch));
-            }
-        }
-
-        let tree = expression::Tree::from_str(s)?;
-        expression::FromTree::from_tree(&tree)
+        Policy::parse_with(s, &mut FromStrParser)
     }
 );
 
@@ -327,21 +322,54 @@ serde_string_impl_pk!(Policy, "a miniscript semantic policy");
 impl_from_tree!(
     Policy<Pk>,
     fn from_tree(top: &expression::Tree) -> Result<Policy<Pk>, Error> {
+        Policy::from_tree_with(top, &mut FromStrParser)
+    }
+);
+
+impl<Pk: MiniscriptKey> Policy<Pk> {
+    /// Parse a policy from string, with the key hashes and hashes parsed by
+    /// `parser` rather than by their [`FromStr`](core::str::FromStr)
+    /// implementations
+    pub fn from_str_with<P, E>(s: &str, parser: &mut P) -> Result<Policy<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        Policy::parse_with(s, parser).map_err(Error::unlocated)
+    }
+
+    /// Same as [`Policy::from_str_with`], with the errors located in `s`
+    fn parse_with<P, E>(s: &str, parser: &mut P) -> Result<Policy<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
+        expression::printable(s)?;
+
+        let tree = expression::Tree::parse(s)?;
+        Policy::from_tree_with(&tree, parser).map_err(|e| e.at(tree.pos))
+    }
+
+    /// Parses an expression tree into a policy, with the key hashes and
+    /// hashes parsed by `parser`
+    fn from_tree_with<P, E>(top: &expression::Tree, parser: &mut P) -> Result<Policy<Pk>, Error>
+    where
+        P: KeyParser<Pk, E>,
+        E: ToString,
+    {
         match (top.name, top.args.len()) {
             ("UNSATISFIABLE", 0) => Ok(Policy::Unsatisfiable),
             ("TRIVIAL", 0) => Ok(Policy::Trivial),
-            ("pkh", 1) => expression::terminal(&top.args[0], |pk| {
-                Pk::Hash::from_str(pk).map(Policy::KeyHash)
-            }),
+            ("pkh", 1) => {
+                expression::terminal(&top.args[0], |pk| parser.pkh(pk).map(Policy::KeyHash))
+            }
             ("after", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Policy::After)
-            }),
-            ("older", 1) => expression::terminal(&top.args[0], |x| {
-                expression::parse_num(x).map(Policy::Older)
-            }),
-            ("sha256", 1) => expression::terminal(&top.args[0], |x| {
-                Pk::Sha256::from_str(x).map(Policy::Sha256)
+                expression::parse_policy_num(x).map(Policy::After)
             }),
+            ("older", 1) => concrete::parse_older(&top.args[0]).map(Policy::Older),
+            ("sha256", 1) => {
+                expression::terminal(&top.args[0], |x| parser.sha256(x).map(Policy::Sha256))
+            }
             ("hash256", 1) => expression::terminal(&top.args[0], |x| {
                 sha256d::Hash::from_hex(x).map(Policy::Hash256)
             }),
@@ -357,7 +385,7 @@ impl_from_tree!(
                 }
                 let mut subs = Vec::with_capacity(nsubs);
                 for arg in &top.args {
-                    subs.push(Policy::from_tree(arg)?);
+                    subs.push(Policy::from_tree_with(arg, parser).map_err(|e| e.at(arg.pos))?);
                 }
                 Ok(Policy::Threshold(nsubs, subs))
             }
@@ -367,7 +395,7 @@ impl_from_tree!(
                 }
                 let mut subs = Vec::with_capacity(nsubs);
                 for arg in &top.args {
-                    subs.push(Policy::from_tree(arg)?);
+                    subs.push(Policy::from_tree_with(arg, parser).map_err(|e| e.at(arg.pos))?);
                 }
                 Ok(Policy::Threshold(1, subs))
             }
@@ -380,7 +408,7 @@ impl_from_tree!(
                     return Err(errstr(top.args[0].args[0].name));
                 }
 
-                let thresh = expression::parse_num(top.args[0].name)?;
+                let thresh = expression::parse_policy_num(top.args[0].name)?;
 
                 // thresh(1) and thresh(n) are disallowed in semantic policies
                 if thresh <= 1 || thresh >= (nsubs as u32 - 1) {
@@ -394,16 +422,14 @@ impl_from_tree!(
 
                 let mut subs = Vec::with_capacity(top.args.len() - 1);
                 for arg in &top.args[1..] {
-                    subs.push(Policy::from_tree(arg)?);
+                    subs.push(Policy::from_tree_with(arg, parser).map_err(|e| e.at(arg.pos))?);
                 }
                 Ok(Policy::Threshold(thresh as usize, subs))
             }
             _ => Err(errstr(top.name)),
         }
     }
-);
 
-impl<Pk: MiniscriptKey> Policy<Pk> {
     /// Flatten out trees of `And`s and `Or`s; eliminate `Trivial` and
     /// `Unsatisfiable`s. Does not reorder any branches; use `.sort`.
     pub fn normalized(self) -> Policy<Pk> {
@@ -0,0 +1,548 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Checks
+//!
+//! Checks a PSBT before it is signed or broadcast: its fee, the sighash
+//! types of its signatures and the presence of the fields its inputs need.
+//! The sighash types can also be checked against a policy of the caller.
+//!
+
+use core::ops::RangeInclusive;
+
+use bitcoin::util::psbt::{self, PsbtSighashType};
+use bitcoin::{EcdsaSighashType, SchnorrSighashType};
+
+use super::Psbt;
+use crate::prelude::*;
+use crate::{Descriptor, MiniscriptKey, ToPublicKey};
+
+/// A problem found by [`check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsbtIssue {
+    /// The PSBT does not have one input map per transaction input
+    WrongInputCount {
+        /// Number of inputs in the transaction
+        in_tx: usize,
+        /// Number of input maps in the PSBT
+        in_map: usize,
+    },
+    /// Descriptors were given, but not one per input
+    WrongDescriptorCount {
+        /// Number of descriptors
+        descriptors: usize,
+        /// Number of inputs
+        inputs: usize,
+    },
+    /// The input has neither `witness_utxo` nor `non_witness_utxo`, or the
+    /// latter has no output at the spent index
+    MissingUtxo(usize),
+    /// The segwit input has no `witness_utxo`
+    MissingWitnessUtxo(usize),
+    /// The P2SH input has no `redeem_script`
+    MissingRedeemScript(usize),
+    /// The P2WSH input has no `witness_script`
+    MissingWitnessScript(usize),
+    /// The input spending a `tr()` descriptor has no `tap_internal_key`
+    MissingTapInternalKey(usize),
+    /// The input spending a `tr()` descriptor with script paths has no
+    /// `tap_scripts`
+    MissingTapScripts(usize),
+    /// The taproot input has no `tap_key_origins`
+    MissingTapKeyOrigins(usize),
+    /// The input is not finalized and the size of its witness cannot be
+    /// estimated, because its descriptor was not given or cannot be
+    /// satisfied. The fee rate is not checked.
+    UnknownWitnessSize(usize),
+    /// The outputs spend more than the inputs
+    NegativeFee {
+        /// Total amount of the inputs
+        inputs: u64,
+        /// Total amount of the outputs
+        outputs: u64,
+    },
+    /// The fee rate is outside of the accepted range
+    FeeRateOutOfRange {
+        /// The fee
+        fee: u64,
+        /// The virtual size the fee rate was computed with
+        vsize: usize,
+    },
+    /// A signature of the input does not use the sighash type of the input,
+    /// or the default one if it has none
+    SighashMismatch {
+        /// Index of the input
+        index: usize,
+        /// The sighash type the signature should use
+        required: PsbtSighashType,
+        /// The sighash type of the signature
+        got: PsbtSighashType,
+    },
+    /// The input is, or is to be, signed with a sighash type the policy
+    /// passed to [`check_sighash_policy`] does not allow
+    SighashNotAllowed {
+        /// Index of the input
+        index: usize,
+        /// The disallowed sighash type
+        sighash: PsbtSighashType,
+    },
+}
+
+/// The result of [`check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsbtReport {
+    /// The fee paid by the transaction, `None` if an input amount is unknown
+    /// or the outputs spend more than the inputs
+    pub fee: Option<u64>,
+    /// Virtual size of the transaction, including the final scriptSigs and
+    /// witnesses of the finalized inputs, and the maximum satisfaction
+    /// weight of the descriptors of the other inputs if known
+    pub vsize: usize,
+    /// Problems found, empty if the PSBT passed every check
+    pub issues: Vec<PsbtIssue>,
+}
+
+impl PsbtReport {
+    /// Whether the PSBT passed every check
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Checks that the inputs of the PSBT cover its outputs with a fee rate, in
+/// satoshis per virtual byte, within `fee_rate`, that every signature uses
+/// the sighash type of its input, and that each input has the fields needed
+/// to sign it.
+///
+/// `descriptors[i]` is the descriptor of the `i`th input, or `descriptors`
+/// is empty if they are not known. The size of the witnesses of the inputs
+/// which are not finalized is estimated with
+/// [`Descriptor::max_satisfaction_weight`], so that the fee rate is a lower
+/// bound. Without descriptors, the fee rate of a PSBT which is not finalized
+/// is not checked, and [`PsbtIssue::UnknownWitnessSize`] is reported for
+/// each input which is not. The descriptors also tell which taproot fields
+/// are needed: without them only `tap_key_origins` is checked.
+pub fn check<Pk: MiniscriptKey + ToPublicKey>(
+    psbt: &Psbt,
+    descriptors: &[Descriptor<Pk>],
+    fee_rate: RangeInclusive<u64>,
+) -> PsbtReport {
+    let mut issues = vec![];
+    let tx = &psbt.unsigned_tx;
+    if tx.input.len() != psbt.inputs.len() {
+        issues.push(PsbtIssue::WrongInputCount {
+            in_tx: tx.input.len(),
+            in_map: psbt.inputs.len(),
+        });
+    } else if !descriptors.is_empty() && descriptors.len() != psbt.inputs.len() {
+        issues.push(PsbtIssue::WrongDescriptorCount {
+            descriptors: descriptors.len(),
+            inputs: psbt.inputs.len(),
+        });
+    }
+    if !issues.is_empty() {
+        return PsbtReport {
+            fee: None,
+            vsize: (tx.weight() + 3) / 4,
+            issues,
+        };
+    }
+
+    let mut final_tx = tx.clone();
+    let mut in_amount = Some(0u64);
+    // Weight of the scriptSigs and witnesses of the inputs not finalized,
+    // `None` if it is unknown
+    let mut estimated_weight = Some(0);
+    let mut estimated_segwit = false;
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let descriptor = descriptors.get(index);
+        if let Some(ref script_sig) = input.final_script_sig {
+            final_tx.input[index].script_sig = script_sig.clone();
+        }
+        if let Some(ref witness) = input.final_script_witness {
+            final_tx.input[index].witness = witness.clone();
+        }
+
+        let vout = tx.input[index].previous_output.vout as usize;
+        let utxo = match (&input.witness_utxo, &input.non_witness_utxo) {
+            (Some(utxo), _) => Some(utxo),
+            (None, Some(prev_tx)) => prev_tx.output.get(vout),
+            (None, None) => None,
+        };
+        let utxo = match utxo {
+            Some(utxo) => utxo,
+            None => {
+                issues.push(PsbtIssue::MissingUtxo(index));
+                in_amount = None;
+                continue;
+            }
+        };
+        in_amount = in_amount.map(|amount| amount.saturating_add(utxo.value));
+
+        let spk = &utxo.script_pubkey;
+        let is_taproot = spk.is_v1_p2tr();
+        let is_finalized = input.final_script_sig.is_some() || input.final_script_witness.is_some();
+        if !is_finalized {
+            match descriptor.map(|desc| desc.max_satisfaction_weight()) {
+                Some(Ok(weight)) => {
+                    estimated_weight = estimated_weight.map(|w| w + weight);
+                    estimated_segwit |= descriptor
+                        .map(|desc| desc.desc_type().segwit_version().is_some())
+                        .unwrap_or(false);
+                }
+                _ => {
+                    issues.push(PsbtIssue::UnknownWitnessSize(index));
+                    estimated_weight = None;
+                }
+            }
+            if is_taproot {
+                if let Some(Descriptor::Tr(tr)) = descriptor {
+                    if input.tap_internal_key.is_none() {
+                        issues.push(PsbtIssue::MissingTapInternalKey(index));
+                    }
+                    if tr.iter_scripts().next().is_some() && input.tap_scripts.is_empty() {
+                        issues.push(PsbtIssue::MissingTapScripts(index));
+                    }
+                }
+                if input.tap_key_origins.is_empty() {
+                    issues.push(PsbtIssue::MissingTapKeyOrigins(index));
+                }
+            }
+
+            let inner = if spk.is_p2sh() {
+                if input.redeem_script.is_none() {
+                    issues.push(PsbtIssue::MissingRedeemScript(index));
+                }
+                input.redeem_script.as_ref()
+            } else {
+                Some(spk)
+            };
+            if let Some(inner) = inner {
+                if inner.is_witness_program() && input.witness_utxo.is_none() {
+                    issues.push(PsbtIssue::MissingWitnessUtxo(index));
+                }
+                if inner.is_v0_p2wsh() && input.witness_script.is_none() {
+                    issues.push(PsbtIssue::MissingWitnessScript(index));
+                }
+            }
+        }
+
+        let required = required_sighash(input, is_taproot);
+        for got in sig_sighashes(input) {
+            if got != required {
+                issues.push(PsbtIssue::SighashMismatch {
+                    index,
+                    required,
+                    got,
+                });
+            }
+        }
+    }
+
+    // The estimated witnesses add the segwit marker and flag if the final
+    // ones do not
+    let has_witness = final_tx.input.iter().any(|txin| !txin.witness.is_empty());
+    let weight = match estimated_weight {
+        Some(w) if estimated_segwit && !has_witness => final_tx.weight() + w + 2,
+        Some(w) => final_tx.weight() + w,
+        None => final_tx.weight(),
+    };
+    let vsize = (weight + 3) / 4;
+    let out_amount = tx
+        .output
+        .iter()
+        .fold(0u64, |amount, txout| amount.saturating_add(txout.value));
+    let fee = match in_amount {
+        Some(inputs) if inputs < out_amount => {
+            issues.push(PsbtIssue::NegativeFee {
+                inputs,
+                outputs: out_amount,
+            });
+            None
+        }
+        Some(inputs) => Some(inputs - out_amount),
+        None => None,
+    };
+    if let (Some(fee), Some(_)) = (fee, estimated_weight) {
+        let vsize = vsize as u64;
+        let in_range = fee >= fee_rate.start().saturating_mul(vsize)
+            && fee <= fee_rate.end().saturating_mul(vsize);
+        if !in_range {
+            issues.push(PsbtIssue::FeeRateOutOfRange {
+                fee,
+                vsize: vsize as usize,
+            });
+        }
+    }
+
+    PsbtReport { fee, vsize, issues }
+}
+
+/// Checks the sighash type of every signature of the PSBT, and the one each
+/// input is to be signed with, against the `allowed` ones.
+///
+/// For instance a wallet only allowing `SIGHASH_ALL` and `SIGHASH_DEFAULT`
+/// detects inputs with `ANYONECANPAY` signatures, which let anyone add inputs
+/// and outputs to the transaction, or `NONE` and `SINGLE` ones, which let
+/// anyone change the outputs. Each disallowed type is reported once per input.
+pub fn check_sighash_policy(psbt: &Psbt, allowed: &[PsbtSighashType]) -> Vec<PsbtIssue> {
+    let mut issues = vec![];
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let is_taproot = match input.witness_utxo {
+            Some(ref utxo) => utxo.script_pubkey.is_v1_p2tr(),
+            None => {
+                input.tap_internal_key.is_some()
+                    || input.tap_key_sig.is_some()
+                    || !input.tap_script_sigs.is_empty()
+            }
+        };
+        let mut seen = vec![];
+        let sighashes = Some(required_sighash(input, is_taproot))
+            .into_iter()
+            .chain(sig_sighashes(input));
+        for sighash in sighashes {
+            if !allowed.contains(&sighash) && !seen.contains(&sighash) {
+                seen.push(sighash);
+                issues.push(PsbtIssue::SighashNotAllowed { index, sighash });
+            }
+        }
+    }
+    issues
+}
+
+// The sighash type signatures of the input should use
+fn required_sighash(input: &psbt::Input, is_taproot: bool) -> PsbtSighashType {
+    match input.sighash_type {
+        Some(ty) => ty,
+        None if is_taproot => PsbtSighashType::from(SchnorrSighashType::Default),
+        None => PsbtSighashType::from(EcdsaSighashType::All),
+    }
+}
+
+// The sighash types of the signatures of the input
+fn sig_sighashes(input: &psbt::Input) -> impl Iterator<Item = PsbtSighashType> + '_ {
+    let ecdsa_tys = input
+        .partial_sigs
+        .values()
+        .map(|sig| PsbtSighashType::from(sig.hash_ty));
+    let schnorr_tys = input
+        .tap_key_sig
+        .iter()
+        .chain(input.tap_script_sigs.values())
+        .map(|sig| PsbtSighashType::from(sig.hash_ty));
+    ecdsa_tys.chain(schnorr_tys)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Witness, XOnlyPublicKey};
+
+    use super::*;
+    use crate::descriptor::{DerivationIndex, DescriptorPublicKey, TapTree};
+    use crate::psbt::update_input_at_index;
+    use crate::sync::Arc;
+    use crate::{Miniscript, Tap, Terminal};
+
+    fn psbt(spks: &[Script], amounts: &[u64], out_amount: u64) -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    previous_output: OutPoint::default(),
+                    ..Default::default()
+                };
+                spks.len()
+            ],
+            output: vec![TxOut {
+                value: out_amount,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (input, (spk, &value)) in psbt.inputs.iter_mut().zip(spks.iter().zip(amounts)) {
+            input.witness_utxo = Some(TxOut {
+                value,
+                script_pubkey: spk.clone(),
+            });
+        }
+        psbt
+    }
+
+    #[test]
+    fn check_psbt() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk =
+            bitcoin::PublicKey::new(bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let wpkh = Descriptor::new_wpkh(pk).unwrap();
+        let wsh = Descriptor::new_wsh(Miniscript::from_ast(Terminal::True).unwrap()).unwrap();
+
+        // 2 000 sats of fee for the 60 vbytes of the unsigned transaction and
+        // the 28 vbytes of the witness of the input, with the segwit marker
+        let mut psbt = psbt(&[wpkh.script_pubkey()], &[12_000], 10_000);
+        let report = check(&psbt, core::slice::from_ref(&wpkh), 1..=100);
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.fee, Some(2_000));
+        assert_eq!(report.vsize, 89);
+        assert_eq!(
+            check(&psbt, core::slice::from_ref(&wpkh), 1..=10).issues,
+            vec![PsbtIssue::FeeRateOutOfRange {
+                fee: 2_000,
+                vsize: 89
+            }]
+        );
+        // Without the descriptor, the fee rate cannot be checked
+        let report = check::<bitcoin::PublicKey>(&psbt, &[], 1..=10);
+        assert_eq!(report.fee, Some(2_000));
+        assert_eq!(report.issues, vec![PsbtIssue::UnknownWitnessSize(0)]);
+        // unless the input is finalized
+        psbt.inputs[0].final_script_witness = Some(Witness::from_vec(vec![vec![0; 72]; 2]));
+        let report = check::<bitcoin::PublicKey>(&psbt, &[], 1..=100);
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.vsize, 98);
+        assert_eq!(
+            check::<bitcoin::PublicKey>(&psbt, &[wpkh.clone(), wpkh.clone()], 1..=100).issues,
+            vec![PsbtIssue::WrongDescriptorCount {
+                descriptors: 2,
+                inputs: 1
+            }]
+        );
+
+        psbt.unsigned_tx.output[0].value = 13_000;
+        assert_eq!(
+            check::<bitcoin::PublicKey>(&psbt, &[], 1..=100).issues,
+            vec![PsbtIssue::NegativeFee {
+                inputs: 12_000,
+                outputs: 13_000
+            }]
+        );
+
+        let descriptors = [wpkh.clone(), wsh.clone()];
+        let mut psbt = self::psbt(
+            &[wpkh.script_pubkey(), wsh.script_pubkey()],
+            &[6_000, 6_000],
+            10_000,
+        );
+        let msg = Message::from_slice(&[2; 32]).unwrap();
+        let sig = bitcoin::EcdsaSig {
+            sig: secp.sign_ecdsa(&msg, &sk),
+            hash_ty: EcdsaSighashType::None,
+        };
+        psbt.inputs[0].partial_sigs.insert(pk, sig);
+        psbt.inputs[1].witness_utxo = None;
+        let report = check(&psbt, &descriptors, 1..=100);
+        assert_eq!(report.fee, None);
+        assert_eq!(
+            report.issues,
+            vec![
+                PsbtIssue::SighashMismatch {
+                    index: 0,
+                    required: PsbtSighashType::from(EcdsaSighashType::All),
+                    got: PsbtSighashType::from(EcdsaSighashType::None),
+                },
+                PsbtIssue::MissingUtxo(1),
+            ]
+        );
+
+        psbt.inputs[0].sighash_type = Some(PsbtSighashType::from(EcdsaSighashType::None));
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 6_000,
+            script_pubkey: wsh.script_pubkey(),
+        });
+        assert_eq!(
+            check(&psbt, &descriptors, 1..=100).issues,
+            vec![PsbtIssue::MissingWitnessScript(1)]
+        );
+    }
+
+    #[test]
+    fn check_taproot_fields() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        let internal_key =
+            XOnlyPublicKey::from(bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let leaf = Miniscript::<_, Tap>::from_ast(Terminal::Check(Arc::new(
+            Miniscript::from_ast(Terminal::PkK(internal_key)).unwrap(),
+        )))
+        .unwrap();
+        let tr = Descriptor::new_tr(internal_key, Some(TapTree::Leaf(Arc::new(leaf)))).unwrap();
+        let descriptors = core::slice::from_ref(&tr);
+
+        let mut psbt = psbt(&[tr.script_pubkey()], &[12_000], 10_000);
+        assert_eq!(
+            check(&psbt, descriptors, 1..=100).issues,
+            vec![
+                PsbtIssue::MissingTapInternalKey(0),
+                PsbtIssue::MissingTapScripts(0),
+                PsbtIssue::MissingTapKeyOrigins(0),
+            ]
+        );
+        assert_eq!(
+            check::<XOnlyPublicKey>(&psbt, &[], 1..=100).issues,
+            vec![
+                PsbtIssue::UnknownWitnessSize(0),
+                PsbtIssue::MissingTapKeyOrigins(0),
+            ]
+        );
+
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(&tr.to_string()).unwrap();
+        update_input_at_index(&mut psbt.inputs[0], &desc, DerivationIndex::new(0).unwrap())
+            .unwrap();
+        let report = check(&psbt, descriptors, 1..=100);
+        assert!(report.is_ok(), "{:?}", report);
+    }
+
+    #[test]
+    fn sighash_policy() {
+        let secp = Secp256k1::new();
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pk =
+            bitcoin::PublicKey::new(bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &sk));
+        let wpkh = Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap());
+        let allowed = [
+            PsbtSighashType::from(EcdsaSighashType::All),
+            PsbtSighashType::from(SchnorrSighashType::Default),
+            PsbtSighashType::from(SchnorrSighashType::All),
+        ];
+
+        let mut psbt = psbt(&[wpkh.clone(), wpkh], &[6_000, 6_000], 10_000);
+        assert_eq!(check_sighash_policy(&psbt, &allowed), vec![]);
+
+        let acp = PsbtSighashType::from(EcdsaSighashType::AllPlusAnyoneCanPay);
+        let msg = Message::from_slice(&[2; 32]).unwrap();
+        let sig = bitcoin::EcdsaSig {
+            sig: secp.sign_ecdsa(&msg, &sk),
+            hash_ty: EcdsaSighashType::AllPlusAnyoneCanPay,
+        };
+        psbt.inputs[0].partial_sigs.insert(pk, sig);
+        psbt.inputs[1].sighash_type = Some(acp);
+        assert_eq!(
+            check_sighash_policy(&psbt, &allowed),
+            vec![
+                PsbtIssue::SighashNotAllowed {
+                    index: 0,
+                    sighash: acp
+                },
+                PsbtIssue::SighashNotAllowed {
+                    index: 1,
+                    sighash: acp
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,483 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Combiner
+//!
+//! Implements the Combiner role defined in BIP 174. Unlike
+//! [`Psbt::combine`], which picks one side when two PSBTs disagree, the
+//! disagreements are reported.
+//!
+
+use core::{cmp, fmt};
+#[cfg(feature = "std")]
+use std::error;
+
+use bitcoin::util::psbt::{self, PsbtSighashType};
+
+use super::Psbt;
+use crate::prelude::*;
+
+/// Two PSBTs holding different values for the same field, see [`combine`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// The global maps disagree on `field`
+    Global(&'static str),
+    /// The maps of the input at `index` disagree on `field`
+    Input {
+        /// Index of the input
+        index: usize,
+        /// Name of the field in [`psbt::Input`]
+        field: &'static str,
+    },
+    /// The maps of the output at `index` disagree on `field`
+    Output {
+        /// Index of the output
+        index: usize,
+        /// Name of the field in [`psbt::Output`]
+        field: &'static str,
+    },
+    /// Two signatures by the same key, for the input at `index`, use
+    /// different sighash types
+    SighashType {
+        /// Index of the input
+        index: usize,
+        /// Name of the signature field in [`psbt::Input`]
+        field: &'static str,
+        /// The sighash type of the signature in the first PSBT having one
+        first: PsbtSighashType,
+        /// The sighash type of the conflicting signature
+        second: PsbtSighashType,
+    },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Conflict::Global(field) => write!(f, "conflicting global {}", field),
+            Conflict::Input { index, field } => {
+                write!(f, "conflicting {} for input {}", field, index)
+            }
+            Conflict::Output { index, field } => {
+                write!(f, "conflicting {} for output {}", field, index)
+            }
+            Conflict::SighashType {
+                index,
+                field,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} of input {} signed with both {} and {}",
+                field, index, first, second
+            ),
+        }
+    }
+}
+
+/// Error combining PSBTs, see [`combine`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum CombineError {
+    /// No PSBT was given
+    Empty,
+    /// The PSBT at the given position is for a different transaction than
+    /// the first one, or has a different number of input or output maps
+    DifferentTransaction(usize),
+    /// The PSBTs disagree on some fields
+    Conflicts(Vec<Conflict>),
+}
+
+impl fmt::Display for CombineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CombineError::Empty => f.write_str("no PSBT to combine"),
+            CombineError::DifferentTransaction(pos) => {
+                write!(f, "PSBT {} is for a different transaction", pos)
+            }
+            CombineError::Conflicts(ref conflicts) => {
+                f.write_str("PSBTs conflict:")?;
+                for conflict in conflicts {
+                    write!(f, " {};", conflict)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for CombineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+/// Merges PSBTs of the same transaction into one holding the fields of all
+/// of them.
+///
+/// Fields set in several PSBTs must have the same value, except for
+/// signatures: two signatures by the same key are only a conflict if their
+/// sighash types differ, and the first one is kept otherwise. If a
+/// `witness_utxo` and a `non_witness_utxo` are present, they must describe
+/// the same output. The highest PSBT version is kept.
+///
+/// Every conflict is reported, none of the PSBTs is preferred.
+pub fn combine(psbts: Vec<Psbt>) -> Result<Psbt, CombineError> {
+    let mut psbts = psbts.into_iter();
+    let mut ret = psbts.next().ok_or(CombineError::Empty)?;
+    let mut conflicts = vec![];
+    for (pos, psbt) in psbts.enumerate() {
+        if psbt.unsigned_tx != ret.unsigned_tx
+            || psbt.inputs.len() != ret.inputs.len()
+            || psbt.outputs.len() != ret.outputs.len()
+        {
+            return Err(CombineError::DifferentTransaction(pos + 1));
+        }
+        ret.version = cmp::max(ret.version, psbt.version);
+        for (field, conflict) in vec![
+            ("xpub", merge_map(&mut ret.xpub, &psbt.xpub)),
+            (
+                "proprietary",
+                merge_map(&mut ret.proprietary, &psbt.proprietary),
+            ),
+            ("unknown", merge_map(&mut ret.unknown, &psbt.unknown)),
+        ] {
+            if conflict {
+                conflicts.push(Conflict::Global(field));
+            }
+        }
+        for (index, (ours, theirs)) in ret.inputs.iter_mut().zip(&psbt.inputs).enumerate() {
+            combine_input(index, ours, theirs, &mut conflicts);
+        }
+        for (index, (ours, theirs)) in ret.outputs.iter_mut().zip(&psbt.outputs).enumerate() {
+            combine_output(index, ours, theirs, &mut conflicts);
+        }
+    }
+
+    for (index, input) in ret.inputs.iter().enumerate() {
+        let vout = ret.unsigned_tx.input[index].previous_output.vout as usize;
+        if let (Some(utxo), Some(prev_tx)) = (&input.witness_utxo, &input.non_witness_utxo) {
+            let conflict = Conflict::Input {
+                index,
+                field: "witness_utxo",
+            };
+            if prev_tx.output.get(vout) != Some(utxo) && !conflicts.contains(&conflict) {
+                conflicts.push(conflict);
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(ret)
+    } else {
+        conflicts.dedup();
+        Err(CombineError::Conflicts(conflicts))
+    }
+}
+
+fn combine_input(
+    index: usize,
+    ours: &mut psbt::Input,
+    theirs: &psbt::Input,
+    conflicts: &mut Vec<Conflict>,
+) {
+    for (field, conflict) in vec![
+        (
+            "non_witness_utxo",
+            merge_opt(&mut ours.non_witness_utxo, &theirs.non_witness_utxo),
+        ),
+        (
+            "witness_utxo",
+            merge_opt(&mut ours.witness_utxo, &theirs.witness_utxo),
+        ),
+        (
+            "sighash_type",
+            merge_opt(&mut ours.sighash_type, &theirs.sighash_type),
+        ),
+        (
+            "redeem_script",
+            merge_opt(&mut ours.redeem_script, &theirs.redeem_script),
+        ),
+        (
+            "witness_script",
+            merge_opt(&mut ours.witness_script, &theirs.witness_script),
+        ),
+        (
+            "bip32_derivation",
+            merge_map(&mut ours.bip32_derivation, &theirs.bip32_derivation),
+        ),
+        (
+            "final_script_sig",
+            merge_opt(&mut ours.final_script_sig, &theirs.final_script_sig),
+        ),
+        (
+            "final_script_witness",
+            merge_opt(&mut ours.final_script_witness, &theirs.final_script_witness),
+        ),
+        (
+            "ripemd160_preimages",
+            merge_map(&mut ours.ripemd160_preimages, &theirs.ripemd160_preimages),
+        ),
+        (
+            "sha256_preimages",
+            merge_map(&mut ours.sha256_preimages, &theirs.sha256_preimages),
+        ),
+        (
+            "hash160_preimages",
+            merge_map(&mut ours.hash160_preimages, &theirs.hash160_preimages),
+        ),
+        (
+            "hash256_preimages",
+            merge_map(&mut ours.hash256_preimages, &theirs.hash256_preimages),
+        ),
+        (
+            "tap_scripts",
+            merge_map(&mut ours.tap_scripts, &theirs.tap_scripts),
+        ),
+        (
+            "tap_key_origins",
+            merge_map(&mut ours.tap_key_origins, &theirs.tap_key_origins),
+        ),
+        (
+            "tap_internal_key",
+            merge_opt(&mut ours.tap_internal_key, &theirs.tap_internal_key),
+        ),
+        (
+            "tap_merkle_root",
+            merge_opt(&mut ours.tap_merkle_root, &theirs.tap_merkle_root),
+        ),
+        (
+            "proprietary",
+            merge_map(&mut ours.proprietary, &theirs.proprietary),
+        ),
+        ("unknown", merge_map(&mut ours.unknown, &theirs.unknown)),
+    ] {
+        if conflict {
+            conflicts.push(Conflict::Input { index, field });
+        }
+    }
+
+    let mut sighash_conflict = |field, (first, second)| {
+        conflicts.push(Conflict::SighashType {
+            index,
+            field,
+            first,
+            second,
+        })
+    };
+    for types in merge_sigs(&mut ours.partial_sigs, &theirs.partial_sigs, |sig| {
+        PsbtSighashType::from(sig.hash_ty)
+    }) {
+        sighash_conflict("partial_sigs", types);
+    }
+    for types in merge_sigs(&mut ours.tap_script_sigs, &theirs.tap_script_sigs, |sig| {
+        PsbtSighashType::from(sig.hash_ty)
+    }) {
+        sighash_conflict("tap_script_sigs", types);
+    }
+    match (&ours.tap_key_sig, &theirs.tap_key_sig) {
+        (Some(first), Some(second)) if first.hash_ty != second.hash_ty => sighash_conflict(
+            "tap_key_sig",
+            (
+                PsbtSighashType::from(first.hash_ty),
+                PsbtSighashType::from(second.hash_ty),
+            ),
+        ),
+        (None, Some(_)) => ours.tap_key_sig = theirs.tap_key_sig,
+        _ => {}
+    }
+}
+
+fn combine_output(
+    index: usize,
+    ours: &mut psbt::Output,
+    theirs: &psbt::Output,
+    conflicts: &mut Vec<Conflict>,
+) {
+    for (field, conflict) in vec![
+        (
+            "redeem_script",
+            merge_opt(&mut ours.redeem_script, &theirs.redeem_script),
+        ),
+        (
+            "witness_script",
+            merge_opt(&mut ours.witness_script, &theirs.witness_script),
+        ),
+        (
+            "bip32_derivation",
+            merge_map(&mut ours.bip32_derivation, &theirs.bip32_derivation),
+        ),
+        (
+            "tap_internal_key",
+            merge_opt(&mut ours.tap_internal_key, &theirs.tap_internal_key),
+        ),
+        ("tap_tree", merge_opt(&mut ours.tap_tree, &theirs.tap_tree)),
+        (
+            "tap_key_origins",
+            merge_map(&mut ours.tap_key_origins, &theirs.tap_key_origins),
+        ),
+        (
+            "proprietary",
+            merge_map(&mut ours.proprietary, &theirs.proprietary),
+        ),
+        ("unknown", merge_map(&mut ours.unknown, &theirs.unknown)),
+    ] {
+        if conflict {
+            conflicts.push(Conflict::Output { index, field });
+        }
+    }
+}
+
+// Sets `ours` to `theirs` if unset, returning whether both are set to
+// different values
+fn merge_opt<T: Clone + PartialEq>(ours: &mut Option<T>, theirs: &Option<T>) -> bool {
+    match (ours.as_ref(), theirs) {
+        (Some(ours), Some(theirs)) => ours != theirs,
+        (None, Some(_)) => {
+            *ours = theirs.clone();
+            false
+        }
+        (_, None) => false,
+    }
+}
+
+// Adds the entries of `theirs` missing from `ours`, returning whether a key
+// is present in both with different values
+fn merge_map<K: Ord + Clone, V: Clone + PartialEq>(
+    ours: &mut BTreeMap<K, V>,
+    theirs: &BTreeMap<K, V>,
+) -> bool {
+    let mut conflict = false;
+    for (key, value) in theirs {
+        match ours.get(key) {
+            Some(ours) => conflict |= ours != value,
+            None => {
+                ours.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    conflict
+}
+
+// Adds the signatures of `theirs` by keys without one in `ours`, returning
+// the sighash types of the signatures by the same key with different ones
+fn merge_sigs<K: Ord + Clone, S: Clone>(
+    ours: &mut BTreeMap<K, S>,
+    theirs: &BTreeMap<K, S>,
+    sighash_type: impl Fn(&S) -> PsbtSighashType,
+) -> Vec<(PsbtSighashType, PsbtSighashType)> {
+    let mut conflicts = vec![];
+    for (key, sig) in theirs {
+        match ours.get(key) {
+            Some(ours) if sighash_type(ours) != sighash_type(sig) => {
+                conflicts.push((sighash_type(ours), sighash_type(sig)))
+            }
+            Some(_) => {}
+            None => {
+                ours.insert(key.clone(), sig.clone());
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{EcdsaSig, EcdsaSighashType, OutPoint, Script, Transaction, TxIn, TxOut};
+
+    use super::*;
+
+    fn psbt() -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn combine_psbts() {
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..3u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pk =
+            |sk| bitcoin::PublicKey::new(bitcoin::secp256k1::PublicKey::from_secret_key(&secp, sk));
+        let sig = |sk, hash_ty| EcdsaSig {
+            sig: secp.sign_ecdsa(&Message::from_slice(&[3; 32]).unwrap(), sk),
+            hash_ty,
+        };
+        let utxo = TxOut {
+            value: 10_000,
+            script_pubkey: Script::new(),
+        };
+
+        let mut a = psbt();
+        a.inputs[0].witness_utxo = Some(utxo.clone());
+        a.inputs[0]
+            .partial_sigs
+            .insert(pk(&sks[0]), sig(&sks[0], EcdsaSighashType::All));
+        let mut b = psbt();
+        b.inputs[0]
+            .partial_sigs
+            .insert(pk(&sks[1]), sig(&sks[1], EcdsaSighashType::All));
+        b.outputs[0].witness_script = Some(Script::new());
+
+        let combined = combine(vec![a.clone(), b.clone()]).unwrap();
+        assert_eq!(combined, combine(vec![b.clone(), a.clone()]).unwrap());
+        assert_eq!(combined.inputs[0].partial_sigs.len(), 2);
+        assert_eq!(combined.inputs[0].witness_utxo, Some(utxo));
+        assert_eq!(combined.outputs[0].witness_script, Some(Script::new()));
+        assert_eq!(combine(vec![]), Err(CombineError::Empty));
+
+        let mut c = psbt();
+        c.unsigned_tx.lock_time = 1;
+        assert_eq!(
+            combine(vec![a.clone(), b.clone(), c]),
+            Err(CombineError::DifferentTransaction(2))
+        );
+
+        let mut c = psbt();
+        c.inputs[0].witness_utxo = Some(TxOut {
+            value: 20_000,
+            script_pubkey: Script::new(),
+        });
+        c.inputs[0]
+            .partial_sigs
+            .insert(pk(&sks[0]), sig(&sks[0], EcdsaSighashType::Single));
+        assert_eq!(
+            combine(vec![a, b, c]),
+            Err(CombineError::Conflicts(vec![
+                Conflict::Input {
+                    index: 0,
+                    field: "witness_utxo"
+                },
+                Conflict::SighashType {
+                    index: 0,
+                    field: "partial_sigs",
+                    first: PsbtSighashType::from(EcdsaSighashType::All),
+                    second: PsbtSighashType::from(EcdsaSighashType::Single),
+                },
+            ]))
+        );
+    }
+}
@@ -22,11 +22,12 @@
 use bitcoin::blockdata::witness::Witness;
 use bitcoin::secp256k1::{self, Secp256k1};
 use bitcoin::util::key::XOnlyPublicKey;
-use bitcoin::util::sighash::Prevouts;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
 use bitcoin::util::taproot::LeafVersion;
 use bitcoin::{self, PublicKey, Script, TxOut};
 
 use super::{sanity_check, Error, InputError, Psbt, PsbtInputSatisfier};
+use crate::miniscript::satisfy::SatisfactionError;
 use crate::prelude::*;
 use crate::util::witness_size;
 use crate::{interpreter, BareCtx, Descriptor, Legacy, Miniscript, Satisfier, Segwitv0, Tap};
@@ -50,6 +51,8 @@ fn construct_tap_witness(
     }
     // Next script spends
     let (mut min_wit, mut min_wit_len) = (None, None);
+    // The error of a leaf which could only be satisfied malleably
+    let mut malleable_err = None;
     if let Some(block_map) =
         <PsbtInputSatisfier as Satisfier<XOnlyPublicKey>>::lookup_tap_control_block_map(sat)
     {
@@ -70,7 +73,19 @@ fn construct_tap_witness(
             } else {
                 match ms.satisfy(sat) {
                     Ok(ms) => ms,
-                    Err(..) => continue,
+                    Err(e) => {
+                        let malleable = match e {
+                            crate::Error::Unsatisfiable(ref errs) => match errs.first() {
+                                Some(&SatisfactionError::MalleableOnly { .. }) => true,
+                                _ => false,
+                            },
+                            _ => false,
+                        };
+                        if malleable {
+                            malleable_err = Some(e);
+                        }
+                        continue;
+                    }
                 }
             };
             wit.push(ms.encode().into_bytes());
@@ -84,7 +99,11 @@ fn construct_tap_witness(
                 min_wit_len = wit_len;
             }
         }
-        min_wit.ok_or(InputError::CouldNotSatisfyTr)
+        match (min_wit, malleable_err) {
+            (Some(wit), _) => Ok(wit),
+            (None, Some(e)) => Err(InputError::MiniscriptError(e)),
+            (None, None) => Err(InputError::CouldNotSatisfyTr),
+        }
     } else {
         // No control blocks found
         Err(InputError::CouldNotSatisfyTr)
@@ -262,6 +281,8 @@ pub fn interpreter_check<C: secp256k1::Verification>(
 ) -> Result<(), Error> {
     let utxos = prevouts(psbt)?;
     let utxos = &Prevouts::All(&utxos);
+    // The sighash data of the transaction is shared by all its inputs
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
     for (index, input) in psbt.inputs.iter().enumerate() {
         let empty_script_sig = Script::new();
         let empty_witness = Witness::default();
@@ -272,7 +293,7 @@ pub fn interpreter_check<C: secp256k1::Verification>(
             .map(|wit_slice| Witness::from_vec(wit_slice.to_vec())) // TODO: Update rust-bitcoin psbt API to use witness
             .unwrap_or(empty_witness);
 
-        interpreter_inp_check(psbt, secp, index, utxos, &witness, script_sig)?;
+        interpreter_inp_check(psbt, secp, &mut cache, index, utxos, &witness, script_sig)?;
     }
     Ok(())
 }
@@ -281,6 +302,7 @@ pub fn interpreter_check<C: secp256k1::Verification>(
 fn interpreter_inp_check<C: secp256k1::Verification, T: Borrow<TxOut>>(
     psbt: &Psbt,
     secp: &Secp256k1<C>,
+    cache: &mut SighashCache<&bitcoin::Transaction>,
     index: usize,
     utxos: &Prevouts<T>,
     witness: &Witness,
@@ -297,7 +319,7 @@ fn interpreter_inp_check<C: secp256k1::Verification, T: Borrow<TxOut>>(
         let interpreter =
             interpreter::Interpreter::from_txdata(spk, script_sig, witness, csv, cltv)
                 .map_err(|e| Error::InputError(InputError::Interpreter(e), index))?;
-        let iter = interpreter.iter(secp, &psbt.unsigned_tx, index, utxos);
+        let iter = interpreter.iter_with_cache(secp, cache, index, utxos);
         if let Some(error) = iter.filter_map(Result::err).next() {
             return Err(Error::InputError(InputError::Interpreter(error), index));
         };
@@ -380,7 +402,8 @@ fn finalize_input_helper<C: secp256k1::Verification>(
     let witness = bitcoin::Witness::from_vec(witness);
     let utxos = prevouts(psbt)?;
     let utxos = &Prevouts::All(&utxos);
-    interpreter_inp_check(psbt, secp, index, utxos, &witness, &script_sig)?;
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    interpreter_inp_check(psbt, secp, &mut cache, index, utxos, &witness, &script_sig)?;
 
     Ok((witness, script_sig))
 }
@@ -19,31 +19,49 @@
 //! `https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki`
 //!
 
+use core::borrow::BorrowMut;
 use core::fmt;
 use core::ops::Deref;
 #[cfg(feature = "std")]
 use std::error;
 
-use bitcoin::hashes::{hash160, ripemd160, sha256d};
-use bitcoin::secp256k1::{self, Secp256k1, VerifyOnly};
+use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::address::WitnessVersion;
+use bitcoin::util::bip32;
 use bitcoin::util::psbt::{self, PartiallySignedTransaction as Psbt};
 use bitcoin::util::sighash::SighashCache;
-use bitcoin::util::taproot::{self, ControlBlock, LeafVersion, TapLeafHash};
+use bitcoin::util::taproot::{self, ControlBlock, LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX};
 use bitcoin::{self, EcdsaSighashType, SchnorrSighashType, Script};
 
+use crate::descriptor::{DerivationIndex, DerivationRange, DerivedDescriptorKey};
 use crate::miniscript::iter::PkPkh;
 use crate::miniscript::limits::SEQUENCE_LOCKTIME_DISABLE_FLAG;
 use crate::miniscript::satisfy::{After, Older};
+use crate::policy::{semantic, Liftable};
 use crate::prelude::*;
+use crate::util::{varint_len, with_secp};
 use crate::{
     descriptor, interpreter, Descriptor, DescriptorPublicKey, MiniscriptKey, PkTranslator,
-    Preimage32, Satisfier, ToPublicKey, TranslatePk,
+    Preimage32, PreimageProvider, Satisfier, ToPublicKey, TranslatePk,
 };
 
+mod check;
+mod combine;
 mod finalizer;
+mod roles;
+mod signer;
+mod status;
+mod v2;
 
+pub use self::check::{check, check_sighash_policy, PsbtIssue, PsbtReport};
+pub use self::combine::{combine, CombineError, Conflict};
 #[allow(deprecated)]
 pub use self::finalizer::{finalize, finalize_mall, interpreter_check};
+pub use self::roles::{Creator, Extractor, Finalizer, Signer, Updater};
+pub use self::signer::PsbtSigner;
+pub use self::status::{input_status, InputNeed};
+pub use self::v2::{InputV2, OutputV2, PsbtV2, PsbtV2Error};
 
 /// Error type for entire Psbt
 #[derive(Debug)]
@@ -86,7 +104,7 @@ impl fmt::Display for Error {
 
 #[cfg(feature = "std")]
 impl error::Error for Error {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::Error::*;
 
         match self {
@@ -162,7 +180,7 @@ pub enum InputError {
 
 #[cfg(feature = "std")]
 impl error::Error for InputError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::InputError::*;
 
         match self {
@@ -366,6 +384,7 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
             .hash160_preimages
             .get(&h)
             .and_then(try_vec_as_preimage32)
+            .filter(|p| hash160::Hash::hash(p) == h)
     }
 
     fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
@@ -373,6 +392,7 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
             .sha256_preimages
             .get(&Pk::to_sha256(h))
             .and_then(try_vec_as_preimage32)
+            .filter(|p| sha256::Hash::hash(p) == Pk::to_sha256(h))
     }
 
     fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
@@ -380,6 +400,7 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
             .hash256_preimages
             .get(&h)
             .and_then(try_vec_as_preimage32)
+            .filter(|p| sha256d::Hash::hash(p) == h)
     }
 
     fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
@@ -387,6 +408,7 @@ impl<'psbt, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for PsbtInputSatisfie
             .ripemd160_preimages
             .get(&h)
             .and_then(try_vec_as_preimage32)
+            .filter(|p| ripemd160::Hash::hash(p) == h)
     }
 }
 
@@ -571,6 +593,45 @@ pub trait PsbtExt {
         descriptor: &Descriptor<DescriptorPublicKey>,
     ) -> Result<(), UtxoUpdateError>;
 
+    /// Update PSBT output with a descriptor and check that the output's `script_pubkey` matches
+    /// it.
+    ///
+    /// This is the checked version of [`PsbtOutputExt::update_with_descriptor_unchecked`]. The
+    /// `descriptor` **must not have any wildcards** in it.
+    fn update_output_with_descriptor(
+        &mut self,
+        output_index: usize,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), OutputUpdateError>
+    where
+        Self: BorrowMut<Psbt>,
+    {
+        let psbt = self.borrow_mut();
+        let n_outputs = psbt.outputs.len();
+        let output = psbt
+            .outputs
+            .get_mut(output_index)
+            .ok_or(OutputUpdateError::IndexOutOfBounds(output_index, n_outputs))?;
+        let txout = psbt
+            .unsigned_tx
+            .output
+            .get(output_index)
+            .ok_or(OutputUpdateError::MissingTxOut)?;
+
+        let (_, spk_check_passed) = update_item_with_descriptor_helper(
+            output,
+            descriptor,
+            Some(txout.script_pubkey.clone()),
+        )
+        .map_err(OutputUpdateError::DerivationError)?;
+
+        if !spk_check_passed {
+            return Err(OutputUpdateError::MismatchedScriptPubkey);
+        }
+
+        Ok(())
+    }
+
     /// Get the sighash message(data to sign) at input index `idx` based on the sighash
     /// flag specified in the [`Psbt`] sighash field. If the input sighash flag psbt field is `None`
     /// the [`SchnorrSighashType::Default`](bitcoin::util::sighash::SchnorrSighashType::Default) is chosen
@@ -794,7 +855,7 @@ impl PsbtExt for Psbt {
         };
 
         let (_, spk_check_passed) =
-            update_input_with_descriptor_helper(input, desc, Some(expected_spk))
+            update_item_with_descriptor_helper(input, desc, Some(expected_spk))
                 .map_err(UtxoUpdateError::DerivationError)?;
 
         if !spk_check_passed {
@@ -925,17 +986,252 @@ impl PsbtInputExt for psbt::Input {
         &mut self,
         descriptor: &Descriptor<DescriptorPublicKey>,
     ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError> {
-        let (derived, _) = update_input_with_descriptor_helper(self, descriptor, None)?;
+        let (derived, _) = update_item_with_descriptor_helper(self, descriptor, None)?;
+        Ok(derived)
+    }
+}
+
+/// Extension trait for PSBT outputs
+pub trait PsbtOutputExt {
+    /// Given the descriptor of an output populate the PSBT output's fields, so signers can
+    /// recognize it, e.g. as change.
+    ///
+    /// This fills `redeem_script`, `witness_script` and `bip32_derivation` for pre-taproot
+    /// descriptors, and `tap_internal_key`, `tap_tree` and `tap_key_origins` for taproot ones.
+    /// `tap_tree` is left empty if the descriptor has opaque leaves.
+    ///
+    /// If the descriptor contains wildcards or otherwise cannot be transformed into a concrete
+    /// descriptor an error will be returned. Note that this method doesn't check that the
+    /// `script_pubkey` of the output matches the descriptor, see [`update_output_with_descriptor`].
+    ///
+    /// [`update_output_with_descriptor`]: PsbtExt::update_output_with_descriptor
+    fn update_with_descriptor_unchecked(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError>;
+}
+
+impl PsbtOutputExt for psbt::Output {
+    fn update_with_descriptor_unchecked(
+        &mut self,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, descriptor::ConversionError> {
+        let (derived, _) = update_item_with_descriptor_helper(self, descriptor, None)?;
         Ok(derived)
     }
 }
 
+/// Extract the final transaction, like [`PsbtExt::extract`], and check that the weight of the
+/// scriptSig and witness of each input does not exceed the
+/// [`Descriptor::max_satisfaction_weight`] of the descriptor it spends, `descriptors[i]` being
+/// the descriptor of the `i`th input.
+///
+/// For taproot inputs with an annex, the bound is the one of
+/// [`Descriptor::max_satisfaction_weight_with_annex`].
+pub fn extract_with_check<C: secp256k1::Verification, Pk: MiniscriptKey + ToPublicKey>(
+    psbt: &Psbt,
+    secp: &Secp256k1<C>,
+    descriptors: &[Descriptor<Pk>],
+) -> Result<bitcoin::Transaction, ExtractError> {
+    if descriptors.len() != psbt.inputs.len() {
+        return Err(ExtractError::WrongDescriptorCount {
+            descriptors: descriptors.len(),
+            inputs: psbt.inputs.len(),
+        });
+    }
+    let tx = psbt.extract(secp).map_err(ExtractError::Psbt)?;
+    for (index, (txin, desc)) in tx.input.iter().zip(descriptors).enumerate() {
+        let witness = txin.witness.to_vec();
+        let annex = match (desc.desc_type().segwit_version(), witness.last()) {
+            (Some(WitnessVersion::V1), Some(last))
+                if witness.len() > 1 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) =>
+            {
+                Some(last.len())
+            }
+            _ => None,
+        };
+        let predicted = match annex {
+            Some(annex_len) => desc.max_satisfaction_weight_with_annex(annex_len),
+            None => desc.max_satisfaction_weight(),
+        }
+        .map_err(|e| ExtractError::Descriptor(index, e))?;
+
+        let script_sig_len = txin.script_sig.len();
+        let mut actual = 4 * (varint_len(script_sig_len) + script_sig_len);
+        if !witness.is_empty() {
+            actual += varint_len(witness.len())
+                + witness
+                    .iter()
+                    .map(|elem| varint_len(elem.len()) + elem.len())
+                    .sum::<usize>();
+        }
+        if actual > predicted {
+            return Err(ExtractError::WeightExceeded {
+                index,
+                predicted,
+                actual,
+            });
+        }
+    }
+    Ok(tx)
+}
+
+/// Populate the fields of a PSBT input from the descriptor of the utxo it spends, derived at
+/// `index`.
+///
+/// This fills `redeem_script`, `witness_script` and `bip32_derivation` for pre-taproot
+/// descriptors, and `tap_internal_key`, `tap_merkle_root`, `tap_scripts` and `tap_key_origins`
+/// for taproot ones. If `witness_utxo` is already present its `script_pubkey` must match the
+/// derived descriptor, otherwise nothing is updated. If it is missing for a segwit descriptor, it
+/// is taken from the single output of `non_witness_utxo` paying to the derived descriptor, if any.
+///
+/// Returns the derived descriptor.
+pub fn update_input_at_index(
+    input: &mut psbt::Input,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    index: DerivationIndex,
+) -> Result<Descriptor<bitcoin::PublicKey>, UtxoUpdateError> {
+    struct Underive;
+
+    impl PkTranslator<DerivedDescriptorKey, DescriptorPublicKey, ()> for Underive {
+        fn pk(&mut self, pk: &DerivedDescriptorKey) -> Result<DescriptorPublicKey, ()> {
+            Ok(pk.as_key().clone())
+        }
+
+        fn pkh(&mut self, pkh: &DerivedDescriptorKey) -> Result<DescriptorPublicKey, ()> {
+            Ok(pkh.as_key().clone())
+        }
+    }
+
+    let descriptor = descriptor
+        .derive(index)
+        .translate_pk(&mut Underive)
+        .expect("Unwrapping a derived key cannot fail");
+    let check_script = input
+        .witness_utxo
+        .as_ref()
+        .map(|utxo| utxo.script_pubkey.clone());
+    let (derived, spk_matches) =
+        update_item_with_descriptor_helper(input, &descriptor, check_script)
+            .map_err(UtxoUpdateError::DerivationError)?;
+    if !spk_matches {
+        return Err(UtxoUpdateError::MismatchedScriptPubkey);
+    }
+
+    if input.witness_utxo.is_none() && derived.desc_type().segwit_version().is_some() {
+        if let Some(non_witness_utxo) = &input.non_witness_utxo {
+            let spk = derived.script_pubkey();
+            let mut outputs = non_witness_utxo
+                .output
+                .iter()
+                .filter(|txout| txout.script_pubkey == spk);
+            if let (Some(txout), None) = (outputs.next(), outputs.next()) {
+                input.witness_utxo = Some(txout.clone());
+            }
+        }
+    }
+    Ok(derived)
+}
+
+/// Write the preimages of the hashes of `descriptor` known by `provider` to the BIP 174 preimage
+/// fields of a PSBT input, from which the finalizer reads them.
+///
+/// Preimages which do not hash to the expected value are skipped. Returns the number of
+/// preimages written.
+pub fn update_input_preimages<Pk, P>(
+    input: &mut psbt::Input,
+    descriptor: &Descriptor<Pk>,
+    provider: &P,
+) -> Result<usize, crate::Error>
+where
+    Pk: MiniscriptKey + ToPublicKey,
+    P: PreimageProvider<Pk>,
+{
+    fn collect_hashes<'a, Pk: MiniscriptKey>(
+        policy: &'a semantic::Policy<Pk>,
+        hashes: &mut Vec<&'a semantic::Policy<Pk>>,
+    ) {
+        match *policy {
+            semantic::Policy::Sha256(..)
+            | semantic::Policy::Hash256(..)
+            | semantic::Policy::Ripemd160(..)
+            | semantic::Policy::Hash160(..) => hashes.push(policy),
+            semantic::Policy::Threshold(_, ref subs) => {
+                for sub in subs {
+                    collect_hashes(sub, hashes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let policy = descriptor.lift()?;
+    let mut hashes = vec![];
+    collect_hashes(&policy, &mut hashes);
+    let mut written = 0;
+    for hash in hashes {
+        let added = match *hash {
+            semantic::Policy::Sha256(ref h) => {
+                let hash = Pk::to_sha256(h);
+                provider
+                    .sha256_preimage(h)
+                    .filter(|p| sha256::Hash::hash(p) == hash)
+                    .map(|p| input.sha256_preimages.insert(hash, p.to_vec()))
+            }
+            semantic::Policy::Hash256(h) => provider
+                .hash256_preimage(h)
+                .filter(|p| sha256d::Hash::hash(p) == h)
+                .map(|p| input.hash256_preimages.insert(h, p.to_vec())),
+            semantic::Policy::Ripemd160(h) => provider
+                .ripemd160_preimage(h)
+                .filter(|p| ripemd160::Hash::hash(p) == h)
+                .map(|p| input.ripemd160_preimages.insert(h, p.to_vec())),
+            semantic::Policy::Hash160(h) => provider
+                .hash160_preimage(h)
+                .filter(|p| hash160::Hash::hash(p) == h)
+                .map(|p| input.hash160_preimages.insert(h, p.to_vec())),
+            _ => None,
+        };
+        // Only count the preimages which were not already present
+        if let Some(None) = added {
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Check that the output at `output_index` pays to the change `descriptor` derived at an index
+/// within `range`, returning that index.
+///
+/// Only the script pubkey of the unsigned transaction is checked, not the PSBT output map which
+/// another party could have filled with the key origins of our own wallet. A signer should
+/// call this before treating an output as change, otherwise a PSBT could make a payment to an
+/// attacker look like it goes back to the wallet.
+pub fn verify_change<C: secp256k1::Verification>(
+    psbt: &Psbt,
+    secp: &Secp256k1<C>,
+    output_index: usize,
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    range: DerivationRange,
+) -> Result<DerivationIndex, OutputUpdateError> {
+    let n_outputs = psbt.outputs.len();
+    if output_index >= n_outputs {
+        return Err(OutputUpdateError::IndexOutOfBounds(output_index, n_outputs));
+    }
+    let txout = psbt
+        .unsigned_tx
+        .output
+        .get(output_index)
+        .ok_or(OutputUpdateError::MissingTxOut)?;
+    descriptor
+        .find_derivation_index(secp, &txout.script_pubkey, range)
+        .map_err(OutputUpdateError::DerivationError)?
+        .ok_or(OutputUpdateError::MismatchedScriptPubkey)
+}
+
 // Traverse the pkh lookup while maintaining a reverse map for storing the map
 // hash160 -> (XonlyPublicKey)/PublicKey
-struct XOnlyHashLookUp(
-    pub BTreeMap<hash160::Hash, bitcoin::XOnlyPublicKey>,
-    pub secp256k1::Secp256k1<VerifyOnly>,
-);
+struct XOnlyHashLookUp(pub BTreeMap<hash160::Hash, bitcoin::XOnlyPublicKey>);
 
 impl PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::ConversionError>
     for XOnlyHashLookUp
@@ -944,14 +1240,14 @@ impl PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::Conversio
         &mut self,
         xpk: &DescriptorPublicKey,
     ) -> Result<bitcoin::PublicKey, descriptor::ConversionError> {
-        xpk.derive_public_key(&self.1)
+        with_secp(|secp| xpk.derive_public_key(secp))
     }
 
     fn pkh(
         &mut self,
         xpk: &DescriptorPublicKey,
     ) -> Result<hash160::Hash, descriptor::ConversionError> {
-        let pk = xpk.derive_public_key(&self.1)?;
+        let pk = with_secp(|secp| xpk.derive_public_key(secp))?;
         let xonly = pk.to_x_only_pubkey();
         let hash = xonly.to_pubkeyhash();
         self.0.insert(hash, xonly);
@@ -961,10 +1257,7 @@ impl PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::Conversio
 
 // Traverse the pkh lookup while maintaining a reverse map for storing the map
 // hash160 -> (XonlyPublicKey)/PublicKey
-struct KeySourceLookUp(
-    pub BTreeMap<secp256k1::PublicKey, bitcoin::util::bip32::KeySource>,
-    pub secp256k1::Secp256k1<VerifyOnly>,
-);
+struct KeySourceLookUp(pub BTreeMap<secp256k1::PublicKey, bitcoin::util::bip32::KeySource>);
 
 impl PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::ConversionError>
     for KeySourceLookUp
@@ -973,7 +1266,7 @@ impl PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::Conversio
         &mut self,
         xpk: &DescriptorPublicKey,
     ) -> Result<bitcoin::PublicKey, descriptor::ConversionError> {
-        let derived = xpk.derive_public_key(&self.1)?;
+        let derived = with_secp(|secp| xpk.derive_public_key(secp))?;
         self.0.insert(
             derived.to_public_key().inner,
             (xpk.master_fingerprint(), xpk.full_derivation_path()),
@@ -989,18 +1282,115 @@ impl PkTranslator<DescriptorPublicKey, bitcoin::PublicKey, descriptor::Conversio
     }
 }
 
-fn update_input_with_descriptor_helper(
-    input: &mut psbt::Input,
+// The fields of PSBT inputs and outputs filled from a descriptor
+trait PsbtFields {
+    fn redeem_script(&mut self) -> &mut Option<Script>;
+    fn witness_script(&mut self) -> &mut Option<Script>;
+    fn bip32_derivation(&mut self) -> &mut BTreeMap<secp256k1::PublicKey, bip32::KeySource>;
+    fn tap_internal_key(&mut self) -> &mut Option<bitcoin::XOnlyPublicKey>;
+    fn tap_key_origins(
+        &mut self,
+    ) -> &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)>;
+
+    // Input only fields
+    fn tap_merkle_root(&mut self) -> Option<&mut Option<taproot::TapBranchHash>> {
+        None
+    }
+    fn tap_scripts(&mut self) -> Option<&mut BTreeMap<ControlBlock, (Script, LeafVersion)>> {
+        None
+    }
+
+    // Output only fields
+    fn tap_tree(&mut self) -> Option<&mut Option<psbt::TapTree>> {
+        None
+    }
+}
+
+impl PsbtFields for psbt::Input {
+    fn redeem_script(&mut self) -> &mut Option<Script> {
+        &mut self.redeem_script
+    }
+    fn witness_script(&mut self) -> &mut Option<Script> {
+        &mut self.witness_script
+    }
+    fn bip32_derivation(&mut self) -> &mut BTreeMap<secp256k1::PublicKey, bip32::KeySource> {
+        &mut self.bip32_derivation
+    }
+    fn tap_internal_key(&mut self) -> &mut Option<bitcoin::XOnlyPublicKey> {
+        &mut self.tap_internal_key
+    }
+    fn tap_key_origins(
+        &mut self,
+    ) -> &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)> {
+        &mut self.tap_key_origins
+    }
+
+    fn tap_merkle_root(&mut self) -> Option<&mut Option<taproot::TapBranchHash>> {
+        Some(&mut self.tap_merkle_root)
+    }
+    fn tap_scripts(&mut self) -> Option<&mut BTreeMap<ControlBlock, (Script, LeafVersion)>> {
+        Some(&mut self.tap_scripts)
+    }
+}
+
+impl PsbtFields for psbt::Output {
+    fn redeem_script(&mut self) -> &mut Option<Script> {
+        &mut self.redeem_script
+    }
+    fn witness_script(&mut self) -> &mut Option<Script> {
+        &mut self.witness_script
+    }
+    fn bip32_derivation(&mut self) -> &mut BTreeMap<secp256k1::PublicKey, bip32::KeySource> {
+        &mut self.bip32_derivation
+    }
+    fn tap_internal_key(&mut self) -> &mut Option<bitcoin::XOnlyPublicKey> {
+        &mut self.tap_internal_key
+    }
+    fn tap_key_origins(
+        &mut self,
+    ) -> &mut BTreeMap<bitcoin::XOnlyPublicKey, (Vec<TapLeafHash>, bip32::KeySource)> {
+        &mut self.tap_key_origins
+    }
+
+    fn tap_tree(&mut self) -> Option<&mut Option<psbt::TapTree>> {
+        Some(&mut self.tap_tree)
+    }
+}
+
+fn update_item_with_descriptor_helper<F: PsbtFields>(
+    item: &mut F,
     descriptor: &Descriptor<DescriptorPublicKey>,
     check_script: Option<Script>,
     // the return value is a tuple here since the two internal calls to it require different info.
     // One needs the derived descriptor and the other needs to know whether the script_pubkey check
     // failed.
 ) -> Result<(Descriptor<bitcoin::PublicKey>, bool), descriptor::ConversionError> {
-    let secp = secp256k1::Secp256k1::verification_only();
+    let derived = if let Descriptor::RawTr(rawtr_xpk) = &descriptor {
+        let output_key = rawtr_xpk.output_key();
+        let derived_key = with_secp(|secp| output_key.derive_public_key(secp))?;
+        let derived = Descriptor::new_rawtr(derived_key);
+
+        if let Some(check_script) = check_script {
+            if check_script != derived.script_pubkey() {
+                return Ok((derived, false));
+            }
+        }
+
+        // The internal key is unknown, so only the origin of the output key can be recorded
+        item.tap_key_origins().insert(
+            derived_key.to_x_only_pubkey(),
+            (
+                vec![],
+                (
+                    output_key.master_fingerprint(),
+                    output_key.full_derivation_path(),
+                ),
+            ),
+        );
 
-    let derived = if let Descriptor::Tr(_) = &descriptor {
-        let mut hash_lookup = XOnlyHashLookUp(BTreeMap::new(), secp);
+        derived
+    } else if let Descriptor::Tr(_) = &descriptor {
+        let mut hash_lookup = XOnlyHashLookUp(BTreeMap::new());
         // Feed in information about pkh -> pk mapping here
         let derived = descriptor.translate_pk(&mut hash_lookup)?;
 
@@ -1015,9 +1405,14 @@ fn update_input_with_descriptor_helper(
             let spend_info = tr_derived.spend_info();
             let ik_derived = spend_info.internal_key();
             let ik_xpk = tr_xpk.internal_key();
-            input.tap_internal_key = Some(ik_derived);
-            input.tap_merkle_root = spend_info.merkle_root();
-            input.tap_key_origins.insert(
+            *item.tap_internal_key() = Some(ik_derived);
+            if let Some(tap_merkle_root) = item.tap_merkle_root() {
+                *tap_merkle_root = spend_info.merkle_root();
+            }
+            if let Some(tap_tree) = item.tap_tree() {
+                *tap_tree = tr_derived.psbt_tap_tree();
+            }
+            item.tap_key_origins().insert(
                 ik_derived,
                 (
                     vec![],
@@ -1034,7 +1429,9 @@ fn update_input_with_descriptor_helper(
                 let control_block = spend_info
                     .control_block(&leaf_script)
                     .expect("Control block must exist in script map for every known leaf");
-                input.tap_scripts.insert(control_block, leaf_script);
+                if let Some(tap_scripts) = item.tap_scripts() {
+                    tap_scripts.insert(control_block, leaf_script);
+                }
 
                 for (pk_pkh_derived, pk_pkh_xpk) in ms_derived.iter_pk_pkh().zip(ms.iter_pk_pkh()) {
                     let (xonly, xpk) = match (pk_pkh_derived, pk_pkh_xpk) {
@@ -1051,8 +1448,7 @@ fn update_input_with_descriptor_helper(
                         _ => unreachable!("the iterators work in the same order"),
                     };
 
-                    input
-                        .tap_key_origins
+                    item.tap_key_origins()
                         .entry(xonly)
                         .and_modify(|(tapleaf_hashes, _)| {
                             if tapleaf_hashes.last() != Some(&tapleaf_hash) {
@@ -1072,7 +1468,7 @@ fn update_input_with_descriptor_helper(
         derived
     } else {
         // have to use a RefCell because we can't pass FnMut to translate_pk2
-        let mut bip32_derivation = KeySourceLookUp(BTreeMap::new(), Secp256k1::verification_only());
+        let mut bip32_derivation = KeySourceLookUp(BTreeMap::new());
         let derived = descriptor.translate_pk(&mut bip32_derivation)?;
 
         if let Some(check_script) = check_script {
@@ -1081,22 +1477,28 @@ fn update_input_with_descriptor_helper(
             }
         }
 
-        input.bip32_derivation = bip32_derivation.0;
+        *item.bip32_derivation() = bip32_derivation.0;
 
         match &derived {
-            Descriptor::Bare(_) | Descriptor::Pkh(_) | Descriptor::Wpkh(_) => {}
+            Descriptor::Bare(_)
+            | Descriptor::Pkh(_)
+            | Descriptor::Wpkh(_)
+            | Descriptor::Raw(_)
+            | Descriptor::Addr(_) => {}
             Descriptor::Sh(sh) => match sh.as_inner() {
                 descriptor::ShInner::Wsh(wsh) => {
-                    input.witness_script = Some(wsh.inner_script());
-                    input.redeem_script = Some(wsh.inner_script().to_v0_p2wsh());
+                    *item.witness_script() = Some(wsh.inner_script());
+                    *item.redeem_script() = Some(wsh.inner_script().to_v0_p2wsh());
                 }
-                descriptor::ShInner::Wpkh(..) => input.redeem_script = Some(sh.inner_script()),
+                descriptor::ShInner::Wpkh(..) => *item.redeem_script() = Some(sh.inner_script()),
                 descriptor::ShInner::SortedMulti(_) | descriptor::ShInner::Ms(_) => {
-                    input.redeem_script = Some(sh.inner_script())
+                    *item.redeem_script() = Some(sh.inner_script())
                 }
             },
-            Descriptor::Wsh(wsh) => input.witness_script = Some(wsh.inner_script()),
-            Descriptor::Tr(_) => unreachable!("Tr is dealt with separately"),
+            Descriptor::Wsh(wsh) => *item.witness_script() = Some(wsh.inner_script()),
+            Descriptor::Tr(_) | Descriptor::RawTr(_) => {
+                unreachable!("Tr and RawTr are dealt with separately")
+            }
         }
 
         derived
@@ -1153,7 +1555,7 @@ impl fmt::Display for UtxoUpdateError {
 
 #[cfg(feature = "std")]
 impl error::Error for UtxoUpdateError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::UtxoUpdateError::*;
 
         match self {
@@ -1163,6 +1565,109 @@ impl error::Error for UtxoUpdateError {
     }
 }
 
+/// Return error type for [`PsbtExt::update_output_with_descriptor`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum OutputUpdateError {
+    /// Index out of bounds
+    IndexOutOfBounds(usize, usize),
+    /// The PSBT transaction didn't have an output at that index
+    MissingTxOut,
+    /// Derivation error
+    DerivationError(descriptor::ConversionError),
+    /// The output's script_pubkey did not match the descriptor
+    MismatchedScriptPubkey,
+}
+
+impl fmt::Display for OutputUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputUpdateError::IndexOutOfBounds(ind, len) => {
+                write!(f, "index {}, psbt output len: {}", ind, len)
+            }
+            OutputUpdateError::MissingTxOut => {
+                write!(f, "Missing txout in the unsigned transaction")
+            }
+            OutputUpdateError::DerivationError(e) => write!(f, "Key derivation error {}", e),
+            OutputUpdateError::MismatchedScriptPubkey => {
+                write!(f, "The output's script pubkey didn't match the descriptor")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for OutputUpdateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::OutputUpdateError::*;
+
+        match self {
+            IndexOutOfBounds(_, _) | MissingTxOut | MismatchedScriptPubkey => None,
+            DerivationError(e) => Some(e),
+        }
+    }
+}
+
+/// Return error type for [`extract_with_check`]
+#[derive(Debug)]
+pub enum ExtractError {
+    /// Extracting the transaction failed
+    Psbt(Error),
+    /// There is not one descriptor per input
+    WrongDescriptorCount {
+        /// Number of descriptors
+        descriptors: usize,
+        /// Number of inputs
+        inputs: usize,
+    },
+    /// The maximum satisfaction weight of the descriptor of the input at this index could not
+    /// be computed
+    Descriptor(usize, crate::Error),
+    /// The scriptSig and witness of an input weigh more than predicted by its descriptor
+    WeightExceeded {
+        /// Index of the input
+        index: usize,
+        /// The maximum satisfaction weight of the descriptor
+        predicted: usize,
+        /// The weight of the scriptSig and witness of the input
+        actual: usize,
+    },
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ExtractError::Psbt(ref e) => e.fmt(f),
+            ExtractError::WrongDescriptorCount {
+                descriptors,
+                inputs,
+            } => write!(f, "{} descriptors for {} inputs", descriptors, inputs),
+            ExtractError::Descriptor(index, ref e) => {
+                write!(f, "descriptor of input {}: {}", index, e)
+            }
+            ExtractError::WeightExceeded {
+                index,
+                predicted,
+                actual,
+            } => write!(
+                f,
+                "input {} weighs {} weight units, more than the {} predicted by its descriptor",
+                index, actual, predicted
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ExtractError::Psbt(ref e) => Some(e),
+            ExtractError::Descriptor(_, ref e) => Some(e),
+            ExtractError::WrongDescriptorCount { .. } | ExtractError::WeightExceeded { .. } => None,
+        }
+    }
+}
+
 /// Return error type for [`PsbtExt::sighash_msg`]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub enum SighashError {
@@ -1204,7 +1709,7 @@ impl fmt::Display for SighashError {
 
 #[cfg(feature = "std")]
 impl error::Error for SighashError {
-    fn cause(&self) -> Option<&dyn error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         use self::SighashError::*;
 
         match self {
@@ -1259,7 +1764,9 @@ mod tests {
     use bitcoin::{OutPoint, TxIn, TxOut, XOnlyPublicKey};
 
     use super::*;
-    use crate::Miniscript;
+    use crate::descriptor::DerivationIndex;
+    use crate::psbt::PsbtSigner;
+    use crate::{ForEachKey, Miniscript};
 
     #[test]
     fn test_extract_bip174() {
@@ -1439,6 +1946,294 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_update_input_at_index() {
+        let desc = "wsh(multi(1,[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/0/*,[73c5da0a/48'/0'/0'/2']xpub6DkFAXWQ2dHxq2vatrt9qyA3bXYU4ToWQwCHbf5XB2mSTexcHZCeKS1VZYcPoBd5X8yVcbXFHJR9R8UCVpt82VX1VhR28mCyxUFL4r6KFrf/1/*))";
+        let desc = Descriptor::<DescriptorPublicKey>::from_str(desc).unwrap();
+        let index = DerivationIndex::new(3).unwrap();
+        let secp = Secp256k1::verification_only();
+        let derived = desc.derived_descriptor(&secp, index).unwrap();
+
+        let non_witness_utxo = bitcoin::Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: 1_000,
+                    script_pubkey: Script::new(),
+                },
+                TxOut {
+                    value: 2_000,
+                    script_pubkey: derived.script_pubkey(),
+                },
+            ],
+        };
+        let mut psbt_input = psbt::Input {
+            non_witness_utxo: Some(non_witness_utxo.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            update_input_at_index(&mut psbt_input, &desc, index),
+            Ok(derived.clone())
+        );
+        assert_eq!(
+            psbt_input.witness_utxo,
+            Some(non_witness_utxo.output[1].clone())
+        );
+        assert_eq!(
+            psbt_input.witness_script,
+            Some(derived.explicit_script().unwrap())
+        );
+        let paths: Vec<_> = psbt_input
+            .bip32_derivation
+            .values()
+            .map(|(_, path)| path.to_string())
+            .collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&"m/48'/0'/0'/2'/0/3".to_string()));
+        assert!(paths.contains(&"m/48'/0'/0'/2'/1/3".to_string()));
+
+        // A witness_utxo paying to another index is rejected
+        let mut psbt_input = psbt::Input {
+            witness_utxo: Some(non_witness_utxo.output[1].clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            update_input_at_index(&mut psbt_input, &desc, DerivationIndex::new(4).unwrap()),
+            Err(UtxoUpdateError::MismatchedScriptPubkey)
+        );
+        assert_eq!(psbt_input.witness_script, None);
+        assert!(psbt_input.bip32_derivation.is_empty());
+    }
+
+    #[test]
+    fn test_update_output_tr() {
+        let root_xpub = ExtendedPubKey::from_str("xpub661MyMwAqRbcFkPHucMnrGNzDwb6teAX1RbKQmqtEF8kK3Z7LZ59qafCjB9eCRLiTVG3uxBxgKvRgbubRhqSKXnGGb1aoaqLrpMBDrVxga8").unwrap();
+        let fingerprint = root_xpub.fingerprint();
+        let xpub = format!("[{}/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ", fingerprint);
+        let desc = format!(
+            "tr({}/0/0,{{pk({}/0/1),multi_a(2,{}/0/1,{}/1/0)}})",
+            xpub, xpub, xpub, xpub
+        );
+        let desc = Descriptor::from_str(&desc).unwrap();
+        let derived = desc
+            .derived_descriptor(
+                &Secp256k1::verification_only(),
+                DerivationIndex::new(0).unwrap(),
+            )
+            .unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: derived.script_pubkey(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(psbt.update_output_with_descriptor(0, &desc), Ok(()));
+        let output = &psbt.outputs[0];
+        let internal_key = XOnlyPublicKey::from_str(
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+        )
+        .unwrap();
+        assert_eq!(output.tap_internal_key, Some(internal_key));
+        assert_eq!(output.tap_key_origins.len(), 3);
+        let tap_tree = output.tap_tree.clone().unwrap();
+        let spend_info = tap_tree
+            .into_builder()
+            .finalize(&Secp256k1::verification_only(), internal_key)
+            .unwrap();
+        assert_eq!(
+            bitcoin::Script::new_v1_p2tr_tweaked(spend_info.output_key()),
+            derived.script_pubkey()
+        );
+
+        psbt.unsigned_tx.output[0].script_pubkey = Script::default();
+        assert_eq!(
+            psbt.update_output_with_descriptor(0, &desc),
+            Err(OutputUpdateError::MismatchedScriptPubkey)
+        );
+        assert_eq!(
+            psbt.update_output_with_descriptor(1, &desc),
+            Err(OutputUpdateError::IndexOutOfBounds(1, 1))
+        );
+    }
+
+    #[test]
+    fn test_extract_with_check() {
+        use crate::plan::Assumptions;
+
+        let secp = Secp256k1::new();
+        let tprv = "tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc";
+        let (desc, keymap) = Descriptor::parse_descriptor(
+            &secp,
+            &format!("wsh(multi(2,{}/0/*,{}/1/*))", tprv, tprv),
+        )
+        .unwrap();
+        let index = DerivationIndex::new(0).unwrap();
+        let derived = desc.derive(index);
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: derived.script_pubkey(),
+        });
+        update_input_at_index(&mut psbt.inputs[0], &desc, index).unwrap();
+        let mut assets = Assumptions::new();
+        derived.for_each_key(|key| {
+            assets.keys.insert(key.as_key().clone());
+            true
+        });
+        let requests = derived.signing_requests(&assets, None).unwrap();
+        assert_eq!(keymap.sign_input(&secp, &mut psbt, 0, &requests), Ok(2));
+        psbt.finalize_mut(&secp).unwrap();
+
+        let tx = extract_with_check(&psbt, &secp, core::slice::from_ref(&derived)).unwrap();
+        assert_eq!(tx, psbt.extract(&secp).unwrap());
+
+        // A single key spend weighs less than the witness of a multisig
+        let key = assets.keys.iter().next().unwrap().clone();
+        let wpkh = Descriptor::new_wpkh(key).unwrap();
+        match extract_with_check(&psbt, &secp, &[wpkh]) {
+            Err(ExtractError::WeightExceeded {
+                index: 0,
+                predicted: 112,
+                actual: 222,
+            }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        match extract_with_check::<_, DerivedDescriptorKey>(&psbt, &secp, &[]) {
+            Err(ExtractError::WrongDescriptorCount {
+                descriptors: 0,
+                inputs: 1,
+            }) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_update_input_preimages() {
+        use crate::plan::Assumptions;
+
+        // Knows a single preimage, which it returns for any hash
+        struct Htlc([u8; 32]);
+        impl<Pk: MiniscriptKey> PreimageProvider<Pk> for Htlc {
+            fn sha256_preimage(&self, _: &Pk::Sha256) -> Option<Preimage32> {
+                Some(self.0)
+            }
+        }
+
+        let secp = Secp256k1::new();
+        let preimage = [7; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let tprv = "tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc";
+        let (desc, keymap) = Descriptor::parse_descriptor(
+            &secp,
+            &format!("wsh(and_v(v:pk({}/0/*),sha256({})))", tprv, hash),
+        )
+        .unwrap();
+        let index = DerivationIndex::new(0).unwrap();
+        let derived = desc.derive(index);
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: derived.script_pubkey(),
+        });
+        update_input_at_index(&mut psbt.inputs[0], &desc, index).unwrap();
+        let mut assets = Assumptions::new().sha256(hash);
+        derived.for_each_key(|key| {
+            assets.keys.insert(key.as_key().clone());
+            true
+        });
+        let requests = derived.signing_requests(&assets, None).unwrap();
+        assert_eq!(keymap.sign_input(&secp, &mut psbt, 0, &requests), Ok(1));
+
+        // A preimage of the wrong hash is neither written nor used
+        let wrong = Htlc([8; 32]);
+        assert_eq!(
+            update_input_preimages(&mut psbt.inputs[0], &derived, &wrong),
+            Ok(0)
+        );
+        psbt.inputs[0].sha256_preimages.insert(hash, vec![8; 32]);
+        assert!(psbt.clone().finalize_inp(&secp, 0).is_err());
+
+        psbt.inputs[0].sha256_preimages.clear();
+        let htlc = Htlc(preimage);
+        assert_eq!(
+            update_input_preimages(&mut psbt.inputs[0], &derived, &htlc),
+            Ok(1)
+        );
+        assert_eq!(
+            update_input_preimages(&mut psbt.inputs[0], &derived, &htlc),
+            Ok(0)
+        );
+        assert_eq!(
+            psbt.inputs[0].sha256_preimages.get(&hash),
+            Some(&preimage.to_vec())
+        );
+        psbt.finalize_mut(&secp).unwrap();
+    }
+
+    #[test]
+    fn test_verify_change() {
+        let secp = Secp256k1::new();
+        let xpub = "tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK";
+        let change = Descriptor::from_str(&format!("wpkh({}/1/*)", xpub)).unwrap();
+        let receive = Descriptor::from_str(&format!("wpkh({}/0/*)", xpub)).unwrap();
+        let index = DerivationIndex::new(5).unwrap();
+
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: change.derive(index).script_pubkey(),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let range = DerivationRange::new(0..10).unwrap();
+        assert_eq!(verify_change(&psbt, &secp, 0, &change, range), Ok(index));
+        assert_eq!(
+            verify_change(
+                &psbt,
+                &secp,
+                0,
+                &change,
+                DerivationRange::new(0..5).unwrap()
+            ),
+            Err(OutputUpdateError::MismatchedScriptPubkey)
+        );
+        assert_eq!(
+            verify_change(&psbt, &secp, 0, &receive, range),
+            Err(OutputUpdateError::MismatchedScriptPubkey)
+        );
+        assert_eq!(
+            verify_change(&psbt, &secp, 1, &change, range),
+            Err(OutputUpdateError::IndexOutOfBounds(1, 1))
+        );
+    }
+
     #[test]
     fn test_update_input_checks() {
         let desc = format!("tr([73c5da0a/86'/0'/0']xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ/0/0)");
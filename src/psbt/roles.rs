@@ -0,0 +1,328 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Roles
+//!
+//! Wrappers around a [`Psbt`] for the roles defined in BIP 174, each only
+//! exposing the operations of its role. A role hands the PSBT over to the
+//! next one, so that e.g. no input can be added to a PSBT once it is signed:
+//!
+//! [`Creator`] → [`Updater`] → [`Signer`] → [`Finalizer`] → [`Extractor`]
+//!
+//! A role can also be started from a PSBT received from another party.
+
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::psbt;
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Witness};
+
+use super::{
+    extract_with_check, update_input_at_index, Error, ExtractError, OutputUpdateError, Psbt,
+    PsbtExt, PsbtSigner, SighashError, UtxoUpdateError,
+};
+use crate::descriptor::{DerivationIndex, DescriptorPublicKey};
+use crate::plan::SigningRequest;
+use crate::prelude::*;
+use crate::{Descriptor, MiniscriptKey, ToPublicKey};
+
+macro_rules! impl_role {
+    ($role:ident) => {
+        impl $role {
+            /// Takes over a PSBT at this stage of the workflow
+            pub fn new(psbt: Psbt) -> Self {
+                $role(psbt)
+            }
+
+            /// The PSBT being worked on
+            pub fn psbt(&self) -> &Psbt {
+                &self.0
+            }
+
+            /// Gives the PSBT back, e.g. to send it to another party
+            pub fn into_psbt(self) -> Psbt {
+                self.0
+            }
+        }
+    };
+}
+
+/// The Creator role: builds the unsigned transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Creator(Psbt);
+
+impl Creator {
+    /// Creates a PSBT for a transaction with no inputs and no outputs
+    pub fn new(version: i32, lock_time: u32) -> Self {
+        let tx = Transaction {
+            version,
+            lock_time,
+            input: vec![],
+            output: vec![],
+        };
+        Creator(Psbt::from_unsigned_tx(tx).expect("no scriptSig in an empty transaction"))
+    }
+
+    /// Creates a PSBT for `tx`, which must have empty scriptSigs and witnesses
+    pub fn from_unsigned_tx(tx: Transaction) -> Result<Self, psbt::Error> {
+        Psbt::from_unsigned_tx(tx).map(Creator)
+    }
+
+    /// The PSBT being worked on
+    pub fn psbt(&self) -> &Psbt {
+        &self.0
+    }
+
+    /// Adds an input spending `previous_output`, returning its index
+    pub fn add_input(&mut self, previous_output: OutPoint, sequence: u32) -> usize {
+        self.0.unsigned_tx.input.push(TxIn {
+            previous_output,
+            script_sig: Script::new(),
+            sequence,
+            witness: Witness::default(),
+        });
+        self.0.inputs.push(psbt::Input::default());
+        self.0.inputs.len() - 1
+    }
+
+    /// Adds an output, returning its index
+    pub fn add_output(&mut self, txout: TxOut) -> usize {
+        self.0.unsigned_tx.output.push(txout);
+        self.0.outputs.push(psbt::Output::default());
+        self.0.outputs.len() - 1
+    }
+
+    /// Hands the PSBT over to the Updater role, after which the transaction
+    /// can no longer change
+    pub fn updater(self) -> Updater {
+        Updater(self.0)
+    }
+}
+
+/// The Updater role: adds the information needed to sign the inputs and
+/// to recognize the outputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Updater(Psbt);
+
+impl_role!(Updater);
+
+impl Updater {
+    /// The input maps, to fill the fields not covered by descriptors such as
+    /// `non_witness_utxo` or preimages
+    pub fn inputs_mut(&mut self) -> &mut [psbt::Input] {
+        &mut self.0.inputs
+    }
+
+    /// The output maps
+    pub fn outputs_mut(&mut self) -> &mut [psbt::Output] {
+        &mut self.0.outputs
+    }
+
+    /// See [`PsbtExt::update_input_with_descriptor`]
+    pub fn update_input_with_descriptor(
+        &mut self,
+        input_index: usize,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), UtxoUpdateError> {
+        self.0.update_input_with_descriptor(input_index, descriptor)
+    }
+
+    /// Same as [`update_input_at_index`], for the input at
+    /// `input_index`. Returns the derived descriptor.
+    ///
+    /// [`update_input_at_index`]: super::update_input_at_index
+    pub fn update_input_at_index(
+        &mut self,
+        input_index: usize,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+        index: DerivationIndex,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, UtxoUpdateError> {
+        let n_inputs = self.0.inputs.len();
+        let input = self
+            .0
+            .inputs
+            .get_mut(input_index)
+            .ok_or(UtxoUpdateError::IndexOutOfBounds(input_index, n_inputs))?;
+        update_input_at_index(input, descriptor, index)
+    }
+
+    /// See [`PsbtExt::update_output_with_descriptor`]
+    pub fn update_output_with_descriptor(
+        &mut self,
+        output_index: usize,
+        descriptor: &Descriptor<DescriptorPublicKey>,
+    ) -> Result<(), OutputUpdateError> {
+        self.0
+            .update_output_with_descriptor(output_index, descriptor)
+    }
+
+    /// Hands the PSBT over to the Signer role
+    pub fn signer(self) -> Signer {
+        Signer(self.0)
+    }
+}
+
+/// The Signer role: adds signatures to the inputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signer(Psbt);
+
+impl_role!(Signer);
+
+impl Signer {
+    /// See [`PsbtSigner::sign_input`]
+    pub fn sign_input<S, C, Pk>(
+        &mut self,
+        signer: &S,
+        secp: &Secp256k1<C>,
+        index: usize,
+        requests: &[SigningRequest<Pk>],
+    ) -> Result<usize, SighashError>
+    where
+        S: PsbtSigner,
+        C: secp256k1::Signing + secp256k1::Verification,
+        Pk: MiniscriptKey + ToPublicKey,
+    {
+        signer.sign_input(secp, &mut self.0, index, requests)
+    }
+
+    /// Hands the PSBT over to the Finalizer role
+    pub fn finalizer(self) -> Finalizer {
+        Finalizer(self.0)
+    }
+}
+
+/// The Finalizer role: builds the final scriptSigs and witnesses
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finalizer(Psbt);
+
+impl_role!(Finalizer);
+
+impl Finalizer {
+    /// Finalizes all inputs, see [`PsbtExt::finalize`]. On failure, the
+    /// inputs that could be finalized are, and more signatures can be
+    /// added by going back to the [`Signer`] role.
+    #[allow(clippy::result_large_err)] // Same as `PsbtExt::finalize`.
+    pub fn finalize<C: secp256k1::Verification>(
+        self,
+        secp: &Secp256k1<C>,
+    ) -> Result<Extractor, (Self, Vec<Error>)> {
+        self.0
+            .finalize(secp)
+            .map(Extractor)
+            .map_err(|(psbt, errs)| (Finalizer(psbt), errs))
+    }
+
+    /// Same as [`Finalizer::finalize`], but allows for malleable
+    /// satisfactions
+    #[allow(clippy::result_large_err)] // Same as `PsbtExt::finalize_mall`.
+    pub fn finalize_mall<C: secp256k1::Verification>(
+        self,
+        secp: &Secp256k1<C>,
+    ) -> Result<Extractor, (Self, Vec<Error>)> {
+        self.0
+            .finalize_mall(secp)
+            .map(Extractor)
+            .map_err(|(psbt, errs)| (Finalizer(psbt), errs))
+    }
+
+    /// Goes back to the Signer role
+    pub fn signer(self) -> Signer {
+        Signer(self.0)
+    }
+}
+
+/// The Transaction Extractor role: produces the network transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extractor(Psbt);
+
+impl_role!(Extractor);
+
+impl Extractor {
+    /// See [`PsbtExt::extract`]
+    pub fn extract<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+    ) -> Result<Transaction, Error> {
+        self.0.extract(secp)
+    }
+
+    /// See [`extract_with_check`]
+    pub fn extract_with_check<C: secp256k1::Verification, Pk: MiniscriptKey + ToPublicKey>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptors: &[Descriptor<Pk>],
+    ) -> Result<Transaction, ExtractError> {
+        extract_with_check(&self.0, secp, descriptors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptor::KeyMap;
+    use crate::plan::Assumptions;
+    use crate::ForEachKey;
+
+    const TPRV: &str = "tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc";
+
+    fn wallet(desc: &str) -> (Descriptor<DescriptorPublicKey>, KeyMap) {
+        Descriptor::parse_descriptor(&Secp256k1::signing_only(), desc).unwrap()
+    }
+
+    #[test]
+    fn workflow() {
+        let secp = Secp256k1::new();
+        let (desc, keymap) = wallet(&format!("wsh(multi(2,{}/0/*,{}/1/*))", TPRV, TPRV));
+        let (change, _) = wallet(&format!("wpkh({}/2/0)", TPRV));
+        let index = DerivationIndex::new(3).unwrap();
+        let derived = desc.derive(index);
+
+        let mut creator = Creator::new(2, 0);
+        assert_eq!(creator.add_input(OutPoint::default(), 0xfffffffd), 0);
+        assert_eq!(
+            creator.add_output(TxOut {
+                value: 9_000,
+                script_pubkey: change.derive(index).script_pubkey(),
+            }),
+            0
+        );
+
+        let mut updater = creator.updater();
+        updater.inputs_mut()[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: derived.script_pubkey(),
+        });
+        updater.update_input_at_index(0, &desc, index).unwrap();
+        updater.update_output_with_descriptor(0, &change).unwrap();
+        assert_eq!(updater.psbt().outputs[0].bip32_derivation.len(), 1);
+
+        let mut assets = Assumptions::new();
+        derived.for_each_key(|key| {
+            assets.keys.insert(key.as_key().clone());
+            true
+        });
+        let requests = derived.signing_requests(&assets, None).unwrap();
+        let mut signer = updater.signer();
+        // A single signature does not satisfy the multisig
+        assert_eq!(signer.sign_input(&keymap, &secp, 0, &requests[..1]), Ok(1));
+        let (finalizer, errs) = signer.finalizer().finalize(&secp).unwrap_err();
+        assert_eq!(errs.len(), 1);
+
+        let mut signer = finalizer.signer();
+        assert_eq!(signer.sign_input(&keymap, &secp, 0, &requests[1..]), Ok(1));
+        let extractor = signer.finalizer().finalize(&secp).unwrap();
+        let tx = extractor
+            .extract_with_check(&secp, core::slice::from_ref(&derived))
+            .unwrap();
+        assert_eq!(tx.input[0].witness.len(), 4);
+    }
+}
@@ -0,0 +1,333 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Signer
+//!
+//! Implements the Signer role defined in BIP 174, producing only the
+//! signatures listed by a spend plan, see [`Descriptor::signing_requests`].
+//!
+//! [`Descriptor::signing_requests`]: crate::Descriptor::signing_requests
+
+use bitcoin::secp256k1::{self, KeyPair, Secp256k1, SecretKey};
+use bitcoin::util::bip32;
+use bitcoin::util::psbt::PsbtSighashType;
+use bitcoin::util::schnorr::TapTweak;
+use bitcoin::util::sighash::SighashCache;
+use bitcoin::{EcdsaSighashType, SchnorrSighashType};
+
+use super::{finalizer, Psbt, PsbtExt, SighashError};
+use crate::descriptor::{DescriptorSecretKey, KeyMap, Wildcard};
+use crate::plan::SigningRequest;
+use crate::{MiniscriptKey, ToPublicKey};
+
+/// Private key material able to sign PSBT inputs
+///
+/// Implementors only have to find the private key of a [`SigningRequest`],
+/// [`PsbtSigner::sign_input`] takes care of the sighash and of the kind of
+/// signature the input needs.
+pub trait PsbtSigner {
+    /// The private key of `request.key`, or `None` if it is not held by this
+    /// signer
+    fn secret_key<C: secp256k1::Signing, Pk: MiniscriptKey + ToPublicKey>(
+        &self,
+        secp: &Secp256k1<C>,
+        request: &SigningRequest<Pk>,
+    ) -> Option<SecretKey>;
+
+    /// Signs the input at `index` for each of the `requests` this signer holds
+    /// the key of, returning the number of signatures added.
+    ///
+    /// ECDSA signatures are added to `partial_sigs`, taproot key spend
+    /// signatures to `tap_key_sig` and taproot script spend signatures to
+    /// `tap_script_sigs` under the leaf hash of the request. The input must
+    /// already hold the fields needed to compute its sighash, see
+    /// [`PsbtExt::update_input_with_descriptor`]. If the input has no
+    /// `sighash_type`, it is set to the one of the requests when it is not
+    /// the default; an input whose `sighash_type` differs from the one of a
+    /// request is not signed.
+    fn sign_input<C: secp256k1::Signing + secp256k1::Verification, Pk>(
+        &self,
+        secp: &Secp256k1<C>,
+        psbt: &mut Psbt,
+        index: usize,
+        requests: &[SigningRequest<Pk>],
+    ) -> Result<usize, SighashError>
+    where
+        Pk: MiniscriptKey + ToPublicKey,
+    {
+        if index >= psbt.inputs.len() {
+            return Err(SighashError::IndexOutOfBounds(index, psbt.inputs.len()));
+        }
+        let spk = finalizer::get_scriptpubkey(psbt, index)
+            .map_err(|_e| SighashError::MissingInputUtxo)?
+            .clone();
+        let is_taproot = spk.is_v1_p2tr();
+        let default_ty = if is_taproot {
+            PsbtSighashType::from(SchnorrSighashType::Default)
+        } else {
+            PsbtSighashType::from(EcdsaSighashType::All)
+        };
+
+        let tx = psbt.unsigned_tx.clone();
+        let mut cache = SighashCache::new(&tx);
+        let mut n_signed = 0;
+        for request in requests {
+            let sk = match self.secret_key(secp, request) {
+                Some(sk) => sk,
+                None => continue,
+            };
+            match psbt.inputs[index].sighash_type {
+                Some(ty) if ty != request.sighash_type => {
+                    return Err(SighashError::InvalidSighashType)
+                }
+                None if request.sighash_type != default_ty => {
+                    psbt.inputs[index].sighash_type = Some(request.sighash_type)
+                }
+                _ => {}
+            }
+            let msg = psbt
+                .sighash_msg(index, &mut cache, request.leaf_hash)?
+                .to_secp_msg();
+
+            let input = &mut psbt.inputs[index];
+            if is_taproot {
+                let hash_ty = request
+                    .sighash_type
+                    .schnorr_hash_ty()
+                    .map_err(|_e| SighashError::InvalidSighashType)?;
+                let keypair = KeyPair::from_secret_key(secp, sk);
+                let xonly = request.key.to_x_only_pubkey();
+                match request.leaf_hash {
+                    Some(leaf_hash) => {
+                        let sig = secp.sign_schnorr_no_aux_rand(&msg, &keypair);
+                        let sig = bitcoin::SchnorrSig { sig, hash_ty };
+                        input.tap_script_sigs.insert((xonly, leaf_hash), sig);
+                    }
+                    None => {
+                        // The key of a `rawtr` descriptor is the output key itself,
+                        // any other key spend is by the internal key
+                        let keypair = if spk[2..] == xonly.serialize()[..] {
+                            keypair
+                        } else {
+                            keypair.tap_tweak(secp, input.tap_merkle_root).into_inner()
+                        };
+                        let sig = secp.sign_schnorr_no_aux_rand(&msg, &keypair);
+                        input.tap_key_sig = Some(bitcoin::SchnorrSig { sig, hash_ty });
+                    }
+                }
+            } else {
+                let hash_ty = request
+                    .sighash_type
+                    .ecdsa_hash_ty()
+                    .map_err(|_e| SighashError::InvalidSighashType)?;
+                let sig = secp.sign_ecdsa(&msg, &sk);
+                input.partial_sigs.insert(
+                    request.key.to_public_key(),
+                    bitcoin::EcdsaSig { sig, hash_ty },
+                );
+            }
+            n_signed += 1;
+        }
+        Ok(n_signed)
+    }
+}
+
+// Whether `sk` is the private key of `pk`. Keys known by their x-only
+// serialization match both private keys having it.
+fn is_key_of<C: secp256k1::Signing, Pk: ToPublicKey>(
+    secp: &Secp256k1<C>,
+    sk: &SecretKey,
+    pk: &Pk,
+) -> bool {
+    let derived = secp256k1::PublicKey::from_secret_key(secp, sk);
+    if pk.is_x_only_key() {
+        derived.serialize()[1..] == pk.to_x_only_pubkey().serialize()[..]
+    } else {
+        derived == pk.to_public_key().inner
+    }
+}
+
+impl PsbtSigner for DescriptorSecretKey {
+    fn secret_key<C: secp256k1::Signing, Pk: MiniscriptKey + ToPublicKey>(
+        &self,
+        secp: &Secp256k1<C>,
+        request: &SigningRequest<Pk>,
+    ) -> Option<SecretKey> {
+        let sk = match *self {
+            DescriptorSecretKey::Single(ref single) => single.key.inner,
+            DescriptorSecretKey::XPrv(ref xprv) => {
+                let path = match request.origin {
+                    Some((fingerprint, ref path)) => {
+                        let (xprv_fingerprint, xprv_path) = match xprv.origin {
+                            Some((fingerprint, ref path)) => (fingerprint, path.clone()),
+                            None => (xprv.xkey.fingerprint(secp), bip32::DerivationPath::master()),
+                        };
+                        let xprv_len = xprv_path.as_ref().len();
+                        if fingerprint != xprv_fingerprint
+                            || path.as_ref().len() < xprv_len
+                            || path.as_ref()[..xprv_len] != xprv_path.as_ref()[..]
+                        {
+                            return None;
+                        }
+                        bip32::DerivationPath::from(&path.as_ref()[xprv_len..])
+                    }
+                    // Without an origin only the key itself can be tried
+                    None if xprv.wildcard == Wildcard::None => xprv.derivation_path.clone(),
+                    None => return None,
+                };
+                xprv.xkey.derive_priv(secp, &path).ok()?.private_key
+            }
+        };
+        if is_key_of(secp, &sk, &request.key) {
+            Some(sk)
+        } else {
+            None
+        }
+    }
+}
+
+impl PsbtSigner for KeyMap {
+    fn secret_key<C: secp256k1::Signing, Pk: MiniscriptKey + ToPublicKey>(
+        &self,
+        secp: &Secp256k1<C>,
+        request: &SigningRequest<Pk>,
+    ) -> Option<SecretKey> {
+        self.values().find_map(|key| key.secret_key(secp, request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+
+    use super::*;
+    use crate::descriptor::{DerivationIndex, DerivedDescriptorKey};
+    use crate::plan::Assumptions;
+    use crate::prelude::*;
+    use crate::psbt::update_input_at_index;
+    use crate::{Descriptor, ForEachKey};
+
+    const TPRV: &str = "tprv8ZgxMBicQKsPcwcD4gSnMti126ZiETsuX7qwrtMypr6FBwAP65puFn4v6c3jrN9VwtMRMph6nyT63NrfUL4C3nBzPcduzVSuHD7zbX2JKVc";
+
+    // A PSBT spending a single output of `desc`, derived at `index`
+    fn psbt_spending(desc: &str, index: u32) -> (Psbt, Descriptor<DerivedDescriptorKey>, KeyMap) {
+        let secp = Secp256k1::signing_only();
+        let (desc, keymap) = Descriptor::parse_descriptor(&secp, desc).unwrap();
+        let index = DerivationIndex::new(index).unwrap();
+        let derived = desc.derive(index);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: derived.script_pubkey(),
+        });
+        update_input_at_index(&mut psbt.inputs[0], &desc, index).unwrap();
+        (psbt, derived, keymap)
+    }
+
+    fn keys(desc: &Descriptor<DerivedDescriptorKey>) -> Vec<DerivedDescriptorKey> {
+        let mut keys = vec![];
+        desc.for_each_key(|k| {
+            keys.push(k.as_key().clone());
+            true
+        });
+        keys
+    }
+
+    #[test]
+    fn sign_ecdsa() {
+        let secp = Secp256k1::new();
+        let desc = format!(
+            "wsh(or_d(pk({}/0/*),and_v(v:pk({}/1/*),older(10))))",
+            TPRV, TPRV
+        );
+        let (mut psbt, derived, keymap) = psbt_spending(&desc, 5);
+        let keys = keys(&derived);
+        let sighash_type = PsbtSighashType::from(EcdsaSighashType::AllPlusAnyoneCanPay);
+        let requests = derived
+            .signing_requests(&Assumptions::new().key(keys[0].clone()), Some(sighash_type))
+            .unwrap();
+        assert_eq!(requests[0].sighash_type, sighash_type);
+
+        // Another wallet holds none of the keys
+        let (_, _, other) = psbt_spending(
+            "wpkh(tprv8ZgxMBicQKsPd3krDUsBAmtnRsK3rb8u5yi1zhQgMhF1tR8MW7xfE4rnrbbsrbPR52e7rKapu6ztw1jXveJSCGHEriUGZV7mCe88duLp5pj/0/*)",
+            5,
+        );
+        assert_eq!(other.sign_input(&secp, &mut psbt, 0, &requests), Ok(0));
+
+        assert_eq!(keymap.sign_input(&secp, &mut psbt, 0, &requests), Ok(1));
+        let input = &psbt.inputs[0];
+        assert_eq!(input.partial_sigs.len(), 1);
+        assert!(input.partial_sigs.contains_key(&keys[0].to_public_key()));
+        assert_eq!(input.sighash_type, Some(sighash_type));
+        psbt.finalize_mut(&secp).unwrap();
+
+        let (mut psbt, _, _) = psbt_spending(&desc, 5);
+        psbt.inputs[0].sighash_type = Some(PsbtSighashType::from(EcdsaSighashType::Single));
+        assert_eq!(
+            keymap.sign_input(&secp, &mut psbt, 0, &requests),
+            Err(SighashError::InvalidSighashType)
+        );
+    }
+
+    #[test]
+    fn sign_schnorr() {
+        let secp = Secp256k1::new();
+        let desc = format!("tr({}/0/*,pk({}/1/*))", TPRV, TPRV);
+        // Leaf keys come first, the internal key last
+        let (mut psbt, derived, keymap) = psbt_spending(&desc, 2);
+        let keys = keys(&derived);
+
+        // Key spend
+        let requests = derived
+            .signing_requests(&Assumptions::new().key(keys[1].clone()), None)
+            .unwrap();
+        assert_eq!(keymap.sign_input(&secp, &mut psbt, 0, &requests), Ok(1));
+        assert!(psbt.inputs[0].tap_key_sig.is_some());
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+        psbt.finalize_mut(&secp).unwrap();
+
+        // Script spend, with the sighash type of the input
+        let (mut psbt, _, _) = psbt_spending(&desc, 2);
+        let sighash_type = PsbtSighashType::from(SchnorrSighashType::SinglePlusAnyoneCanPay);
+        psbt.inputs[0].sighash_type = Some(sighash_type);
+        let requests = derived
+            .signing_requests(
+                &Assumptions::new().key(keys[0].clone()),
+                psbt.inputs[0].sighash_type,
+            )
+            .unwrap();
+        assert_eq!(keymap.sign_input(&secp, &mut psbt, 0, &requests), Ok(1));
+        assert!(psbt.inputs[0].tap_key_sig.is_none());
+        let leaf_hash = requests[0].leaf_hash.unwrap();
+        assert!(psbt.inputs[0]
+            .tap_script_sigs
+            .contains_key(&(keys[0].to_x_only_pubkey(), leaf_hash)));
+        let sig = psbt.inputs[0].tap_script_sigs.values().next().unwrap();
+        assert_eq!(sig.hash_ty, SchnorrSighashType::SinglePlusAnyoneCanPay);
+        psbt.finalize_mut(&secp).unwrap();
+    }
+}
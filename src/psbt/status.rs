@@ -0,0 +1,434 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Input Status
+//!
+//! Tells what an input still needs before it can be finalized, combining
+//! the spend planning of [`crate::plan`] with the signatures and preimages
+//! already present in the PSBT.
+//!
+
+use core::cell::RefCell;
+
+use bitcoin::hashes::{hash160, ripemd160, sha256d};
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::util::psbt;
+use bitcoin::util::taproot::{ControlBlock, LeafVersion, TapLeafHash};
+
+use super::{Error, InputError, Psbt, PsbtInputSatisfier};
+use crate::miniscript::satisfy::dummy_schnorr_sig;
+use crate::plan::{scriptsig_elements, Assumptions, KeyOrigin, SigRecorder, SigningRequest};
+use crate::prelude::*;
+use crate::{Descriptor, ForEach, ForEachKey, MiniscriptKey, Preimage32, Satisfier, ToPublicKey};
+
+/// Something an input still needs before it can be finalized, see
+/// [`input_status`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InputNeed<Pk: MiniscriptKey> {
+    /// A signature by `key`, on the leaf `leaf_hash` for taproot script
+    /// spends
+    Signature(SigningRequest<Pk>),
+    /// The preimage of a sha256 hash
+    Sha256Preimage(Pk::Sha256),
+    /// The preimage of a hash256 hash
+    Hash256Preimage(sha256d::Hash),
+    /// The preimage of a ripemd160 hash
+    Ripemd160Preimage(ripemd160::Hash),
+    /// The preimage of a hash160 hash
+    Hash160Preimage(hash160::Hash),
+    /// The chain must reach the given block height or timestamp, as
+    /// required by an `after` fragment
+    After(u32),
+    /// The spent output must be as old as the given relative timelock, as
+    /// required by an `older` fragment
+    Older(u32),
+}
+
+/// Lists what the input at `index`, controlled by `descriptor`, still needs
+/// before it can be finalized.
+///
+/// If the signatures and preimages already in the input satisfy the
+/// descriptor, only the timelocks of that satisfaction are listed.
+/// Otherwise, every key of the descriptor is assumed to be able to sign and
+/// every preimage to be obtainable, and the needs of the cheapest spend path
+/// are listed, preferring the keys which already signed. Timelocks must be
+/// allowed by the `nLockTime` and `nSequence` of the transaction; a spend
+/// path they rule out is never chosen.
+///
+/// Finalized inputs need nothing.
+pub fn input_status<Pk>(
+    psbt: &Psbt,
+    index: usize,
+    descriptor: &Descriptor<Pk>,
+) -> Result<Vec<InputNeed<Pk>>, Error>
+where
+    Pk: MiniscriptKey + ToPublicKey + KeyOrigin,
+{
+    if index >= psbt.inputs.len() || index >= psbt.unsigned_tx.input.len() {
+        return Err(Error::InputIdxOutofBounds {
+            psbt_inp: psbt.inputs.len(),
+            index,
+        });
+    }
+    let input = &psbt.inputs[index];
+    if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+        return Ok(vec![]);
+    }
+    let miniscript_err = |e| Error::InputError(InputError::MiniscriptError(e), index);
+
+    let mut assets = Assumptions::new();
+    descriptor.for_each_key(|key| {
+        // The keys of `pkh` fragments are only known by their hash
+        if let ForEach::Key(pk) = key {
+            assets.keys.insert(pk.clone());
+        }
+        true
+    });
+    let present = StatusSatisfier::new(psbt, index, &assets, true);
+    let sat = if descriptor.get_satisfaction(&present).is_ok() {
+        present
+    } else {
+        StatusSatisfier::new(psbt, index, &assets, false)
+    };
+
+    let mut needs = vec![];
+    if !sat.present_only {
+        let key_spend = match *descriptor {
+            Descriptor::Tr(ref tr) => Some(tr.internal_key().clone()),
+            Descriptor::RawTr(ref rawtr) => Some(rawtr.output_key().clone()),
+            _ => None,
+        };
+        let is_taproot = key_spend.is_some();
+        let recorder = SigRecorder::new(&sat, key_spend, is_taproot);
+        let (witness, script_sig) = descriptor
+            .get_satisfaction(&recorder)
+            .map_err(miniscript_err)?;
+        for request in recorder.requests(&witness, &script_sig, input.sighash_type) {
+            if !has_sig(input, &request, is_taproot) {
+                needs.push(InputNeed::Signature(request));
+            }
+        }
+        for elem in witness.iter().chain(&scriptsig_elements(&script_sig)) {
+            if let Some(need) = sat.preimage_for(elem) {
+                if !needs.contains(&need) {
+                    needs.push(need);
+                }
+            }
+        }
+    }
+
+    // A timelock is needed if the satisfaction changes without it
+    let satisfaction = descriptor.get_satisfaction(&sat).map_err(miniscript_err)?;
+    let timelocks = sat.timelocks.borrow().clone();
+    for timelock in timelocks {
+        let mut blocked = StatusSatisfier::new(psbt, index, &assets, sat.present_only);
+        blocked.blocked = Some(timelock.clone());
+        if descriptor.get_satisfaction(&blocked).ok().as_ref() != Some(&satisfaction) {
+            needs.push(timelock);
+        }
+    }
+    Ok(needs)
+}
+
+// Whether the input already has the signature of `request`
+fn has_sig<Pk: MiniscriptKey + ToPublicKey>(
+    input: &psbt::Input,
+    request: &SigningRequest<Pk>,
+    is_taproot: bool,
+) -> bool {
+    match request.leaf_hash {
+        Some(leaf_hash) => input
+            .tap_script_sigs
+            .contains_key(&(request.key.to_x_only_pubkey(), leaf_hash)),
+        None if is_taproot => input.tap_key_sig.is_some(),
+        None => input
+            .partial_sigs
+            .contains_key(&request.key.to_public_key()),
+    }
+}
+
+// Dummy preimage standing for the `idx`th missing preimage looked up
+fn dummy_preimage(idx: usize) -> Preimage32 {
+    let mut preimage = [0xa5; 32];
+    preimage[28..].copy_from_slice(&(idx as u32).to_be_bytes());
+    preimage
+}
+
+// Satisfier answering with the signatures and preimages of the input, and,
+// unless `present_only`, with dummy ones for those missing. Timelocks are
+// checked against the transaction, except `blocked`, and the ones which
+// pass are recorded.
+struct StatusSatisfier<'a, Pk: MiniscriptKey> {
+    psbt: PsbtInputSatisfier<'a>,
+    assets: &'a Assumptions<Pk>,
+    present_only: bool,
+    blocked: Option<InputNeed<Pk>>,
+    preimages: RefCell<Vec<InputNeed<Pk>>>,
+    timelocks: RefCell<Vec<InputNeed<Pk>>>,
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> StatusSatisfier<'a, Pk> {
+    fn new(psbt: &'a Psbt, index: usize, assets: &'a Assumptions<Pk>, present_only: bool) -> Self {
+        StatusSatisfier {
+            psbt: PsbtInputSatisfier::new(psbt, index),
+            assets,
+            present_only,
+            blocked: None,
+            preimages: RefCell::new(vec![]),
+            timelocks: RefCell::new(vec![]),
+        }
+    }
+
+    fn input(&self) -> &psbt::Input {
+        &self.psbt.psbt.inputs[self.psbt.index]
+    }
+
+    fn missing_preimage(&self, need: InputNeed<Pk>) -> Option<Preimage32> {
+        if self.present_only {
+            return None;
+        }
+        let mut preimages = self.preimages.borrow_mut();
+        let idx = match preimages.iter().position(|p| *p == need) {
+            Some(idx) => idx,
+            None => {
+                preimages.push(need);
+                preimages.len() - 1
+            }
+        };
+        Some(dummy_preimage(idx))
+    }
+
+    // The missing preimage `elem` stands for, if any
+    fn preimage_for(&self, elem: &[u8]) -> Option<InputNeed<Pk>> {
+        let preimages = self.preimages.borrow();
+        (0..preimages.len())
+            .find(|idx| dummy_preimage(*idx)[..] == *elem)
+            .map(|idx| preimages[idx].clone())
+    }
+
+    fn check_timelock(&self, timelock: InputNeed<Pk>, passes: bool) -> bool {
+        if !passes || self.blocked.as_ref() == Some(&timelock) {
+            return false;
+        }
+        let mut timelocks = self.timelocks.borrow_mut();
+        if !timelocks.contains(&timelock) {
+            timelocks.push(timelock);
+        }
+        true
+    }
+
+    fn has_signed(&self, pk: &Pk) -> bool {
+        let input = self.input();
+        let x_only = pk.to_x_only_pubkey();
+        input.partial_sigs.contains_key(&pk.to_public_key())
+            || input.tap_script_sigs.keys().any(|&(key, _)| key == x_only)
+    }
+}
+
+impl<'a, Pk: MiniscriptKey + ToPublicKey> Satisfier<Pk> for StatusSatisfier<'a, Pk> {
+    fn lookup_ecdsa_sig(&self, pk: &Pk) -> Option<bitcoin::EcdsaSig> {
+        let sig = Satisfier::<Pk>::lookup_ecdsa_sig(&self.psbt, pk);
+        if self.present_only {
+            sig
+        } else {
+            sig.or_else(|| self.assets.lookup_ecdsa_sig(pk))
+        }
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<bitcoin::SchnorrSig> {
+        let sig = Satisfier::<Pk>::lookup_tap_key_spend_sig(&self.psbt);
+        if self.present_only {
+            sig
+        } else {
+            sig.or_else(|| Some(dummy_schnorr_sig(self.assets.sig_sizes.schnorr())))
+        }
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &Pk, h: &TapLeafHash) -> Option<bitcoin::SchnorrSig> {
+        let sig = Satisfier::<Pk>::lookup_tap_leaf_script_sig(&self.psbt, pk, h);
+        if self.present_only {
+            sig
+        } else {
+            sig.or_else(|| self.assets.lookup_tap_leaf_script_sig(pk, h))
+        }
+    }
+
+    fn lookup_tap_control_block_map(
+        &self,
+    ) -> Option<&BTreeMap<ControlBlock, (bitcoin::Script, LeafVersion)>> {
+        Satisfier::<Pk>::lookup_tap_control_block_map(&self.psbt)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &Pk::Hash) -> Option<Pk> {
+        self.assets.lookup_pkh_pk(pkh)
+    }
+
+    fn lookup_pkh_ecdsa_sig(
+        &self,
+        pkh: &Pk::Hash,
+    ) -> Option<(bitcoin::PublicKey, bitcoin::EcdsaSig)> {
+        let sig = Satisfier::<Pk>::lookup_pkh_ecdsa_sig(&self.psbt, pkh);
+        if self.present_only {
+            sig
+        } else {
+            sig.or_else(|| self.assets.lookup_pkh_ecdsa_sig(pkh))
+        }
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        pkh: &(Pk::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, bitcoin::SchnorrSig)> {
+        let sig = Satisfier::<Pk>::lookup_pkh_tap_leaf_script_sig(&self.psbt, pkh);
+        if self.present_only {
+            sig
+        } else {
+            sig.or_else(|| self.assets.lookup_pkh_tap_leaf_script_sig(pkh))
+        }
+    }
+
+    fn lookup_sha256(&self, h: &Pk::Sha256) -> Option<Preimage32> {
+        Satisfier::<Pk>::lookup_sha256(&self.psbt, h)
+            .or_else(|| self.missing_preimage(InputNeed::Sha256Preimage(h.clone())))
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<Preimage32> {
+        Satisfier::<Pk>::lookup_hash256(&self.psbt, h)
+            .or_else(|| self.missing_preimage(InputNeed::Hash256Preimage(h)))
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<Preimage32> {
+        Satisfier::<Pk>::lookup_ripemd160(&self.psbt, h)
+            .or_else(|| self.missing_preimage(InputNeed::Ripemd160Preimage(h)))
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<Preimage32> {
+        Satisfier::<Pk>::lookup_hash160(&self.psbt, h)
+            .or_else(|| self.missing_preimage(InputNeed::Hash160Preimage(h)))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        let passes = Satisfier::<Pk>::check_older(&self.psbt, n);
+        self.check_timelock(InputNeed::Older(n), passes)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        let passes = Satisfier::<Pk>::check_after(&self.psbt, n);
+        self.check_timelock(InputNeed::After(n), passes)
+    }
+
+    fn key_preference(&self, pk: &Pk) -> Option<u32> {
+        if self.has_signed(pk) {
+            Some(0)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{Script, Transaction, TxIn, TxOut};
+
+    use super::*;
+
+    fn psbt(lock_time: u32, sequence: u32) -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time,
+            input: vec![TxIn {
+                sequence,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn input_needs() {
+        let secp = Secp256k1::new();
+        let sks: Vec<_> = (1..3u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect();
+        let pks: Vec<_> = sks
+            .iter()
+            .map(|sk| {
+                bitcoin::PublicKey::new(bitcoin::secp256k1::PublicKey::from_secret_key(&secp, sk))
+            })
+            .collect();
+        let sig = |sk| bitcoin::EcdsaSig {
+            sig: secp.sign_ecdsa(&Message::from_slice(&[3; 32]).unwrap(), sk),
+            hash_ty: bitcoin::EcdsaSighashType::All,
+        };
+        let request = |pk: bitcoin::PublicKey| SigningRequest {
+            key: pk,
+            origin: None,
+            leaf_hash: None,
+            sighash_type: psbt::PsbtSighashType::from(bitcoin::EcdsaSighashType::All),
+        };
+
+        let preimage = [4; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(and_v(v:pk({}),and_v(v:sha256({}),after(100))))",
+            pks[0], hash
+        ))
+        .unwrap();
+        let mut psbt = self::psbt(100, 0xfffffffe);
+        assert_eq!(
+            input_status(&psbt, 0, &desc).unwrap(),
+            vec![
+                InputNeed::Signature(request(pks[0])),
+                InputNeed::Sha256Preimage(hash),
+                InputNeed::After(100),
+            ]
+        );
+        psbt.inputs[0].partial_sigs.insert(pks[0], sig(&sks[0]));
+        psbt.inputs[0]
+            .sha256_preimages
+            .insert(hash, preimage.to_vec());
+        assert_eq!(
+            input_status(&psbt, 0, &desc).unwrap(),
+            vec![InputNeed::After(100)]
+        );
+        // The lock time of the transaction is too early
+        assert!(input_status(&self::psbt(99, 0xfffffffe), 0, &desc).is_err());
+
+        // The timelocked branch is only needed once its key signed
+        let desc = Descriptor::<bitcoin::PublicKey>::from_str(&format!(
+            "wsh(or_d(pk({}),and_v(v:pk({}),older(10))))",
+            pks[0], pks[1]
+        ))
+        .unwrap();
+        let mut psbt = self::psbt(0, 10);
+        assert_eq!(
+            input_status(&psbt, 0, &desc).unwrap(),
+            vec![InputNeed::Signature(request(pks[0]))]
+        );
+        psbt.inputs[0].partial_sigs.insert(pks[1], sig(&sks[1]));
+        assert_eq!(
+            input_status(&psbt, 0, &desc).unwrap(),
+            vec![InputNeed::Older(10)]
+        );
+        assert!(input_status(&psbt, 1, &desc).is_err());
+    }
+}
@@ -0,0 +1,604 @@
+// Miniscript
+// Written in 2022 by
+//     rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # PSBT Version 2
+//!
+//! Reads and writes PSBTs in the version 2 format defined in BIP 370,
+//! `https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki`, where
+//! the unsigned transaction is replaced by per-input and per-output fields.
+//! The fields shared with version 0 use the [`psbt::Input`] and
+//! [`psbt::Output`] maps, so a [`PsbtV2`] converts to and from a v0 [`Psbt`]
+//! and can be finalized with the same logic.
+//!
+
+use core::{cmp, fmt};
+#[cfg(feature = "std")]
+use std::error;
+
+use bitcoin::consensus::encode::{self, deserialize, deserialize_partial, serialize, VarInt};
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32::{ExtendedPubKey, KeySource};
+use bitcoin::util::psbt::{self, raw};
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+
+use super::{Error, Psbt, PsbtExt};
+use crate::miniscript::limits::LOCKTIME_THRESHOLD;
+use crate::prelude::*;
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+
+/// Error reading or converting a [`PsbtV2`]
+#[derive(Debug)]
+pub enum PsbtV2Error {
+    /// The serialization is invalid
+    Encode(encode::Error),
+    /// The PSBT version is not 2
+    UnsupportedVersion(u32),
+    /// A field required by BIP 370 is missing
+    MissingField(&'static str),
+    /// A field has an invalid value
+    InvalidField(&'static str),
+    /// A field excluded from version 2 is present
+    ExcludedField(&'static str),
+    /// No lock time satisfies the requirements of every input
+    LockTimeConflict,
+    /// Finalizing some of the inputs failed
+    Finalize(Vec<Error>),
+}
+
+impl fmt::Display for PsbtV2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PsbtV2Error::Encode(ref e) => write!(f, "PSBT encoding error: {}", e),
+            PsbtV2Error::UnsupportedVersion(v) => write!(f, "unsupported PSBT version {}", v),
+            PsbtV2Error::MissingField(field) => write!(f, "missing PSBTv2 field {}", field),
+            PsbtV2Error::InvalidField(field) => write!(f, "invalid PSBTv2 field {}", field),
+            PsbtV2Error::ExcludedField(field) => {
+                write!(f, "field {} is not allowed in PSBTv2", field)
+            }
+            PsbtV2Error::LockTimeConflict => {
+                f.write_str("the inputs require both a height and a time lock time")
+            }
+            PsbtV2Error::Finalize(ref errs) => {
+                f.write_str("finalization failed:")?;
+                for e in errs {
+                    write!(f, " {};", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for PsbtV2Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            PsbtV2Error::Encode(ref e) => Some(e),
+            PsbtV2Error::UnsupportedVersion(_)
+            | PsbtV2Error::MissingField(_)
+            | PsbtV2Error::InvalidField(_)
+            | PsbtV2Error::ExcludedField(_)
+            | PsbtV2Error::LockTimeConflict
+            | PsbtV2Error::Finalize(_) => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<encode::Error> for PsbtV2Error {
+    fn from(e: encode::Error) -> PsbtV2Error {
+        PsbtV2Error::Encode(e)
+    }
+}
+
+/// An input of a [`PsbtV2`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputV2 {
+    /// The output spent by the input
+    pub previous_output: OutPoint,
+    /// The `nSequence` of the input, final if `None`
+    pub sequence: Option<u32>,
+    /// The minimal time based lock time the input needs
+    pub required_time_locktime: Option<u32>,
+    /// The minimal height based lock time the input needs
+    pub required_height_locktime: Option<u32>,
+    /// The fields shared with version 0
+    pub input: psbt::Input,
+}
+
+/// An output of a [`PsbtV2`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputV2 {
+    /// The amount of the output
+    pub amount: u64,
+    /// The script pubkey of the output
+    pub script_pubkey: Script,
+    /// The fields shared with version 0
+    pub output: psbt::Output,
+}
+
+/// A PSBT in the version 2 format of BIP 370
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsbtV2 {
+    /// The version of the transaction
+    pub tx_version: i32,
+    /// The lock time of the transaction when no input requires one
+    pub fallback_locktime: Option<u32>,
+    /// The `PSBT_GLOBAL_TX_MODIFIABLE` flags, if present
+    pub tx_modifiable: Option<u8>,
+    /// The extended public keys used by the inputs and outputs
+    pub xpub: BTreeMap<ExtendedPubKey, KeySource>,
+    /// Global proprietary key-value pairs
+    pub proprietary: BTreeMap<raw::ProprietaryKey, Vec<u8>>,
+    /// Unknown global key-value pairs
+    pub unknown: BTreeMap<raw::Key, Vec<u8>>,
+    /// The inputs
+    pub inputs: Vec<InputV2>,
+    /// The outputs
+    pub outputs: Vec<OutputV2>,
+}
+
+impl PsbtV2 {
+    /// Converts a version 0 PSBT, moving the fields of its unsigned
+    /// transaction to the inputs and outputs. A non-zero lock time becomes
+    /// the fallback lock time.
+    pub fn from_v0(psbt: Psbt) -> PsbtV2 {
+        let tx = psbt.unsigned_tx;
+        let inputs = tx
+            .input
+            .iter()
+            .zip(psbt.inputs)
+            .map(|(txin, input)| InputV2 {
+                previous_output: txin.previous_output,
+                sequence: Some(txin.sequence),
+                required_time_locktime: None,
+                required_height_locktime: None,
+                input,
+            })
+            .collect();
+        let outputs = tx
+            .output
+            .into_iter()
+            .zip(psbt.outputs)
+            .map(|(txout, output)| OutputV2 {
+                amount: txout.value,
+                script_pubkey: txout.script_pubkey,
+                output,
+            })
+            .collect();
+        PsbtV2 {
+            tx_version: tx.version,
+            fallback_locktime: if tx.lock_time == 0 {
+                None
+            } else {
+                Some(tx.lock_time)
+            },
+            tx_modifiable: None,
+            xpub: psbt.xpub,
+            proprietary: psbt.proprietary,
+            unknown: psbt.unknown,
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Converts to a version 0 PSBT, whose unsigned transaction uses
+    /// [`PsbtV2::lock_time`].
+    pub fn to_v0(&self) -> Result<Psbt, PsbtV2Error> {
+        let lock_time = self.lock_time().ok_or(PsbtV2Error::LockTimeConflict)?;
+        Ok(self.to_v0_with_lock_time(lock_time))
+    }
+
+    /// The lock time of the transaction, following the algorithm of BIP 370:
+    /// the greatest lock time required by the inputs, of the kind all of them
+    /// support, preferring heights, or the fallback lock time if no input
+    /// requires one.
+    ///
+    /// Returns `None` if an input only supports a height and another only a
+    /// time.
+    pub fn lock_time(&self) -> Option<u32> {
+        let requiring = self.inputs.iter().filter(|inp| {
+            inp.required_time_locktime.is_some() || inp.required_height_locktime.is_some()
+        });
+        let (mut heights, mut times) = (Some(0), Some(0));
+        let mut any = false;
+        for inp in requiring {
+            any = true;
+            heights =
+                heights.and_then(|max: u32| inp.required_height_locktime.map(|h| cmp::max(max, h)));
+            times = times.and_then(|max: u32| inp.required_time_locktime.map(|t| cmp::max(max, t)));
+        }
+        if !any {
+            return Some(self.fallback_locktime.unwrap_or(0));
+        }
+        heights.or(times)
+    }
+
+    /// Finalizes every input, see [`PsbtExt::finalize_mut`]
+    pub fn finalize_mut<C: secp256k1::Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(), PsbtV2Error> {
+        let mut psbt = self.to_v0()?;
+        let res = psbt.finalize_mut(secp);
+        for (inp, finalized) in self.inputs.iter_mut().zip(psbt.inputs) {
+            inp.input = finalized;
+        }
+        res.map_err(PsbtV2Error::Finalize)
+    }
+
+    /// Serializes in the version 2 format
+    pub fn serialize(&self) -> Vec<u8> {
+        // The fields shared with version 0 are serialized by rust-bitcoin,
+        // the maps are then edited to replace the unsigned transaction
+        let v0 = serialize(&self.to_v0_with_lock_time(0));
+        let mut pos = PSBT_MAGIC.len();
+        let mut global = read_map(&v0, &mut pos).expect("rust-bitcoin serialization");
+        global.retain(|(key, _)| key[..] != [PSBT_GLOBAL_UNSIGNED_TX]);
+        global.push((vec![PSBT_GLOBAL_TX_VERSION], serialize(&self.tx_version)));
+        if let Some(lock_time) = self.fallback_locktime {
+            global.push((vec![PSBT_GLOBAL_FALLBACK_LOCKTIME], serialize(&lock_time)));
+        }
+        let n_inputs = VarInt(self.inputs.len() as u64);
+        global.push((vec![PSBT_GLOBAL_INPUT_COUNT], serialize(&n_inputs)));
+        let n_outputs = VarInt(self.outputs.len() as u64);
+        global.push((vec![PSBT_GLOBAL_OUTPUT_COUNT], serialize(&n_outputs)));
+        if let Some(flags) = self.tx_modifiable {
+            global.push((vec![PSBT_GLOBAL_TX_MODIFIABLE], vec![flags]));
+        }
+        global.push((vec![PSBT_GLOBAL_VERSION], serialize(&2u32)));
+
+        let mut ret = PSBT_MAGIC.to_vec();
+        write_map(&mut ret, global);
+        for inp in &self.inputs {
+            let mut map = read_map(&v0, &mut pos).expect("rust-bitcoin serialization");
+            let txid = inp.previous_output.txid;
+            map.push((vec![PSBT_IN_PREVIOUS_TXID], txid.into_inner().to_vec()));
+            let vout = inp.previous_output.vout;
+            map.push((vec![PSBT_IN_OUTPUT_INDEX], serialize(&vout)));
+            if let Some(sequence) = inp.sequence {
+                map.push((vec![PSBT_IN_SEQUENCE], serialize(&sequence)));
+            }
+            if let Some(time) = inp.required_time_locktime {
+                map.push((vec![PSBT_IN_REQUIRED_TIME_LOCKTIME], serialize(&time)));
+            }
+            if let Some(height) = inp.required_height_locktime {
+                map.push((vec![PSBT_IN_REQUIRED_HEIGHT_LOCKTIME], serialize(&height)));
+            }
+            write_map(&mut ret, map);
+        }
+        for out in &self.outputs {
+            let mut map = read_map(&v0, &mut pos).expect("rust-bitcoin serialization");
+            map.push((vec![PSBT_OUT_AMOUNT], serialize(&out.amount)));
+            map.push((vec![PSBT_OUT_SCRIPT], out.script_pubkey.to_bytes()));
+            write_map(&mut ret, map);
+        }
+        ret
+    }
+
+    /// Deserializes a PSBT in the version 2 format
+    pub fn deserialize(bytes: &[u8]) -> Result<PsbtV2, PsbtV2Error> {
+        if !bytes.starts_with(PSBT_MAGIC) {
+            return Err(encode::Error::ParseFailed("invalid PSBT magic").into());
+        }
+        let mut pos = PSBT_MAGIC.len();
+
+        let mut global = read_map(bytes, &mut pos)?;
+        let version = take_field(&mut global, PSBT_GLOBAL_VERSION, "PSBT_GLOBAL_VERSION")?
+            .ok_or(PsbtV2Error::UnsupportedVersion(0))?;
+        if version != 2 {
+            return Err(PsbtV2Error::UnsupportedVersion(version));
+        }
+        if global
+            .iter()
+            .any(|(key, _)| key[..] == [PSBT_GLOBAL_UNSIGNED_TX])
+        {
+            return Err(PsbtV2Error::ExcludedField("PSBT_GLOBAL_UNSIGNED_TX"));
+        }
+        let tx_version = take_field(
+            &mut global,
+            PSBT_GLOBAL_TX_VERSION,
+            "PSBT_GLOBAL_TX_VERSION",
+        )?
+        .ok_or(PsbtV2Error::MissingField("PSBT_GLOBAL_TX_VERSION"))?;
+        let fallback_locktime = take_field(
+            &mut global,
+            PSBT_GLOBAL_FALLBACK_LOCKTIME,
+            "PSBT_GLOBAL_FALLBACK_LOCKTIME",
+        )?;
+        let n_inputs: VarInt = take_field(
+            &mut global,
+            PSBT_GLOBAL_INPUT_COUNT,
+            "PSBT_GLOBAL_INPUT_COUNT",
+        )?
+        .ok_or(PsbtV2Error::MissingField("PSBT_GLOBAL_INPUT_COUNT"))?;
+        let n_outputs: VarInt = take_field(
+            &mut global,
+            PSBT_GLOBAL_OUTPUT_COUNT,
+            "PSBT_GLOBAL_OUTPUT_COUNT",
+        )?
+        .ok_or(PsbtV2Error::MissingField("PSBT_GLOBAL_OUTPUT_COUNT"))?;
+        let tx_modifiable = take_field(
+            &mut global,
+            PSBT_GLOBAL_TX_MODIFIABLE,
+            "PSBT_GLOBAL_TX_MODIFIABLE",
+        )?;
+
+        let mut input_maps = vec![];
+        let mut inputs = vec![];
+        for _ in 0..n_inputs.0 {
+            let mut map = read_map(bytes, &mut pos)?;
+            let txid: Txid = take_field(&mut map, PSBT_IN_PREVIOUS_TXID, "PSBT_IN_PREVIOUS_TXID")?
+                .ok_or(PsbtV2Error::MissingField("PSBT_IN_PREVIOUS_TXID"))?;
+            let vout = take_field(&mut map, PSBT_IN_OUTPUT_INDEX, "PSBT_IN_OUTPUT_INDEX")?
+                .ok_or(PsbtV2Error::MissingField("PSBT_IN_OUTPUT_INDEX"))?;
+            let sequence = take_field(&mut map, PSBT_IN_SEQUENCE, "PSBT_IN_SEQUENCE")?;
+            let required_time_locktime: Option<u32> = take_field(
+                &mut map,
+                PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                "PSBT_IN_REQUIRED_TIME_LOCKTIME",
+            )?;
+            if required_time_locktime.map_or(false, |t| t < LOCKTIME_THRESHOLD) {
+                return Err(PsbtV2Error::InvalidField("PSBT_IN_REQUIRED_TIME_LOCKTIME"));
+            }
+            let required_height_locktime: Option<u32> = take_field(
+                &mut map,
+                PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME",
+            )?;
+            if required_height_locktime.map_or(false, |h| h == 0 || h >= LOCKTIME_THRESHOLD) {
+                return Err(PsbtV2Error::InvalidField(
+                    "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME",
+                ));
+            }
+            input_maps.push(map);
+            inputs.push(InputV2 {
+                previous_output: OutPoint { txid, vout },
+                sequence,
+                required_time_locktime,
+                required_height_locktime,
+                input: psbt::Input::default(),
+            });
+        }
+        let mut output_maps = vec![];
+        let mut outputs = vec![];
+        for _ in 0..n_outputs.0 {
+            let mut map = read_map(bytes, &mut pos)?;
+            let amount = take_field(&mut map, PSBT_OUT_AMOUNT, "PSBT_OUT_AMOUNT")?
+                .ok_or(PsbtV2Error::MissingField("PSBT_OUT_AMOUNT"))?;
+            let script_pubkey = take_raw_field(&mut map, PSBT_OUT_SCRIPT)
+                .ok_or(PsbtV2Error::MissingField("PSBT_OUT_SCRIPT"))?;
+            output_maps.push(map);
+            outputs.push(OutputV2 {
+                amount,
+                script_pubkey: Script::from(script_pubkey),
+                output: psbt::Output::default(),
+            });
+        }
+        if pos != bytes.len() {
+            return Err(encode::Error::ParseFailed("data not consumed entirely").into());
+        }
+
+        let mut v2 = PsbtV2 {
+            tx_version,
+            fallback_locktime,
+            tx_modifiable,
+            xpub: BTreeMap::new(),
+            proprietary: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs,
+            outputs,
+        };
+        // The remaining fields are those of version 0, parse them as such
+        let tx = v2.to_v0_with_lock_time(0).unsigned_tx;
+        global.push((vec![PSBT_GLOBAL_UNSIGNED_TX], serialize(&tx)));
+        let mut v0 = PSBT_MAGIC.to_vec();
+        write_map(&mut v0, global);
+        for map in input_maps.into_iter().chain(output_maps) {
+            write_map(&mut v0, map);
+        }
+        let v0: Psbt = deserialize(&v0)?;
+        v2.xpub = v0.xpub;
+        v2.proprietary = v0.proprietary;
+        v2.unknown = v0.unknown;
+        for (inp, input) in v2.inputs.iter_mut().zip(v0.inputs) {
+            inp.input = input;
+        }
+        for (out, output) in v2.outputs.iter_mut().zip(v0.outputs) {
+            out.output = output;
+        }
+        Ok(v2)
+    }
+
+    fn to_v0_with_lock_time(&self, lock_time: u32) -> Psbt {
+        let unsigned_tx = Transaction {
+            version: self.tx_version,
+            lock_time,
+            input: self
+                .inputs
+                .iter()
+                .map(|inp| TxIn {
+                    previous_output: inp.previous_output,
+                    sequence: inp.sequence.unwrap_or(0xffff_ffff),
+                    ..Default::default()
+                })
+                .collect(),
+            output: self
+                .outputs
+                .iter()
+                .map(|out| TxOut {
+                    value: out.amount,
+                    script_pubkey: out.script_pubkey.clone(),
+                })
+                .collect(),
+        };
+        Psbt {
+            unsigned_tx,
+            version: 0,
+            xpub: self.xpub.clone(),
+            proprietary: self.proprietary.clone(),
+            unknown: self.unknown.clone(),
+            inputs: self.inputs.iter().map(|inp| inp.input.clone()).collect(),
+            outputs: self.outputs.iter().map(|out| out.output.clone()).collect(),
+        }
+    }
+}
+
+// The key-value pairs of a PSBT map, keys including their type
+type RawMap = Vec<(Vec<u8>, Vec<u8>)>;
+
+fn read_bytes(bytes: &[u8], pos: &mut usize) -> Result<Vec<u8>, encode::Error> {
+    let (len, consumed): (VarInt, usize) = deserialize_partial(&bytes[*pos..])?;
+    *pos += consumed;
+    let end = pos
+        .checked_add(len.0 as usize)
+        .filter(|end| *end <= bytes.len())
+        .ok_or(encode::Error::ParseFailed("truncated PSBT"))?;
+    let ret = bytes[*pos..end].to_vec();
+    *pos = end;
+    Ok(ret)
+}
+
+// Reads a map starting at `pos`, leaving `pos` after its separator
+fn read_map(bytes: &[u8], pos: &mut usize) -> Result<RawMap, encode::Error> {
+    let mut map = vec![];
+    loop {
+        let key = read_bytes(bytes, pos)?;
+        if key.is_empty() {
+            return Ok(map);
+        }
+        let value = read_bytes(bytes, pos)?;
+        map.push((key, value));
+    }
+}
+
+fn write_map(out: &mut Vec<u8>, mut map: RawMap) {
+    map.sort();
+    for (key, value) in map {
+        out.extend(serialize(&VarInt(key.len() as u64)));
+        out.extend(key);
+        out.extend(serialize(&VarInt(value.len() as u64)));
+        out.extend(value);
+    }
+    out.push(0x00);
+}
+
+// Removes the field with the given type and no key data from the map
+fn take_raw_field(map: &mut RawMap, type_value: u8) -> Option<Vec<u8>> {
+    let pos = map.iter().position(|(key, _)| key[..] == [type_value])?;
+    Some(map.remove(pos).1)
+}
+
+fn take_field<T: encode::Decodable>(
+    map: &mut RawMap,
+    type_value: u8,
+    name: &'static str,
+) -> Result<Option<T>, PsbtV2Error> {
+    match take_raw_field(map, type_value) {
+        Some(value) => deserialize(&value)
+            .map(Some)
+            .map_err(|_| PsbtV2Error::InvalidField(name)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::hex::FromHex;
+
+    use super::*;
+
+    // The finalized PSBT of the BIP 174 test vectors
+    const BIP174_FINALIZED: &str = "70736274ff01009a020000000258e87a21b56daf0c23be8e7070456c336f7cbaa5c8757924f545887bb2abdd750000000000ffffffff838d0427d0ec650a68aa46bb0b098aea4422c071b2ca78352a077959d07cea1d0100000000ffffffff0270aaf00800000000160014d85c2b71d0060b09c9886aeb815e50991dda124d00e1f5050000000016001400aea9a2e5f0f876a588df5546e8742d1d87008f00000000000100bb0200000001aad73931018bd25f84ae400b68848be09db706eac2ac18298babee71ab656f8b0000000048473044022058f6fc7c6a33e1b31548d481c826c015bd30135aad42cd67790dab66d2ad243b02204a1ced2604c6735b6393e5b41691dd78b00f0c5942fb9f751856faa938157dba01feffffff0280f0fa020000000017a9140fb9463421696b82c833af241c78c17ddbde493487d0f20a270100000017a91429ca74f8a08f81999428185c97b5d852e4063f6187650000000107da00473044022074018ad4180097b873323c0015720b3684cc8123891048e7dbcd9b55ad679c99022073d369b740e3eb53dcefa33823c8070514ca55a7dd9544f157c167913261118c01483045022100f61038b308dc1da865a34852746f015772934208c6d24454393cd99bdf2217770220056e675a675a6d0a02b85b14e5e29074d8a25a9b5760bea2816f661910a006ea01475221029583bf39ae0a609747ad199addd634fa6108559d6c5cd39b4c2183f1ab96e07f2102dab61ff49a14db6a7d02b0cd1fbb78fc4b18312b5b4e54dae4dba2fbfef536d752ae0001012000c2eb0b0000000017a914b7f5faf40e3d40a5a459b1db3535f2b72fa921e8870107232200208c2353173743b595dfb4a07b72ba8e42e3797da74e87fe7d9d7497e3b20289030108da0400473044022062eb7a556107a7c73f45ac4ab5a1dddf6f7075fb1275969a7f383efff784bcb202200c05dbb7470dbf2f08557dd356c7325c1ed30913e996cd3840945db12228da5f01473044022065f45ba5998b59a27ffe1a7bed016af1f1f90d54b3aa8f7450aa5f56a25103bd02207f724703ad1edb96680b284b56d4ffcb88f7fb759eabbe08aa30f29b851383d20147522103089dc10c7ac6db54f91329af617333db388cead0c231f723379d1b99030b02dc21023add904f3d6dcf59ddb906b0dee23529b7ffb9ed50e5e86151926860221f0e7352ae00220203a9a4c37f5996d3aa25dbac6b570af0650394492942460b354753ed9eeca5877110d90c6a4f000000800000008004000080002202027f6399757d2eff55a136ad02c684b1838b6556e5f1b6b34282a94b6b5005109610d90c6a4f00000080000000800500008000";
+
+    #[test]
+    fn roundtrip() {
+        let v0: Psbt = deserialize(&Vec::<u8>::from_hex(BIP174_FINALIZED).unwrap()).unwrap();
+        let v2 = PsbtV2::from_v0(v0.clone());
+        assert_eq!(v2.inputs.len(), 2);
+        assert_eq!(v2.outputs.len(), 2);
+        assert_eq!(v2.to_v0().unwrap(), v0);
+
+        let bytes = v2.serialize();
+        assert_eq!(PsbtV2::deserialize(&bytes).unwrap(), v2);
+        // Version 0 parsers reject it
+        assert!(deserialize::<Psbt>(&bytes).is_err());
+        match PsbtV2::deserialize(&serialize(&v0)) {
+            Err(PsbtV2Error::UnsupportedVersion(0)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        // Drop the output count
+        let mut pos = PSBT_MAGIC.len();
+        let mut global = read_map(&bytes, &mut pos).unwrap();
+        take_raw_field(&mut global, PSBT_GLOBAL_OUTPUT_COUNT).unwrap();
+        let mut truncated = PSBT_MAGIC.to_vec();
+        write_map(&mut truncated, global);
+        truncated.extend(&bytes[pos..]);
+        match PsbtV2::deserialize(&truncated) {
+            Err(PsbtV2Error::MissingField("PSBT_GLOBAL_OUTPUT_COUNT")) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn lock_time() {
+        let v0: Psbt = deserialize(&Vec::<u8>::from_hex(BIP174_FINALIZED).unwrap()).unwrap();
+        let mut v2 = PsbtV2::from_v0(v0);
+        v2.fallback_locktime = Some(10);
+        assert_eq!(v2.lock_time(), Some(10));
+
+        // Heights are preferred when every input supports both
+        v2.inputs[0].required_height_locktime = Some(100);
+        v2.inputs[0].required_time_locktime = Some(LOCKTIME_THRESHOLD + 1);
+        assert_eq!(v2.lock_time(), Some(100));
+        v2.inputs[1].required_height_locktime = Some(200);
+        assert_eq!(v2.lock_time(), Some(200));
+        assert_eq!(v2.to_v0().unwrap().unsigned_tx.lock_time, 200);
+
+        // Times only when an input does not support heights
+        v2.inputs[1].required_height_locktime = None;
+        v2.inputs[1].required_time_locktime = Some(LOCKTIME_THRESHOLD);
+        assert_eq!(v2.lock_time(), Some(LOCKTIME_THRESHOLD + 1));
+
+        v2.inputs[0].required_time_locktime = None;
+        assert_eq!(v2.lock_time(), None);
+        match v2.to_v0() {
+            Err(PsbtV2Error::LockTimeConflict) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+
+        let bytes = v2.serialize();
+        assert_eq!(PsbtV2::deserialize(&bytes).unwrap(), v2);
+    }
+}
@@ -0,0 +1,421 @@
+// Miniscript
+// Written in 2022 by rust-miniscript developers
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Testing Utilities
+//!
+//! Generators of random descriptors, miniscripts and policies which are
+//! accepted by the parsers of this crate, along with mutations of them, to
+//! seed fuzzers with structurally valid inputs rather than random bytes.
+//! Available with the `test-utils` feature.
+//!
+//! The keys are written as hex public keys or as extended public keys, so
+//! that the strings parse with [`DescriptorPublicKey`] keys.
+//!
+//! ```
+//! use std::str::FromStr;
+//!
+//! use miniscript::testing::Generator;
+//! use miniscript::{Descriptor, DescriptorPublicKey};
+//!
+//! let mut gen = Generator::new(42);
+//! let corpus: Vec<String> = (0..16).map(|_| gen.descriptor(3)).collect();
+//! for s in &corpus {
+//!     assert!(Descriptor::<DescriptorPublicKey>::from_str(s).is_ok());
+//! }
+//! let mutated = gen.mutate(&corpus[0]);
+//! ```
+
+use core::str::FromStr;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::util::bip32;
+
+use crate::expression::Tree;
+use crate::policy::Concrete;
+use crate::prelude::*;
+use crate::{Descriptor, DescriptorPublicKey, Miniscript, Segwitv0, Tap};
+
+// Number of attempts at generating a valid string before falling back to a
+// single key
+const MAX_ATTEMPTS: usize = 32;
+
+// Names and numbers written by the mutations
+const FRAGMENT_NAMES: &[&str] = &[
+    "pk", "pkh", "pk_k", "pk_h", "older", "after", "sha256", "and_v", "and_b", "andor", "or_b",
+    "or_c", "or_d", "or_i", "thresh", "multi", "multi_a", "and", "or", "wsh", "sh", "wpkh", "tr",
+];
+const NUMBERS: &[&str] = &[
+    "0",
+    "1",
+    "2",
+    "65535",
+    "2147483648",
+    "4294967295",
+    "4294967296",
+];
+const CHARSET: &[u8] = b"()[]{},:@/*'#0123456789abcdefhkpuvxz";
+
+/// Generator of random descriptor, miniscript and policy strings
+///
+/// The generator is deterministic: the same seed always gives the same
+/// strings. Each string has distinct keys, so that it passes the sanity
+/// checks of parsing.
+pub struct Generator {
+    state: u64,
+    secp: Secp256k1<secp256k1::SignOnly>,
+}
+
+impl Generator {
+    /// Creates a generator from a seed
+    pub fn new(seed: u64) -> Generator {
+        Generator {
+            state: seed,
+            secp: Secp256k1::signing_only(),
+        }
+    }
+
+    /// Creates a generator seeded by the input of a fuzzer
+    pub fn from_bytes(data: &[u8]) -> Generator {
+        let hash = sha256::Hash::hash(data);
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&hash[..8]);
+        Generator::new(u64::from_le_bytes(seed))
+    }
+
+    /// Generates a concrete policy of depth at most `max_depth`
+    pub fn policy(&mut self, max_depth: usize) -> String {
+        self.valid(
+            |gen| gen.policy_at(max_depth),
+            |s| Concrete::<DescriptorPublicKey>::from_str(s).is_ok(),
+            |gen| format!("pk({})", gen.key(false)),
+        )
+    }
+
+    /// Generates a segwit v0 miniscript of depth at most `max_depth`
+    pub fn miniscript(&mut self, max_depth: usize) -> String {
+        self.valid(
+            |gen| gen.ms_b(max_depth, false),
+            |s| Miniscript::<DescriptorPublicKey, Segwitv0>::from_str(s).is_ok(),
+            |gen| format!("pk({})", gen.key(false)),
+        )
+    }
+
+    /// Generates a tapscript miniscript of depth at most `max_depth`
+    pub fn tap_miniscript(&mut self, max_depth: usize) -> String {
+        self.valid(
+            |gen| gen.ms_b(max_depth, true),
+            |s| Miniscript::<DescriptorPublicKey, Tap>::from_str(s).is_ok(),
+            |gen| format!("pk({})", gen.key(true)),
+        )
+    }
+
+    /// Generates a descriptor, the miniscripts and taproot tree of which
+    /// have a depth at most `max_depth`
+    pub fn descriptor(&mut self, max_depth: usize) -> String {
+        self.valid(
+            |gen| match gen.below(9) {
+                0 => format!("pkh({})", gen.key(false)),
+                1 => format!("wpkh({})", gen.key(false)),
+                2 => format!("sh(wpkh({}))", gen.key(false)),
+                3 => format!("sh({})", gen.ms_b(max_depth, false)),
+                4 => format!("wsh({})", gen.ms_b(max_depth, false)),
+                5 => format!("sh(wsh({}))", gen.ms_b(max_depth, false)),
+                6 => format!("wsh({})", gen.multi("sortedmulti", false)),
+                7 => format!("tr({})", gen.key(true)),
+                _ => format!("tr({},{})", gen.key(true), gen.tap_tree(max_depth)),
+            },
+            |s| Descriptor::<DescriptorPublicKey>::from_str(s).is_ok(),
+            |gen| format!("wpkh({})", gen.key(false)),
+        )
+    }
+
+    /// Mutates a string, by changing some of its fragments, arguments or
+    /// characters. The result is meant to be close to a valid string, not to
+    /// be valid.
+    pub fn mutate(&mut self, s: &str) -> String {
+        // Taproot trees are not parsed as expressions
+        if !s.contains('{') && self.below(2) == 0 {
+            if let Ok(mut tree) = Tree::from_str(s) {
+                let n_nodes = tree.iter().count();
+                let n = self.below(n_nodes);
+                let node = nth_node(&mut tree, n);
+                match self.below(5) {
+                    0 => node.name = FRAGMENT_NAMES[self.below(FRAGMENT_NAMES.len())],
+                    1 => node.name = NUMBERS[self.below(NUMBERS.len())],
+                    2 if !node.args.is_empty() => {
+                        let i = self.below(node.args.len());
+                        let arg = node.args[i].clone();
+                        node.args.insert(i, arg);
+                    }
+                    3 if !node.args.is_empty() => {
+                        let i = self.below(node.args.len());
+                        node.args.remove(i);
+                    }
+                    _ if node.args.len() > 1 => {
+                        let i = self.below(node.args.len());
+                        let j = self.below(node.args.len());
+                        node.args.swap(i, j);
+                    }
+                    _ => node.args.clear(),
+                }
+                return tree.to_string();
+            }
+        }
+        let mut bytes = s.as_bytes().to_vec();
+        let pos = self.below(bytes.len() + 1);
+        match self.below(3) {
+            0 if pos < bytes.len() => bytes[pos] = CHARSET[self.below(CHARSET.len())],
+            1 if pos < bytes.len() => {
+                let len = 1 + self.below(bytes.len() - pos);
+                bytes.drain(pos..pos + len);
+            }
+            _ => bytes.insert(pos, CHARSET[self.below(CHARSET.len())]),
+        }
+        // Only ASCII bytes are written, at the place of a whole character
+        String::from_utf8(bytes).unwrap_or_else(|_| s.to_owned())
+    }
+
+    // Generates strings until one is valid
+    fn valid<G, V, F>(&mut self, mut generate: G, is_valid: V, fallback: F) -> String
+    where
+        G: FnMut(&mut Self) -> String,
+        V: Fn(&str) -> bool,
+        F: FnOnce(&mut Self) -> String,
+    {
+        for _ in 0..MAX_ATTEMPTS {
+            let s = generate(self);
+            if is_valid(&s) {
+                return s;
+            }
+        }
+        fallback(self)
+    }
+
+    // Splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A random number in `0..n`, for a small `n`
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            (self.next_u64() % n as u64) as usize
+        }
+    }
+
+    fn bytes32(&mut self) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.next_u64().to_le_bytes());
+        sha256::Hash::from_engine(engine).into_inner()
+    }
+
+    // A new key, x-only in tapscript, which is sometimes an extended key
+    fn key(&mut self, x_only: bool) -> String {
+        let seed = self.bytes32();
+        if self.below(4) == 0 {
+            let xpriv = bip32::ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &seed)
+                .expect("32-byte seed");
+            let xpub = bip32::ExtendedPubKey::from_priv(&self.secp, &xpriv);
+            let path = if self.below(2) == 0 { "/0/*" } else { "/1/2" };
+            return format!("[{}/0']{}{}", xpriv.fingerprint(&self.secp), xpub, path);
+        }
+        let sk = secp256k1::SecretKey::from_slice(&seed).unwrap_or(secp256k1::ONE_KEY);
+        let pk = secp256k1::PublicKey::from_secret_key(&self.secp, &sk).to_string();
+        if x_only {
+            pk[2..].to_owned()
+        } else {
+            pk
+        }
+    }
+
+    fn multi(&mut self, name: &str, x_only: bool) -> String {
+        let n = 1 + self.below(3);
+        let k = 1 + self.below(n);
+        let mut ret = format!("{}({}", name, k);
+        for _ in 0..n {
+            ret.push(',');
+            ret.push_str(&self.key(x_only));
+        }
+        ret.push(')');
+        ret
+    }
+
+    fn policy_at(&mut self, depth: usize) -> String {
+        match if depth == 0 { 0 } else { self.below(7) } {
+            0 | 1 => format!("pk({})", self.key(false)),
+            2 => format!("and({},{})", self.policy_at(depth - 1), self.older()),
+            3 => format!(
+                "and({},{})",
+                self.policy_at(depth - 1),
+                self.policy_at(depth - 1)
+            ),
+            4 => {
+                let (l, r) = (1 + self.below(9), 1 + self.below(9));
+                let (left, right) = (self.policy_at(depth - 1), self.policy_at(depth - 1));
+                format!("or({}@{},{}@{})", l, left, r, right)
+            }
+            5 => format!(
+                "or({},{})",
+                self.policy_at(depth - 1),
+                self.policy_at(depth - 1)
+            ),
+            _ => {
+                let n = 2 + self.below(2);
+                let k = 1 + self.below(n);
+                let subs: Vec<String> = (0..n).map(|_| self.policy_at(depth - 1)).collect();
+                format!("thresh({},{})", k, subs.join(","))
+            }
+        }
+    }
+
+    fn older(&mut self) -> String {
+        format!("older({})", 1 + self.below(65535))
+    }
+
+    // A miniscript of type `B`, which requires a signature
+    fn ms_b(&mut self, depth: usize, tap: bool) -> String {
+        match if depth == 0 { 0 } else { self.below(10) } {
+            0 | 1 => self.ms_bdu(tap),
+            2 => format!("and_v(v:{},{})", self.ms_b(depth - 1, tap), self.older()),
+            3 => format!(
+                "and_v(v:{},{})",
+                self.ms_b(depth - 1, tap),
+                self.ms_b(depth - 1, tap)
+            ),
+            4 => {
+                let hash = sha256::Hash::from_inner(self.bytes32());
+                format!("and_v(v:{},sha256({}))", self.ms_b(depth - 1, tap), hash)
+            }
+            5 => format!("or_d({},{})", self.ms_bdu(tap), self.ms_b(depth - 1, tap)),
+            6 => format!(
+                "or_i({},{})",
+                self.ms_b(depth - 1, tap),
+                self.ms_b(depth - 1, tap)
+            ),
+            7 => format!(
+                "andor({},{},{})",
+                self.ms_bdu(tap),
+                self.ms_b(depth - 1, tap),
+                self.ms_b(depth - 1, tap)
+            ),
+            8 => format!("or_b({},s:pk({}))", self.ms_bdu(tap), self.key(tap)),
+            _ => {
+                let n = 2 + self.below(2);
+                let k = 1 + self.below(n);
+                let mut ret = format!("thresh({},{}", k, self.ms_bdu(tap));
+                for _ in 1..n {
+                    ret.push_str(&format!(",s:pk({})", self.key(tap)));
+                }
+                ret.push(')');
+                ret
+            }
+        }
+    }
+
+    // A miniscript of type `Bdu`
+    fn ms_bdu(&mut self, tap: bool) -> String {
+        match self.below(3) {
+            0 => format!("pk({})", self.key(tap)),
+            1 if !tap => format!("pkh({})", self.key(tap)),
+            _ if tap => self.multi("multi_a", tap),
+            _ => self.multi("multi", tap),
+        }
+    }
+
+    fn tap_tree(&mut self, depth: usize) -> String {
+        if depth == 0 || self.below(2) == 0 {
+            self.ms_b(depth, true)
+        } else {
+            format!(
+                "{{{},{}}}",
+                self.tap_tree(depth - 1),
+                self.tap_tree(depth - 1)
+            )
+        }
+    }
+}
+
+// The `n`th node of a tree in pre-order
+fn nth_node<'t, 'a>(tree: &'t mut Tree<'a>, n: usize) -> &'t mut Tree<'a> {
+    let mut node = tree;
+    let mut n = n;
+    while n > 0 {
+        n -= 1;
+        let mut i = 0;
+        // Skip the subtrees before the one holding the node
+        loop {
+            let size = node.args[i].iter().count();
+            if n < size {
+                break;
+            }
+            n -= size;
+            i += 1;
+        }
+        node = &mut node.args[i];
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::Generator;
+    use crate::policy::Concrete;
+    use crate::{Descriptor, DescriptorPublicKey, Miniscript, Segwitv0, Tap};
+
+    #[test]
+    fn generated_strings_parse() {
+        let mut gen = Generator::new(0);
+        for _ in 0..64 {
+            let s = gen.descriptor(3);
+            assert!(
+                Descriptor::<DescriptorPublicKey>::from_str(&s).is_ok(),
+                "{}",
+                s
+            );
+            let s = gen.miniscript(3);
+            assert!(
+                Miniscript::<DescriptorPublicKey, Segwitv0>::from_str(&s).is_ok(),
+                "{}",
+                s
+            );
+            let s = gen.tap_miniscript(3);
+            assert!(
+                Miniscript::<DescriptorPublicKey, Tap>::from_str(&s).is_ok(),
+                "{}",
+                s
+            );
+            let s = gen.policy(3);
+            assert!(
+                Concrete::<DescriptorPublicKey>::from_str(&s).is_ok(),
+                "{}",
+                s
+            );
+            let _ = gen.mutate(&s);
+        }
+
+        // Deterministic for a seed
+        let mut other = Generator::from_bytes(b"seed");
+        let first = other.descriptor(2);
+        assert_eq!(Generator::from_bytes(b"seed").descriptor(2), first);
+        assert_ne!(gen.descriptor(2), first);
+    }
+}
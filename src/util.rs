@@ -1,4 +1,5 @@
 use bitcoin::blockdata::script;
+use bitcoin::secp256k1::{All, Secp256k1};
 use bitcoin::Script;
 
 use crate::miniscript::context;
@@ -8,6 +9,28 @@ pub(crate) fn varint_len(n: usize) -> usize {
     bitcoin::VarInt(n as u64).len()
 }
 
+// Runs `f` with a secp context shared by all calls, so that hot paths such as
+// deriving many keys do not each pay for setting up a fresh context. This is
+// the global context with the `global-context` feature, a thread-local one
+// otherwise with `std`, and a fresh one without `std`.
+pub(crate) fn with_secp<R>(f: impl FnOnce(&Secp256k1<All>) -> R) -> R {
+    #[cfg(feature = "global-context")]
+    {
+        f(bitcoin::secp256k1::SECP256K1)
+    }
+    #[cfg(all(feature = "std", not(feature = "global-context")))]
+    {
+        thread_local! {
+            static SECP: Secp256k1<All> = Secp256k1::new();
+        }
+        SECP.with(|secp| f(secp))
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        f(&Secp256k1::new())
+    }
+}
+
 // Helper function to calculate witness size
 pub(crate) fn witness_size(wit: &[Vec<u8>]) -> usize {
     wit.iter().map(Vec::len).sum::<usize>() + varint_len(wit.len())
@@ -25,6 +48,13 @@ pub(crate) fn witness_to_scriptsig(witness: &[Vec<u8>]) -> Script {
     b.into_script()
 }
 
+// Sort keys by their x-only serialization, as done for `sortedmulti_a`
+pub(crate) fn sort_x_only_keys<Pk: ToPublicKey>(keys: &[Pk]) -> Vec<Pk> {
+    let mut keys = keys.to_vec();
+    keys.sort_by_key(|pk| pk.to_x_only_pubkey().serialize());
+    keys
+}
+
 // trait for pushing key that depend on context
 pub(crate) trait MsKeyBuilder {
     /// Serialize the key as bytes based on script context. Used when encoding miniscript into bitcoin script
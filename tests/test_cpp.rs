@@ -14,6 +14,7 @@ use bitcoin::util::psbt;
 use bitcoin::util::psbt::PartiallySignedTransaction as Psbt;
 use bitcoin::{self, Amount, OutPoint, Transaction, TxIn, TxOut, Txid};
 use bitcoind::bitcoincore_rpc::{json, Client, RpcApi};
+use miniscript::descriptor::DerivationIndex;
 use miniscript::miniscript::iter;
 use miniscript::psbt::PsbtExt;
 use miniscript::{Descriptor, Miniscript, MiniscriptKey, Segwitv0};
@@ -32,7 +33,10 @@ pub(crate) fn parse_miniscripts(
     for line in read_lines("tests/data/random_ms.txt") {
         let ms = test_util::parse_insane_ms(&line.unwrap(), pubdata);
         let wsh = Descriptor::new_wsh(ms).unwrap();
-        desc_vec.push(wsh.derived_descriptor(secp, 0).unwrap());
+        desc_vec.push(
+            wsh.derived_descriptor(secp, DerivationIndex::new(0).unwrap())
+                .unwrap(),
+        );
     }
     desc_vec
 }
@@ -16,6 +16,7 @@ use bitcoin::{
     self, secp256k1, Amount, OutPoint, SchnorrSig, Script, Transaction, TxIn, TxOut, Txid,
 };
 use bitcoind::bitcoincore_rpc::{json, Client, RpcApi};
+use miniscript::descriptor::DerivationIndex;
 use miniscript::miniscript::iter;
 use miniscript::psbt::{PsbtExt, PsbtInputExt};
 use miniscript::{Descriptor, Miniscript, MiniscriptKey, ScriptContext, ToPublicKey};
@@ -56,7 +57,9 @@ pub fn test_desc_satisfy(cl: &Client, testdata: &TestData, desc: &str) -> Witnes
     assert_eq!(blocks.len(), 1);
 
     let desc = test_util::parse_test_desc(&desc, &testdata.pubdata);
-    let derived_desc = desc.derived_descriptor(&secp, 0).unwrap();
+    let derived_desc = desc
+        .derived_descriptor(&secp, DerivationIndex::new(0).unwrap())
+        .unwrap();
     // Next send some btc to each address corresponding to the miniscript
     let txid = cl
         .send_to_address(
@@ -228,7 +231,12 @@ pub fn test_desc_satisfy(cl: &Client, testdata: &TestData, desc: &str) -> Witnes
                     }
                     miniscript::descriptor::WshInner::Ms(ref ms) => find_sks_ms(&ms, testdata),
                 },
-                Descriptor::Tr(_tr) => unreachable!("Tr checked earlier"),
+                Descriptor::Tr(_) | Descriptor::RawTr(_) => {
+                    unreachable!("Tr checked earlier, RawTr is not tested")
+                }
+                Descriptor::Raw(_) | Descriptor::Addr(_) => {
+                    unreachable!("Watch-only descriptors are not tested")
+                }
             };
             let msg = psbt
                 .sighash_msg(0, &mut sighash_cache, None)